@@ -0,0 +1,60 @@
+//! Selection of the anti-aliasing technique. Each technique reads the color buffer and writes the
+//! post buffer.
+
+use serde::Deserialize;
+
+use crate::temporal_resolve::TaaSettings;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum AntiAliasing {
+    /// Copy the color buffer as is.
+    Off,
+    /// Fast approximate anti-aliasing, which blurs along edges found from the luma of each pixel.
+    Fxaa,
+    /// Enhanced subpixel morphological anti-aliasing (SMAA 1x), which blends across edges by
+    /// the coverage of the line between the ends of each edge, looked up in a precomputed area
+    /// texture.
+    Smaa,
+    /// Temporal anti-aliasing, which jitters the projection and accumulates frames.
+    Taa(TaaSettings),
+}
+
+impl Default for AntiAliasing {
+    fn default() -> Self {
+        Self::Taa(TaaSettings::default())
+    }
+}
+
+impl AntiAliasing {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Fxaa,
+            Self::Fxaa => Self::Smaa,
+            Self::Smaa => Self::Taa(TaaSettings::default()),
+            Self::Taa(_) => Self::Off,
+        }
+    }
+
+    /// The length of the sequence of subpixel offsets the projection is jittered by, or `None` if
+    /// the projection shouldn't be jittered.
+    pub fn jitter_sequence_length(&self) -> Option<u32> {
+        match self {
+            Self::Taa(taa) => Some(taa.jitter_sequence_length),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn only_taa_jitters() {
+    assert_eq!(AntiAliasing::default().jitter_sequence_length(), Some(12));
+
+    let mut anti_aliasing = AntiAliasing::Off;
+
+    for _ in 0..3 {
+        assert_eq!(anti_aliasing.jitter_sequence_length(), None);
+        anti_aliasing = anti_aliasing.next();
+    }
+
+    assert_eq!(anti_aliasing, AntiAliasing::default());
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use std::{mem, slice};
+
+use bytemuck::Pod;
+use memmap2::Mmap;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A slice of plain data, which is either owned or borrowed from a memory mapped scene cache.
+/// Mapped blobs are copied if they are modified.
+#[derive(Clone)]
+pub struct Blob<T> {
+    data: BlobData<T>,
+}
+
+#[derive(Clone)]
+enum BlobData<T> {
+    Owned(Vec<T>),
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl<T: Pod> Blob<T> {
+    /// Borrow the bytes in `range` of `mmap`. Fails if the bytes aren't aligned to `T` or if
+    /// the size isn't a multiple of the size of `T`.
+    pub fn mapped(mmap: Arc<Mmap>, range: Range<usize>) -> eyre::Result<Self> {
+        let bytes = mmap
+            .get(range.clone())
+            .ok_or_else(|| eyre::eyre!("range {range:?} is out of bounds"))?;
+
+        if let Err(err) = bytemuck::try_cast_slice::<u8, T>(bytes) {
+            return Err(eyre::eyre!("can't cast mapped bytes: {err}"));
+        }
+
+        Ok(Self {
+            data: BlobData::Mapped(mmap, range),
+        })
+    }
+
+    /// Returns the owned data, copying it if it is mapped.
+    pub fn to_mut(&mut self) -> &mut Vec<T> {
+        if let BlobData::Mapped(..) = self.data {
+            self.data = BlobData::Owned(self.to_vec());
+        }
+
+        match &mut self.data {
+            BlobData::Owned(data) => data,
+            BlobData::Mapped(..) => unreachable!(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::cast_slice(self)
+    }
+}
+
+impl<T: Pod> Deref for Blob<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match &self.data {
+            BlobData::Owned(data) => data,
+            BlobData::Mapped(mmap, range) => bytemuck::cast_slice(&mmap[range.clone()]),
+        }
+    }
+}
+
+impl<T> Default for Blob<T> {
+    fn default() -> Self {
+        Self {
+            data: BlobData::Owned(Vec::new()),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Blob<T> {
+    fn from(data: Vec<T>) -> Self {
+        Self {
+            data: BlobData::Owned(data),
+        }
+    }
+}
+
+impl<T: Pod> Extend<T> for Blob<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.to_mut().extend(iter);
+    }
+}
+
+impl<T: Pod + PartialEq> PartialEq for Blob<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Pod + Eq> Eq for Blob<T> {}
+
+impl<T: Pod> fmt::Debug for Blob<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.data {
+            BlobData::Owned(_) => "Owned",
+            BlobData::Mapped(..) => "Mapped",
+        };
+
+        write!(f, "Blob::{kind}({} items)", self.len())
+    }
+}
+
+impl<T: Pod> Serialize for Blob<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.as_bytes())
+    }
+}
+
+impl<'de, T: Pod> Deserialize<'de> for Blob<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BlobVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Pod> Visitor<'de> for BlobVisitor<T> {
+            type Value = Blob<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a byte array")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                if !bytes.len().is_multiple_of(mem::size_of::<T>()) {
+                    return Err(E::invalid_length(bytes.len(), &self));
+                }
+
+                // Copy into a `Vec<T>` as `bytes` may not be aligned to `T`.
+                let mut data = vec![T::zeroed(); bytes.len() / mem::size_of::<T>()];
+                bytemuck::cast_slice_mut::<T, u8>(&mut data).copy_from_slice(bytes);
+
+                Ok(Blob::from(data))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BlobVisitor(PhantomData))
+    }
+}
+
+impl<'a, T: Pod> IntoIterator for &'a Blob<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
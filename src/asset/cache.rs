@@ -0,0 +1,336 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use eyre::{Result, WrapErr};
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use super::{Blob, CameraProjection, DirectionalLight, Instance, Material, Mesh, Scene, Texture};
+
+/// Scene caches are split into sections, which are listed in a table of contents following the
+/// header:
+///
+/// | section         | content                                                   |
+/// |-----------------|-----------------------------------------------------------|
+/// | 0               | `Metadata` serialized with bincode                        |
+/// | 1               | vertices                                                  |
+/// | 2               | indices                                                   |
+/// | 3..             | texture mips, ordered by texture and then by mip level    |
+///
+/// Every section is aligned to `SECTION_ALIGNMENT`, so the vertices, indices and mips can be
+/// cast directly from the memory mapped file.
+const MAGIC: [u8; 8] = *b"RNDSCENE";
+
+/// Bump this when the layout of the scene cache changes to invalidate cached scenes.
+const VERSION: u32 = 9;
+
+const SECTION_ALIGNMENT: u64 = 16;
+
+const METADATA_SECTION: usize = 0;
+const VERTEX_SECTION: usize = 1;
+const INDEX_SECTION: usize = 2;
+const FIRST_MIP_SECTION: usize = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    section_count: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Section {
+    offset: u64,
+    size: u64,
+}
+
+impl Section {
+    fn range(self) -> Range<usize> {
+        self.offset as usize..(self.offset + self.size) as usize
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TextureMetadata {
+    hash: u64,
+    format: wgpu::TextureFormat,
+    extent: wgpu::Extent3d,
+    mip_level_count: u32,
+}
+
+/// Everything in the scene except the vertices, indices and texture mips. Borrows from the
+/// scene when writing the cache.
+#[derive(Serialize, Deserialize)]
+struct Metadata<'a> {
+    directional_light: DirectionalLight,
+    textures: Vec<TextureMetadata>,
+    materials: Cow<'a, [Material]>,
+    meshes: Cow<'a, [Mesh]>,
+    cameras: Cow<'a, [CameraProjection]>,
+    instances: Cow<'a, [Instance]>,
+}
+
+/// Write `scene` to the cache at `path`. Fails and keeps the old cache if it can't be replaced,
+/// which happens on Windows while it's memory mapped.
+pub fn write(scene: &Scene, path: &Path) -> Result<()> {
+    let metadata = Metadata {
+        directional_light: scene.directional_light,
+        textures: scene
+            .textures
+            .iter()
+            .map(|texture| TextureMetadata {
+                hash: texture.hash,
+                format: texture.format,
+                extent: texture.extent,
+                mip_level_count: texture.mip_level_count,
+            })
+            .collect(),
+        materials: Cow::Borrowed(&scene.materials),
+        meshes: Cow::Borrowed(&scene.meshes),
+        cameras: Cow::Borrowed(&scene.cameras),
+        instances: Cow::Borrowed(&scene.instances),
+    };
+
+    let metadata = bincode::serialize(&metadata)?;
+
+    let sections: Vec<&[u8]> = [
+        metadata.as_slice(),
+        scene.vertices.as_bytes(),
+        scene.indices.as_bytes(),
+    ]
+    .into_iter()
+    .chain(
+        scene
+            .textures
+            .iter()
+            .flat_map(|texture| texture.mips.iter().map(|mip| mip.as_bytes())),
+    )
+    .collect();
+
+    let header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        section_count: sections.len() as u32,
+    };
+
+    let mut offset = (mem::size_of::<Header>() + sections.len() * mem::size_of::<Section>()) as u64;
+    let table: Vec<Section> = sections
+        .iter()
+        .map(|section| {
+            offset = offset.next_multiple_of(SECTION_ALIGNMENT);
+            let entry = Section {
+                size: section.len() as u64,
+                offset,
+            };
+
+            offset += entry.size;
+            entry
+        })
+        .collect();
+
+    // Write to a temporary file which replaces the cache once it's complete, so that a scene cache
+    // which is mapped is never modified and an interrupted write doesn't leave a corrupt cache.
+    let temp_path = temp_path(path);
+    let result = write_sections(&temp_path, &header, &table, &sections).and_then(|()| {
+        // Windows doesn't replace files that are memory mapped, such as the cache of a loaded
+        // scene. The rename is atomic, so the old cache is kept whole.
+        fs::rename(&temp_path, path)
+            .wrap_err_with(|| format!("failed to replace {path:?}, the old scene cache is kept"))
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// A path next to `path` which is unique to this write, so processes and threads caching the same
+/// scene don't write to the same file.
+fn temp_path(path: &Path) -> PathBuf {
+    static WRITE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+    let write_index = WRITE_COUNT.fetch_add(1, atomic::Ordering::Relaxed);
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{}-{write_index}.tmp", process::id()));
+
+    PathBuf::from(temp_path)
+}
+
+fn write_sections(
+    path: &Path,
+    header: &Header,
+    table: &[Section],
+    sections: &[&[u8]],
+) -> Result<()> {
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    writer.write_all(bytemuck::bytes_of(header))?;
+    writer.write_all(bytemuck::cast_slice(table))?;
+
+    let mut position = (mem::size_of::<Header>() + mem::size_of_val(table)) as u64;
+    for (entry, section) in table.iter().zip(sections) {
+        let padding = [0; SECTION_ALIGNMENT as usize];
+        writer.write_all(&padding[..(entry.offset - position) as usize])?;
+        writer.write_all(section)?;
+        position = entry.offset + entry.size;
+    }
+
+    writer.into_inner()?.sync_all()?;
+
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Scene> {
+    let file = fs::File::open(path)?;
+
+    // Safety: The scene cache must not be modified while it is mapped. `write` replaces the file
+    // instead of modifying it, so mapped caches stay intact.
+    let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+
+    let header: Header = mmap
+        .get(..mem::size_of::<Header>())
+        .map(bytemuck::pod_read_unaligned)
+        .ok_or_else(|| eyre::eyre!("scene cache is too small"))?;
+
+    if header.magic != MAGIC {
+        return Err(eyre::eyre!("file isn't a scene cache"));
+    }
+
+    if header.version != VERSION {
+        return Err(eyre::eyre!(
+            "scene cache has version {}, expected {VERSION}",
+            header.version
+        ));
+    }
+
+    let table_start = mem::size_of::<Header>();
+    let table_end = table_start + header.section_count as usize * mem::size_of::<Section>();
+
+    let table: Vec<Section> = mmap
+        .get(table_start..table_end)
+        .ok_or_else(|| eyre::eyre!("table of contents is out of bounds"))?
+        .chunks(mem::size_of::<Section>())
+        .map(bytemuck::pod_read_unaligned)
+        .collect();
+
+    let section = |index: usize| {
+        table
+            .get(index)
+            .map(|section| section.range())
+            .ok_or_else(|| eyre::eyre!("scene cache is missing section {index}"))
+    };
+
+    let metadata_bytes = mmap
+        .get(section(METADATA_SECTION)?)
+        .ok_or_else(|| eyre::eyre!("metadata is out of bounds"))?;
+    let metadata: Metadata =
+        bincode::deserialize(metadata_bytes).wrap_err("failed to deserialize metadata")?;
+
+    let vertices = Blob::mapped(mmap.clone(), section(VERTEX_SECTION)?)?;
+    let indices = Blob::mapped(mmap.clone(), section(INDEX_SECTION)?)?;
+
+    let mut mip_section = FIRST_MIP_SECTION;
+    let textures = metadata
+        .textures
+        .into_iter()
+        .map(|texture| {
+            let mips = (0..texture.mip_level_count)
+                .map(|_| {
+                    let index = mip_section;
+                    mip_section += 1;
+
+                    Blob::mapped(mmap.clone(), section(index)?)
+                })
+                .collect::<Result<_>>()?;
+
+            Ok(Texture {
+                hash: texture.hash,
+                format: texture.format,
+                extent: texture.extent,
+                mip_level_count: texture.mip_level_count,
+                mips,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Scene {
+        directional_light: metadata.directional_light,
+        materials: metadata.materials.into_owned(),
+        meshes: metadata.meshes.into_owned(),
+        cameras: metadata.cameras.into_owned(),
+        instances: metadata.instances.into_owned(),
+        vertices,
+        indices,
+        textures,
+    })
+}
+
+#[test]
+fn mapped_scene_matches_written_scene() {
+    use super::Vertex;
+
+    let scene = Scene {
+        vertices: vec![Vertex::default(); 3].into(),
+        indices: vec![0, 1, 2].into(),
+        textures: vec![Texture {
+            hash: 1,
+            format: wgpu::TextureFormat::R8Unorm,
+            extent: wgpu::Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 2,
+            mips: vec![vec![1, 2, 3, 4].into(), vec![5].into()],
+        }],
+        ..Default::default()
+    };
+
+    let file_name = format!("rendinator-test-{}.scene", process::id());
+    let path = std::env::temp_dir().join(&file_name);
+    write(&scene, &path).unwrap();
+    let mapped = read(&path).unwrap();
+
+    // Rewriting the cache replaces the file, so the mapped scene is unchanged. Windows can't
+    // replace a mapped file, in which case the old cache is kept.
+    let rewritten = Scene {
+        indices: vec![2, 1, 0].into(),
+        ..Default::default()
+    };
+    let expected_indices = match write(&rewritten, &path) {
+        Ok(()) => [2, 1, 0],
+        Err(_) if cfg!(windows) => [0, 1, 2],
+        Err(err) => panic!("{err:?}"),
+    };
+    assert_eq!(*read(&path).unwrap().indices, expected_indices);
+    fs::remove_file(&path).unwrap();
+
+    let directory = path.parent().unwrap();
+    let temp_files = fs::read_dir(directory)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&file_name))
+        .count();
+    assert_eq!(temp_files, 0);
+
+    assert_eq!(mapped.vertices.as_bytes(), scene.vertices.as_bytes());
+    assert_eq!(*mapped.indices, [0, 1, 2]);
+    assert_eq!(mapped.textures, scene.textures);
+}
+
+#[test]
+fn temp_paths_are_unique() {
+    let path = Path::new("scene.scene");
+    assert_ne!(temp_path(path), temp_path(path));
+    assert_eq!(temp_path(path).extension().unwrap(), "tmp");
+}
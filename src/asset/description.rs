@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+use super::{AssetPath, DirectionalLight, Instance, MaterialFlags, Scene, Texture, Transform};
+
+/// Vertices store the material index in 16 bits.
+const MAX_MATERIALS: usize = u16::MAX as usize + 1;
+
+/// A scene made up of several glTF files, each placed in the scene by its own transform.
+///
+/// Scene descriptions are written in RON:
+///
+/// ```ron
+/// (
+///     assets: [
+///         (path: "sponza/Sponza.gltf"),
+///         (
+///             path: "props/Lantern.gltf",
+///             transform: (translation: (0.0, 2.0, 0.0), scale: (0.1, 0.1, 0.1)),
+///         ),
+///     ],
+/// )
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct SceneDescription {
+    pub assets: Vec<SceneAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SceneAsset {
+    /// The path of the glTF file relative to the scene description.
+    pub path: PathBuf,
+    /// The path of the scene cache relative to the scene description. Defaults to `path` with
+    /// the `scene` extension.
+    #[serde(default)]
+    pub cache: Option<PathBuf>,
+    /// The root transform of the asset.
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+impl SceneDescription {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read scene description at {path:?}"))?;
+
+        ron::from_str(&source)
+            .wrap_err_with(|| format!("failed to parse scene description at {path:?}"))
+    }
+
+    /// The asset paths of the assets relative to `directory`, which should be the directory of
+    /// the scene description.
+    pub fn asset_paths(&self, directory: &Path) -> impl Iterator<Item = AssetPath> + '_ {
+        let directory = directory.to_owned();
+
+        self.assets.iter().map(move |asset| {
+            let cache = asset
+                .cache
+                .clone()
+                .unwrap_or_else(|| asset.path.with_extension("scene"));
+
+            AssetPath::new(directory.join(&asset.path), directory.join(cache))
+        })
+    }
+}
+
+/// Merges scenes into a single scene. Identical textures are only added once, which is decided by
+/// the texture hash.
+#[derive(Default)]
+pub struct SceneMerger {
+    scene: Scene,
+    textures: HashMap<u64, u32>,
+    /// The scenes only have a single directional light, so the light of the first scene that has
+    /// one is used.
+    has_light: bool,
+}
+
+impl SceneMerger {
+    fn add_texture(&mut self, texture: Texture) -> u32 {
+        if let Some(&index) = self.textures.get(&texture.hash) {
+            return index;
+        }
+
+        let hash = texture.hash;
+        let index = self.scene.add_texture(texture);
+        self.textures.insert(hash, index);
+
+        index
+    }
+
+    /// Merge `scene` into the merged scene. The instances of `scene` are placed under a new
+    /// root instance with `transform`. Fails if the merged scene would have too many materials.
+    pub fn merge(
+        &mut self,
+        name: Option<String>,
+        scene: Scene,
+        transform: Transform,
+    ) -> Result<()> {
+        let material_count = self.scene.materials.len() + scene.materials.len();
+
+        if material_count > MAX_MATERIALS {
+            return Err(eyre::eyre!(
+                "merged scene has {material_count} materials, but at most {MAX_MATERIALS} are \
+                 supported"
+            ));
+        }
+
+        self.merge_light(scene.directional_light, &transform);
+
+        let texture_indices: Vec<u32> = scene
+            .textures
+            .into_iter()
+            .map(|texture| self.add_texture(texture))
+            .collect();
+
+        let material_offset = self.scene.materials.len() as u32;
+        let mesh_offset = self.scene.meshes.len() as u32;
+        let vertex_offset = self.scene.vertices.len() as u32;
+        let index_offset = self.scene.indices.len() as u32;
+        let camera_offset = self.scene.cameras.len() as u32;
+
+        self.scene
+            .materials
+            .extend(scene.materials.into_iter().map(|mut material| {
+                let remap = |texture: &mut u32| *texture = texture_indices[*texture as usize];
+
+                remap(&mut material.albedo_texture);
+                remap(&mut material.normal_texture);
+                remap(&mut material.specular_texture);
+                remap(&mut material.emissive_texture);
+
+                let flags = MaterialFlags::from_bits_truncate(material.flags);
+                let optional_textures = [
+                    (MaterialFlags::OCCLUSION, &mut material.occlusion_texture),
+                    (
+                        MaterialFlags::CLEARCOAT_TEXTURE,
+                        &mut material.clearcoat_texture,
+                    ),
+                    (
+                        MaterialFlags::CLEARCOAT_ROUGHNESS_TEXTURE,
+                        &mut material.clearcoat_roughness_texture,
+                    ),
+                    (
+                        MaterialFlags::CLEARCOAT_NORMAL_TEXTURE,
+                        &mut material.clearcoat_normal_texture,
+                    ),
+                    (
+                        MaterialFlags::SHEEN_COLOR_TEXTURE,
+                        &mut material.sheen_color_texture,
+                    ),
+                    (
+                        MaterialFlags::SHEEN_ROUGHNESS_TEXTURE,
+                        &mut material.sheen_roughness_texture,
+                    ),
+                    (
+                        MaterialFlags::TRANSMISSION_TEXTURE,
+                        &mut material.transmission_texture,
+                    ),
+                ];
+
+                for (flag, texture) in optional_textures {
+                    if flags.contains(flag) {
+                        remap(texture);
+                    }
+                }
+
+                material
+            }));
+
+        // The first scene doesn't need remapping, so its possibly memory mapped data is kept
+        // as is instead of copied.
+        if vertex_offset == 0 && material_offset == 0 {
+            self.scene.vertices = scene.vertices;
+        } else {
+            let vertices = self.scene.vertices.to_mut();
+            vertices.reserve(scene.vertices.len());
+            vertices.extend(scene.vertices.iter().map(|vertex| {
+                let mut vertex = *vertex;
+                vertex.material += material_offset as u16;
+                vertex
+            }));
+        }
+
+        if index_offset == 0 && vertex_offset == 0 {
+            self.scene.indices = scene.indices;
+        } else {
+            let indices = self.scene.indices.to_mut();
+            indices.reserve(scene.indices.len());
+            indices.extend(scene.indices.iter().map(|index| index + vertex_offset));
+        }
+
+        self.scene
+            .meshes
+            .extend(scene.meshes.into_iter().map(|mut mesh| {
+                for primitive in &mut mesh.primitives {
+                    primitive.indices.start += index_offset;
+                    primitive.indices.end += index_offset;
+                    primitive.material += material_offset;
+                }
+
+                mesh
+            }));
+
+        self.scene.cameras.extend(scene.cameras);
+
+        fn remap_instance(instance: &mut Instance, mesh_offset: u32, camera_offset: u32) {
+            if let Some(mesh) = &mut instance.mesh {
+                *mesh += mesh_offset;
+            }
+
+            if let Some(camera) = &mut instance.camera {
+                *camera += camera_offset;
+            }
+
+            for child in &mut instance.children {
+                remap_instance(child, mesh_offset, camera_offset);
+            }
+        }
+
+        let mut children = scene.instances;
+        for instance in &mut children {
+            remap_instance(instance, mesh_offset, camera_offset);
+        }
+
+        self.scene.instances.push(Instance {
+            mesh: None,
+            camera: None,
+            name,
+            transform,
+            children,
+        });
+
+        Ok(())
+    }
+
+    fn merge_light(&mut self, light: DirectionalLight, transform: &Transform) {
+        if light == DirectionalLight::default() {
+            return;
+        }
+
+        if self.has_light {
+            eprintln!("only the directional light of the first scene with one is used");
+            return;
+        }
+
+        let direction = transform.rotation * light.direction.truncate();
+
+        self.scene.directional_light = DirectionalLight {
+            direction: direction.normalize_or_zero().extend(light.direction.w),
+            ..light
+        };
+
+        self.has_light = true;
+    }
+
+    pub fn finish(self) -> Scene {
+        self.scene
+    }
+}
+
+#[test]
+fn merge_remaps_indices() {
+    use super::{BoundingSphere, Material, Mesh, Primitive, Vertex};
+    use bytemuck::Zeroable;
+
+    let texture = |value: u8| Texture {
+        hash: value as u64,
+        format: wgpu::TextureFormat::R8Unorm,
+        extent: wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        mips: vec![vec![value].into()],
+    };
+
+    let scene = |textures: Vec<Texture>| {
+        let mut material = Material::zeroed();
+        material.albedo_texture = textures.len() as u32 - 1;
+        material.clearcoat_normal_texture = textures.len() as u32 - 1;
+        material.sheen_color_texture = textures.len() as u32 - 1;
+        material.flags = MaterialFlags::CLEARCOAT_NORMAL_TEXTURE.bits();
+
+        Scene {
+            vertices: vec![Vertex::default(); 3].into(),
+            indices: vec![0, 1, 2].into(),
+            materials: vec![material],
+            meshes: vec![Mesh {
+                primitives: vec![Primitive {
+                    indices: 0..3,
+                    bounding_sphere: BoundingSphere::default(),
+                    material: 0,
+                }],
+            }],
+            instances: vec![Instance {
+                name: None,
+                mesh: Some(0),
+                camera: None,
+                transform: Transform::default(),
+                children: Vec::new(),
+            }],
+            textures,
+            ..Default::default()
+        }
+    };
+
+    let mut merger = SceneMerger::default();
+    merger
+        .merge(None, scene(vec![texture(0)]), Transform::default())
+        .unwrap();
+    merger
+        .merge(
+            None,
+            scene(vec![texture(1), texture(0)]),
+            Transform::default(),
+        )
+        .unwrap();
+    let merged = merger.finish();
+
+    assert_eq!(merged.textures.len(), 2);
+    assert_eq!(merged.materials[1].albedo_texture, 0);
+    assert_eq!(merged.materials[1].clearcoat_normal_texture, 0);
+    // Optional textures are only remapped if their flag is set.
+    assert_eq!(merged.materials[1].sheen_color_texture, 1);
+    assert_eq!(*merged.indices, [0, 1, 2, 3, 4, 5]);
+    assert_eq!(merged.meshes[1].primitives[0].indices, 3..6);
+    assert_eq!(merged.meshes[1].primitives[0].material, 1);
+    assert_eq!(merged.vertices[3].material, 1);
+    assert_eq!(merged.instances[1].children[0].mesh, Some(1));
+}
+
+#[test]
+fn merge_keeps_light_and_limits_materials() {
+    use super::Material;
+    use bytemuck::Zeroable;
+    use glam::{Quat, Vec3, Vec4};
+
+    let light = DirectionalLight {
+        direction: Vec4::new(1.0, 0.0, 0.0, 1.0),
+        irradiance: Vec4::splat(2.0),
+    };
+
+    let transform = Transform {
+        rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+        ..Default::default()
+    };
+
+    let mut merger = SceneMerger::default();
+    merger
+        .merge(None, Scene::default(), Transform::default())
+        .unwrap();
+    merger
+        .merge(
+            None,
+            Scene {
+                directional_light: light,
+                ..Default::default()
+            },
+            transform,
+        )
+        .unwrap();
+
+    let too_many = Scene {
+        materials: vec![Material::zeroed(); MAX_MATERIALS + 1],
+        ..Default::default()
+    };
+
+    assert!(merger.merge(None, too_many, Transform::default()).is_err());
+
+    let merged = merger.finish();
+    let direction = merged.directional_light.direction.truncate();
+
+    assert!(direction.abs_diff_eq(Vec3::Y, 1e-6), "{direction}");
+    assert_eq!(merged.directional_light.irradiance, light.irradiance);
+}
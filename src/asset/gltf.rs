@@ -1,19 +1,25 @@
 use eyre::{Result, WrapErr};
 use glam::{Mat4, Vec2, Vec3, Vec4};
-use gltf::Gltf;
+use gltf::{json, Gltf};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicUsize};
 
 use std::{fs, io, mem};
 
 use crate::asset::{Primitive, Vertex};
 
 use super::{
-    normal, quantize, BoundingSphere, Instance, Material, Mesh, Scene, Texture, Transform,
+    normal, quantize, BoundingSphere, CameraLens, CameraProjection, Instance, LoadProgress,
+    Material, MaterialFlags, Mesh, Scene, Texture, TextureSampler, TextureTransform, Transform,
 };
 
 #[derive(Default)]
-struct FallbackTextures {
+pub(super) struct FallbackTextures {
     albedo_fallback_texture: Option<u32>,
     emissive_fallback_texture: Option<u32>,
     normal_fallback_texture: Option<u32>,
@@ -23,13 +29,13 @@ struct FallbackTextures {
 impl FallbackTextures {
     fn albedo_fallback_texture(&mut self, scene: &mut Scene) -> u32 {
         *self.albedo_fallback_texture.get_or_insert_with(|| {
-            scene.add_texture(fallback_texture(ALBEDO_MAP_RAW_FORMAT, [u8::MAX; 4]))
+            scene.add_texture(fallback_texture(TextureKind::Albedo, [u8::MAX; 4]))
         })
     }
 
     fn emissive_fallback_texture(&mut self, scene: &mut Scene) -> u32 {
         *self.emissive_fallback_texture.get_or_insert_with(|| {
-            scene.add_texture(fallback_texture(EMISSIVE_MAP_RAW_FORMAT, [255; 4]))
+            scene.add_texture(fallback_texture(TextureKind::Emissive, [255; 4]))
         })
     }
 
@@ -38,28 +44,157 @@ impl FallbackTextures {
             let mut normal = [128, 128, 255, 255];
             octahedron_encode_pixel(&mut normal);
 
-            scene.add_texture(fallback_texture(NORMAL_MAP_RAW_FORMAT, normal))
+            scene.add_texture(fallback_texture(TextureKind::Normal, normal))
         })
     }
 
     fn specular_fallback_texture(&mut self, scene: &mut Scene) -> u32 {
         *self.specular_fallback_texture.get_or_insert_with(|| {
-            scene.add_texture(fallback_texture(SPECULAR_MAP_RAW_FORMAT, [255; 2]))
+            scene.add_texture(fallback_texture(TextureKind::Specular, [255; 4]))
         })
     }
 
-    fn fallback_material(&mut self, scene: &mut Scene) -> Material {
+    pub(super) fn fallback_material(&mut self, scene: &mut Scene) -> Material {
         Material {
             albedo_texture: self.albedo_fallback_texture(scene),
             normal_texture: self.normal_fallback_texture(scene),
             specular_texture: self.specular_fallback_texture(scene),
             emissive_texture: self.emissive_fallback_texture(scene),
+            occlusion_texture: 0,
+            flags: MaterialFlags::empty().bits(),
+            occlusion_strength: 1.0,
             base_color: DEFAULT_COLOR,
             emissive: DEFAULT_EMISSIVE,
             metallic: DEFAULT_METALLIC,
             roughness: DEFAULT_ROUGHNESS,
             ior: DEFAULT_IOR,
-            padding: [0; 1],
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            sheen: DEFAULT_SHEEN,
+            specular: DEFAULT_SPECULAR,
+            attenuation: DEFAULT_ATTENUATION,
+            transmission: 0.0,
+            thickness: 0.0,
+            clearcoat_texture: 0,
+            clearcoat_roughness_texture: 0,
+            clearcoat_normal_texture: 0,
+            sheen_color_texture: 0,
+            sheen_roughness_texture: 0,
+            transmission_texture: 0,
+            texture_transforms: [TextureTransform::default(); 11],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TextureKind {
+    Albedo,
+    Emissive,
+    Normal,
+    Specular,
+    Occlusion,
+    Clearcoat,
+    ClearcoatRoughness,
+    ClearcoatNormal,
+    SheenColor,
+    SheenRoughness,
+    Transmission,
+}
+
+impl TextureKind {
+    const ALL: [Self; 11] = [
+        Self::Albedo,
+        Self::Emissive,
+        Self::Normal,
+        Self::Specular,
+        Self::Occlusion,
+        Self::Clearcoat,
+        Self::ClearcoatRoughness,
+        Self::ClearcoatNormal,
+        Self::SheenColor,
+        Self::SheenRoughness,
+        Self::Transmission,
+    ];
+
+    fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureKind::Albedo | TextureKind::SheenColor => ALBEDO_MAP_FORMAT,
+            TextureKind::Emissive => EMISSIVE_MAP_FORMAT,
+            TextureKind::Normal | TextureKind::ClearcoatNormal => NORMAL_MAP_FORMAT,
+            TextureKind::Specular => SPECULAR_MAP_FORMAT,
+            TextureKind::Occlusion => OCCLUSION_MAP_FORMAT,
+            TextureKind::Clearcoat
+            | TextureKind::ClearcoatRoughness
+            | TextureKind::SheenRoughness
+            | TextureKind::Transmission => MASK_MAP_FORMAT,
+        }
+    }
+
+    fn encode(self) -> fn(&mut [u8]) {
+        match self {
+            // Occlusion, clearcoat and transmission are already stored in the red channel.
+            TextureKind::Albedo
+            | TextureKind::Emissive
+            | TextureKind::Occlusion
+            | TextureKind::Clearcoat
+            | TextureKind::SheenColor
+            | TextureKind::Transmission => |_| (),
+            TextureKind::Normal | TextureKind::ClearcoatNormal => octahedron_encode_pixel,
+            TextureKind::ClearcoatRoughness => |rgba| rgba[0] = rgba[1],
+            TextureKind::SheenRoughness => |rgba| rgba[0] = rgba[3],
+            TextureKind::Specular => |rgba| {
+                // Change metallic channel to red. The red channel of ORM images holds occlusion,
+                // which is imported as a separate occlusion texture.
+                rgba[0] = rgba[2];
+            },
+        }
+    }
+}
+
+/// A texture is identified by the glTF image it is created from and how it is encoded, so that
+/// images shared between materials are only compressed once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TextureKey {
+    image: usize,
+    kind: TextureKind,
+}
+
+/// Content addressed cache of compressed textures.
+///
+/// Textures are keyed by a hash of the encoded source image and the texture kind, which means
+/// that only changed images have to be recompressed when an asset is edited.
+struct TextureCache {
+    directory: PathBuf,
+}
+
+impl TextureCache {
+    fn new(directory: &Path) -> Self {
+        if let Err(err) = fs::create_dir_all(directory) {
+            eprintln!("failed to create texture cache at {directory:?}: {err}");
+        }
+
+        Self {
+            directory: directory.to_owned(),
+        }
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        self.directory.join(format!("{hash:016x}.texture"))
+    }
+
+    fn get(&self, hash: u64) -> Option<Texture> {
+        fs::read(self.path(hash))
+            .ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+    }
+
+    fn insert(&self, hash: u64, texture: &Texture) {
+        let result = bincode::serialize(texture)
+            .map_err(eyre::Report::from)
+            .and_then(|bytes| Ok(fs::write(self.path(hash), bytes)?));
+
+        if let Err(err) = result {
+            eprintln!("failed to cache texture: {err}");
         }
     }
 }
@@ -107,7 +242,11 @@ impl Importer {
         })
     }
 
-    fn image(&self, source: gltf::image::Source) -> Result<image::DynamicImage> {
+    /// Returns the encoded image data and format of `source`.
+    fn image_data(
+        &self,
+        source: gltf::image::Source,
+    ) -> Result<(Cow<'_, [u8]>, image::ImageFormat)> {
         match source {
             gltf::image::Source::View { view, mime_type } => {
                 let format = match mime_type {
@@ -116,19 +255,120 @@ impl Importer {
                     _ => return Err(eyre::eyre!("invalid image type, must be png or jpg")),
                 };
 
-                let input = self.buffer_data(&view, None, 0);
-                image::load(io::Cursor::new(&input), format).wrap_err("failed to load inline image")
+                Ok((Cow::Borrowed(self.buffer_data(&view, None, 0)), format))
             }
             gltf::image::Source::Uri { uri, .. } => {
                 let uri = Path::new(uri);
                 let path: PathBuf = [self.parent_path.as_path(), Path::new(uri)]
                     .iter()
                     .collect();
-                image::open(&path).wrap_err_with(|| format!("failed to load image from {uri:?}"))
+                let format = image::ImageFormat::from_path(&path)
+                    .wrap_err_with(|| format!("unknown image format of {uri:?}"))?;
+                let data = fs::read(&path)
+                    .wrap_err_with(|| format!("failed to load image from {uri:?}"))?;
+
+                Ok((Cow::Owned(data), format))
             }
         }
     }
 
+    /// Load the textures of `kinds` created from `image`. The image is decoded at most once,
+    /// which matters for ORM images used for both the specular and occlusion textures.
+    fn load_image_textures(
+        &self,
+        image: usize,
+        kinds: &[TextureKind],
+        cache: &TextureCache,
+    ) -> Result<Vec<(TextureKey, Texture)>> {
+        let source = self
+            .gltf
+            .images()
+            .nth(image)
+            .ok_or_else(|| eyre::eyre!("invalid image index {image}"))?
+            .source();
+
+        let (data, format) = self.image_data(source)?;
+        let mut decoded = None;
+        let mut textures = Vec::with_capacity(kinds.len());
+
+        for &kind in kinds {
+            let key = TextureKey { image, kind };
+            let hash = texture_hash(&data, kind);
+
+            if let Some(texture) = cache.get(hash) {
+                textures.push((key, texture));
+                continue;
+            }
+
+            let decoded = match &mut decoded {
+                Some(decoded) => decoded,
+                None => decoded.insert(
+                    image::load(io::Cursor::new(&data), format)
+                        .wrap_err_with(|| format!("failed to decode image {image}"))?,
+                ),
+            };
+
+            let texture = create_texture(hash, decoded.clone(), kind.format(), kind.encode())?;
+            cache.insert(hash, &texture);
+
+            textures.push((key, texture));
+        }
+
+        Ok(textures)
+    }
+
+    /// Load every texture referenced by a material in parallel. The textures are added to
+    /// `scene` and the texture index of each key is returned.
+    fn load_textures(
+        &self,
+        scene: &mut Scene,
+        cache: &TextureCache,
+        progress: &(dyn Fn(LoadProgress) + Sync),
+    ) -> Result<HashMap<TextureKey, u32>> {
+        let mut images: Vec<(usize, Vec<TextureKind>)> = Vec::new();
+
+        for material in self.gltf.materials() {
+            for kind in TextureKind::ALL {
+                let Some((texture, _)) = material_texture(&self.gltf, &material, kind) else {
+                    continue;
+                };
+
+                let image = texture.source().index();
+
+                match images.iter_mut().find(|(other, _)| *other == image) {
+                    Some((_, kinds)) if kinds.contains(&kind) => (),
+                    Some((_, kinds)) => kinds.push(kind),
+                    None => images.push((image, vec![kind])),
+                }
+            }
+        }
+
+        let texture_count: usize = images.iter().map(|(_, kinds)| kinds.len()).sum();
+        let loaded = AtomicUsize::new(0);
+
+        let textures: Vec<_> = images
+            .par_iter()
+            .map(|(image, kinds)| {
+                let textures = self.load_image_textures(*image, kinds, cache)?;
+                let loaded = loaded.fetch_add(textures.len(), atomic::Ordering::Relaxed);
+                progress(LoadProgress::Textures {
+                    loaded: loaded + textures.len(),
+                    total: texture_count,
+                });
+
+                Ok(textures)
+            })
+            .collect::<Result<_>>()?;
+
+        let indices = textures
+            .into_iter()
+            .flatten()
+            .map(|(key, texture)| (key, scene.add_texture(texture)))
+            .collect();
+
+        Ok(indices)
+    }
+
     fn buffer_data(
         &self,
         view: &gltf::buffer::View,
@@ -152,55 +392,132 @@ impl Importer {
         &self,
         scene: &mut Scene,
         fallback_textures: &mut FallbackTextures,
+        textures: &HashMap<TextureKey, u32>,
         material: gltf::Material,
     ) -> Result<Material> {
-        let albedo_texture = {
-            if let Some(accessor) = material.pbr_metallic_roughness().base_color_texture() {
-                let image = self.image(accessor.texture().source().source())?;
-                let texture = create_texture(image, ALBEDO_MAP_FORMAT, true, |_| ())?;
-                scene.add_texture(texture)
-            } else {
-                fallback_textures.albedo_fallback_texture(scene)
-            }
+        let texture = |kind| {
+            material_texture(&self.gltf, &material, kind).map(|(texture, transform)| {
+                let image = texture.source().index();
+                (textures[&TextureKey { image, kind }], transform)
+            })
         };
 
-        let emissive_texture = {
-            if let Some(accessor) = material.emissive_texture() {
-                let image = self.image(accessor.texture().source().source())?;
-                let texture = create_texture(image, EMISSIVE_MAP_FORMAT, true, |_| ())?;
-                scene.add_texture(texture)
-            } else {
-                fallback_textures.emissive_fallback_texture(scene)
+        let (albedo_texture, albedo_transform) =
+            texture(TextureKind::Albedo).unwrap_or_else(|| {
+                let texture = fallback_textures.albedo_fallback_texture(scene);
+                (texture, TextureTransform::default())
+            });
+
+        let (emissive_texture, emissive_transform) =
+            texture(TextureKind::Emissive).unwrap_or_else(|| {
+                let texture = fallback_textures.emissive_fallback_texture(scene);
+                (texture, TextureTransform::default())
+            });
+
+        let (normal_texture, normal_transform) =
+            texture(TextureKind::Normal).unwrap_or_else(|| {
+                let texture = fallback_textures.normal_fallback_texture(scene);
+                (texture, TextureTransform::default())
+            });
+
+        let (specular_texture, specular_transform) =
+            texture(TextureKind::Specular).unwrap_or_else(|| {
+                let texture = fallback_textures.specular_fallback_texture(scene);
+                (texture, TextureTransform::default())
+            });
+
+        warn_unsupported_textures(&material);
+
+        let mut flags = MaterialFlags::empty();
+
+        // Optional textures are only sampled if their flag is set, so they don't need fallbacks.
+        let mut optional_texture = |kind, flag| match texture(kind) {
+            Some(texture) => {
+                flags |= flag;
+                texture
             }
+            None => (0, TextureTransform::default()),
         };
 
-        let normal_texture = {
-            if let Some(accessor) = material.normal_texture() {
-                let image = self.image(accessor.texture().source().source())?;
-                let texture =
-                    create_texture(image, NORMAL_MAP_FORMAT, true, octahedron_encode_pixel)?;
-                scene.add_texture(texture)
-            } else {
-                fallback_textures.normal_fallback_texture(scene)
-            }
-        };
+        let (occlusion_texture, occlusion_transform) =
+            optional_texture(TextureKind::Occlusion, MaterialFlags::OCCLUSION);
+        let (clearcoat_texture, clearcoat_transform) =
+            optional_texture(TextureKind::Clearcoat, MaterialFlags::CLEARCOAT_TEXTURE);
+        let (clearcoat_roughness_texture, clearcoat_roughness_transform) = optional_texture(
+            TextureKind::ClearcoatRoughness,
+            MaterialFlags::CLEARCOAT_ROUGHNESS_TEXTURE,
+        );
+        let (clearcoat_normal_texture, clearcoat_normal_transform) = optional_texture(
+            TextureKind::ClearcoatNormal,
+            MaterialFlags::CLEARCOAT_NORMAL_TEXTURE,
+        );
+        let (sheen_color_texture, sheen_color_transform) =
+            optional_texture(TextureKind::SheenColor, MaterialFlags::SHEEN_COLOR_TEXTURE);
+        let (sheen_roughness_texture, sheen_roughness_transform) = optional_texture(
+            TextureKind::SheenRoughness,
+            MaterialFlags::SHEEN_ROUGHNESS_TEXTURE,
+        );
+        let (transmission_texture, transmission_transform) = optional_texture(
+            TextureKind::Transmission,
+            MaterialFlags::TRANSMISSION_TEXTURE,
+        );
+
+        let occlusion_strength = material
+            .occlusion_texture()
+            .map_or(1.0, |occlusion| occlusion.strength());
+
+        let clearcoat: f32 =
+            extension_factor(&material, "KHR_materials_clearcoat", "clearcoatFactor")
+                .unwrap_or(0.0);
+        let clearcoat_roughness: f32 = extension_factor(
+            &material,
+            "KHR_materials_clearcoat",
+            "clearcoatRoughnessFactor",
+        )
+        .unwrap_or(0.0);
+
+        if clearcoat > 0.0 {
+            flags |= MaterialFlags::CLEARCOAT;
+        }
 
-        let specular_texture = {
-            let accessor = material
-                .pbr_metallic_roughness()
-                .metallic_roughness_texture();
-
-            if let Some(accessor) = accessor {
-                let image = self.image(accessor.texture().source().source())?;
-                let texture = create_texture(image, SPECULAR_MAP_FORMAT, true, |rgba| {
-                    // Change metallic channel to red.
-                    rgba[0] = rgba[2];
-                })?;
-                scene.add_texture(texture)
-            } else {
-                fallback_textures.specular_fallback_texture(scene)
-            }
-        };
+        let sheen_color: [f32; 3] =
+            extension_factor(&material, "KHR_materials_sheen", "sheenColorFactor")
+                .unwrap_or([0.0; 3]);
+        let sheen_roughness: f32 =
+            extension_factor(&material, "KHR_materials_sheen", "sheenRoughnessFactor")
+                .unwrap_or(0.0);
+
+        let sheen = Vec3::from_array(sheen_color).extend(sheen_roughness);
+
+        if sheen.truncate().max_element() > 0.0 {
+            flags |= MaterialFlags::SHEEN;
+        }
+
+        let specular = material.specular().map_or(DEFAULT_SPECULAR, |specular| {
+            Vec3::from_array(specular.specular_color_factor()).extend(specular.specular_factor())
+        });
+
+        let transmission = material
+            .transmission()
+            .map_or(0.0, |transmission| transmission.transmission_factor());
+
+        if transmission > 0.0 {
+            flags |= MaterialFlags::TRANSMISSION;
+        }
+
+        let (attenuation, thickness) =
+            material
+                .volume()
+                .map_or((DEFAULT_ATTENUATION, 0.0), |volume| {
+                    let attenuation = Vec3::from_array(volume.attenuation_color())
+                        .extend(volume.attenuation_distance());
+                    (attenuation, volume.thickness_factor())
+                });
+
+        // Volumes are only defined for transmissive materials.
+        if flags.contains(MaterialFlags::TRANSMISSION) && thickness > 0.0 {
+            flags |= MaterialFlags::VOLUME;
+        }
 
         let metallic = material.pbr_metallic_roughness().metallic_factor();
         let roughness = material.pbr_metallic_roughness().roughness_factor();
@@ -216,12 +533,40 @@ impl Importer {
             emissive_texture,
             normal_texture,
             specular_texture,
+            occlusion_texture,
+            flags: flags.bits(),
+            occlusion_strength,
             base_color,
             emissive,
             metallic,
             roughness,
             ior,
-            padding: [0; 1],
+            clearcoat,
+            clearcoat_roughness,
+            sheen,
+            specular,
+            attenuation,
+            transmission,
+            thickness,
+            clearcoat_texture,
+            clearcoat_roughness_texture,
+            clearcoat_normal_texture,
+            sheen_color_texture,
+            sheen_roughness_texture,
+            transmission_texture,
+            texture_transforms: [
+                albedo_transform,
+                normal_transform,
+                specular_transform,
+                emissive_transform,
+                occlusion_transform,
+                clearcoat_transform,
+                clearcoat_roughness_transform,
+                clearcoat_normal_transform,
+                sheen_color_transform,
+                sheen_roughness_transform,
+                transmission_transform,
+            ],
         })
     }
 
@@ -241,7 +586,7 @@ impl Importer {
         let indices = match accessor.data_type() {
             DataType::U32 => index_data
                 .chunks(4)
-                .map(|bytes| bytemuck::pod_read_unaligned(bytes))
+                .map(bytemuck::pod_read_unaligned)
                 .collect(),
             DataType::U16 => index_data
                 .chunks(2)
@@ -306,17 +651,29 @@ impl Importer {
                     }
                 };
 
-                let texcoords = match primitive.get(&gltf::Semantic::TexCoords(0)) {
-                    None => vec![Vec2::ZERO; normals.len()],
-                    Some(accessor) => {
-                        verify_accessor("texcoords", &accessor, DataType::F32, Dimensions::Vec2)?;
-                        self.accessor_data(&accessor)
-                            .chunks(mem::size_of::<Vec2>())
-                            .map(bytemuck::pod_read_unaligned)
-                            .collect()
-                    }
+                let load_texcoords = |set| -> Result<Option<Vec<Vec2>>> {
+                    let Some(accessor) = primitive.get(&gltf::Semantic::TexCoords(set)) else {
+                        return Ok(None);
+                    };
+
+                    verify_accessor("texcoords", &accessor, DataType::F32, Dimensions::Vec2)?;
+
+                    let texcoords = self
+                        .accessor_data(&accessor)
+                        .chunks(mem::size_of::<Vec2>())
+                        .map(bytemuck::pod_read_unaligned)
+                        .collect();
+
+                    Ok(Some(texcoords))
                 };
 
+                let texcoords =
+                    load_texcoords(0)?.unwrap_or_else(|| vec![Vec2::ZERO; normals.len()]);
+
+                // Fallback to the primary texture coordinates, so that materials referencing the
+                // secondary set still get sensible coordinates.
+                let secondary_texcoords = load_texcoords(1)?.unwrap_or_else(|| texcoords.clone());
+
                 let tangents = match primitive.get(&gltf::Semantic::Tangents) {
                     None => generate_tangents(&positions, &texcoords, &normals, &indices)?,
                     Some(accessor) => {
@@ -335,15 +692,17 @@ impl Importer {
                     texcoords
                         .iter()
                         .cloned()
+                        .zip(secondary_texcoords.iter().cloned())
+                        .map(|(primary, secondary)| [primary, secondary])
                         .zip(normals.iter().cloned())
                         .zip(tangents.iter().cloned())
                         .zip(positions.iter().cloned())
-                        .map(|(((texcoord, normal), tangent), position)| {
+                        .map(|(((texcoords, normal), tangent), position)| {
                             Vertex::new(
                                 &bounding_sphere,
                                 position,
                                 normal,
-                                texcoord,
+                                texcoords,
                                 tangent,
                                 material,
                             )
@@ -363,15 +722,28 @@ impl Importer {
         })
     }
 
-    pub fn load_scene(self) -> Result<Scene> {
+    /// Load the scene. Compressed textures are cached in the directory `texture_cache`.
+    pub fn load_scene(
+        self,
+        texture_cache: &Path,
+        progress: &(dyn Fn(LoadProgress) + Sync),
+    ) -> Result<Scene> {
         let mut scene = Scene::default();
         let mut fallback_textures = FallbackTextures::default();
 
-        scene.instances = load_instances(self.gltf.scenes().flat_map(|scene| scene.nodes()));
+        let texture_cache = TextureCache::new(texture_cache);
+        let textures = self.load_textures(&mut scene, &texture_cache, progress)?;
+
+        scene.instances = load_instances(
+            &mut scene.cameras,
+            self.gltf.scenes().flat_map(|scene| scene.nodes()),
+        );
         scene.materials = self
             .gltf
             .materials()
-            .map(|material| self.load_material(&mut scene, &mut fallback_textures, material))
+            .map(|material| {
+                self.load_material(&mut scene, &mut fallback_textures, &textures, material)
+            })
             .collect::<Result<_>>()?;
         scene.meshes = self
             .gltf
@@ -379,10 +751,219 @@ impl Importer {
             .map(|mesh| self.load_mesh(&mut scene, &mut fallback_textures, mesh))
             .collect::<Result<_>>()?;
 
+        progress(LoadProgress::Meshes);
+
         Ok(scene)
     }
 }
 
+/// Returns the texture of `kind` used by `material` and how it's sampled.
+fn material_texture<'a>(
+    document: &'a gltf::Document,
+    material: &gltf::Material<'a>,
+    kind: TextureKind,
+) -> Option<(gltf::Texture<'a>, TextureTransform)> {
+    let pbr = material.pbr_metallic_roughness();
+
+    let extension_texture = |extension, name| {
+        let info: json::Value = extension_factor(material, extension, name)?;
+        let texture = document
+            .textures()
+            .nth(info.get("index")?.as_u64()? as usize)?;
+        let texcoord = info.get("texCoord").and_then(|texcoord| texcoord.as_u64());
+
+        let transform = parsed_texture_transform(
+            &texture,
+            texcoord.unwrap_or(0) as u32,
+            info.get("extensions")
+                .and_then(|extensions| extensions.get("KHR_texture_transform")),
+        );
+
+        Some((texture, transform))
+    };
+
+    let info = match kind {
+        TextureKind::Albedo => pbr.base_color_texture(),
+        TextureKind::Emissive => material.emissive_texture(),
+        TextureKind::Specular => pbr.metallic_roughness_texture(),
+        TextureKind::Transmission => material.transmission()?.transmission_texture(),
+        // Like their factors, the textures of clearcoat and sheen have to be read from the
+        // extension json.
+        TextureKind::Clearcoat => {
+            return extension_texture("KHR_materials_clearcoat", "clearcoatTexture");
+        }
+        TextureKind::ClearcoatRoughness => {
+            return extension_texture("KHR_materials_clearcoat", "clearcoatRoughnessTexture");
+        }
+        TextureKind::ClearcoatNormal => {
+            return extension_texture("KHR_materials_clearcoat", "clearcoatNormalTexture");
+        }
+        TextureKind::SheenColor => {
+            return extension_texture("KHR_materials_sheen", "sheenColorTexture");
+        }
+        TextureKind::SheenRoughness => {
+            return extension_texture("KHR_materials_sheen", "sheenRoughnessTexture");
+        }
+        // `NormalTexture` and `OcclusionTexture` don't expose the texture transform extension,
+        // so it has to be parsed manually.
+        TextureKind::Normal => {
+            let normal = material.normal_texture()?;
+            let transform = parsed_texture_transform(
+                &normal.texture(),
+                normal.tex_coord(),
+                normal.extension_value("KHR_texture_transform"),
+            );
+
+            return Some((normal.texture(), transform));
+        }
+        TextureKind::Occlusion => {
+            let occlusion = material.occlusion_texture()?;
+            let transform = parsed_texture_transform(
+                &occlusion.texture(),
+                occlusion.tex_coord(),
+                occlusion.extension_value("KHR_texture_transform"),
+            );
+
+            return Some((occlusion.texture(), transform));
+        }
+    }?;
+
+    let transform = match info.texture_transform() {
+        Some(transform) => texture_transform(
+            &info.texture(),
+            transform.tex_coord().unwrap_or(info.tex_coord()),
+            transform.offset(),
+            transform.rotation(),
+            transform.scale(),
+        ),
+        None => texture_transform(&info.texture(), info.tex_coord(), [0.0; 2], 0.0, [1.0; 2]),
+    };
+
+    Some((info.texture(), transform))
+}
+
+/// Returns the factor `name` of `extension`. Clearcoat and sheen aren't supported by the gltf
+/// crate, so their factors have to be read from the extension json.
+fn extension_factor<T: DeserializeOwned>(
+    material: &gltf::Material,
+    extension: &str,
+    name: &str,
+) -> Option<T> {
+    let value = material.extension_value(extension)?.get(name)?;
+    json::deserialize::from_value(value.clone()).ok()
+}
+
+/// Print a warning if `material` has textures from the specular and volume extensions, which
+/// aren't imported. Only their factors are used.
+fn warn_unsupported_textures(material: &gltf::Material) {
+    let extension_textures = [
+        ("KHR_materials_specular", "specularTexture"),
+        ("KHR_materials_specular", "specularColorTexture"),
+        ("KHR_materials_volume", "thicknessTexture"),
+    ];
+
+    let unsupported: Vec<_> = extension_textures
+        .into_iter()
+        .filter(|(extension, name)| {
+            material
+                .extension_value(extension)
+                .is_some_and(|value| value.get(name).is_some())
+        })
+        .map(|(_, name)| name)
+        .collect();
+
+    if !unsupported.is_empty() {
+        let name = material.name().unwrap_or("unnamed");
+        eprintln!("material {name:?} ignores the unsupported textures {unsupported:?}");
+    }
+}
+
+fn parsed_texture_transform(
+    texture: &gltf::Texture,
+    texcoord: u32,
+    extension: Option<&json::Value>,
+) -> TextureTransform {
+    let transform: Option<json::extensions::texture::TextureTransform> =
+        extension.and_then(|value| json::deserialize::from_value(value.clone()).ok());
+
+    match transform {
+        Some(transform) => texture_transform(
+            texture,
+            transform.tex_coord.unwrap_or(texcoord),
+            transform.offset.0,
+            transform.rotation.0,
+            transform.scale.0,
+        ),
+        None => texture_transform(texture, texcoord, [0.0; 2], 0.0, [1.0; 2]),
+    }
+}
+
+fn texture_transform(
+    texture: &gltf::Texture,
+    texcoord: u32,
+    offset: [f32; 2],
+    rotation: f32,
+    scale: [f32; 2],
+) -> TextureTransform {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    use wgpu::FilterMode::{Linear, Nearest};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+
+    let sampler = texture.sampler();
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => Nearest,
+        Some(MagFilter::Linear) | None => Linear,
+    };
+
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) => (Nearest, None),
+        Some(MinFilter::Linear) => (Linear, None),
+        Some(MinFilter::NearestMipmapNearest) => (Nearest, Some(Nearest)),
+        Some(MinFilter::LinearMipmapNearest) => (Linear, Some(Nearest)),
+        Some(MinFilter::NearestMipmapLinear) => (Nearest, Some(Linear)),
+        Some(MinFilter::LinearMipmapLinear) | None => (Linear, Some(Linear)),
+    };
+
+    let sampler = TextureSampler {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+    };
+
+    if texcoord > 1 {
+        eprintln!("texture coordinate set {texcoord} isn't supported, using set 1");
+    }
+
+    TextureTransform::new(
+        Vec2::from_array(offset),
+        rotation,
+        Vec2::from_array(scale),
+        texcoord.min(1),
+        sampler,
+    )
+}
+
+/// FNV-1a hash of the source data of a texture and how it's encoded. This must be stable between
+/// runs, so the standard library hasher can't be used.
+fn texture_hash(data: &[u8], kind: TextureKind) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    let tag = [TEXTURE_CACHE_VERSION, kind as u8];
+    for byte in tag.iter().chain(data) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
 fn load_indices(scene: &mut Scene, indices: &[u32]) -> Range<u32> {
     let offset = scene.vertices.len() as u32;
 
@@ -409,17 +990,43 @@ fn bounding_sphere(primitive: &gltf::Primitive) -> BoundingSphere {
     }
 }
 
-fn load_instances<'a>(nodes: impl Iterator<Item = gltf::Node<'a>>) -> Vec<Instance> {
+fn load_instances<'a>(
+    cameras: &mut Vec<CameraProjection>,
+    nodes: impl Iterator<Item = gltf::Node<'a>>,
+) -> Vec<Instance> {
     let nodes = nodes.map(|node| {
         let mesh = node.mesh().map(|mesh| mesh.index() as u32);
         let transform = Transform::from(Mat4::from_cols_array_2d(&node.transform().matrix()));
 
-        let children = load_instances(node.children());
+        let camera = node.camera().map(|camera| {
+            cameras.push(match camera.projection() {
+                gltf::camera::Projection::Perspective(perspective) => CameraProjection {
+                    lens: CameraLens::Perspective {
+                        yfov: perspective.yfov(),
+                    },
+                    z_near: perspective.znear(),
+                    z_far: perspective.zfar(),
+                },
+                // The aspect ratio of the window is used instead of `xmag`.
+                gltf::camera::Projection::Orthographic(orthographic) => CameraProjection {
+                    lens: CameraLens::Orthographic {
+                        height: 2.0 * orthographic.ymag(),
+                    },
+                    z_near: orthographic.znear(),
+                    z_far: Some(orthographic.zfar()),
+                },
+            });
+
+            cameras.len() as u32 - 1
+        });
+
+        let children = load_instances(cameras, node.children());
         let name = node.name().map(String::from);
 
         Instance {
             name,
             mesh,
+            camera,
             transform,
             children,
         }
@@ -428,144 +1035,79 @@ fn load_instances<'a>(nodes: impl Iterator<Item = gltf::Node<'a>>) -> Vec<Instan
     nodes.collect()
 }
 
+/// Create a block compressed texture from `image`. The image is resampled to a square with a
+/// power of two extent, so that every mip of the texture can be placed in a layer of the texture
+/// pools of the texture streamer. Mips are created down to the size of a block.
 fn create_texture(
+    hash: u64,
     mut image: image::DynamicImage,
     format: wgpu::TextureFormat,
-    create_mips: bool,
-    mut encode: impl FnMut(&mut [u8]) + Clone + Copy,
+    mut encode: impl FnMut(&mut [u8]) + Copy,
 ) -> Result<Texture> {
-    let mut mip_level_count = if create_mips {
-        let extent = u32::max(image.width(), image.height()) as f32;
-        extent.log2().floor() as u32 + 1
-    } else {
-        1
-    };
-
-    let extent = wgpu::Extent3d {
-        width: image.width(),
-        height: image.height(),
-        depth_or_array_layers: 1,
-    };
+    if !matches!(
+        format,
+        wgpu::TextureFormat::Bc1RgbaUnormSrgb
+            | wgpu::TextureFormat::Bc4RUnorm
+            | wgpu::TextureFormat::Bc5RgUnorm
+    ) {
+        return Err(eyre::eyre!("can't compress image with format {format:?}"));
+    }
 
+    let extent = square_texture_extent(image.width(), image.height());
     let filter_type = image::imageops::FilterType::Lanczos3;
 
-    let texture = match format {
-        wgpu::TextureFormat::Bc1RgbaUnorm
-        | wgpu::TextureFormat::Bc1RgbaUnormSrgb
-        | wgpu::TextureFormat::Bc5RgSnorm
-        | wgpu::TextureFormat::Bc5RgUnorm => {
-            fn round_to_block_size(extent: u32) -> u32 {
-                const BLOCK_SIZE: u32 = 4;
-                let quarter = (extent + BLOCK_SIZE - 1) / BLOCK_SIZE;
-                let rounded = quarter * BLOCK_SIZE;
-
-                u32::max(rounded, BLOCK_SIZE)
-            }
-
-            if create_mips {
-                // Block compressed textures can't have 1x1 and 2x2 mips.
-                mip_level_count -= 2;
-            }
-
-            let mut mips = Vec::new();
-
-            for level in 0..mip_level_count {
-                image = if level == 0 {
-                    image.resize_exact(
-                        round_to_block_size(image.width()),
-                        round_to_block_size(image.height()),
-                        filter_type,
-                    )
-                } else {
-                    image.resize_exact(
-                        round_to_block_size(image.width() / 2),
-                        round_to_block_size(image.height() / 2),
-                        filter_type,
-                    )
-                };
-
-                let mut mip = image.clone().into_rgba8();
-                mip.pixels_mut().for_each(|pixel| encode(&mut pixel.0));
-                mips.append(&mut compress_image(format, mip)?);
-            }
-
-            Texture {
-                mips: mips.into_boxed_slice(),
-                mip_level_count,
-                extent,
-                format,
-            }
-        }
-        format => {
-            if format.is_compressed() {
-                return Err(eyre::eyre!("can't compress image with format {format:?}"));
-            }
-
-            let mut mips = Vec::new();
-            for level in 0..mip_level_count {
-                if level != 0 {
-                    image = image.resize_exact(image.width() / 2, image.height() / 2, filter_type);
-                }
-
-                let mut raw = match format {
-                    wgpu::TextureFormat::R8Unorm => {
-                        let mut mip = image.clone().into_luma8();
-                        mip.pixels_mut().for_each(|pixel| encode(&mut pixel.0));
-                        mip.into_raw()
-                    }
-                    wgpu::TextureFormat::Rg8Unorm => {
-                        let mut mip = image.clone().into_rgb8();
-                        mip.pixels_mut().for_each(|pixel| encode(&mut pixel.0));
+    // Block compressed textures can't have 1x1 and 2x2 mips.
+    let mip_level_count = extent.ilog2() - 1;
+    let mut mips = Vec::new();
 
-                        let size = mip.width() * mip.height() * 2;
-                        let mut raw = Vec::with_capacity(size as usize);
+    for level in 0..mip_level_count {
+        let mip_extent = extent >> level;
+        image = image.resize_exact(mip_extent, mip_extent, filter_type);
 
-                        for pixel in mip.pixels() {
-                            raw.push(pixel[1]);
-                            raw.push(pixel[2]);
-                        }
+        let mut mip = image.clone().into_rgba8();
+        mip.pixels_mut().for_each(|pixel| encode(&mut pixel.0));
+        mips.push(compress_image(format, mip)?.into());
+    }
 
-                        raw
-                    }
-                    wgpu::TextureFormat::Rgba8UnormSrgb => {
-                        let mut mip = image.clone().into_rgba8();
-                        mip.pixels_mut().for_each(|pixel| encode(&mut pixel.0));
-                        mip.into_raw()
-                    }
-                    format => {
-                        return Err(eyre::eyre!("invalid format: {format:?}"));
-                    }
-                };
+    Ok(Texture {
+        hash,
+        mips,
+        mip_level_count,
+        extent: wgpu::Extent3d {
+            width: extent,
+            height: extent,
+            depth_or_array_layers: 1,
+        },
+        format,
+    })
+}
 
-                mips.append(&mut raw);
-            }
+/// The power of two nearest to the largest side of an image, which is at least the size of a
+/// block.
+fn square_texture_extent(width: u32, height: u32) -> u32 {
+    const BLOCK_SIZE: u32 = 4;
 
-            Texture {
-                mips: mips.into_boxed_slice(),
-                mip_level_count,
-                extent,
-                format,
-            }
-        }
-    };
+    let extent = u32::max(width, height).max(1) as f32;
+    let extent = 1 << extent.log2().round() as u32;
 
-    Ok(texture)
+    u32::max(extent, BLOCK_SIZE)
 }
 
-fn fallback_texture<const N: usize>(format: wgpu::TextureFormat, pixel: [u8; N]) -> Texture {
-    let extent = wgpu::Extent3d {
-        depth_or_array_layers: 1,
-        width: 4,
-        height: 4,
-    };
-
-    let mips = (0..16).flat_map(|_| pixel).collect();
+fn fallback_texture(kind: TextureKind, pixel: [u8; 4]) -> Texture {
+    let image = image::RgbaImage::from_pixel(4, 4, image::Rgba(pixel));
+    let mip =
+        compress_image(kind.format(), image).expect("fallback textures should be compressible");
 
     Texture {
+        hash: texture_hash(&pixel, kind),
         mip_level_count: 1,
-        extent,
-        mips,
-        format,
+        extent: wgpu::Extent3d {
+            depth_or_array_layers: 1,
+            width: 4,
+            height: 4,
+        },
+        mips: vec![mip.into()],
+        format: kind.format(),
     }
 }
 
@@ -588,6 +1130,7 @@ fn compress_image(format: wgpu::TextureFormat, image: image::RgbaImage) -> Resul
         wgpu::TextureFormat::Bc1RgbaUnorm | wgpu::TextureFormat::Bc1RgbaUnormSrgb => {
             texpresso::Format::Bc1
         }
+        wgpu::TextureFormat::Bc4RUnorm => texpresso::Format::Bc4,
         wgpu::TextureFormat::Bc5RgSnorm | wgpu::TextureFormat::Bc5RgUnorm => texpresso::Format::Bc5,
         format => {
             panic!("invalid format {format:?}");
@@ -727,16 +1270,20 @@ impl<'a> mikktspace::Geometry for TangentGenerator<'a> {
 }
 
 const ALBEDO_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bc1RgbaUnormSrgb;
-const ALBEDO_MAP_RAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 
 const NORMAL_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bc5RgUnorm;
-const NORMAL_MAP_RAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
 
 const SPECULAR_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bc5RgUnorm;
-const SPECULAR_MAP_RAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
+
+const OCCLUSION_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bc4RUnorm;
 
 const EMISSIVE_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bc1RgbaUnormSrgb;
-const EMISSIVE_MAP_RAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Single channel textures of the material extensions, with the channel moved to red.
+const MASK_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bc4RUnorm;
+
+/// Bump this when the texture encoding changes to invalidate cached textures.
+const TEXTURE_CACHE_VERSION: u8 = 2;
 
 const DEFAULT_IOR: f32 = 1.4;
 const DEFAULT_METALLIC: f32 = 0.0;
@@ -744,3 +1291,14 @@ const DEFAULT_ROUGHNESS: f32 = 1.0;
 
 const DEFAULT_COLOR: Vec4 = Vec4::splat(1.0);
 const DEFAULT_EMISSIVE: Vec4 = Vec4::splat(0.0);
+const DEFAULT_SHEEN: Vec4 = Vec4::splat(0.0);
+const DEFAULT_SPECULAR: Vec4 = Vec4::splat(1.0);
+const DEFAULT_ATTENUATION: Vec4 = Vec4::new(1.0, 1.0, 1.0, f32::INFINITY);
+
+#[test]
+fn texture_extent_is_square_power_of_two() {
+    assert_eq!(square_texture_extent(1000, 600), 1024);
+    assert_eq!(square_texture_extent(1536, 512), 2048);
+    assert_eq!(square_texture_extent(1400, 1400), 1024);
+    assert_eq!(square_texture_extent(1, 2), 4);
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use super::{AssetPath, Scene};
+
+/// Where a scene is loaded from.
+#[derive(Clone, Debug)]
+pub enum SceneSource {
+    Gltf(AssetPath),
+    /// A RON scene description, see `SceneDescription`.
+    Description(PathBuf),
+}
+
+impl SceneSource {
+    /// Files with the `ron` extension are scene descriptions, everything else is loaded as
+    /// glTF with the scene cache next to it.
+    pub fn from_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|extension| extension == "ron") {
+            Self::Description(path.to_owned())
+        } else {
+            Self::Gltf(AssetPath::new(path, path.with_extension("scene")))
+        }
+    }
+
+    /// The path of the camera bookmarks of the scene.
+    pub fn bookmarks_path(&self) -> PathBuf {
+        match self {
+            Self::Gltf(path) => path.asset.with_extension("bookmarks.ron"),
+            Self::Description(path) => path.with_extension("bookmarks.ron"),
+        }
+    }
+
+    /// Load the scene on this thread. `SceneLoader` loads it on a background thread instead.
+    pub fn load(&self, progress: &(dyn Fn(LoadProgress) + Sync)) -> eyre::Result<Scene> {
+        match self {
+            Self::Gltf(path) => Scene::from_gltf(path, progress),
+            Self::Description(path) => Scene::from_description(path, progress),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadProgress {
+    /// The glTF file has been parsed or the scene cache has been mapped.
+    Parsed,
+    /// The meshes have been loaded.
+    Meshes,
+    /// `loaded` of `total` textures have been loaded.
+    Textures { loaded: usize, total: usize },
+    /// The scene has been uploaded to the GPU. Sent by the renderer, not the loader.
+    Uploaded,
+}
+
+impl fmt::Display for LoadProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parsed => write!(f, "parsed scene"),
+            Self::Meshes => write!(f, "loaded meshes"),
+            Self::Textures { loaded, total } => write!(f, "loaded texture {loaded}/{total}"),
+            Self::Uploaded => write!(f, "uploaded scene"),
+        }
+    }
+}
+
+pub enum LoadEvent {
+    Progress(LoadProgress),
+    Loaded(Scene),
+    Failed(eyre::Report),
+}
+
+/// Loads a scene on a background thread.
+pub struct SceneLoader {
+    source: SceneSource,
+    events: mpsc::Receiver<LoadEvent>,
+}
+
+impl SceneLoader {
+    pub fn spawn(source: SceneSource) -> Self {
+        let (sender, events) = mpsc::channel();
+        let loader_source = source.clone();
+
+        thread::spawn(move || {
+            let progress = |progress| {
+                let _ = sender.send(LoadEvent::Progress(progress));
+            };
+
+            let event = match loader_source.load(&progress) {
+                Ok(scene) => LoadEvent::Loaded(scene),
+                Err(err) => LoadEvent::Failed(err),
+            };
+
+            // The receiver may have been dropped if another scene started loading.
+            let _ = sender.send(event);
+        });
+
+        Self { source, events }
+    }
+
+    pub fn source(&self) -> &SceneSource {
+        &self.source
+    }
+
+    /// Returns the events received since the last call without blocking.
+    pub fn poll(&self) -> mpsc::TryIter<'_, LoadEvent> {
+        self.events.try_iter()
+    }
+}
@@ -1,9 +1,14 @@
+mod blob;
+mod cache;
+mod description;
 mod gltf;
+mod loader;
 mod normal;
 mod quantize;
+#[cfg(test)]
+mod synthetic;
 
 use std::{
-    fs,
     ops::Range,
     path::{Path, PathBuf},
 };
@@ -14,10 +19,15 @@ use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
 use half::f16;
 use serde::{Deserialize, Serialize};
 
+pub use blob::Blob;
+use description::{SceneDescription, SceneMerger};
+pub use loader::{LoadEvent, LoadProgress, SceneLoader, SceneSource};
 use normal::TangentFrame;
+#[cfg(test)]
+pub use synthetic::{Geometry, SceneBuilder};
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Pod, Zeroable, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable, Serialize, Deserialize)]
 pub struct DirectionalLight {
     pub direction: Vec4,
     pub irradiance: Vec4,
@@ -33,12 +43,23 @@ impl Default for DirectionalLight {
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Transform {
     pub scale: Vec3,
     pub rotation: Quat,
     pub translation: Vec3,
 }
 
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            scale: Vec3::ONE,
+            rotation: Quat::IDENTITY,
+            translation: Vec3::ZERO,
+        }
+    }
+}
+
 impl From<Mat4> for Transform {
     fn from(matrix: Mat4) -> Self {
         let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
@@ -91,16 +112,175 @@ pub struct Primitive {
 pub struct Instance {
     pub name: Option<String>,
     pub mesh: Option<u32>,
+    /// Index into `Scene::cameras`.
+    pub camera: Option<u32>,
     pub transform: Transform,
     pub children: Vec<Instance>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A camera imported from glTF. Cameras look down the negative z axis of their instance.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CameraProjection {
+    pub lens: CameraLens,
+    pub z_near: f32,
+    pub z_far: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CameraLens {
+    /// `yfov` is the vertical field of view in radians.
+    Perspective { yfov: f32 },
+    /// `height` is the vertical extent of the view in world units.
+    Orthographic { height: f32 },
+}
+
+/// A camera placed in the scene by an instance.
+#[derive(Clone, Debug)]
+pub struct SceneCamera {
+    pub name: Option<String>,
+    pub transform: Mat4,
+    pub projection: CameraProjection,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Texture {
+    /// Identifies the content of the texture. Textures with the same hash are identical.
+    pub hash: u64,
     pub format: wgpu::TextureFormat,
     pub extent: wgpu::Extent3d,
     pub mip_level_count: u32,
-    pub mips: Box<[u8]>,
+    /// The data of each mip level.
+    pub mips: Vec<Blob<u8>>,
+}
+
+/// How a material texture is addressed and filtered. Material textures are sampled with a single
+/// repeating, linear sampler, so the other address modes and nearest filtering are emulated by
+/// the shade pass. This keeps the samplers of the shade pass within the limits of every adapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureSampler {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    /// `None` if the texture isn't mipmapped, in which case only the most detailed mip is
+    /// sampled.
+    pub mipmap_filter: Option<wgpu::FilterMode>,
+}
+
+impl TextureSampler {
+    /// Pack the sampler into the bits read by the shade pass. Must match the `SAMPLER_*`
+    /// constants in `mesh.wgsl`.
+    ///
+    /// | bits | content                                          |
+    /// |------|--------------------------------------------------|
+    /// | 0..2 | index of the u address mode in `ADDRESS_MODES`   |
+    /// | 2..4 | index of the v address mode in `ADDRESS_MODES`   |
+    /// | 4    | set if the mag filter is nearest                 |
+    /// | 5    | set if the min filter is nearest                 |
+    /// | 6    | set if the mipmap filter is nearest              |
+    /// | 7    | set if the texture isn't mipmapped               |
+    pub fn bits(self) -> u32 {
+        let address_mode = |mode| {
+            ADDRESS_MODES
+                .iter()
+                .position(|m| *m == mode)
+                .unwrap_or_else(|| panic!("unsupported address mode {mode:?}")) as u32
+        };
+
+        let is_nearest = |filter| (filter == wgpu::FilterMode::Nearest) as u32;
+
+        let mipmap_bits = match self.mipmap_filter {
+            Some(filter) => is_nearest(filter) << 6,
+            None => 1 << 7,
+        };
+
+        address_mode(self.address_mode_u)
+            | address_mode(self.address_mode_v) << 2
+            | is_nearest(self.mag_filter) << 4
+            | is_nearest(self.min_filter) << 5
+            | mipmap_bits
+    }
+}
+
+impl Default for TextureSampler {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: Some(wgpu::FilterMode::Linear),
+        }
+    }
+}
+
+/// The address modes of material textures, in the order of the address mode bits of
+/// `TextureSampler::bits`.
+const ADDRESS_MODES: [wgpu::AddressMode; 3] = [
+    wgpu::AddressMode::Repeat,
+    wgpu::AddressMode::MirrorRepeat,
+    wgpu::AddressMode::ClampToEdge,
+];
+
+/// How a material texture is sampled. The texture coordinates are transformed by the affine
+/// transform made up of `x_axis`, `y_axis` and `offset`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable, Serialize, Deserialize)]
+pub struct TextureTransform {
+    x_axis: Vec2,
+    y_axis: Vec2,
+    offset: Vec2,
+    texcoord: u32,
+    /// See `TextureSampler::bits`.
+    sampler_bits: u32,
+}
+
+impl TextureTransform {
+    pub fn new(
+        offset: Vec2,
+        rotation: f32,
+        scale: Vec2,
+        texcoord: u32,
+        sampler: TextureSampler,
+    ) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+
+        Self {
+            x_axis: Vec2::new(cos, -sin) * scale.x,
+            y_axis: Vec2::new(sin, cos) * scale.y,
+            sampler_bits: sampler.bits(),
+            texcoord,
+            offset,
+        }
+    }
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self::new(Vec2::ZERO, 0.0, Vec2::ONE, 0, TextureSampler::default())
+    }
+}
+
+bitflags::bitflags! {
+    /// The optional parts of the material model used by a material. The shade pass only
+    /// evaluates the lobes of the features that are set, and only samples the optional textures
+    /// that are set. Specular and volume only use the factors of their glTF extensions, not
+    /// their textures.
+    #[repr(C)]
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct MaterialFlags: u32 {
+        const OCCLUSION = 1 << 0;
+        const CLEARCOAT = 1 << 1;
+        const SHEEN = 1 << 2;
+        const TRANSMISSION = 1 << 3;
+        const VOLUME = 1 << 4;
+        const CLEARCOAT_TEXTURE = 1 << 5;
+        const CLEARCOAT_ROUGHNESS_TEXTURE = 1 << 6;
+        const CLEARCOAT_NORMAL_TEXTURE = 1 << 7;
+        const SHEEN_COLOR_TEXTURE = 1 << 8;
+        const SHEEN_ROUGHNESS_TEXTURE = 1 << 9;
+        const TRANSMISSION_TEXTURE = 1 << 10;
+    }
 }
 
 #[repr(C)]
@@ -110,15 +290,38 @@ pub struct Material {
     normal_texture: u32,
     specular_texture: u32,
     emissive_texture: u32,
+    /// Only valid if `MaterialFlags::OCCLUSION` is set.
+    occlusion_texture: u32,
+    flags: u32,
+    occlusion_strength: f32,
+    ior: f32,
     base_color: Vec4,
     emissive: Vec4,
     metallic: f32,
     roughness: f32,
-    ior: f32,
-    padding: [u32; 1],
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    /// The sheen color in rgb and the sheen roughness in alpha.
+    sheen: Vec4,
+    /// The specular color in rgb and the specular factor in alpha.
+    specular: Vec4,
+    /// The attenuation color in rgb and the attenuation distance in alpha.
+    attenuation: Vec4,
+    transmission: f32,
+    thickness: f32,
+    /// The textures of the material extensions. Each is only valid if its flag is set.
+    clearcoat_texture: u32,
+    clearcoat_roughness_texture: u32,
+    clearcoat_normal_texture: u32,
+    sheen_color_texture: u32,
+    sheen_roughness_texture: u32,
+    transmission_texture: u32,
+    /// The transforms of the albedo, normal, specular, emissive and occlusion textures, followed
+    /// by the textures of the material extensions.
+    texture_transforms: [TextureTransform; 11],
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Mesh {
     pub primitives: Vec<Primitive>,
 }
@@ -139,16 +342,6 @@ impl Position {
                 .map(|value| quantize::quantize_snorm::<16>(value) as i16),
         }
     }
-
-    #[cfg(test)]
-    fn to_vec3(self, bounding_sphere: &BoundingSphere) -> Vec3 {
-        let vec = Vec3::from_array(
-            self.encoded
-                .map(|value| quantize::dequantize_snorm::<16>(value as i32)),
-        );
-
-        (vec * bounding_sphere.radius) + bounding_sphere.center
-    }
 }
 
 #[repr(C)]
@@ -158,6 +351,7 @@ pub struct Vertex {
     pub position: Position,
     pub material: u16,
     pub tangent_frame: TangentFrame,
+    pub secondary_texcoord: [f16; 2],
 }
 
 impl Vertex {
@@ -165,12 +359,13 @@ impl Vertex {
         bounding_sphere: &BoundingSphere,
         position: Vec3,
         normal: Vec3,
-        texcoord: Vec2,
+        texcoords: [Vec2; 2],
         tangent: Vec4,
         material: u32,
     ) -> Self {
         let tangent_frame = TangentFrame::new(normal, tangent);
-        let texcoord = texcoord.to_array().map(f16::from_f32);
+        let [texcoord, secondary_texcoord] =
+            texcoords.map(|texcoord| texcoord.to_array().map(f16::from_f32));
         let position = Position::new(position, bounding_sphere);
 
         debug_assert!({
@@ -183,6 +378,7 @@ impl Vertex {
             tangent_frame,
             position,
             texcoord,
+            secondary_texcoord,
         }
     }
 }
@@ -206,14 +402,15 @@ impl AssetPath {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default)]
 pub struct Scene {
     pub directional_light: DirectionalLight,
-    pub vertices: Vec<Vertex>,
-    pub indices: Vec<u32>,
+    pub vertices: Blob<Vertex>,
+    pub indices: Blob<u32>,
     pub textures: Vec<Texture>,
     pub materials: Vec<Material>,
     pub meshes: Vec<Mesh>,
+    pub cameras: Vec<CameraProjection>,
     pub instances: Vec<Instance>,
 }
 
@@ -250,40 +447,114 @@ impl Scene {
         }
     }
 
-    pub fn from_gltf(path: &AssetPath) -> Result<Self> {
-        let scene = fs::read(&path.cache)
-            .ok()
-            .and_then(|bytes| bincode::deserialize(&bytes).ok());
+    /// The cameras placed by the instances with their world transforms.
+    pub fn scene_cameras(&self) -> Vec<SceneCamera> {
+        let mut cameras = Vec::new();
+
+        self.visit_instances(|instance, parent_transform: Option<&Mat4>| {
+            let transform: Mat4 = instance.transform.into();
+            let transform = match parent_transform {
+                Some(parent_transform) => *parent_transform * transform,
+                None => transform,
+            };
+
+            if let Some(camera) = instance.camera {
+                cameras.push(SceneCamera {
+                    name: instance.name.clone(),
+                    projection: self.cameras[camera as usize],
+                    transform,
+                });
+            }
+
+            transform
+        });
+
+        cameras
+    }
+
+    /// Load the glTF scene at `path.asset`, or memory map the scene cache at `path.cache` if it
+    /// exists. `progress` is called from the loading threads as the scene is loaded.
+    pub fn from_gltf(path: &AssetPath, progress: &(dyn Fn(LoadProgress) + Sync)) -> Result<Self> {
+        let scene = cache::read(&path.cache)
+            .map_err(|err| {
+                if path.cache.exists() {
+                    eprintln!("failed to read scene cache at {:?}: {err}", path.cache);
+                }
+            })
+            .ok();
 
         if let Some(scene) = scene {
+            progress(LoadProgress::Parsed);
             Ok(scene)
         } else {
             let importer = gltf::Importer::new(&path.asset).wrap_err_with(|| {
                 format!("failed creating gltf importer for file at {:?}", path.asset)
             })?;
 
-            let scene = importer.load_scene().wrap_err_with(|| {
-                format!("failed loading gltf scene for file at {:?}", path.asset)
-            })?;
+            progress(LoadProgress::Parsed);
 
-            scene.serialize(&path.cache);
+            let texture_cache = path.cache.with_extension("textures");
+            let scene = importer
+                .load_scene(&texture_cache, progress)
+                .wrap_err_with(|| {
+                    format!("failed loading gltf scene for file at {:?}", path.asset)
+                })?;
 
-            Ok(scene)
+            scene.cache(&path.cache);
+
+            // Map the scene back from the cache, so that the texture streamer reads textures
+            // from the mapping instead of keeping them in memory.
+            Ok(cache::read(&path.cache).unwrap_or(scene))
         }
     }
 
-    fn serialize(&self, path: &Path) {
-        match bincode::serialize(&self) {
-            Ok(bytes) => {
-                if let Err(err) = fs::write(path, &bytes) {
-                    eprintln!("failed to cache scene: {err}");
-                } else {
-                    println!("cached scene to: {:?}", path);
-                }
-            }
-            Err(err) => {
-                eprintln!("failed serialize scene: {err}");
-            }
+    /// Load a scene made up of the glTF files referenced by the scene description at `path`.
+    /// Each glTF file has its own scene cache.
+    pub fn from_description(path: &Path, progress: &(dyn Fn(LoadProgress) + Sync)) -> Result<Self> {
+        let description = SceneDescription::load(path)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let mut merger = SceneMerger::default();
+
+        for (asset, asset_path) in description
+            .assets
+            .iter()
+            .zip(description.asset_paths(directory))
+        {
+            let scene = Self::from_gltf(&asset_path, progress)?;
+            let name = asset_path
+                .asset
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned());
+
+            merger
+                .merge(name, scene, asset.transform)
+                .wrap_err_with(|| format!("failed to merge {:?}", asset_path.asset))?;
+        }
+
+        Ok(merger.finish())
+    }
+
+    fn cache(&self, path: &Path) {
+        if let Err(err) = cache::write(self, path) {
+            eprintln!("failed to cache scene: {err}");
+        } else {
+            println!("cached scene to: {:?}", path);
         }
     }
 }
+
+#[test]
+fn sampler_bits() {
+    assert_eq!(TextureSampler::default().bits(), 0);
+
+    let sampler = TextureSampler {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::MirrorRepeat,
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: None,
+    };
+
+    assert_eq!(sampler.bits(), 2 | 1 << 2 | 1 << 4 | 1 << 7);
+}
@@ -0,0 +1,203 @@
+//! Scenes built in code, used to test rendering without loading assets.
+
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec3, Vec4};
+
+use super::gltf::FallbackTextures;
+use super::{
+    BoundingSphere, DirectionalLight, Instance, Material, Mesh, Primitive, Scene, Transform, Vertex,
+};
+
+/// The vertices and indices of a shape.
+pub struct Geometry {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    indices: Vec<u32>,
+}
+
+impl Geometry {
+    /// A square in the xz plane with sides of length 2, facing up.
+    pub fn plane() -> Self {
+        let positions = [[-1.0, 1.0], [1.0, 1.0], [1.0, -1.0], [-1.0, -1.0]]
+            .map(|[x, z]| Vec3::new(x, 0.0, z))
+            .to_vec();
+
+        Self {
+            normals: vec![Vec3::Y; positions.len()],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            positions,
+        }
+    }
+
+    /// A cube with sides of length 2 centered at the origin.
+    pub fn cube() -> Self {
+        let mut geometry = Self {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        };
+
+        for normal in [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ] {
+            let tangent = normal.any_orthonormal_vector();
+            let bitangent = normal.cross(tangent);
+            let offset = geometry.positions.len() as u32;
+
+            for [u, v] in [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]] {
+                geometry
+                    .positions
+                    .push(normal + tangent * u + bitangent * v);
+                geometry.normals.push(normal);
+            }
+
+            geometry
+                .indices
+                .extend([0, 1, 2, 0, 2, 3].map(|index| index + offset));
+        }
+
+        geometry
+    }
+
+    /// A unit sphere centered at the origin.
+    pub fn sphere(segments: u32) -> Self {
+        let rings = segments / 2;
+
+        let mut positions = Vec::new();
+        for ring in 0..=rings {
+            let theta = ring as f32 / rings as f32 * PI;
+
+            for segment in 0..=segments {
+                let phi = segment as f32 / segments as f32 * TAU;
+                positions.push(Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ));
+            }
+        }
+
+        let mut indices = Vec::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let top = ring * (segments + 1) + segment;
+                let bottom = top + segments + 1;
+
+                indices.extend([top, top + 1, bottom, top + 1, bottom + 1, bottom]);
+            }
+        }
+
+        Self {
+            normals: positions.clone(),
+            positions,
+            indices,
+        }
+    }
+}
+
+/// Builds a scene of untextured meshes.
+pub struct SceneBuilder {
+    scene: Scene,
+    fallback_textures: FallbackTextures,
+}
+
+impl SceneBuilder {
+    /// `sun_direction` points towards the sun.
+    pub fn new(sun_direction: Vec3, sun_irradiance: Vec3) -> Self {
+        let scene = Scene {
+            directional_light: DirectionalLight {
+                direction: sun_direction.normalize().extend(1.0),
+                irradiance: sun_irradiance.extend(1.0),
+            },
+            ..Default::default()
+        };
+
+        Self {
+            fallback_textures: FallbackTextures::default(),
+            scene,
+        }
+    }
+
+    pub fn add_material(&mut self, base_color: Vec4, metallic: f32, roughness: f32) -> u32 {
+        let material = Material {
+            base_color,
+            metallic,
+            roughness,
+            ..self.fallback_textures.fallback_material(&mut self.scene)
+        };
+
+        self.scene.add_material(material)
+    }
+
+    pub fn add_mesh(&mut self, geometry: &Geometry, material: u32) -> u32 {
+        let (min, max) = geometry.positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), position| (min.min(*position), max.max(*position)),
+        );
+
+        let center = min + (max - min) * 0.5;
+        let bounding_sphere = BoundingSphere {
+            radius: (center - max).length(),
+            center,
+        };
+
+        let offset = self.scene.vertices.len() as u32;
+        let start = self.scene.indices.len() as u32;
+
+        self.scene
+            .indices
+            .extend(geometry.indices.iter().map(|index| index + offset));
+        self.scene
+            .vertices
+            .extend(
+                geometry
+                    .positions
+                    .iter()
+                    .zip(&geometry.normals)
+                    .map(|(position, normal)| {
+                        let tangent = normal.any_orthonormal_vector().extend(1.0);
+                        let texcoords = [position.truncate(); 2];
+
+                        Vertex::new(
+                            &bounding_sphere,
+                            *position,
+                            *normal,
+                            texcoords,
+                            tangent,
+                            material,
+                        )
+                    }),
+            );
+
+        let mesh = self.scene.meshes.len() as u32;
+        self.scene.meshes.push(Mesh {
+            primitives: vec![Primitive {
+                indices: start..self.scene.indices.len() as u32,
+                bounding_sphere,
+                material,
+            }],
+        });
+
+        mesh
+    }
+
+    pub fn add_instance(&mut self, mesh: u32, transform: Transform) {
+        self.scene.instances.push(Instance {
+            name: None,
+            mesh: Some(mesh),
+            camera: None,
+            transform,
+            children: Vec::new(),
+        });
+    }
+
+    pub fn finish(self) -> Scene {
+        self.scene
+    }
+}
@@ -1,17 +1,375 @@
 use std::borrow::Cow;
 
+use bytemuck::{NoUninit, Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
 use crate::{
+    camera::Camera,
     context::Context,
-    resources::{self, ConstState, Skybox, SKYBOX_FORMAT},
+    resources::{
+        self, ConstState, FroxelVolume, ParamsBuffer, RenderTarget, ShadowCascades, Skybox,
+        SKYBOX_FORMAT,
+    },
+    util,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AtmosphereSettings {
+    /// Attenuate distant geometry and add the light scattered by the atmosphere in front of it.
+    pub aerial_perspective: bool,
+    /// Distances are multiplied by this for the aerial perspective, which exaggerates the haze in
+    /// scenes much smaller than a landscape.
+    pub aerial_perspective_scale: f32,
+    /// The distance in meters covered by the froxel volume. Geometry further away gets the
+    /// aerial perspective and fog at this distance.
+    pub volume_distance: f32,
+    pub height_fog: Option<HeightFog>,
+    pub model: AtmosphereModel,
+    /// The width and height of each face of the baked skybox.
+    pub skybox_size: u32,
+    pub celestial: CelestialSettings,
+}
+
+impl Default for AtmosphereSettings {
+    fn default() -> Self {
+        Self {
+            aerial_perspective: true,
+            aerial_perspective_scale: 1.0,
+            volume_distance: 1000.0,
+            height_fog: None,
+            model: AtmosphereModel::ClearEarth,
+            skybox_size: 256,
+            celestial: CelestialSettings::default(),
+        }
+    }
+}
+
+/// The composition of the atmosphere.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum AtmosphereModel {
+    ClearEarth,
+    /// The earth with more aerosols, which makes the sky paler and the sun glow wider.
+    Hazy,
+    /// A thin atmosphere of carbon dioxide and fine dust, which absorbs blue light.
+    Mars,
+    /// Parameters from the settings. Missing fields are those of the clear earth.
+    Custom(AtmosphereParams),
+}
+
+impl AtmosphereModel {
+    pub fn next(self) -> Self {
+        match self {
+            Self::ClearEarth => Self::Hazy,
+            Self::Hazy => Self::Mars,
+            Self::Mars | Self::Custom(_) => Self::ClearEarth,
+        }
+    }
+
+    pub fn params(&self) -> AtmosphereParams {
+        match self {
+            Self::ClearEarth => AtmosphereParams::default(),
+            Self::Hazy => AtmosphereParams {
+                mie_scattering: Vec3::splat(2.0e-5),
+                mie_absorption: Vec3::splat(4.4e-6),
+                mie_height: 1800.0,
+                mie_g: 0.76,
+                ..Default::default()
+            },
+            Self::Mars => AtmosphereParams {
+                planet_radius: 3_389_500.0,
+                atmosphere_height: 80_000.0,
+                rayleigh_scattering: Vec3::new(1.9e-7, 1.1e-7, 0.6e-7),
+                rayleigh_height: 11_000.0,
+                mie_scattering: Vec3::new(2.0e-5, 1.3e-5, 0.8e-5),
+                mie_absorption: Vec3::new(0.4e-5, 0.8e-5, 1.4e-5),
+                mie_height: 11_000.0,
+                mie_g: 0.65,
+                ozone_absorption: Vec3::ZERO,
+                ..Default::default()
+            },
+            Self::Custom(params) => *params,
+        }
+    }
+}
+
+/// The parameters of the atmosphere. Scattering and absorption coefficients are per meter at the
+/// ground, and heights and distances are in meters. Must match `AtmosphereParams` in the
+/// `atmosphere` shader include.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable, Deserialize)]
+#[serde(default)]
+pub struct AtmosphereParams {
+    pub rayleigh_scattering: Vec3,
+    pub planet_radius: f32,
+    pub mie_scattering: Vec3,
+    pub atmosphere_height: f32,
+    pub mie_absorption: Vec3,
+    /// The height at which the density of the molecules causing Rayleigh scattering has fallen
+    /// to `1 / e`.
+    pub rayleigh_height: f32,
+    pub ozone_absorption: Vec3,
+    /// The height at which the density of the aerosols causing Mie scattering has fallen to
+    /// `1 / e`.
+    pub mie_height: f32,
+    /// The ozone density falls off linearly from its center height.
+    pub ozone_center_height: f32,
+    pub ozone_width: f32,
+    /// The asymmetry of Mie scattering. Values closer to one scatter more light forwards.
+    pub mie_g: f32,
+    /// The number of samples along each view ray when baking the sky.
+    pub sample_count: u32,
+}
+
+impl Default for AtmosphereParams {
+    fn default() -> Self {
+        Self {
+            rayleigh_scattering: Vec3::new(5.802, 13.558, 33.1) * 1e-6,
+            planet_radius: 6_371_000.0,
+            mie_scattering: Vec3::splat(3.996e-6),
+            atmosphere_height: 100_000.0,
+            mie_absorption: Vec3::ZERO,
+            rayleigh_height: 8000.0,
+            ozone_absorption: Vec3::new(0.65, 1.881, 0.085) * 1e-6,
+            mie_height: 1200.0,
+            // The ozone layer start at around 10 km height and has a width of 30 km.
+            ozone_center_height: 25_000.0,
+            ozone_width: 30_000.0,
+            mie_g: 0.85,
+            sample_count: 16,
+        }
+    }
+}
+
+impl AtmosphereParams {
+    /// Clamp the parameters to values the shaders can handle.
+    fn sanitized(self) -> Self {
+        Self {
+            rayleigh_scattering: self.rayleigh_scattering.max(Vec3::ZERO),
+            planet_radius: self.planet_radius.max(1000.0),
+            mie_scattering: self.mie_scattering.max(Vec3::ZERO),
+            atmosphere_height: self.atmosphere_height.max(1.0),
+            mie_absorption: self.mie_absorption.max(Vec3::ZERO),
+            rayleigh_height: self.rayleigh_height.max(1.0),
+            ozone_absorption: self.ozone_absorption.max(Vec3::ZERO),
+            mie_height: self.mie_height.max(1.0),
+            ozone_center_height: self.ozone_center_height,
+            ozone_width: self.ozone_width.max(1.0),
+            mie_g: self.mie_g.clamp(-0.99, 0.99),
+            sample_count: self.sample_count.clamp(2, 256),
+        }
+    }
+}
+
+/// The sun disk, moon and stars drawn in the sky behind the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CelestialSettings {
+    /// The angular radius of the sun disk in degrees. Zero hides the sun disk.
+    pub sun_angular_radius: f32,
+    pub moon: Option<Moon>,
+    /// The radiance of the brightest stars. They fade out as the sky gets brighter.
+    pub star_brightness: f32,
+}
+
+impl Default for CelestialSettings {
+    fn default() -> Self {
+        Self {
+            sun_angular_radius: 0.2666,
+            moon: Some(Moon::default()),
+            star_brightness: 0.5,
+        }
+    }
+}
+
+/// A moon lit by the sun, so its phase follows from the angle between it and the sun.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Moon {
+    /// The direction towards the moon.
+    pub direction: Vec3,
+    /// The angular radius in degrees.
+    pub angular_radius: f32,
+    /// The fraction of the sun light that the surface reflects.
+    pub albedo: f32,
+}
+
+impl Default for Moon {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(-0.4, 0.5, -0.75),
+            angular_radius: 0.259,
+            albedo: 0.12,
+        }
+    }
+}
+
+/// Must match `CelestialParams` in `atmosphere.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+struct CelestialParams {
+    moon_direction: Vec3,
+    /// The angular radius of the moon in radians, zero if there is no moon.
+    moon_angular_radius: f32,
+    /// The angular radius of the sun in radians.
+    sun_angular_radius: f32,
+    moon_albedo: f32,
+    star_brightness: f32,
+    padding: u32,
+}
+
+impl CelestialParams {
+    fn new(settings: &CelestialSettings) -> Self {
+        let moon = settings.moon.filter(|moon| moon.direction != Vec3::ZERO);
+
+        Self {
+            moon_direction: moon.map_or(Vec3::Y, |moon| moon.direction.normalize()),
+            moon_angular_radius: moon
+                .map_or(0.0, |moon| moon.angular_radius.clamp(0.0, 45.0))
+                .to_radians(),
+            sun_angular_radius: settings.sun_angular_radius.clamp(0.0, 45.0).to_radians(),
+            moon_albedo: moon.map_or(0.0, |moon| moon.albedo.max(0.0)),
+            star_brightness: settings.star_brightness.max(0.0),
+            padding: 0,
+        }
+    }
+}
+
+/// Fog that thins out exponentially with height.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HeightFog {
+    /// The extinction coefficient per meter at `base_height`.
+    pub density: f32,
+    /// How fast the density falls off per meter above `base_height`.
+    pub height_falloff: f32,
+    pub base_height: f32,
+    /// The Henyey-Greenstein asymmetry of the scattering. Positive values scatter more light
+    /// forwards, which makes the fog glow around the sun.
+    pub anisotropy: f32,
+    /// The fraction of the extinguished light that is scattered.
+    pub albedo: Vec3,
+    /// Shadow the sun light scattered by the fog with the shadow cascades.
+    pub sun_shafts: bool,
+}
+
+impl Default for HeightFog {
+    fn default() -> Self {
+        Self {
+            density: 0.01,
+            height_falloff: 0.1,
+            base_height: 0.0,
+            anisotropy: 0.6,
+            albedo: Vec3::ONE,
+            sun_shafts: true,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq, NoUninit)]
+    struct VolumeFlags: u32 {
+        const AERIAL_PERSPECTIVE = 1 << 0;
+        const HEIGHT_FOG = 1 << 1;
+        const SUN_SHAFTS = 1 << 2;
+    }
+}
+
+/// Must match `Params` in `atmosphere.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, NoUninit)]
+struct VolumeParams {
+    /// The same as `ShadeParams::ray_matrix`.
+    ray_matrix: Mat4,
+    fog_albedo: Vec3,
+    fog_density: f32,
+    volume_distance: f32,
+    aerial_perspective_scale: f32,
+    fog_height_falloff: f32,
+    fog_base_height: f32,
+    fog_anisotropy: f32,
+    flags: VolumeFlags,
+    padding: [u32; 2],
+}
+
+impl VolumeParams {
+    /// Returns `None` if neither aerial perspective nor fog is enabled.
+    fn new(settings: &AtmosphereSettings, ray_matrix: Mat4) -> Option<Self> {
+        let mut flags = VolumeFlags::empty();
+        flags.set(VolumeFlags::AERIAL_PERSPECTIVE, settings.aerial_perspective);
+
+        let fog = settings.height_fog.unwrap_or(HeightFog {
+            density: 0.0,
+            ..Default::default()
+        });
+
+        flags.set(VolumeFlags::HEIGHT_FOG, settings.height_fog.is_some());
+        flags.set(
+            VolumeFlags::SUN_SHAFTS,
+            settings.height_fog.is_some() && fog.sun_shafts,
+        );
+
+        (!flags.is_empty()).then(|| Self {
+            ray_matrix,
+            fog_albedo: fog.albedo.clamp(Vec3::ZERO, Vec3::ONE),
+            fog_density: fog.density.max(0.0),
+            volume_distance: settings.volume_distance.max(1.0),
+            aerial_perspective_scale: settings.aerial_perspective_scale.max(0.0),
+            fog_height_falloff: fog.height_falloff.max(0.0),
+            fog_base_height: fog.base_height,
+            fog_anisotropy: fog.anisotropy.clamp(-0.99, 0.99),
+            flags,
+            padding: [0; 2],
+        })
+    }
+}
+
+const TRANSMITTANCE_LUT_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+    width: 256,
+    height: 64,
+    depth_or_array_layers: 1,
 };
 
+const MULTI_SCATTERING_LUT_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+    width: 32,
+    height: 32,
+    depth_or_array_layers: 1,
+};
+
+const LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const FILTERABLE: wgpu::TextureSampleType = wgpu::TextureSampleType::Float { filterable: true };
+
+/// Bakes the sky and computes the aerial perspective and fog with the transmittance and
+/// multi-scattering LUTs of "A Scalable and Production Ready Sky and Atmosphere Rendering
+/// Technique" by Hillaire.
 pub struct AtmospherePhase {
+    transmittance: Pass,
+    multi_scattering: Pass,
+    sky: Pass,
+    aerial_perspective: Pass,
+    transmittance_lut: RenderTarget,
+    multi_scattering_lut: RenderTarget,
+    params_buffer: wgpu::Buffer,
+    params: AtmosphereParams,
+    celestial_buffer: wgpu::Buffer,
+    celestial: CelestialParams,
+    volume_params: ParamsBuffer<VolumeParams>,
+    skybox_size: wgpu::Extent3d,
+    needs_bake: bool,
+}
+
+struct Pass {
     pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
 }
 
 impl AtmospherePhase {
-    pub fn new(context: &mut Context, skybox: &Skybox) -> Self {
+    pub fn new(context: &mut Context, skybox: &Skybox, froxel_volume: &FroxelVolume) -> Self {
         let module = context.create_shader_module(
             include_str!("shaders/atmosphere.wgsl"),
             "shaders/atmosphere.wgsl",
@@ -25,74 +383,483 @@ impl AtmospherePhase {
                 source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
             });
 
-        let bind_group_layout =
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+        let transmittance_lut = RenderTarget::new(
+            context,
+            "transmittance lut",
+            TRANSMITTANCE_LUT_SIZE,
+            LUT_FORMAT,
+            usage,
+        );
+        let multi_scattering_lut = RenderTarget::new(
+            context,
+            "multi scattering lut",
+            MULTI_SCATTERING_LUT_SIZE,
+            LUT_FORMAT,
+            usage,
+        );
+
+        let params = AtmosphereParams::default();
+        let params_buffer = context
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("atmosphere params"),
+                contents: bytemuck::bytes_of(&params),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let celestial = CelestialParams::new(&CelestialSettings::default());
+        let celestial_buffer =
             context
                 .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: Some("atmosphere"),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: SKYBOX_FORMAT,
-                            view_dimension: wgpu::TextureViewDimension::D2Array,
-                        },
-                        count: None,
-                    }],
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("celestial params"),
+                    contents: bytemuck::bytes_of(&celestial),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
 
-        let pipeline_layout =
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let params_entry = uniform_entry(9);
+
+        let sky_lights_entry = wgpu::BindGroupLayoutEntry {
+            binding: 11,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let skybox_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: SKYBOX_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+            },
+            count: None,
+        };
+
+        let volume_entry = wgpu::BindGroupLayoutEntry {
+            binding: 5,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: resources::FROXEL_VOLUME_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+            },
+            count: None,
+        };
+
+        let skybox_cube_entry = wgpu::BindGroupLayoutEntry {
+            binding: 8,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: FILTERABLE,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let transmittance = util::texture_layout_entry(2, FILTERABLE);
+        let multi_scattering = util::texture_layout_entry(4, FILTERABLE);
+
+        let passes = [
+            (
+                "transmittance lut",
+                "transmittance",
+                vec![
+                    util::storage_texture_layout_entry(1, LUT_FORMAT),
+                    params_entry,
+                ],
+            ),
+            (
+                "multi scattering lut",
+                "multi_scattering",
+                vec![
+                    transmittance,
+                    util::storage_texture_layout_entry(3, LUT_FORMAT),
+                    params_entry,
+                ],
+            ),
+            (
+                "sky",
+                "sky",
+                vec![
+                    skybox_entry,
+                    transmittance,
+                    multi_scattering,
+                    params_entry,
+                    uniform_entry(10),
+                    sky_lights_entry,
+                ],
+            ),
+            (
+                "aerial perspective",
+                "aerial_perspective",
+                vec![
+                    transmittance,
+                    multi_scattering,
+                    volume_entry,
+                    skybox_cube_entry,
+                    params_entry,
+                    ParamsBuffer::<VolumeParams>::layout_entry(
+                        12,
+                        wgpu::ShaderStages::COMPUTE,
+                        false,
+                    ),
+                ],
+            ),
+        ];
+
+        let volume_params = ParamsBuffer::new(context, "volume params", 1);
+
+        let resources = pass_resources(
+            &transmittance_lut,
+            &multi_scattering_lut,
+            &params_buffer,
+            &celestial_buffer,
+            &volume_params,
+            skybox,
+            froxel_volume,
+        );
+
+        let passes: Vec<_> = passes
+            .into_iter()
+            .zip(resources)
+            .map(|((label, entry_point, entries), resources)| {
+                let bind_group_layout =
+                    context
+                        .device
+                        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                            label: Some(label),
+                            entries: &entries,
+                        });
+
+                // Only the aerial perspective samples the shadow cascades.
+                let mut bind_group_layouts =
+                    vec![ConstState::bind_group_layout(context), &bind_group_layout];
+
+                if entry_point == "aerial_perspective" {
+                    bind_group_layouts.push(ShadowCascades::bind_group_layout(context));
+                }
+
+                let pipeline_layout =
+                    context
+                        .device
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            label: Some(label),
+                            bind_group_layouts: &bind_group_layouts,
+                            push_constant_ranges: &[],
+                        });
+
+                let pipeline =
+                    context
+                        .device
+                        .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                            label: Some(label),
+                            layout: Some(&pipeline_layout),
+                            module: &shader,
+                            entry_point,
+                        });
+
+                let bind_group = create_bind_group(context, &bind_group_layout, resources);
+
+                Pass {
+                    pipeline,
+                    bind_group_layout,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        let mut passes = passes.into_iter();
+        let mut next_pass = || passes.next().unwrap();
+
+        Self {
+            transmittance: next_pass(),
+            multi_scattering: next_pass(),
+            sky: next_pass(),
+            aerial_perspective: next_pass(),
+            transmittance_lut,
+            multi_scattering_lut,
+            params_buffer,
+            params,
+            celestial_buffer,
+            celestial,
+            volume_params,
+            skybox_size: skybox.size,
+            needs_bake: true,
+        }
+    }
+
+    /// Apply `params`, which bakes the LUTs and skybox again if they have changed.
+    pub fn set_params(&mut self, context: &Context, params: AtmosphereParams) {
+        let params = params.sanitized();
+
+        if params != self.params {
             context
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("atmosphere"),
-                    bind_group_layouts: &[
-                        ConstState::bind_group_layout(context),
-                        &bind_group_layout,
-                    ],
-                    push_constant_ranges: &[],
-                });
+                .queue
+                .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
 
-        let pipeline = context
-            .device
-            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("atmosphere"),
-                layout: Some(&pipeline_layout),
-                module: &shader,
-                entry_point: "main",
-            });
+            self.params = params;
+            self.needs_bake = true;
+        }
+    }
 
-        let bind_group = context
-            .device
-            .create_bind_group(&wgpu::BindGroupDescriptor {
+    /// Apply the celestial settings, which bakes the skybox again if they have changed.
+    pub fn set_celestial(&mut self, context: &Context, settings: &CelestialSettings) {
+        let celestial = CelestialParams::new(settings);
+
+        if celestial != self.celestial {
+            context
+                .queue
+                .write_buffer(&self.celestial_buffer, 0, bytemuck::bytes_of(&celestial));
+
+            self.celestial = celestial;
+            self.needs_bake = true;
+        }
+    }
+
+    /// Bind a new skybox, which is baked on the next frame.
+    pub fn set_skybox(&mut self, context: &Context, skybox: &Skybox, froxel_volume: &FroxelVolume) {
+        let resources = pass_resources(
+            &self.transmittance_lut,
+            &self.multi_scattering_lut,
+            &self.params_buffer,
+            &self.celestial_buffer,
+            &self.volume_params,
+            skybox,
+            froxel_volume,
+        );
+
+        let passes = [
+            &mut self.transmittance,
+            &mut self.multi_scattering,
+            &mut self.sky,
+            &mut self.aerial_perspective,
+        ];
+
+        for (pass, resources) in passes.into_iter().zip(resources) {
+            pass.bind_group = create_bind_group(context, &pass.bind_group_layout, resources);
+        }
+
+        self.skybox_size = skybox.size;
+        self.needs_bake = true;
+    }
+
+    /// True if the parameters, celestial settings or skybox have changed since the last bake.
+    pub fn needs_bake(&self) -> bool {
+        self.needs_bake
+    }
+
+    /// Compute the LUTs and bake the skybox. Only needs to run when the sun or the atmosphere
+    /// changes.
+    pub fn record(&mut self, const_state: &ConstState, encoder: &mut wgpu::CommandEncoder) {
+        let passes = [
+            (&self.transmittance, TRANSMITTANCE_LUT_SIZE),
+            (&self.multi_scattering, MULTI_SCATTERING_LUT_SIZE),
+            (&self.sky, self.skybox_size),
+        ];
+
+        for (pass, size) in passes {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("atmosphere"),
-                layout: &bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&skybox.array_view),
-                }],
             });
 
-        Self {
-            pipeline,
-            bind_group,
+            compute_pass.set_pipeline(&pass.pipeline);
+            compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
+            compute_pass.set_bind_group(1, &pass.bind_group, &[]);
+
+            let x = util::div_ceil(size.width, 8);
+            let y = util::div_ceil(size.height, 8);
+
+            compute_pass.dispatch_workgroups(x, y, size.depth_or_array_layers);
         }
+
+        self.needs_bake = false;
     }
 
-    pub fn record(&self, const_state: &ConstState, encoder: &mut wgpu::CommandEncoder) {
+    /// Compute the aerial perspective and fog of the froxel volume. Must be recorded after the
+    /// shadow cascades. Returns the distance covered by the volume, or `None` if it was skipped
+    /// because both are disabled.
+    pub fn record_volume(
+        &self,
+        context: &Context,
+        camera: &Camera,
+        settings: &AtmosphereSettings,
+        const_state: &ConstState,
+        shadow_cascades: &ShadowCascades,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<f32> {
+        let view = Mat4::look_to_rh(Vec3::ZERO, camera.front, camera.up());
+        let params = VolumeParams::new(settings, (camera.proj() * view).inverse())?;
+        self.volume_params.write(context, 0, &params);
+
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("atmosphere"),
+            label: Some("aerial perspective"),
         });
 
-        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_pipeline(&self.aerial_perspective.pipeline);
         compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
-        compute_pass.set_bind_group(1, &self.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.aerial_perspective.bind_group, &[]);
+        compute_pass.set_bind_group(2, &shadow_cascades.bind_group, &[]);
+
+        let x = util::div_ceil(resources::FROXEL_VOLUME_SIZE.width, 8);
+        let y = util::div_ceil(resources::FROXEL_VOLUME_SIZE.height, 8);
+
+        compute_pass.dispatch_workgroups(x, y, 1);
+
+        Some(params.volume_distance)
+    }
+}
+
+/// The bindings and resources of each pass.
+fn pass_resources<'a>(
+    transmittance_lut: &'a RenderTarget,
+    multi_scattering_lut: &'a RenderTarget,
+    params_buffer: &'a wgpu::Buffer,
+    celestial_buffer: &'a wgpu::Buffer,
+    volume_params: &'a ParamsBuffer<VolumeParams>,
+    skybox: &'a Skybox,
+    froxel_volume: &'a FroxelVolume,
+) -> [Vec<(u32, wgpu::BindingResource<'a>)>; 4] {
+    use wgpu::BindingResource::TextureView;
+
+    let transmittance = || (2, TextureView(&transmittance_lut.view));
+    let multi_scattering = || (4, TextureView(&multi_scattering_lut.view));
+    let params = || (9, params_buffer.as_entire_binding());
+
+    [
+        vec![(1, TextureView(&transmittance_lut.view)), params()],
+        vec![
+            transmittance(),
+            (3, TextureView(&multi_scattering_lut.view)),
+            params(),
+        ],
+        vec![
+            (0, TextureView(&skybox.array_view)),
+            transmittance(),
+            multi_scattering(),
+            params(),
+            (10, celestial_buffer.as_entire_binding()),
+            (11, skybox.lights.as_entire_binding()),
+        ],
+        vec![
+            transmittance(),
+            multi_scattering(),
+            (5, TextureView(&froxel_volume.view)),
+            (8, TextureView(&skybox.cube_view)),
+            params(),
+            (12, volume_params.binding()),
+        ],
+    ]
+}
+
+fn create_bind_group(
+    context: &Context,
+    layout: &wgpu::BindGroupLayout,
+    resources: Vec<(u32, wgpu::BindingResource)>,
+) -> wgpu::BindGroup {
+    let entries: Vec<_> = resources
+        .into_iter()
+        .map(|(binding, resource)| wgpu::BindGroupEntry { binding, resource })
+        .collect();
+
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("atmosphere"),
+            entries: &entries,
+            layout,
+        })
+}
+
+#[test]
+fn volume_is_skipped_when_disabled() {
+    let settings = AtmosphereSettings {
+        aerial_perspective: false,
+        ..Default::default()
+    };
+
+    assert!(VolumeParams::new(&settings, Mat4::IDENTITY).is_none());
+
+    let fog = HeightFog {
+        sun_shafts: false,
+        ..Default::default()
+    };
+
+    let params = VolumeParams::new(
+        &AtmosphereSettings {
+            height_fog: Some(fog),
+            ..settings
+        },
+        Mat4::IDENTITY,
+    )
+    .unwrap();
+
+    assert_eq!(params.flags, VolumeFlags::HEIGHT_FOG);
+}
 
-        let x = resources::SKYBOX_SIZE.width / 8;
-        let y = resources::SKYBOX_SIZE.height / 8;
-        let z = resources::SKYBOX_SIZE.depth_or_array_layers;
+#[test]
+fn presets_are_valid() {
+    // The size of the struct in WGSL, which rounds up to the alignment of `vec3f`.
+    assert_eq!(std::mem::size_of::<AtmosphereParams>(), 80);
 
-        compute_pass.dispatch_workgroups(x, y, z);
+    let mut model = AtmosphereModel::ClearEarth;
+
+    for _ in 0..3 {
+        let params = model.params();
+        assert_eq!(params, params.sanitized(), "{model:?}");
+
+        model = model.next();
     }
+
+    assert_eq!(model, AtmosphereModel::ClearEarth);
+
+    let params = AtmosphereParams {
+        mie_g: 1.0,
+        sample_count: 0,
+        ..Default::default()
+    };
+
+    assert!(params.sanitized().mie_g < 1.0);
+    assert_eq!(params.sanitized().sample_count, 2);
+}
+
+#[test]
+fn celestial_params() {
+    let params = CelestialParams::new(&CelestialSettings {
+        moon: Some(Moon {
+            direction: Vec3::new(0.0, 2.0, 0.0),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+
+    assert_eq!(params.moon_direction, Vec3::Y);
+    assert!((params.sun_angular_radius - 0.00465).abs() < 1e-5);
+    assert!(params.moon_angular_radius > 0.0);
+
+    let params = CelestialParams::new(&CelestialSettings {
+        moon: None,
+        ..Default::default()
+    });
+
+    assert_eq!(params.moon_angular_radius, 0.0);
+    assert_eq!(std::mem::size_of::<CelestialParams>(), 32);
 }
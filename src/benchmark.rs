@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+
+use crate::bookmarks::{self, Keyframe};
+use crate::camera::Camera;
+
+/// Options of the benchmark mode, which is enabled with `--benchmark`:
+///
+/// ```text
+/// rendinator --benchmark [scene] [--warmup N] [--frames N] [--output PATH] [--fallback-adapter]
+///     [--headless]
+/// ```
+#[derive(Clone, Debug)]
+pub struct BenchmarkOptions {
+    pub scene: Option<PathBuf>,
+    /// Frames rendered before recording.
+    pub warmup_frames: u32,
+    /// Frames recorded.
+    pub frames: u32,
+    /// Where the report is written. Printed to stdout if `None`.
+    pub output: Option<PathBuf>,
+    /// Use the software adapter. The benchmark fails to start if it lacks features the renderer
+    /// requires.
+    pub force_fallback_adapter: bool,
+    /// Render to a texture of `HEADLESS_SIZE` instead of a window, for machines without a
+    /// display.
+    pub headless: bool,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            warmup_frames: 60,
+            frames: 600,
+            output: None,
+            force_fallback_adapter: false,
+            headless: false,
+        }
+    }
+}
+
+impl BenchmarkOptions {
+    /// Parse the command line arguments, excluding the program name. Returns `None` if
+    /// `--benchmark` isn't passed.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>> {
+        if !args.iter().any(|arg| arg == "--benchmark") {
+            return Ok(None);
+        }
+
+        let mut options = Self::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| eyre::eyre!("{name} is missing a value"))
+            };
+
+            match arg.as_str() {
+                "--benchmark" => (),
+                "--fallback-adapter" => options.force_fallback_adapter = true,
+                "--headless" => options.headless = true,
+                "--warmup" => {
+                    options.warmup_frames = value("--warmup")?
+                        .parse()
+                        .wrap_err("invalid warmup frame count")?;
+                }
+                "--frames" => {
+                    options.frames = value("--frames")?.parse().wrap_err("invalid frame count")?;
+                }
+                "--output" => options.output = Some(PathBuf::from(value("--output")?)),
+                arg if arg.starts_with("--") => return Err(eyre::eyre!("unknown option {arg}")),
+                scene => options.scene = Some(PathBuf::from(scene)),
+            }
+        }
+
+        Ok(Some(options))
+    }
+}
+
+/// The frame size of headless benchmarks.
+pub const HEADLESS_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+    width: 1280,
+    height: 720,
+    depth_or_array_layers: 1,
+};
+
+/// Every frame advances the camera path by this many seconds, independent of the frame time.
+const DELTA_TIME: Duration = Duration::from_micros(16_667);
+
+/// Plays a camera path with a fixed time step and records frame times.
+pub struct Benchmark {
+    options: BenchmarkOptions,
+    path: Vec<Keyframe>,
+    frame: u32,
+    cpu_frame_times: Vec<f64>,
+    gpu_wait_times: Vec<f64>,
+    gpu_phase_times: BTreeMap<&'static str, Vec<f64>>,
+}
+
+impl Benchmark {
+    pub fn new(options: BenchmarkOptions) -> Self {
+        Self {
+            options,
+            path: Vec::new(),
+            frame: 0,
+            cpu_frame_times: Vec::new(),
+            gpu_wait_times: Vec::new(),
+            gpu_phase_times: BTreeMap::new(),
+        }
+    }
+
+    pub fn delta_time(&self) -> Duration {
+        DELTA_TIME
+    }
+
+    /// Start the benchmark once the scene has loaded. Fails if the scene has no camera path.
+    pub fn start(&mut self, path: &[Keyframe]) -> Result<()> {
+        if path.is_empty() {
+            return Err(eyre::eyre!(
+                "the scene has no camera path, add keyframes to its bookmarks"
+            ));
+        }
+
+        self.path = path.to_vec();
+        self.frame = 0;
+
+        Ok(())
+    }
+
+    pub fn is_started(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    /// Move the camera along the path. The path loops if the benchmark is longer than the path.
+    pub fn update_camera(&self, camera: &mut Camera) {
+        let Some(last) = self.path.last() else {
+            return;
+        };
+
+        let time = self.frame as f32 * DELTA_TIME.as_secs_f32();
+        let time = if last.time > 0.0 {
+            time % last.time
+        } else {
+            0.0
+        };
+
+        if let Some(sample) = bookmarks::sample_path(&self.path, time) {
+            camera.set_view(&sample);
+        }
+    }
+
+    /// Record the timings of a frame. Returns true when every frame has been recorded.
+    pub fn record(
+        &mut self,
+        cpu_frame_time: Duration,
+        gpu_wait_time: Duration,
+        gpu_phase_times: &[(&'static str, f64)],
+    ) -> bool {
+        if self.frame >= self.options.warmup_frames {
+            self.cpu_frame_times
+                .push(cpu_frame_time.as_secs_f64() * 1e3);
+            self.gpu_wait_times.push(gpu_wait_time.as_secs_f64() * 1e3);
+
+            for (phase, time) in gpu_phase_times {
+                self.gpu_phase_times.entry(phase).or_default().push(*time);
+            }
+        }
+
+        self.frame += 1;
+        self.frame >= self.options.warmup_frames + self.options.frames
+    }
+
+    /// Write the report as JSON to the output path or stdout.
+    pub fn write_report(&self, adapter: &wgpu::AdapterInfo) -> Result<()> {
+        let report = Report {
+            scene: self.options.scene.clone(),
+            adapter: format!("{} ({:?})", adapter.name, adapter.backend),
+            warmup_frames: self.options.warmup_frames,
+            frames: self.options.frames,
+            delta_time_ms: DELTA_TIME.as_secs_f64() * 1e3,
+            cpu_frame_time_ms: Stats::new(&self.cpu_frame_times),
+            gpu_wait_time_ms: Stats::new(&self.gpu_wait_times),
+            gpu_phase_time_ms: self
+                .gpu_phase_times
+                .iter()
+                .map(|(phase, times)| (*phase, Stats::new(times)))
+                .collect(),
+        };
+
+        let json = serde_json::to_string_pretty(&report)?;
+
+        match &self.options.output {
+            Some(path) => std::fs::write(path, json)
+                .wrap_err_with(|| format!("failed to write benchmark report to {path:?}")),
+            None => {
+                println!("{json}");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    scene: Option<PathBuf>,
+    adapter: String,
+    warmup_frames: u32,
+    frames: u32,
+    delta_time_ms: f64,
+    /// The time spent recording and submitting a frame.
+    cpu_frame_time_ms: Stats,
+    /// The time the CPU waits for the GPU to finish a frame after submitting it. This is the
+    /// GPU time the CPU can't hide, not the latency until the frame is presented.
+    gpu_wait_time_ms: Stats,
+    /// Empty if the adapter doesn't support timestamp queries.
+    gpu_phase_time_ms: BTreeMap<&'static str, Stats>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Stats {
+    min: f64,
+    avg: f64,
+    p95: f64,
+    p99: f64,
+}
+
+impl Stats {
+    fn new(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        // Nearest-rank percentile.
+        let percentile = |p: f64| {
+            let rank = (p * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        Self {
+            min: sorted[0],
+            avg: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+#[test]
+fn stats_percentiles() {
+    let samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+
+    assert_eq!(
+        Stats::new(&samples),
+        Stats {
+            min: 1.0,
+            avg: 50.5,
+            p95: 95.0,
+            p99: 99.0,
+        }
+    );
+}
@@ -1,10 +1,13 @@
-use std::{borrow::Cow, mem};
+use std::path::{Path, PathBuf};
+use std::{borrow::Cow, iter};
 
 use bytemuck::NoUninit;
+use eyre::WrapErr;
+use serde::Deserialize;
 
 use crate::{
     context::Context,
-    resources::{self, ConstState, RenderState},
+    resources::{self, ConstState, ParamsBuffer, RenderState},
 };
 
 bitflags::bitflags! {
@@ -12,23 +15,169 @@ bitflags::bitflags! {
     #[derive(Debug, Clone, Copy, NoUninit)]
     struct BloomFlags: u32 {
         const IS_INITIAL = 1 << 0;
+        const LENS_FLARE = 1 << 1;
     }
 }
 
-pub struct Config {
+/// The bloom mip the lens flare is built from, a quarter of the surface size.
+const FLARE_MIP: usize = 2;
+
+/// The parameters of the initial downsample come first, followed by one slot per mip. There are
+/// fewer mips than bits in the surface size.
+const PARAMS_SLOT_COUNT: usize = u32::BITS as usize;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BloomSettings {
+    pub mode: BloomMode,
+    /// How much of the low frequencies are boosted, as a fraction of what's left after
+    /// `intensity`.
     pub lf_freq: f64,
+    /// The curve of the low frequency boost. Values closer to one boost fewer mips.
     pub lf_curve: f64,
+    /// The fraction of the mips that aren't cut off by the high pass filter.
     pub hp_freq: f64,
+    /// The amount of bloom. In energy conserving mode this is the fraction of the image replaced
+    /// by bloom, in threshold mode the factor the bloom is added with.
     pub intensity: f64,
+    /// A texture of smudges and dust on the lens, which is lit up by the bloom.
+    pub lens_dirt: Option<PathBuf>,
+    /// How much the lens dirt brightens the bloom.
+    pub lens_dirt_intensity: f32,
+    pub lens_flare: LensFlare,
 }
 
-impl Default for Config {
+impl Default for BloomSettings {
     fn default() -> Self {
         Self {
+            mode: BloomMode::EnergyConserving,
             intensity: 0.15,
             lf_freq: 0.7,
             lf_curve: 0.95,
             hp_freq: 1.0,
+            lens_dirt: None,
+            lens_dirt_intensity: 1.0,
+            lens_flare: LensFlare::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum BloomMode {
+    /// Blend the whole image with its blurred mips. Physically plausible, but everything blooms
+    /// a little.
+    EnergyConserving,
+    /// Add only the parts of the image brighter than `threshold` in exposed luminance, with a
+    /// smooth transition of width `knee` around it.
+    Threshold { threshold: f32, knee: f32 },
+}
+
+/// Ghosts, a halo and an anamorphic streak built from the bloom mips. Each part is disabled when
+/// its intensity is zero, which is the default.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LensFlare {
+    /// The exposed luminance above which pixels cause flares.
+    pub threshold: f32,
+    pub ghost_count: u32,
+    /// The distance between ghosts as a fraction of the distance to the center of the screen.
+    pub ghost_spacing: f32,
+    pub ghost_intensity: f32,
+    /// The radius of the halo as a fraction of the screen height.
+    pub halo_radius: f32,
+    pub halo_intensity: f32,
+    pub streak_intensity: f32,
+    /// The length of the horizontal streak as a fraction of the screen width.
+    pub streak_length: f32,
+}
+
+impl Default for LensFlare {
+    fn default() -> Self {
+        Self {
+            threshold: 2.0,
+            ghost_count: 4,
+            ghost_spacing: 0.3,
+            ghost_intensity: 0.0,
+            halo_radius: 0.6,
+            halo_intensity: 0.0,
+            streak_intensity: 0.0,
+            streak_length: 0.5,
+        }
+    }
+}
+
+impl LensFlare {
+    fn is_enabled(&self) -> bool {
+        self.ghost_intensity > 0.0 || self.halo_intensity > 0.0 || self.streak_intensity > 0.0
+    }
+}
+
+/// Must match `Params` in `bloom.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, NoUninit)]
+struct BloomParams {
+    level: u32,
+    flags: BloomFlags,
+    threshold: f32,
+    knee: f32,
+    lens_dirt_intensity: f32,
+    flare_threshold: f32,
+    ghost_count: u32,
+    ghost_spacing: f32,
+    ghost_intensity: f32,
+    halo_radius: f32,
+    halo_intensity: f32,
+    streak_intensity: f32,
+    streak_length: f32,
+    padding: [u32; 3],
+}
+
+impl BloomParams {
+    /// The thresholds are in exposed luminance, so they are divided by `exposure` as bloom runs
+    /// before the exposure is applied.
+    fn new(settings: &BloomSettings, exposure: f32) -> Self {
+        let exposure = if exposure > 0.0 { exposure } else { 1.0 };
+
+        let (threshold, knee) = match settings.mode {
+            BloomMode::EnergyConserving => (0.0, 0.0),
+            BloomMode::Threshold { threshold, knee } => {
+                (threshold.max(0.0) / exposure, knee.max(0.0) / exposure)
+            }
+        };
+
+        let flare = &settings.lens_flare;
+        let flags = if flare.is_enabled() {
+            BloomFlags::LENS_FLARE
+        } else {
+            BloomFlags::empty()
+        };
+
+        Self {
+            level: 0,
+            flags,
+            threshold,
+            knee,
+            lens_dirt_intensity: settings.lens_dirt_intensity.max(0.0),
+            flare_threshold: flare.threshold.max(0.0) / exposure,
+            ghost_count: flare.ghost_count.min(8),
+            ghost_spacing: flare.ghost_spacing,
+            ghost_intensity: flare.ghost_intensity.max(0.0),
+            halo_radius: flare.halo_radius,
+            halo_intensity: flare.halo_intensity.max(0.0),
+            streak_intensity: flare.streak_intensity.max(0.0),
+            streak_length: flare.streak_length.clamp(0.0, 1.0),
+            padding: [0; 3],
+        }
+    }
+
+    fn with_level(self, level: u32) -> Self {
+        Self { level, ..self }
+    }
+
+    fn with_flags(self, flags: BloomFlags) -> Self {
+        Self {
+            flags: self.flags | flags,
+            ..self
         }
     }
 }
@@ -36,10 +185,18 @@ impl Default for Config {
 pub struct BloomPhase {
     upsample: wgpu::RenderPipeline,
     downsample: wgpu::RenderPipeline,
+    flare: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+    composite_additive: wgpu::RenderPipeline,
     bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
     initial_downsample: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+    params: ParamsBuffer<BloomParams>,
     bloom_mips: Vec<Mip>,
-    config: Config,
+    flare_view: wgpu::TextureView,
+    lens_dirt: wgpu::TextureView,
+    settings: BloomSettings,
 }
 
 impl BloomPhase {
@@ -62,16 +219,22 @@ impl BloomPhase {
                 .device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                     label: Some("bloom"),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    }],
+                    entries: &[
+                        texture_layout_entry(0),
+                        ParamsBuffer::<BloomParams>::layout_entry(
+                            1,
+                            wgpu::ShaderStages::FRAGMENT,
+                            true,
+                        ),
+                    ],
+                });
+
+        let composite_bind_group_layout =
+            context
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("bloom composite"),
+                    entries: &[texture_layout_entry(0), texture_layout_entry(1)],
                 });
 
         let pipeline_layout =
@@ -83,98 +246,160 @@ impl BloomPhase {
                         ConstState::bind_group_layout(context),
                         &bind_group_layout,
                     ],
-                    push_constant_ranges: &[wgpu::PushConstantRange {
-                        stages: wgpu::ShaderStages::FRAGMENT,
-                        range: 0..mem::size_of::<u32>() as u32,
-                    }],
+                    push_constant_ranges: &[],
                 });
 
-        let downsample = context
-            .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("bloom downsample"),
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vertex",
-                    buffers: &[],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "downsample",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: resources::COLOR_BUFFER_FORMAT,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    cull_mode: Some(wgpu::Face::Back),
-                    ..Default::default()
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-            });
+        let composite_pipeline_layout =
+            context
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("bloom composite"),
+                    bind_group_layouts: &[
+                        ConstState::bind_group_layout(context),
+                        &bind_group_layout,
+                        &composite_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
 
-        let upsample = context
-            .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("bloom upsample"),
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vertex",
-                    buffers: &[],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "upsample",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: resources::COLOR_BUFFER_FORMAT,
-                        blend: Some(wgpu::BlendState {
-                            color: wgpu::BlendComponent {
-                                src_factor: wgpu::BlendFactor::Constant,
-                                dst_factor: wgpu::BlendFactor::OneMinusConstant,
-                                operation: wgpu::BlendOperation::Add,
-                            },
-                            alpha: wgpu::BlendComponent {
-                                src_factor: wgpu::BlendFactor::Zero,
-                                dst_factor: wgpu::BlendFactor::One,
-                                operation: wgpu::BlendOperation::Add,
-                            },
-                        }),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    cull_mode: Some(wgpu::Face::Back),
-                    ..Default::default()
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
-            });
+        let create_pipeline = |label: &str,
+                               layout: &wgpu::PipelineLayout,
+                               entry_point: &str,
+                               blend: Option<wgpu::BlendState>| {
+            context
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: "vertex",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point,
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: resources::COLOR_BUFFER_FORMAT,
+                            blend,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        cull_mode: Some(wgpu::Face::Back),
+                        ..Default::default()
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                })
+        };
+
+        // Blend the output and the bloom by the blend constant.
+        let mix = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Constant,
+                dst_factor: wgpu::BlendFactor::OneMinusConstant,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+
+        // Add the bloom scaled by the blend constant to the output.
+        let add = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Constant,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            ..mix
+        };
+
+        let downsample = create_pipeline("bloom downsample", &pipeline_layout, "downsample", None);
+        let upsample = create_pipeline("bloom upsample", &pipeline_layout, "upsample", Some(mix));
+        let flare = create_pipeline("lens flare", &pipeline_layout, "flare", None);
+        let composite = create_pipeline(
+            "bloom composite",
+            &composite_pipeline_layout,
+            "composite",
+            Some(mix),
+        );
+        let composite_additive = create_pipeline(
+            "additive bloom composite",
+            &composite_pipeline_layout,
+            "composite",
+            Some(add),
+        );
 
-        let (initial_downsample, bloom_mips) =
-            create_mips(context, render_state, &bind_group_layout);
+        let params = ParamsBuffer::new(context, "bloom params", PARAMS_SLOT_COUNT);
+        let (initial_downsample, bloom_mips, flare_view) =
+            create_mips(context, render_state, &params, &bind_group_layout);
 
-        let config = Config::default();
+        let lens_dirt = create_lens_dirt_texture(context, 1, 1, &[0; 4]);
+        let composite_bind_group = create_composite_bind_group(
+            context,
+            &composite_bind_group_layout,
+            &lens_dirt,
+            &flare_view,
+        );
 
         Self {
             bloom_mips,
             initial_downsample,
+            composite_bind_group,
+            params,
             bind_group_layout,
+            composite_bind_group_layout,
             upsample,
             downsample,
-            config,
+            flare,
+            composite,
+            composite_additive,
+            flare_view,
+            lens_dirt,
+            settings: BloomSettings::default(),
+        }
+    }
+
+    /// Apply `settings`, loading the lens dirt texture if it has changed. Nothing changes if it
+    /// fails to load.
+    pub fn set_settings(&mut self, context: &Context, settings: BloomSettings) -> eyre::Result<()> {
+        if settings.lens_dirt != self.settings.lens_dirt {
+            self.lens_dirt = match &settings.lens_dirt {
+                Some(path) => load_lens_dirt(context, path)?,
+                None => create_lens_dirt_texture(context, 1, 1, &[0; 4]),
+            };
+
+            self.composite_bind_group = create_composite_bind_group(
+                context,
+                &self.composite_bind_group_layout,
+                &self.lens_dirt,
+                &self.flare_view,
+            );
         }
+
+        self.settings = settings;
+
+        Ok(())
     }
 
     fn max_mip(&self) -> u32 {
         self.bloom_mips.len() as u32 - 1
     }
 
+    fn flare_mip(&self) -> &Mip {
+        &self.bloom_mips[FLARE_MIP.min(self.bloom_mips.len() - 1)]
+    }
+
+    /// The dynamic offset of the parameters of the passes reading `level`.
+    fn params_offset(&self, level: u32) -> wgpu::DynamicOffset {
+        (self.params.stride() * (level as u64 + 1)) as wgpu::DynamicOffset
+    }
+
     fn initial_downsample(&self, const_state: &ConstState, encoder: &mut wgpu::CommandEncoder) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("initial bloom downsample"),
@@ -188,9 +413,7 @@ impl BloomPhase {
 
         render_pass.set_pipeline(&self.downsample);
         render_pass.set_bind_group(0, &const_state.bind_group, &[]);
-        render_pass.set_bind_group(1, &self.initial_downsample, &[]);
-
-        render_pass.set_push_constants(wgpu::ShaderStages::FRAGMENT, 0, &0u32.to_ne_bytes());
+        render_pass.set_bind_group(1, &self.initial_downsample, &[0]);
 
         render_pass.draw(0..3, 0..1);
     }
@@ -212,17 +435,35 @@ impl BloomPhase {
 
             render_pass.set_pipeline(&self.downsample);
             render_pass.set_bind_group(0, &const_state.bind_group, &[]);
-            render_pass.set_bind_group(1, &input.bind_group, &[]);
-            render_pass.set_push_constants(
-                wgpu::ShaderStages::FRAGMENT,
-                0,
-                &input.level.to_ne_bytes(),
-            );
+            let offset = self.params_offset(input.level);
+            render_pass.set_bind_group(1, &input.bind_group, &[offset]);
 
             render_pass.draw(0..3, 0..1);
         }
     }
 
+    /// Render the ghosts, halo and streak of the lens flare from the downsampled mips.
+    fn flare(&self, const_state: &ConstState, encoder: &mut wgpu::CommandEncoder) {
+        let input = self.flare_mip();
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("lens flare"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.flare_view,
+                resolve_target: None,
+                ops: wgpu::Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.flare);
+        render_pass.set_bind_group(0, &const_state.bind_group, &[]);
+        let offset = self.params_offset(input.level);
+        render_pass.set_bind_group(1, &input.bind_group, &[offset]);
+
+        render_pass.draw(0..3, 0..1);
+    }
+
     fn upsample(&self, const_state: &ConstState, encoder: &mut wgpu::CommandEncoder) {
         for window in self.bloom_mips.windows(2).rev() {
             let input = &window[1];
@@ -243,20 +484,17 @@ impl BloomPhase {
 
             render_pass.set_pipeline(&self.upsample);
             render_pass.set_bind_group(0, &const_state.bind_group, &[]);
-            render_pass.set_bind_group(1, &input.bind_group, &[]);
-            render_pass.set_push_constants(
-                wgpu::ShaderStages::FRAGMENT,
-                0,
-                &input.level.to_ne_bytes(),
-            );
+            let offset = self.params_offset(input.level);
+            render_pass.set_bind_group(1, &input.bind_group, &[offset]);
 
-            let blend_constant = blend_constant(input.level, self.max_mip(), &self.config);
+            let blend_constant = blend_constant(input.level, self.max_mip(), &self.settings);
             render_pass.set_blend_constant(blend_constant);
 
             render_pass.draw(0..3, 0..1);
         }
     }
 
+    /// Upsample onto the post buffer together with the lens flare and dirt.
     fn final_upsample(
         &self,
         const_state: &ConstState,
@@ -278,32 +516,65 @@ impl BloomPhase {
             depth_stencil_attachment: None,
         });
 
-        render_pass.set_pipeline(&self.upsample);
+        let pipeline = match self.settings.mode {
+            BloomMode::EnergyConserving => &self.composite,
+            BloomMode::Threshold { .. } => &self.composite_additive,
+        };
+
+        render_pass.set_pipeline(pipeline);
         render_pass.set_bind_group(0, &const_state.bind_group, &[]);
-        render_pass.set_bind_group(1, &input.bind_group, &[]);
-        render_pass.set_push_constants(wgpu::ShaderStages::FRAGMENT, 0, &0u32.to_ne_bytes());
+        render_pass.set_bind_group(1, &input.bind_group, &[self.params_offset(0)]);
+        render_pass.set_bind_group(2, &self.composite_bind_group, &[]);
 
-        let blend_constant = blend_constant(0, self.max_mip(), &self.config);
+        let blend_constant = blend_constant(0, self.max_mip(), &self.settings);
         render_pass.set_blend_constant(blend_constant);
 
         render_pass.draw(0..3, 0..1);
     }
 
+    /// Record bloom. `exposure` is the exposure of the last frame that has been read back, which
+    /// the thresholds are relative to.
     pub fn record(
         &self,
+        context: &Context,
+        exposure: f32,
         const_state: &ConstState,
         render_state: &RenderState,
         encoder: &mut wgpu::CommandEncoder,
     ) {
+        let params = BloomParams::new(&self.settings, exposure);
+
+        let initial = params.with_level(0).with_flags(BloomFlags::IS_INITIAL);
+        let levels = self
+            .bloom_mips
+            .iter()
+            .map(|mip| params.with_level(mip.level));
+
+        for (slot, params) in iter::once(initial).chain(levels).enumerate() {
+            self.params.write(context, slot, &params);
+        }
+
         self.initial_downsample(const_state, encoder);
         self.downsample(const_state, encoder);
+
+        if params.flags.contains(BloomFlags::LENS_FLARE) {
+            self.flare(const_state, encoder);
+        }
+
         self.upsample(const_state, encoder);
         self.final_upsample(const_state, render_state, encoder);
     }
 
     pub fn resize_surface(&mut self, context: &Context, render_state: &RenderState) {
-        (self.initial_downsample, self.bloom_mips) =
-            create_mips(context, render_state, &self.bind_group_layout);
+        (self.initial_downsample, self.bloom_mips, self.flare_view) =
+            create_mips(context, render_state, &self.params, &self.bind_group_layout);
+
+        self.composite_bind_group = create_composite_bind_group(
+            context,
+            &self.composite_bind_group_layout,
+            &self.lens_dirt,
+            &self.flare_view,
+        );
     }
 }
 
@@ -313,43 +584,61 @@ struct Mip {
     level: u32,
 }
 
+fn texture_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// Create the bloom mips and the lens flare target, which has the size of the flare mip.
 fn create_mips(
     context: &Context,
     render_state: &RenderState,
+    params: &ParamsBuffer<BloomParams>,
     bind_group_layout: &wgpu::BindGroupLayout,
-) -> (wgpu::BindGroup, Vec<Mip>) {
+) -> (wgpu::BindGroup, Vec<Mip>, wgpu::TextureView) {
     let size = context.surface_size;
     let mip_level_count = size.width.min(size.height).ilog2().max(2) - 1;
 
-    let bloom_texture = context.device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("bloom"),
-        dimension: wgpu::TextureDimension::D2,
-        format: resources::COLOR_BUFFER_FORMAT,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-        mip_level_count,
-        sample_count: 1,
-        view_formats: &[],
-        size,
-    });
-
+    // One texture per mip, since a view of a single mip samples the first mip on GL.
     let mips: Vec<_> = (0..mip_level_count)
         .map(|level| {
-            let view = bloom_texture.create_view(&wgpu::TextureViewDescriptor {
+            let texture = context.device.create_texture(&wgpu::TextureDescriptor {
                 label: Some("bloom"),
-                base_mip_level: level,
-                mip_level_count: Some(1),
-                ..Default::default()
+                dimension: wgpu::TextureDimension::D2,
+                format: resources::COLOR_BUFFER_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                mip_level_count: 1,
+                sample_count: 1,
+                view_formats: &[],
+                size: size.mip_level_size(level, wgpu::TextureDimension::D2),
             });
 
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
             let bind_group = context
                 .device
                 .create_bind_group(&wgpu::BindGroupDescriptor {
                     label: Some("bloom"),
                     layout: bind_group_layout,
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&view),
-                    }],
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: params.binding(),
+                        },
+                    ],
                 });
 
             Mip {
@@ -365,25 +654,125 @@ fn create_mips(
         .create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("bloom"),
             layout: bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&render_state.post.view),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&render_state.post.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params.binding(),
+                },
+            ],
         });
 
-    (initial, mips)
+    let flare_level = (FLARE_MIP as u32).min(mip_level_count - 1);
+    let flare_texture = context.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("lens flare"),
+        dimension: wgpu::TextureDimension::D2,
+        format: resources::COLOR_BUFFER_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        mip_level_count: 1,
+        sample_count: 1,
+        view_formats: &[],
+        size: size.mip_level_size(flare_level, wgpu::TextureDimension::D2),
+    });
+
+    let flare_view = flare_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (initial, mips, flare_view)
 }
 
-fn blend_constant(mip: u32, max_mip: u32, config: &Config) -> wgpu::Color {
+fn create_composite_bind_group(
+    context: &Context,
+    layout: &wgpu::BindGroupLayout,
+    lens_dirt: &wgpu::TextureView,
+    flare: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bloom composite"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(lens_dirt),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(flare),
+                },
+            ],
+        })
+}
+
+fn load_lens_dirt(context: &Context, path: &Path) -> eyre::Result<wgpu::TextureView> {
+    let image = image::open(path)
+        .wrap_err_with(|| format!("failed to load lens dirt texture at {path:?}"))?
+        .to_rgba8();
+
+    Ok(create_lens_dirt_texture(
+        context,
+        image.width(),
+        image.height(),
+        &image,
+    ))
+}
+
+fn create_lens_dirt_texture(
+    context: &Context,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> wgpu::TextureView {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+
+    let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("lens dirt"),
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        mip_level_count: 1,
+        sample_count: 1,
+        view_formats: &[],
+        size,
+    });
+
+    context.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width * 4),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn blend_constant(mip: u32, max_mip: u32, settings: &BloomSettings) -> wgpu::Color {
     let mip = mip as f64;
     let max_mip = max_mip as f64;
 
     let mut lf_boost =
-        (1.0 - (1.0 - (mip / max_mip)).powf(1.0 / (1.0 - config.lf_curve))) * config.lf_freq;
-    lf_boost *= 1.0 - config.intensity;
+        (1.0 - (1.0 - (mip / max_mip)).powf(1.0 / (1.0 - settings.lf_curve))) * settings.lf_freq;
+    lf_boost *= 1.0 - settings.intensity;
 
-    let high_pass_lq = 1.0 - (((mip / max_mip) - config.hp_freq) / config.hp_freq).clamp(0.0, 1.0);
-    let factor = (config.intensity + lf_boost) * high_pass_lq;
+    let high_pass_lq =
+        1.0 - (((mip / max_mip) - settings.hp_freq) / settings.hp_freq).clamp(0.0, 1.0);
+    let factor = (settings.intensity + lf_boost) * high_pass_lq;
 
     wgpu::Color {
         r: factor,
@@ -392,3 +781,24 @@ fn blend_constant(mip: u32, max_mip: u32, config: &Config) -> wgpu::Color {
         a: 1.0,
     }
 }
+
+#[test]
+fn thresholds_are_relative_to_exposure() {
+    let settings = BloomSettings {
+        mode: BloomMode::Threshold {
+            threshold: 1.0,
+            knee: 0.5,
+        },
+        ..Default::default()
+    };
+
+    let params = BloomParams::new(&settings, 0.25);
+    assert_eq!((params.threshold, params.knee), (4.0, 2.0));
+    assert_eq!(params.flare_threshold, 8.0);
+    assert!(!params.flags.contains(BloomFlags::LENS_FLARE));
+
+    // The exposure hasn't been read back yet.
+    let params = BloomParams::new(&BloomSettings::default(), 0.0);
+    assert_eq!((params.threshold, params.knee), (0.0, 0.0));
+    assert_eq!(params.flare_threshold, 2.0);
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+/// Saved cameras of a scene, stored in RON next to the scene:
+///
+/// ```ron
+/// (
+///     cameras: {
+///         1: (pos: (0.0, 2.0, 0.0), front: (1.0, 0.0, 0.0), yaw: 0.0, pitch: 0.0, ...),
+///     },
+///     path: [
+///         (time: 0.0, camera: (...)),
+///         (time: 4.0, camera: (...)),
+///     ],
+/// )
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Bookmarks {
+    /// Numbered bookmarks.
+    #[serde(default)]
+    pub cameras: BTreeMap<u32, Camera>,
+    /// Keyframes of the camera path, ordered by time.
+    #[serde(default)]
+    pub path: Vec<Keyframe>,
+}
+
+impl Bookmarks {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read bookmarks at {path:?}"))?;
+
+        ron::from_str(&source).wrap_err_with(|| format!("failed to parse bookmarks at {path:?}"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, source)
+            .wrap_err_with(|| format!("failed to write bookmarks to {path:?}"))
+    }
+
+    /// Append `camera` to the camera path `seconds` after the last keyframe.
+    pub fn add_keyframe(&mut self, camera: &Camera, seconds: f32) {
+        let time = self
+            .path
+            .last()
+            .map(|keyframe| keyframe.time + seconds)
+            .unwrap_or(0.0);
+
+        self.path.push(Keyframe {
+            camera: camera.clone(),
+            time,
+        });
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub camera: Camera,
+}
+
+/// Returns the camera at `time` along a Catmull-Rom spline through the keyframes, or `None` if
+/// `time` is past the last keyframe. The spline is parameterized by the keyframe times, so the
+/// camera doesn't speed up or slow down abruptly at keyframes which are unevenly spaced.
+pub fn sample_path(keyframes: &[Keyframe], time: f32) -> Option<Camera> {
+    let last = keyframes.last()?;
+    if time > last.time {
+        return None;
+    }
+
+    let segment = keyframes
+        .iter()
+        .rposition(|keyframe| keyframe.time <= time)
+        .unwrap_or(0);
+
+    let keyframe = |index: isize| {
+        let index = index.clamp(0, keyframes.len() as isize - 1);
+        &keyframes[index as usize]
+    };
+
+    let segment = segment as isize;
+    let [k0, k1, k2, k3] = [-1, 0, 1, 2].map(|offset| keyframe(segment + offset));
+
+    let times = [k0, k1, k2, k3].map(|keyframe| keyframe.time);
+    let duration = k2.time - k1.time;
+    let t = if duration > 0.0 {
+        (time - k1.time) / duration
+    } else {
+        0.0
+    };
+
+    // Unwrap the yaws so the camera turns the short way around.
+    let unwrap =
+        |yaw: f32, reference: f32| reference + (yaw - reference + 180.0).rem_euclid(360.0) - 180.0;
+
+    let yaw1 = k1.camera.yaw;
+    let yaw0 = unwrap(k0.camera.yaw, yaw1);
+    let yaw2 = unwrap(k2.camera.yaw, yaw1);
+    let yaw3 = unwrap(k3.camera.yaw, yaw2);
+
+    let spline = |value: fn(&Camera) -> f32| {
+        let [p0, p1, p2, p3] = [k0, k1, k2, k3].map(|keyframe| value(&keyframe.camera));
+        catmull_rom([p0, p1, p2, p3], times, t)
+    };
+
+    let mut camera = k1.camera.clone();
+    camera.pos = catmull_rom(
+        [k0, k1, k2, k3].map(|keyframe| keyframe.camera.pos),
+        times,
+        t,
+    );
+    camera.yaw = catmull_rom([yaw0, yaw1, yaw2, yaw3], times, t);
+    camera.pitch = spline(|camera| camera.pitch);
+    camera.roll = spline(|camera| camera.roll);
+    camera.fov = spline(|camera| camera.fov);
+
+    Some(camera)
+}
+
+/// Interpolate between `p1` and `p2` at `t` in `0..1`. The tangents are the velocities between
+/// the neighbouring points at `times`, scaled to the duration of the segment.
+fn catmull_rom<T>([p0, p1, p2, p3]: [T; 4], [t0, t1, t2, t3]: [f32; 4], t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let duration = t2 - t1;
+    let velocity = |from: T, to: T, seconds: f32| {
+        let scale = if seconds > 0.0 { 1.0 / seconds } else { 0.0 };
+        (to - from) * scale
+    };
+
+    let m1 = velocity(p0, p2, t2 - t0) * duration;
+    let m2 = velocity(p1, p3, t3 - t1) * duration;
+
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    p1 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + m1 * (t3 - 2.0 * t2 + t)
+        + p2 * (3.0 * t2 - 2.0 * t3)
+        + m2 * (t3 - t2)
+}
+
+#[test]
+fn path_passes_through_keyframes() {
+    use glam::Vec3;
+
+    let keyframe = |time: f32, x: f32, yaw: f32| {
+        let mut camera = Camera::new(1.0);
+        camera.pos = Vec3::new(x, 0.0, 0.0);
+        camera.yaw = yaw;
+
+        Keyframe { time, camera }
+    };
+
+    let path = [
+        keyframe(0.0, 0.0, 170.0),
+        keyframe(1.0, 1.0, -170.0),
+        keyframe(3.0, 2.0, -150.0),
+    ];
+
+    let camera = sample_path(&path, 1.0).unwrap();
+    assert!(camera.pos.abs_diff_eq(Vec3::X, 1e-5));
+    assert!((camera.yaw + 170.0).abs() < 1e-3);
+
+    // Turns through 180 degrees rather than through 0.
+    let camera = sample_path(&path, 0.5).unwrap();
+    assert!(camera.yaw.abs() > 170.0);
+
+    assert!(sample_path(&path, 3.5).is_none());
+}
+
+#[test]
+fn path_keeps_speed_between_uneven_keyframes() {
+    use glam::Vec3;
+
+    let keyframe = |time: f32| {
+        let mut camera = Camera::new(1.0);
+        camera.pos = Vec3::new(time, 0.0, 0.0);
+
+        Keyframe { time, camera }
+    };
+
+    // Moving at a constant speed stays at a constant speed.
+    let path = [keyframe(0.0), keyframe(1.0), keyframe(3.0), keyframe(4.0)];
+
+    for time in [0.5, 1.5, 2.0, 2.5, 3.5] {
+        let camera = sample_path(&path, time).unwrap();
+        assert!((camera.pos.x - time).abs() < 1e-4);
+    }
+}
@@ -1,14 +1,95 @@
-use glam::{Mat4, Vec3, Vec4};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
 
+use crate::asset::{CameraLens, SceneCamera};
+use crate::display;
+
+/// How the view is projected. Both projections use reversed Z, so the depth is 1 at the near
+/// plane and goes towards 0 with distance, which distributes the depth precision more evenly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// A perspective projection with the far plane at infinity.
+    #[default]
+    Perspective,
+    /// An orthographic projection. `height` is the vertical extent of the view in world units.
+    Orthographic { height: f32 },
+}
+
+/// The settings of a real camera. When a camera has them, they determine its field of view and
+/// exposure.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhysicalCamera {
+    /// The width and height of the sensor in millimeters.
+    pub sensor_size: Vec2,
+    /// In millimeters.
+    pub focal_length: f32,
+    /// The f-number.
+    pub aperture: f32,
+    /// In seconds.
+    pub shutter_speed: f32,
+    pub iso: f32,
+    /// The distance in meters at which depth of field is sharp, when autofocus is off.
+    pub focus_distance: f32,
+}
+
+impl Default for PhysicalCamera {
+    /// A full frame camera with a 50mm lens. The exposure is about what auto exposure picks for
+    /// the light units of the scenes.
+    fn default() -> Self {
+        Self {
+            sensor_size: Vec2::new(36.0, 24.0),
+            focal_length: 50.0,
+            aperture: 1.4,
+            shutter_speed: 0.5,
+            iso: 400.0,
+            focus_distance: 10.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    /// The vertical field of view in radians.
+    pub fn fov(&self) -> f32 {
+        2.0 * (self.sensor_size.y / (2.0 * self.focal_length)).atan()
+    }
+
+    /// The exposure value at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.aperture * self.aperture / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    /// The factor luminance is scaled by before tonemapping. The saturation based sensitivity
+    /// model, where the max luminance is `1.2 * 2^ev100`.
+    pub fn exposure(&self) -> f32 {
+        display::ev100_to_exposure(self.ev100())
+    }
+}
+
+/// The projection isn't serialized, as it depends on the aspect ratio of the window. Use
+/// `set_view` to apply a deserialized camera.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Camera {
     pub pos: Vec3,
     pub front: Vec3,
     pub yaw: f32,
     pub pitch: f32,
+    /// The rotation around `front` in degrees.
+    pub roll: f32,
+    /// The vertical field of view in radians. Derived from `physical` if it is set.
     pub fov: f32,
     pub z_near: f32,
+    /// Perspective projections have no far plane, but geometry beyond `z_far` is culled and
+    /// doesn't get shadows.
     pub z_far: f32,
+    #[serde(default)]
+    pub projection: Projection,
+    #[serde(default)]
+    pub physical: Option<PhysicalCamera>,
+    #[serde(skip)]
     pub proj: Mat4,
+    #[serde(skip)]
+    aspect_ratio: f32,
 }
 
 impl Camera {
@@ -19,20 +100,73 @@ impl Camera {
         let z_far = 1000.0;
         let fov = std::f32::consts::PI / 4.0;
 
-        let proj = calc_proj(fov, aspect_ratio, z_near, z_far);
+        let projection = Projection::default();
+        let proj = calc_proj(projection, fov, aspect_ratio, z_near, z_far);
 
         Self {
             pos: Vec3::ZERO,
             front: Vec3::X,
             yaw: 0.0,
             pitch: 0.0,
+            roll: 0.0,
             z_near,
             z_far,
             fov,
+            projection,
+            physical: None,
             proj,
+            aspect_ratio,
         }
     }
 
+    /// Create a camera from a camera imported from glTF.
+    pub fn from_scene_camera(scene_camera: &SceneCamera, aspect_ratio: f32) -> Self {
+        let mut camera = Self::new(aspect_ratio);
+        let (_, rotation, translation) = scene_camera.transform.to_scale_rotation_translation();
+
+        let front = rotation * Vec3::NEG_Z;
+        camera.pos = translation;
+        camera.set_orientation(
+            front.z.atan2(front.x).to_degrees(),
+            front.y.clamp(-1.0, 1.0).asin().to_degrees(),
+        );
+
+        // The roll is the angle between the up direction without roll and the up direction of
+        // the glTF camera.
+        let up = rotation * Self::UP;
+        let right = camera.front.cross(Self::UP).normalize();
+        let unrolled_up = right.cross(camera.front);
+        camera.roll = up.dot(right).atan2(up.dot(unrolled_up)).to_degrees();
+
+        let projection = scene_camera.projection;
+        match projection.lens {
+            CameraLens::Perspective { yfov } => camera.fov = yfov,
+            CameraLens::Orthographic { height } => {
+                camera.projection = Projection::Orthographic { height };
+            }
+        }
+
+        camera.z_near = projection.z_near;
+        camera.z_far = projection.z_far.unwrap_or(camera.z_far);
+        camera.resize_proj(aspect_ratio);
+
+        camera
+    }
+
+    /// Move the camera to the position and orientation of `other` and use its lens, but keep the
+    /// current aspect ratio.
+    pub fn set_view(&mut self, other: &Camera) {
+        self.pos = other.pos;
+        self.roll = other.roll;
+        self.fov = other.fov;
+        self.z_near = other.z_near;
+        self.z_far = other.z_far;
+        self.projection = other.projection;
+        self.physical = other.physical;
+        self.set_orientation(other.yaw, other.pitch);
+        self.resize_proj(self.aspect_ratio);
+    }
+
     pub fn move_by_delta(&mut self, delta: CameraDelta) {
         let horizontal = self.front.cross(Self::UP).normalize();
 
@@ -40,9 +174,17 @@ impl Camera {
         self.pos -= self.front * delta.backward;
         self.pos += horizontal * delta.right;
         self.pos -= horizontal * delta.left;
+        self.pos += Self::UP * delta.up;
+        self.pos -= Self::UP * delta.down;
+
+        self.roll = (self.roll + delta.roll) % 360.0;
+        self.set_orientation(self.yaw - delta.yaw, self.pitch + delta.pitch);
+    }
 
-        self.yaw = (self.yaw - delta.yaw) % 360.0;
-        self.pitch = (self.pitch + delta.pitch).clamp(-89.0, 89.0);
+    /// Set the yaw and pitch in degrees. The pitch is clamped to avoid flipping over the poles.
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw % 360.0;
+        self.pitch = pitch.clamp(-89.0, 89.0);
 
         self.front = Vec3::new(
             f32::cos(self.yaw.to_radians()) * f32::cos(self.pitch.to_radians()),
@@ -52,8 +194,13 @@ impl Camera {
         .normalize();
     }
 
+    /// The up direction of the view, which is `UP` rotated by the roll.
+    pub fn up(&self) -> Vec3 {
+        Quat::from_axis_angle(self.front, self.roll.to_radians()) * Self::UP
+    }
+
     pub fn view(&self) -> Mat4 {
-        Mat4::look_at_rh(self.pos, self.pos + self.front, Self::UP)
+        Mat4::look_at_rh(self.pos, self.pos + self.front, self.up())
     }
 
     pub fn proj(&self) -> Mat4 {
@@ -64,12 +211,70 @@ impl Camera {
         self.proj() * self.view()
     }
 
+    pub fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
     pub fn resize_proj(&mut self, aspect_ratio: f32) {
-        self.proj = calc_proj(self.fov, aspect_ratio, self.z_near, self.z_far);
+        if let Some(physical) = &self.physical {
+            self.fov = physical.fov();
+        }
+
+        self.aspect_ratio = aspect_ratio;
+        self.proj = calc_proj(
+            self.projection,
+            self.fov,
+            aspect_ratio,
+            self.z_near,
+            self.z_far,
+        );
+    }
+
+    /// Switch between perspective and orthographic projection. The orthographic view is as high
+    /// as the perspective view is at `distance`.
+    pub fn toggle_projection(&mut self, distance: f32) {
+        self.projection = match self.projection {
+            Projection::Perspective => Projection::Orthographic {
+                height: 2.0 * distance * (self.fov / 2.0).tan(),
+            },
+            Projection::Orthographic { .. } => Projection::Perspective,
+        };
+
+        self.resize_proj(self.aspect_ratio);
+    }
+
+    /// Coefficients to get the view space distance from a depth buffer value `d` as
+    /// `(x * d + y) / (z * d + w)`.
+    pub fn depth_linearize_coeffs(&self) -> Vec4 {
+        match self.projection {
+            Projection::Perspective => Vec4::new(0.0, self.z_near, 1.0, 0.0),
+            Projection::Orthographic { .. } => {
+                Vec4::new(self.z_near - self.z_far, self.z_far, 0.0, 1.0)
+            }
+        }
     }
 
+    /// The planes of the view frustrum. The far plane is at `z_far`, even for perspective
+    /// projections, so that far away geometry is culled.
     pub fn frustrum(&self) -> Frustrum {
-        let proj_view = self.proj_view();
+        let proj = match self.projection {
+            Projection::Perspective => {
+                Mat4::perspective_rh_gl(self.fov, self.aspect_ratio, self.z_near, self.z_far)
+            }
+            Projection::Orthographic { height } => {
+                let (width, height) = (height * self.aspect_ratio, height);
+                Mat4::orthographic_rh_gl(
+                    -width / 2.0,
+                    width / 2.0,
+                    -height / 2.0,
+                    height / 2.0,
+                    self.z_near,
+                    self.z_far,
+                )
+            }
+        };
+
+        let proj_view = proj * self.view();
 
         let planes = [
             proj_view.row(3) + proj_view.row(0),
@@ -97,8 +302,23 @@ impl Camera {
     }
 }
 
-fn calc_proj(fov: f32, aspect_ratio: f32, z_near: f32, z_far: f32) -> Mat4 {
-    Mat4::perspective_rh(fov, aspect_ratio, z_near, z_far)
+fn calc_proj(projection: Projection, fov: f32, aspect_ratio: f32, z_near: f32, z_far: f32) -> Mat4 {
+    match projection {
+        Projection::Perspective => Mat4::perspective_infinite_reverse_rh(fov, aspect_ratio, z_near),
+        Projection::Orthographic { height } => {
+            let width = height * aspect_ratio;
+
+            // Swapping near and far reverses the depth.
+            Mat4::orthographic_rh(
+                -width / 2.0,
+                width / 2.0,
+                -height / 2.0,
+                height / 2.0,
+                z_far,
+                z_near,
+            )
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -107,8 +327,11 @@ pub struct CameraDelta {
     pub right: f32,
     pub forward: f32,
     pub backward: f32,
+    pub up: f32,
+    pub down: f32,
     pub yaw: f32,
     pub pitch: f32,
+    pub roll: f32,
 }
 
 pub struct Frustrum {
@@ -119,3 +342,87 @@ pub struct Frustrum {
     pub far: Vec4,
     pub near: Vec4,
 }
+
+#[test]
+fn scene_camera_matches_view() {
+    use crate::asset::CameraProjection;
+
+    let mut camera = Camera::new(1.0);
+    camera.pos = Vec3::new(1.0, 2.0, 3.0);
+    camera.roll = 30.0;
+    camera.set_orientation(120.0, -20.0);
+
+    let scene_camera = SceneCamera {
+        name: None,
+        transform: camera.view().inverse(),
+        projection: CameraProjection {
+            lens: CameraLens::Perspective { yfov: camera.fov },
+            z_near: camera.z_near,
+            z_far: None,
+        },
+    };
+
+    let imported = Camera::from_scene_camera(&scene_camera, 1.0);
+
+    assert!(imported.pos.abs_diff_eq(camera.pos, 1e-4));
+    assert!(imported.front.abs_diff_eq(camera.front, 1e-4));
+    assert!((imported.roll - camera.roll).abs() < 1e-2);
+    assert_eq!(imported.projection, Projection::Perspective);
+
+    let orthographic = SceneCamera {
+        projection: CameraProjection {
+            lens: CameraLens::Orthographic { height: 8.0 },
+            z_near: 0.0,
+            z_far: Some(100.0),
+        },
+        ..scene_camera
+    };
+
+    let imported = Camera::from_scene_camera(&orthographic, 1.0);
+    assert_eq!(
+        imported.projection,
+        Projection::Orthographic { height: 8.0 }
+    );
+    assert_eq!(imported.z_far, 100.0);
+}
+
+#[test]
+fn reversed_depth_linearizes() {
+    let mut camera = Camera::new(1.0);
+    camera.front = Vec3::NEG_Z;
+
+    for projection in [
+        Projection::Perspective,
+        Projection::Orthographic { height: 10.0 },
+    ] {
+        camera.projection = projection;
+        camera.resize_proj(1.0);
+
+        let coeffs = camera.depth_linearize_coeffs();
+        let linearize = |depth: f32| (coeffs.x * depth + coeffs.y) / (coeffs.z * depth + coeffs.w);
+
+        for distance in [camera.z_near, 1.0, 10.0, 500.0] {
+            let clip = camera.proj() * Vec4::new(0.0, 0.0, -distance, 1.0);
+            let depth = clip.z / clip.w;
+
+            assert!((0.0..=1.0).contains(&depth));
+            assert!((linearize(depth) - distance).abs() < distance * 1e-3);
+        }
+    }
+}
+
+#[test]
+fn physical_camera_exposure() {
+    // Sunny 16: f/16 at 1/100s and ISO 100 is about EV 15.
+    let physical = PhysicalCamera {
+        aperture: 16.0,
+        shutter_speed: 0.01,
+        iso: 100.0,
+        ..Default::default()
+    };
+
+    assert!((physical.ev100() - 14.64).abs() < 0.01);
+
+    // A 50mm lens on a full frame sensor has a vertical field of view of about 27 degrees.
+    assert!((physical.fov().to_degrees() - 26.99).abs() < 0.01);
+}
@@ -0,0 +1,531 @@
+//! Capture of frames to disk. The HDR post target is written as a linear `.exr` and the final
+//! frame as a `.png`, optionally with depth, normal and albedo AOVs in separate `.exr` files.
+//!
+//! Textures are copied to staging buffers when the frame is recorded and read back
+//! asynchronously, so several captures may be in flight when capturing image sequences.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use eyre::{Result, WrapErr};
+use half::f16;
+use image::{ImageBuffer, Rgb, Rgba};
+use serde::Deserialize;
+
+use crate::context::Context;
+use crate::resources::RenderState;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CaptureSettings {
+    /// Captures are written to this directory, which is created if it doesn't exist.
+    pub directory: PathBuf,
+    /// Also write the depth, normal and albedo of each pixel.
+    pub aovs: bool,
+    /// The number of frames captured by an image sequence.
+    pub sequence_frames: u32,
+    /// The frame rate of image sequences. Frames are rendered with a fixed time step of
+    /// `1 / sequence_fps` regardless of how long they take.
+    pub sequence_fps: f32,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("captures"),
+            aovs: false,
+            sequence_frames: 120,
+            sequence_fps: 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureRequest {
+    /// The path of the files without extension. Suffixes such as `.depth` are added for AOVs.
+    pub path: PathBuf,
+    pub aovs: bool,
+}
+
+/// A sequence of captures of consecutive frames.
+pub struct CaptureSequence {
+    directory: PathBuf,
+    aovs: bool,
+    frame: u32,
+    frame_count: u32,
+    delta_time: Duration,
+}
+
+impl CaptureSequence {
+    /// Capture to a new numbered directory in the capture directory.
+    pub fn new(settings: &CaptureSettings) -> Self {
+        Self {
+            directory: unique_path(&settings.directory, "sequence", ""),
+            aovs: settings.aovs,
+            frame: 0,
+            frame_count: settings.sequence_frames,
+            delta_time: Duration::from_secs_f32(1.0 / settings.sequence_fps.max(1.0)),
+        }
+    }
+
+    pub fn delta_time(&self) -> Duration {
+        self.delta_time
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The capture of the next frame, or `None` if the sequence is finished.
+    pub fn next_request(&mut self) -> Option<CaptureRequest> {
+        if self.frame == self.frame_count {
+            return None;
+        }
+
+        let path = self.directory.join(format!("frame_{:05}", self.frame));
+        self.frame += 1;
+
+        Some(CaptureRequest {
+            path,
+            aovs: self.aovs,
+        })
+    }
+}
+
+/// The request of a single capture to a new numbered file in the capture directory.
+pub fn screenshot_request(settings: &CaptureSettings) -> CaptureRequest {
+    CaptureRequest {
+        path: unique_path(&settings.directory, "capture", ".exr"),
+        aovs: settings.aovs,
+    }
+}
+
+/// The first path `directory/{name}_{n}` where `directory/{name}_{n}{extension}` doesn't exist.
+fn unique_path(directory: &Path, name: &str, extension: &str) -> PathBuf {
+    (0..)
+        .map(|index| directory.join(format!("{name}_{index:04}")))
+        .find(|path| {
+            !path
+                .with_extension(extension.trim_start_matches('.'))
+                .exists()
+        })
+        .expect("there should be an unused path")
+}
+
+/// A texture copied to a buffer with rows aligned for texture copies.
+struct Readback {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    bytes_per_row: u32,
+}
+
+impl Readback {
+    /// Copy the upper left `size` of `texture`.
+    fn record(
+        context: &Context,
+        texture: &wgpu::Texture,
+        size: wgpu::Extent3d,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Self {
+        let format = texture.format();
+
+        let bytes_per_pixel = format
+            .block_size(None)
+            .expect("captured textures should have a block size");
+        let bytes_per_row =
+            (size.width * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("capture readback"),
+            size: bytes_per_row as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                depth_or_array_layers: 1,
+                ..size
+            },
+        );
+
+        Self {
+            buffer,
+            format,
+            width: size.width,
+            height: size.height,
+            bytes_per_row,
+        }
+    }
+
+    /// Copy the pixels out of the mapped buffer, without the row padding.
+    fn read(&self) -> ReadbackImage {
+        let bytes_per_pixel = self.format.block_size(None).unwrap_or(4);
+        let unpadded_bytes_per_row = (self.width * bytes_per_pixel) as usize;
+
+        let data = self
+            .buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(self.bytes_per_row as usize)
+            .flat_map(|row| row[..unpadded_bytes_per_row].to_vec())
+            .collect();
+
+        self.buffer.unmap();
+
+        ReadbackImage {
+            format: self.format,
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+}
+
+/// The pixels of a texture read back from the GPU.
+struct ReadbackImage {
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl ReadbackImage {
+    /// Scale the image to `width` and `height` with nearest neighbor filtering, so that depths
+    /// and normals aren't blended across edges.
+    fn resize_nearest(self, width: u32, height: u32) -> Self {
+        if (width, height) == (self.width, self.height) {
+            return self;
+        }
+
+        let bytes_per_pixel = self.format.block_size(None).unwrap_or(4) as usize;
+        let data = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let source_x = (x as u64 * self.width as u64 / width as u64) as usize;
+                let source_y = (y as u64 * self.height as u64 / height as u64) as usize;
+                let offset = (source_y * self.width as usize + source_x) * bytes_per_pixel;
+
+                self.data[offset..offset + bytes_per_pixel].iter().copied()
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            data,
+            ..self
+        }
+    }
+
+    /// The RGBA channels of each pixel. Only float formats are supported.
+    fn float_pixels(&self) -> Vec<[f32; 4]> {
+        match self.format {
+            wgpu::TextureFormat::Rgba16Float => {
+                bytemuck::pod_collect_to_vec::<u8, [f16; 4]>(&self.data)
+                    .into_iter()
+                    .map(|pixel| pixel.map(f16::to_f32))
+                    .collect()
+            }
+            wgpu::TextureFormat::Rgba32Float => {
+                bytemuck::pod_collect_to_vec::<u8, [f32; 4]>(&self.data)
+            }
+            format => panic!("{format:?} isn't a float format"),
+        }
+    }
+
+    /// Write the pixels as an RGBA image. The alpha channel is replaced by one unless
+    /// `keep_alpha` is set.
+    fn write_exr(&self, path: &Path, keep_alpha: bool) -> Result<()> {
+        let pixels = self.float_pixels();
+        let image = ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b, a] = pixels[(y * self.width + x) as usize];
+            Rgba([r, g, b, if keep_alpha { a } else { 1.0 }])
+        });
+
+        image
+            .save(path)
+            .wrap_err_with(|| format!("failed to write {path:?}"))
+    }
+
+    /// Write the first three channels as an RGB image and `channel` as a grayscale image.
+    fn write_exr_split(&self, path: &Path, channel_path: &Path, channel: usize) -> Result<()> {
+        let pixels = self.float_pixels();
+        let pixel = |x: u32, y: u32| pixels[(y * self.width + x) as usize];
+
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b, _] = pixel(x, y);
+            Rgb([r, g, b])
+        })
+        .save(path)
+        .wrap_err_with(|| format!("failed to write {path:?}"))?;
+
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            Rgb([pixel(x, y)[channel]; 3])
+        })
+        .save(channel_path)
+        .wrap_err_with(|| format!("failed to write {channel_path:?}"))
+    }
+
+    /// Write an 8 bit frame as a PNG. HDR frames are skipped, as they can't be stored as PNG
+    /// without tonemapping them again.
+    fn write_png(&self, path: &Path) -> Result<()> {
+        use wgpu::TextureFormat as Format;
+
+        let swap_red_blue = match self.format {
+            Format::Rgba8Unorm | Format::Rgba8UnormSrgb => false,
+            Format::Bgra8Unorm | Format::Bgra8UnormSrgb => true,
+            format => {
+                eprintln!("skipping {path:?}, the frame format {format:?} isn't SDR");
+                return Ok(());
+            }
+        };
+
+        let mut data = self.data.clone();
+        for pixel in data.chunks_exact_mut(4) {
+            if swap_red_blue {
+                pixel.swap(0, 2);
+            }
+
+            pixel[3] = u8::MAX;
+        }
+
+        image::RgbaImage::from_raw(self.width, self.height, data)
+            .expect("readback should contain every pixel")
+            .save(path)
+            .wrap_err_with(|| format!("failed to write {path:?}"))
+    }
+}
+
+struct PendingCapture {
+    request: CaptureRequest,
+    color: Readback,
+    frame: Option<Readback>,
+    aovs: Option<[Readback; 2]>,
+    /// The number of buffers that haven't been mapped yet.
+    unmapped: Arc<AtomicUsize>,
+    is_mapping: bool,
+}
+
+impl PendingCapture {
+    fn readbacks(&self) -> impl Iterator<Item = &Readback> {
+        let aovs = self.aovs.iter().flatten();
+        [Some(&self.color), self.frame.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(aovs)
+    }
+
+    fn write(self) -> Result<()> {
+        let path = &self.request.path;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err_with(|| format!("failed to create capture directory {parent:?}"))?;
+        }
+
+        let with_suffix = |suffix: &str| {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(suffix);
+            path.with_file_name(name)
+        };
+
+        let color = self.color.read();
+        color.write_exr(&with_suffix(".exr"), false)?;
+
+        if let Some(frame) = &self.frame {
+            frame.read().write_png(&with_suffix(".png"))?;
+        }
+
+        // The AOVs are rendered at the render size, which is smaller than the color when it is
+        // upscaled.
+        if let Some([normal_depth, albedo]) = &self.aovs {
+            let read = |aov: &Readback| aov.read().resize_nearest(color.width, color.height);
+
+            read(normal_depth).write_exr_split(
+                &with_suffix(".normal.exr"),
+                &with_suffix(".depth.exr"),
+                3,
+            )?;
+
+            read(albedo).write_exr(&with_suffix(".albedo.exr"), true)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Capturer {
+    requests: Vec<CaptureRequest>,
+    pending: Vec<PendingCapture>,
+    writers: Vec<JoinHandle<()>>,
+}
+
+impl Capturer {
+    /// Capture the next frame.
+    pub fn request(&mut self, request: CaptureRequest) {
+        self.requests.push(request);
+    }
+
+    /// True if the next frame must write the AOV targets.
+    pub fn wants_aovs(&self) -> bool {
+        self.requests.iter().any(|request| request.aovs)
+    }
+
+    /// Copy the targets of the requested captures. `frame_buffer` must be recorded to before.
+    pub fn record(
+        &mut self,
+        context: &Context,
+        render_state: &RenderState,
+        frame_buffer: &wgpu::Texture,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        for request in self.requests.drain(..) {
+            let frame = frame_buffer
+                .usage()
+                .contains(wgpu::TextureUsages::COPY_SRC)
+                .then(|| Readback::record(context, frame_buffer, frame_buffer.size(), encoder));
+
+            if frame.is_none() {
+                eprintln!("the surface can't be copied from, the PNG isn't written");
+            }
+
+            let aovs = request.aovs.then(|| {
+                let aovs = &render_state.aovs;
+                [&aovs.normal_depth, &aovs.albedo].map(|target| {
+                    Readback::record(context, &target.texture, context.render_size, encoder)
+                })
+            });
+
+            self.pending.push(PendingCapture {
+                color: Readback::record(
+                    context,
+                    &render_state.post.texture,
+                    context.surface_size,
+                    encoder,
+                ),
+                unmapped: Arc::new(AtomicUsize::new(0)),
+                is_mapping: false,
+                request,
+                frame,
+                aovs,
+            });
+        }
+    }
+
+    /// Start mapping the buffers copied this frame. Must be called after the frame is
+    /// submitted.
+    pub fn map(&mut self) {
+        for pending in self
+            .pending
+            .iter_mut()
+            .filter(|pending| !pending.is_mapping)
+        {
+            pending.is_mapping = true;
+            pending
+                .unmapped
+                .store(pending.readbacks().count(), atomic::Ordering::Release);
+
+            for readback in pending.readbacks() {
+                let unmapped = pending.unmapped.clone();
+
+                readback
+                    .buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        if let Err(err) = result {
+                            eprintln!("failed to map capture: {err}");
+                        }
+
+                        unmapped.fetch_sub(1, atomic::Ordering::AcqRel);
+                    });
+            }
+        }
+    }
+
+    /// Write the captures that have been read back on a background thread, as encoding is slow.
+    pub fn update(&mut self, context: &Context) {
+        context.device.poll(wgpu::Maintain::Poll);
+
+        let (mapped, pending) = self.pending.drain(..).partition(|pending| {
+            pending.is_mapping && pending.unmapped.load(atomic::Ordering::Acquire) == 0
+        });
+
+        self.pending = pending;
+
+        for capture in mapped {
+            self.writers.push(thread::spawn(move || {
+                let path = capture.request.path.clone();
+
+                match capture.write() {
+                    Ok(()) => println!("saved capture {path:?}"),
+                    Err(err) => eprintln!("{err:?}"),
+                }
+            }));
+        }
+
+        self.writers.retain(|writer| !writer.is_finished());
+    }
+}
+
+impl Drop for Capturer {
+    fn drop(&mut self) {
+        for writer in self.writers.drain(..) {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[test]
+fn sequence_requests_frames() {
+    let settings = CaptureSettings {
+        directory: PathBuf::from("target/capture-test-missing"),
+        sequence_frames: 2,
+        sequence_fps: 50.0,
+        ..Default::default()
+    };
+
+    let mut sequence = CaptureSequence::new(&settings);
+
+    assert_eq!(sequence.delta_time(), Duration::from_millis(20));
+    assert_eq!(
+        sequence.directory(),
+        Path::new("target/capture-test-missing/sequence_0000")
+    );
+
+    let first = sequence.next_request().unwrap();
+    assert!(first.path.ends_with("sequence_0000/frame_00000"));
+    assert!(sequence.next_request().is_some());
+    assert!(sequence.next_request().is_none());
+}
+
+#[test]
+fn aovs_are_scaled_to_color() {
+    let image = ReadbackImage {
+        format: wgpu::TextureFormat::R32Float,
+        width: 2,
+        height: 1,
+        data: bytemuck::cast_slice(&[1.0f32, 2.0]).to_vec(),
+    };
+
+    let scaled = image.resize_nearest(4, 2);
+    let pixels: Vec<f32> = bytemuck::pod_collect_to_vec(&scaled.data);
+
+    assert_eq!((scaled.width, scaled.height), (4, 2));
+    assert_eq!(pixels, [1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]);
+}
@@ -1,84 +1,146 @@
 use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 
+use eyre::WrapErr;
 pub use naga_oil::compose::ShaderDefValue;
 use winit::{dpi::PhysicalSize, window::Window};
 
+/// How the device and surface are created.
+#[derive(Clone, Copy, Debug)]
+pub struct ContextOptions {
+    /// Use a software adapter, such as WARP or llvmpipe.
+    pub force_fallback_adapter: bool,
+    /// Wait for vertical blank before presenting.
+    pub vsync: bool,
+    /// Output HDR if the surface supports a suitable format.
+    pub hdr: bool,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            force_fallback_adapter: false,
+            vsync: true,
+            hdr: false,
+        }
+    }
+}
+
+/// How the display phase encodes the frame, which follows from the surface format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayOutput {
+    /// Tonemapped to `0..1` and encoded as sRGB by the surface.
+    Sdr,
+    /// Linear Rec. 709 in a float surface, where 1.0 is 80 nits.
+    Scrgb,
+    /// Rec. 2020 encoded with the PQ curve in a 10 bit texture. Only headless contexts render
+    /// it, see `choose_surface_format`.
+    Hdr10,
+}
+
+impl DisplayOutput {
+    pub fn from_format(format: wgpu::TextureFormat) -> Self {
+        match format {
+            wgpu::TextureFormat::Rgba16Float => Self::Scrgb,
+            wgpu::TextureFormat::Rgb10a2Unorm => Self::Hdr10,
+            _ => Self::Sdr,
+        }
+    }
+}
+
+/// Pick the first sRGB format, or with `hdr` the scRGB format if the surface supports it.
+///
+/// wgpu only presents float surfaces in an extended color space, and presents every other format
+/// as sRGB. A 10 bit surface would show PQ encoded HDR10 as washed out SDR, so it isn't used.
+/// Headless contexts still render HDR10, as their frames are read back rather than presented.
+fn choose_surface_format(formats: &[wgpu::TextureFormat], hdr: bool) -> wgpu::TextureFormat {
+    let hdr_format =
+        Some(wgpu::TextureFormat::Rgba16Float).filter(|format| hdr && formats.contains(format));
+
+    hdr_format
+        .or_else(|| formats.iter().copied().find(|format| format.is_srgb()))
+        .expect("no supported surface formats")
+}
+
+/// The format of the frames rendered by headless contexts.
+pub const HEADLESS_SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// The format of the frames rendered by headless contexts with HDR enabled, which are read back
+/// rather than presented.
+pub const HEADLESS_HDR_SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgb10a2Unorm;
+
 pub struct Context {
     pub surface_size: wgpu::Extent3d,
+    /// The internal resolution of the passes before the temporal resolve, which is
+    /// `surface_size` scaled by the render scale. They render to the upper left corner of the
+    /// render targets.
+    pub render_size: wgpu::Extent3d,
+    /// The size of the render targets before the temporal resolve, which is `surface_size`
+    /// scaled by the highest render scale, so lowering the render scale doesn't reallocate them.
+    pub render_target_size: wgpu::Extent3d,
+    render_scale: f32,
+    max_render_scale: f32,
     pub surface_format: wgpu::TextureFormat,
-    pub surface: wgpu::Surface,
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
-    pub limits: wgpu::Limits,
-    pub window: Rc<Window>,
-    pub frame_index: usize,
-    pub backend: wgpu::Backend,
+    pub display_output: DisplayOutput,
+    pub surface_usage: wgpu::TextureUsages,
+    /// `None` if the context is headless.
+    pub surface: Option<wgpu::Surface>,
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
+    /// Kept alive for as long as `surface`, which borrows it.
+    #[allow(dead_code)]
+    pub window: Option<Rc<Window>>,
     pub present_mode: wgpu::PresentMode,
+    pub adapter_info: wgpu::AdapterInfo,
     pub shader_composer: naga_oil::compose::Composer,
+    pub shared_layouts: SharedLayouts,
+}
+
+/// Bind group layouts used by many passes, which are created on first use. They belong to the
+/// device, so they can't be shared between contexts.
+#[derive(Default)]
+pub struct SharedLayouts {
+    pub const_state: OnceLock<wgpu::BindGroupLayout>,
+    pub shadow_cascades: OnceLock<wgpu::BindGroupLayout>,
 }
 
 impl Context {
-    pub fn new(window: Rc<Window>) -> Self {
+    /// Fails if there is no adapter, or if the adapter doesn't support the features required by
+    /// the renderer, such as the fallback adapter.
+    pub fn new(window: Rc<Window>, options: ContextOptions) -> eyre::Result<Self> {
         let surface_size = physical_size_to_texture_size(window.inner_size());
+        let instance = create_instance();
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
-            backends: wgpu::Backends::all(),
-        });
-
-        let surface = unsafe {
-            instance
-                .create_surface(window.as_ref())
-                .expect("failed creating surface")
-        };
+        let surface = unsafe { instance.create_surface(window.as_ref()) }
+            .wrap_err("failed creating surface")?;
 
         let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::HighPerformance,
             compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
+            force_fallback_adapter: options.force_fallback_adapter,
         }))
-        .expect("failed request of adapter");
-
-        let backend = adapter.get_info().backend;
-
-        let features = wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
-            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-            | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY
-            | wgpu::Features::TEXTURE_BINDING_ARRAY
-            | wgpu::Features::TEXTURE_COMPRESSION_BC
-            | wgpu::Features::CLEAR_TEXTURE
-            | wgpu::Features::PUSH_CONSTANTS
-            | wgpu::Features::VERTEX_WRITABLE_STORAGE;
-
-        let (device, queue) = pollster::block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                limits: wgpu::Limits {
-                    max_push_constant_size: 128,
-                    max_sampled_textures_per_shader_stage: 128,
-                    ..Default::default()
-                },
-                label: Some("device"),
-                features,
-            },
-            None,
-        ))
-        .expect("failed request of device and queue");
+        .ok_or_else(|| eyre::eyre!("no adapter found"))?;
 
-        let format = surface
-            .get_capabilities(&adapter)
-            .formats
-            .iter()
-            .find(|format| format.is_srgb())
-            .expect("no supported surface formats")
-            .clone();
+        let (device, queue) = request_device(&adapter)?;
 
-        let present_mode = wgpu::PresentMode::Fifo;
+        let capabilities = surface.get_capabilities(&adapter);
+        let format = choose_surface_format(&capabilities.formats, options.hdr);
+
+        // Frames are copied from when they are captured.
+        let surface_usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (capabilities.usages & wgpu::TextureUsages::COPY_SRC);
+
+        let present_mode = if options.vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            wgpu::PresentMode::AutoNoVsync
+        };
         let alpha_mode = wgpu::CompositeAlphaMode::Auto;
 
         surface.configure(
             &device,
             &wgpu::SurfaceConfiguration {
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                usage: surface_usage,
                 width: surface_size.width,
                 height: surface_size.height,
                 view_formats: vec![],
@@ -90,19 +152,79 @@ impl Context {
 
         let shader_composer = create_shader_composer();
 
-        Self {
-            limits: adapter.limits(),
+        Ok(Self {
             surface_format: format,
+            display_output: DisplayOutput::from_format(format),
+            surface_usage,
             surface_size,
+            render_size: surface_size,
+            render_target_size: surface_size,
+            render_scale: 1.0,
+            max_render_scale: 1.0,
             present_mode,
-            window,
-            surface,
-            device,
-            queue,
-            backend,
-            frame_index: 0,
+            adapter_info: adapter.get_info(),
+            window: Some(window),
+            surface: Some(surface),
+            device: Arc::new(device),
+            queue: Arc::new(queue),
             shader_composer,
-        }
+            shared_layouts: SharedLayouts::default(),
+        })
+    }
+
+    /// Create a context without a window, which renders to textures of `surface_size` and
+    /// `HEADLESS_SURFACE_FORMAT`, or `HEADLESS_HDR_SURFACE_FORMAT` with HDR enabled. Fails instead
+    /// of panicking if there is no suitable adapter.
+    pub fn headless(surface_size: wgpu::Extent3d, options: ContextOptions) -> eyre::Result<Self> {
+        let instance = create_instance();
+
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: options.force_fallback_adapter,
+        }))
+        .ok_or_else(|| eyre::eyre!("no adapter found"))?;
+
+        let (device, queue) = request_device(&adapter)?;
+
+        let format = if options.hdr {
+            HEADLESS_HDR_SURFACE_FORMAT
+        } else {
+            HEADLESS_SURFACE_FORMAT
+        };
+
+        Ok(Self {
+            surface_format: format,
+            display_output: DisplayOutput::from_format(format),
+            surface_usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            surface_size,
+            render_size: surface_size,
+            render_target_size: surface_size,
+            render_scale: 1.0,
+            max_render_scale: 1.0,
+            present_mode: wgpu::PresentMode::Fifo,
+            adapter_info: adapter.get_info(),
+            window: None,
+            surface: None,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            shader_composer: create_shader_composer(),
+            shared_layouts: SharedLayouts::default(),
+        })
+    }
+
+    /// Set the render scale, which can't exceed `max_render_scale`. Returns true if
+    /// `render_target_size` has changed, in which case the render targets have to be recreated.
+    pub fn set_render_scale(&mut self, render_scale: f32, max_render_scale: f32) -> bool {
+        let render_target_size = scale_size(self.surface_size, max_render_scale);
+        let has_changed = render_target_size != self.render_target_size;
+
+        self.max_render_scale = max_render_scale;
+        self.render_scale = render_scale.min(max_render_scale);
+        self.render_target_size = render_target_size;
+        self.render_size = scale_size(self.surface_size, self.render_scale);
+
+        has_changed
     }
 
     pub fn create_shader_module(
@@ -113,13 +235,13 @@ impl Context {
     ) -> naga::Module {
         let shader_defs = defs
             .iter()
-            .map(|(def, value)| (def.to_string(), value.clone()))
+            .map(|(def, value)| (def.to_string(), *value))
             .collect();
 
         self.shader_composer
             .make_naga_module(naga_oil::compose::NagaModuleDescriptor {
                 source,
-                file_path: &path,
+                file_path: path,
                 shader_defs,
                 ..Default::default()
             })
@@ -135,12 +257,18 @@ impl Context {
         let has_changed =
             size.width != self.surface_size.width || size.height != self.surface_size.height;
         self.surface_size = physical_size_to_texture_size(size);
+        self.render_size = scale_size(self.surface_size, self.render_scale);
+        self.render_target_size = scale_size(self.surface_size, self.max_render_scale);
+
+        let Some(surface) = &self.surface else {
+            return;
+        };
 
         if !is_minimized && has_changed {
-            self.surface.configure(
+            surface.configure(
                 &self.device,
                 &wgpu::SurfaceConfiguration {
-                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    usage: self.surface_usage,
                     alpha_mode: wgpu::CompositeAlphaMode::Auto,
                     present_mode: self.present_mode,
                     format: self.surface_format,
@@ -153,6 +281,48 @@ impl Context {
     }
 }
 
+fn create_instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+        backends: wgpu::Backends::all(),
+    })
+}
+
+/// The features the renderer can't run without.
+const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+    .union(wgpu::Features::TEXTURE_COMPRESSION_BC)
+    .union(wgpu::Features::CLEAR_TEXTURE)
+    .union(wgpu::Features::VERTEX_WRITABLE_STORAGE);
+
+fn request_device(adapter: &wgpu::Adapter) -> eyre::Result<(wgpu::Device, wgpu::Queue)> {
+    let missing_features = REQUIRED_FEATURES - adapter.features();
+    if !missing_features.is_empty() {
+        let name = adapter.get_info().name;
+        return Err(eyre::eyre!(
+            "adapter {name} doesn't support required features: {missing_features:?}"
+        ));
+    }
+
+    // Timestamp queries are only used for profiling, so they are optional.
+    let features = REQUIRED_FEATURES | (adapter.features() & wgpu::Features::TIMESTAMP_QUERY);
+
+    let device = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            limits: wgpu::Limits {
+                // Every material texture has a layer in the texture pools of its format.
+                max_texture_array_layers: adapter.limits().max_texture_array_layers,
+                ..Default::default()
+            },
+            label: Some("device"),
+            features,
+        },
+        None,
+    ))
+    .wrap_err("failed request of device and queue")?;
+
+    Ok(device)
+}
+
 fn create_shader_composer() -> naga_oil::compose::Composer {
     let mut composer = naga_oil::compose::Composer::default();
     composer.validate = false;
@@ -174,6 +344,9 @@ fn create_shader_composer() -> naga_oil::compose::Composer {
     add_include!("include_shaders/consts.wgsl");
     add_include!("include_shaders/mesh.wgsl");
     add_include!("include_shaders/pbr.wgsl");
+    add_include!("include_shaders/atmosphere.wgsl");
+    add_include!("include_shaders/sky.wgsl");
+    add_include!("include_shaders/shadow.wgsl");
 
     composer
 }
@@ -185,3 +358,36 @@ fn physical_size_to_texture_size(size: PhysicalSize<u32>) -> wgpu::Extent3d {
         depth_or_array_layers: 1,
     }
 }
+
+fn scale_size(size: wgpu::Extent3d, scale: f32) -> wgpu::Extent3d {
+    let scale = |length: u32| ((length as f32 * scale).round() as u32).max(1);
+
+    wgpu::Extent3d {
+        width: scale(size.width),
+        height: scale(size.height),
+        depth_or_array_layers: 1,
+    }
+}
+
+#[test]
+fn surface_format_falls_back_to_srgb() {
+    use wgpu::TextureFormat as Format;
+
+    let sdr_only = [Format::Bgra8Unorm, Format::Bgra8UnormSrgb];
+    assert_eq!(
+        choose_surface_format(&sdr_only, true),
+        Format::Bgra8UnormSrgb
+    );
+
+    // 10 bit surfaces are presented as sRGB, so they can't show HDR.
+    let hdr10 = [Format::Rgb10a2Unorm, Format::Bgra8UnormSrgb];
+    assert_eq!(choose_surface_format(&hdr10, true), Format::Bgra8UnormSrgb);
+
+    let scrgb = [
+        Format::Rgb10a2Unorm,
+        Format::Rgba16Float,
+        Format::Bgra8UnormSrgb,
+    ];
+    assert_eq!(choose_surface_format(&scrgb, false), Format::Bgra8UnormSrgb);
+    assert_eq!(choose_surface_format(&scrgb, true), Format::Rgba16Float);
+}
@@ -0,0 +1,261 @@
+use glam::{Vec2, Vec3};
+
+use crate::bookmarks::{self, Keyframe};
+use crate::camera::{Camera, CameraDelta};
+use crate::input::{Action, Inputs};
+
+const MOUSE_SENSITIVITY: f32 = 0.5;
+
+/// Degrees per second with the right stick all the way to the side.
+const STICK_LOOK_SPEED: f32 = 120.0;
+const SPRINT_FACTOR: f32 = 4.0;
+
+/// Degrees per second.
+const ROLL_SPEED: f32 = 90.0;
+
+/// Moves the camera based on the inputs.
+pub enum CameraController {
+    Fly(FlyController),
+    Orbit(OrbitController),
+    FirstPerson(FirstPersonController),
+    Path(PathController),
+}
+
+impl CameraController {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fly(_) => "fly",
+            Self::Orbit(_) => "orbit",
+            Self::FirstPerson(_) => "first person",
+            Self::Path(_) => "path",
+        }
+    }
+
+    /// Switch to the next controller, starting from the current camera.
+    pub fn next(&self, camera: &mut Camera) -> Self {
+        match self {
+            Self::Fly(_) => Self::Orbit(OrbitController::new(camera)),
+            Self::Orbit(_) => Self::FirstPerson(FirstPersonController::new(camera)),
+            Self::FirstPerson(_) | Self::Path(_) => Self::Fly(FlyController::default()),
+        }
+    }
+
+    /// Update the camera. Switches back to the fly controller when a camera path finishes.
+    pub fn update(&mut self, camera: &mut Camera, inputs: &mut Inputs, delta_time: f32) {
+        match self {
+            Self::Fly(controller) => controller.update(camera, inputs, delta_time),
+            Self::Orbit(controller) => controller.update(camera, inputs, delta_time),
+            Self::FirstPerson(controller) => controller.update(camera, inputs, delta_time),
+            Self::Path(controller) => {
+                if !controller.update(camera, delta_time) {
+                    *self = Self::default();
+                }
+            }
+        }
+    }
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self::Fly(FlyController::default())
+    }
+}
+
+/// Returns the mouse movement in degrees if the look action is held, plus the rotation from the
+/// right stick.
+fn look_delta(inputs: &mut Inputs, delta_time: f32) -> Vec2 {
+    let mouse_delta = inputs.take_mouse_delta();
+
+    // Pushing the stick right turns right, which is the opposite of the mouse delta.
+    let stick = inputs.look_stick() * Vec2::new(-1.0, 1.0);
+    let stick_delta = stick * STICK_LOOK_SPEED * delta_time;
+
+    if inputs.is_held(Action::Look) {
+        mouse_delta * MOUSE_SENSITIVITY + stick_delta
+    } else {
+        stick_delta
+    }
+}
+
+/// Scroll changes the movement speed by this factor per line.
+const SPEED_SCROLL_FACTOR: f32 = 1.1;
+
+/// Free movement in every direction, including roll.
+pub struct FlyController {
+    /// Units per second.
+    pub speed: f32,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self { speed: 20.0 }
+    }
+}
+
+impl FlyController {
+    fn update(&mut self, camera: &mut Camera, inputs: &mut Inputs, delta_time: f32) {
+        let scroll = inputs.take_scroll_delta();
+        self.speed = (self.speed * SPEED_SCROLL_FACTOR.powf(scroll)).clamp(0.1, 1000.0);
+
+        let mut speed = self.speed * delta_time;
+        if inputs.is_held(Action::Sprint) {
+            speed *= SPRINT_FACTOR;
+        }
+
+        let look = look_delta(inputs, delta_time);
+        let forward = inputs.axis(Action::MoveForward, Action::MoveBackward) * speed;
+        let right = inputs.axis(Action::MoveRight, Action::MoveLeft) * speed;
+        let up = inputs.axis(Action::MoveUp, Action::MoveDown) * speed;
+
+        camera.move_by_delta(CameraDelta {
+            forward: forward.max(0.0),
+            backward: (-forward).max(0.0),
+            left: (-right).max(0.0),
+            right: right.max(0.0),
+            up: up.max(0.0),
+            down: (-up).max(0.0),
+            roll: inputs.axis(Action::RollRight, Action::RollLeft) * ROLL_SPEED * delta_time,
+            yaw: look.x,
+            pitch: look.y,
+        });
+    }
+}
+
+/// Scroll changes the orbit distance by this factor per line.
+const ZOOM_SCROLL_FACTOR: f32 = 0.9;
+
+/// Rotates around a target point. Movement moves the target.
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+}
+
+impl OrbitController {
+    /// Orbit around the point `distance` units in front of `camera`.
+    pub fn new(camera: &mut Camera) -> Self {
+        let distance = 10.0;
+        camera.roll = 0.0;
+
+        Self {
+            target: camera.pos + camera.front * distance,
+            distance,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, inputs: &mut Inputs, delta_time: f32) {
+        let scroll = inputs.take_scroll_delta();
+        self.distance = (self.distance * ZOOM_SCROLL_FACTOR.powf(scroll)).clamp(0.1, 1000.0);
+
+        let look = look_delta(inputs, delta_time);
+        camera.set_orientation(camera.yaw - look.x, camera.pitch + look.y);
+
+        // Pan relative to the view, scaled by the distance so panning feels the same when
+        // zoomed in and out.
+        let mut speed = self.distance * delta_time;
+        if inputs.is_held(Action::Sprint) {
+            speed *= SPRINT_FACTOR;
+        }
+
+        let right = camera.front.cross(Camera::UP).normalize();
+        let forward = Camera::UP.cross(right);
+
+        self.target += forward * inputs.axis(Action::MoveForward, Action::MoveBackward) * speed;
+        self.target += right * inputs.axis(Action::MoveRight, Action::MoveLeft) * speed;
+        self.target += Camera::UP * inputs.axis(Action::MoveUp, Action::MoveDown) * speed;
+
+        camera.pos = self.target - camera.front * self.distance;
+    }
+}
+
+/// How quickly the first person controller reaches the target velocity and rotation.
+const SMOOTHING: f32 = 12.0;
+
+/// Walks on the horizontal plane with smoothed movement and mouse look.
+pub struct FirstPersonController {
+    /// Units per second.
+    pub speed: f32,
+    velocity: Vec3,
+    look: Vec2,
+}
+
+impl FirstPersonController {
+    pub fn new(camera: &mut Camera) -> Self {
+        camera.roll = 0.0;
+
+        Self {
+            speed: 5.0,
+            velocity: Vec3::ZERO,
+            look: Vec2::ZERO,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, inputs: &mut Inputs, delta_time: f32) {
+        let smoothing = 1.0 - f32::exp(-SMOOTHING * delta_time);
+
+        // The look delta is in degrees per frame, so it is converted to degrees per second
+        // before smoothing to be independent of the frame rate.
+        let look = look_delta(inputs, delta_time) / delta_time.max(f32::EPSILON);
+        self.look = self.look.lerp(look, smoothing);
+
+        camera.set_orientation(
+            camera.yaw - self.look.x * delta_time,
+            camera.pitch + self.look.y * delta_time,
+        );
+
+        let right = camera.front.cross(Camera::UP).normalize();
+        let forward = Camera::UP.cross(right);
+
+        let direction = forward * inputs.axis(Action::MoveForward, Action::MoveBackward)
+            + right * inputs.axis(Action::MoveRight, Action::MoveLeft);
+
+        let mut speed = self.speed;
+        if inputs.is_held(Action::Sprint) {
+            speed *= SPRINT_FACTOR;
+        }
+
+        // The stick can move slower than full speed, but diagonals aren't faster.
+        let velocity = direction.clamp_length_max(1.0) * speed;
+        self.velocity = self.velocity.lerp(velocity, smoothing);
+
+        camera.pos += self.velocity * delta_time;
+    }
+}
+
+/// Plays back a camera path. Ignores inputs.
+pub struct PathController {
+    keyframes: Vec<Keyframe>,
+    time: f32,
+}
+
+impl PathController {
+    pub fn new(keyframes: Vec<Keyframe>) -> Self {
+        Self {
+            keyframes,
+            time: 0.0,
+        }
+    }
+
+    /// Returns false once the path is finished.
+    fn update(&mut self, camera: &mut Camera, delta_time: f32) -> bool {
+        let Some(sample) = bookmarks::sample_path(&self.keyframes, self.time) else {
+            return false;
+        };
+
+        camera.set_view(&sample);
+        self.time += delta_time;
+
+        true
+    }
+}
+
+#[test]
+fn orbit_keeps_distance_to_target() {
+    let mut camera = Camera::new(1.0);
+    let mut controller = OrbitController::new(&mut camera);
+    let mut inputs = Inputs::default();
+
+    inputs.scrolled(1.0);
+    controller.update(&mut camera, &mut inputs, 1.0 / 60.0);
+
+    assert!((camera.pos.distance(controller.target) - 9.0).abs() < 1e-4);
+}
@@ -10,7 +10,6 @@ pub struct DepthReducePhase {
     initial_reduce: wgpu::ComputePipeline,
     reduce: wgpu::ComputePipeline,
     bind_group_layout: wgpu::BindGroupLayout,
-    initial_bind_group_layout: wgpu::BindGroupLayout,
     initial_bind_group: wgpu::BindGroup,
     bind_groups: Vec<wgpu::BindGroup>,
 }
@@ -21,36 +20,40 @@ impl DepthReducePhase {
         render_state: &RenderState,
         depth_pyramid: &DepthPyramid,
     ) -> Self {
-        let bind_group_layout = create_bind_group_layout(context, false);
-        let initial_bind_group_layout = create_bind_group_layout(context, true);
+        let bind_group_layout = create_bind_group_layout(context);
 
         let initial_bind_group = create_bind_group(
             context,
             &render_state.depth.view,
-            depth_pyramid.mips.first().unwrap(),
-            &initial_bind_group_layout,
+            &depth_pyramid.mips[0].view,
+            &bind_group_layout,
         );
 
         let bind_groups: Vec<_> = depth_pyramid
             .mips
             .windows(2)
-            .map(|window| create_bind_group(context, &window[0], &window[1], &bind_group_layout))
+            .map(|window| {
+                create_bind_group(
+                    context,
+                    &window[0].view,
+                    &window[1].view,
+                    &bind_group_layout,
+                )
+            })
             .collect();
 
         let initial_shader = create_shader(context, true);
         let shader = create_shader(context, false);
 
-        let initial_pipeline_layout = create_pipeline_layout(context, &initial_bind_group_layout);
         let pipeline_layout = create_pipeline_layout(context, &bind_group_layout);
 
-        let initial_reduce = create_pipeline(context, &initial_pipeline_layout, initial_shader);
+        let initial_reduce = create_pipeline(context, &pipeline_layout, initial_shader);
         let reduce = create_pipeline(context, &pipeline_layout, shader);
 
         Self {
             initial_reduce,
             reduce,
             bind_group_layout,
-            initial_bind_group_layout,
             initial_bind_group,
             bind_groups,
         }
@@ -65,15 +68,20 @@ impl DepthReducePhase {
         self.initial_bind_group = create_bind_group(
             context,
             &render_state.depth.view,
-            depth_pyramid.mips.first().unwrap(),
-            &self.initial_bind_group_layout,
+            &depth_pyramid.mips[0].view,
+            &self.bind_group_layout,
         );
 
         self.bind_groups = depth_pyramid
             .mips
             .windows(2)
             .map(|window| {
-                create_bind_group(context, &window[0], &window[1], &self.bind_group_layout)
+                create_bind_group(
+                    context,
+                    &window[0].view,
+                    &window[1].view,
+                    &self.bind_group_layout,
+                )
             })
             .collect();
     }
@@ -92,7 +100,7 @@ impl DepthReducePhase {
         compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
         compute_pass.set_bind_group(1, &self.initial_bind_group, &[]);
 
-        let size = depth_pyramid.texture.size();
+        let size = depth_pyramid.mips[0].texture.size();
         compute_pass.dispatch_workgroups(
             util::div_ceil(size.width, 8),
             util::div_ceil(size.height, 8),
@@ -101,10 +109,10 @@ impl DepthReducePhase {
 
         compute_pass.set_pipeline(&self.reduce);
 
-        for (depth, bind_group) in self.bind_groups.iter().enumerate() {
+        for (bind_group, mip) in self.bind_groups.iter().zip(&depth_pyramid.mips[1..]) {
             compute_pass.set_bind_group(1, bind_group, &[]);
 
-            let size = size.mip_level_size(depth as u32 + 1, wgpu::TextureDimension::D2);
+            let size = mip.texture.size();
             compute_pass.dispatch_workgroups(
                 util::div_ceil(size.width, 8),
                 util::div_ceil(size.height, 8),
@@ -181,17 +189,15 @@ fn create_bind_group(
         })
 }
 
-fn create_bind_group_layout(context: &Context, initial: bool) -> wgpu::BindGroupLayout {
+fn create_bind_group_layout(context: &Context) -> wgpu::BindGroupLayout {
     let entries = [
         wgpu::BindGroupLayoutEntry {
             binding: 0,
             visibility: wgpu::ShaderStages::COMPUTE,
             ty: wgpu::BindingType::Texture {
-                sample_type: if initial {
-                    wgpu::TextureSampleType::Depth
-                } else {
-                    wgpu::TextureSampleType::Float { filterable: true }
-                },
+                // Depth textures are loaded as plain floats, which GL needs to read them in
+                // compute shaders.
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
                 view_dimension: wgpu::TextureViewDimension::D2,
                 multisampled: false,
             },
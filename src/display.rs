@@ -1,20 +1,228 @@
-use std::{array, borrow::Cow, f32::consts::TAU, mem, time::Duration};
+use std::path::Path;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+use std::{array, borrow::Cow, mem, time::Duration};
 
 use bytemuck::NoUninit;
+use glam::{Vec3, Vec4};
+use half::f16;
+use serde::Deserialize;
 
 use crate::{
-    context::{self, Context},
+    camera::Camera,
+    context::{Context, DisplayOutput},
+    grading::{ColorGrading, Lut, Tonemapper},
+    resources::ParamsBuffer,
     util,
 };
 
+/// How the luminance of the scene is scaled before tonemapping.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExposureSettings {
+    pub mode: ExposureMode,
+    /// Stops added to the exposure. Positive values brighten the image.
+    pub compensation: f32,
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        Self {
+            mode: ExposureMode::Auto(AutoExposure::default()),
+            compensation: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum ExposureMode {
+    /// A fixed exposure value at ISO 100.
+    Manual { ev100: f32 },
+    /// Adapt to the average luminance of the scene.
+    Auto(AutoExposure),
+    /// The aperture, shutter speed and ISO of the physical camera of the view, or of
+    /// `PhysicalCamera::default` if it doesn't have one.
+    Physical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AutoExposure {
+    /// The range of exposure values at ISO 100 auto exposure picks from.
+    pub min_ev100: f32,
+    pub max_ev100: f32,
+    /// How fast the exposure adapts when the scene gets brighter, in 1/seconds.
+    pub speed_up: f32,
+    /// How fast the exposure adapts when the scene gets darker, in 1/seconds.
+    pub speed_down: f32,
+    pub metering: Metering,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            min_ev100: -5.0,
+            max_ev100: 6.5,
+            speed_up: 3.0,
+            speed_down: 1.0,
+            metering: Metering::Average,
+        }
+    }
+}
+
+/// How pixels are weighted when measuring the average luminance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum Metering {
+    #[default]
+    Average,
+    /// Pixels near the center count more.
+    CenterWeighted,
+    /// Only pixels within `radius` of the center count. The radius is relative to the height of
+    /// the view.
+    Spot { radius: f32 },
+}
+
+/// The luminance a scene of exposure value `ev100` is measured to have. Uses the reflected light
+/// meter constant of 12.5.
+pub fn ev100_to_luminance(ev100: f32) -> f32 {
+    ev100.exp2() * 12.5 / 100.0
+}
+
+pub fn luminance_to_ev100(luminance: f32) -> f32 {
+    (luminance * 100.0 / 12.5).log2()
+}
+
+/// The exposure of the saturation based sensitivity model.
+pub fn ev100_to_exposure(ev100: f32) -> f32 {
+    1.0 / (1.2 * ev100.exp2())
+}
+
+/// The display transform used for HDR output.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HdrSettings {
+    /// Output HDR if the display supports it. Only read at startup.
+    pub enabled: bool,
+    /// The brightness in nits of diffuse white, which SDR content is displayed at.
+    pub paper_white: f32,
+    /// The brightness in nits highlights are compressed to.
+    pub peak: f32,
+}
+
+impl Default for HdrSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            paper_white: 203.0,
+            peak: 1000.0,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, NoUninit)]
 struct LuminanceParams {
     min_log_luminance: f32,
     inverse_log_luminance_range: f32,
     log_luminance_range: f32,
-    time_coeff: f32,
-    pixel_count: u32,
+    speed_up_coeff: f32,
+    speed_down_coeff: f32,
+    metering: u32,
+    spot_radius: f32,
+    /// The exposure is scaled by this.
+    compensation: f32,
+    /// Used instead of auto exposure if greater than zero.
+    manual_exposure: f32,
+}
+
+/// The state of `Exposure` in `luminance.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
+
+/// Must match `Params` in `display.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct DisplayParams {
+    white_balance: [Vec4; 3],
+    /// The domain of the grading LUT. `w` of the minimum is zero if there is no grading LUT.
+    lut_domain_min: Vec4,
+    lut_domain_max: Vec4,
+    tonemapper: u32,
+    white_point: f32,
+    contrast: f32,
+    saturation: f32,
+    output: u32,
+    paper_white: f32,
+    /// The peak brightness relative to paper white.
+    max_value: f32,
+    padding: u32,
+}
+
+/// A 3D LUT uploaded to the GPU.
+struct LutTexture {
+    view: wgpu::TextureView,
+    domain_min: Vec3,
+    domain_max: Vec3,
+}
+
+impl LutTexture {
+    fn new(context: &Context, lut: &Lut) -> Self {
+        let size = wgpu::Extent3d {
+            width: lut.size,
+            height: lut.size,
+            depth_or_array_layers: lut.size,
+        };
+
+        let data: Vec<f16> = lut
+            .data
+            .iter()
+            .flat_map(|color| color.extend(1.0).to_array())
+            .map(f16::from_f32)
+            .collect();
+
+        let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("lut"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        context.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&data),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(lut.size * mem::size_of::<[f16; 4]>() as u32),
+                rows_per_image: Some(lut.size),
+            },
+            size,
+        );
+
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            domain_min: lut.domain_min,
+            domain_max: lut.domain_max,
+        }
+    }
+}
+
+enum ReadbackState {
+    Idle,
+    Copied,
+    Mapping,
 }
 
 pub struct DisplayPhase {
@@ -24,6 +232,23 @@ pub struct DisplayPhase {
     luminance_bind_group: wgpu::BindGroup,
     luminance_histogram: wgpu::ComputePipeline,
     luminance_average: wgpu::ComputePipeline,
+    luminance_params_buffer: ParamsBuffer<LuminanceParams>,
+    display_params_buffer: ParamsBuffer<DisplayParams>,
+    exposure_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    readback_state: ReadbackState,
+    readback_mapped: Arc<AtomicBool>,
+    measured: Exposure,
+    pub exposure: ExposureSettings,
+    grading_bind_group_layout: wgpu::BindGroupLayout,
+    grading_bind_group: wgpu::BindGroup,
+    lut_sampler: wgpu::Sampler,
+    tonemap_lut: Option<LutTexture>,
+    grading_lut: Option<LutTexture>,
+    identity_lut: LutTexture,
+    color_grading: ColorGrading,
+    output: DisplayOutput,
+    pub hdr: HdrSettings,
 }
 
 impl DisplayPhase {
@@ -50,30 +275,49 @@ impl DisplayPhase {
                 .device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                     label: Some("display"),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
                         },
-                        count: None,
-                    }],
+                        ParamsBuffer::<DisplayParams>::layout_entry(
+                            1,
+                            wgpu::ShaderStages::FRAGMENT,
+                            false,
+                        ),
+                    ],
                 });
 
-        let display_bind_group =
-            create_display_bind_group(context, input, &display_bind_group_layout);
+        let display_params_buffer = ParamsBuffer::new(context, "display params", 1);
+        let luminance_params_buffer = ParamsBuffer::new(context, "luminance params", 1);
+
+        let display_bind_group = create_display_bind_group(
+            context,
+            input,
+            &display_params_buffer,
+            &display_bind_group_layout,
+        );
 
-        let entries: [_; 2] = array::from_fn(|binding| wgpu::BindGroupLayoutEntry {
-            binding: binding as u32,
-            visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                has_dynamic_offset: false,
-                min_binding_size: None,
+        let entries: [_; 3] = array::from_fn(|binding| match binding {
+            2 => {
+                ParamsBuffer::<LuminanceParams>::layout_entry(2, wgpu::ShaderStages::COMPUTE, false)
+            }
+            binding => wgpu::BindGroupLayoutEntry {
+                binding: binding as u32,
+                visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            count: None,
         });
 
         let luminance_bind_group_layout =
@@ -84,12 +328,63 @@ impl DisplayPhase {
                     entries: &entries,
                 });
 
+        let lut_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D3,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let grading_bind_group_layout =
+            context
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("color grading"),
+                    entries: &[
+                        lut_entry(0),
+                        lut_entry(1),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                });
+
+        let lut_sampler = context.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("lut sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            ..Default::default()
+        });
+
+        let identity_lut = LutTexture::new(context, &Lut::identity(2));
+        let grading_bind_group = create_grading_bind_group(
+            context,
+            &grading_bind_group_layout,
+            &lut_sampler,
+            &identity_lut,
+            &identity_lut,
+        );
+
         let pipeline_layout =
             context
                 .device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("display"),
-                    bind_group_layouts: &[&display_bind_group_layout, &luminance_bind_group_layout],
+                    bind_group_layouts: &[
+                        &display_bind_group_layout,
+                        &luminance_bind_group_layout,
+                        &grading_bind_group_layout,
+                    ],
                     push_constant_ranges: &[],
                 });
 
@@ -128,10 +423,17 @@ impl DisplayPhase {
             mapped_at_creation: false,
         });
 
-        let average_luminance = context.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("average luminance"),
-            size: mem::size_of::<f32>() as u64,
-            usage: wgpu::BufferUsages::STORAGE,
+        let exposure_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("exposure"),
+            size: mem::size_of::<Exposure>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("exposure readback"),
+            size: mem::size_of::<Exposure>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
@@ -152,11 +454,15 @@ impl DisplayPhase {
                     wgpu::BindGroupEntry {
                         binding: 1,
                         resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &average_luminance,
+                            buffer: &exposure_buffer,
                             offset: 0,
                             size: None,
                         }),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: luminance_params_buffer.binding(),
+                    },
                 ],
             });
 
@@ -173,21 +479,238 @@ impl DisplayPhase {
             luminance_bind_group,
             luminance_histogram,
             luminance_average,
+            luminance_params_buffer,
+            display_params_buffer,
+            exposure_buffer,
+            readback_buffer,
+            readback_state: ReadbackState::Idle,
+            readback_mapped: Arc::new(AtomicBool::new(false)),
+            measured: Exposure::default(),
+            exposure: ExposureSettings::default(),
+            grading_bind_group_layout,
+            grading_bind_group,
+            lut_sampler,
+            tonemap_lut: None,
+            grading_lut: None,
+            identity_lut,
+            color_grading: ColorGrading::default(),
+            output: context.display_output,
+            hdr: HdrSettings::default(),
+        }
+    }
+
+    pub fn output(&self) -> DisplayOutput {
+        self.output
+    }
+
+    pub fn color_grading(&self) -> &ColorGrading {
+        &self.color_grading
+    }
+
+    /// Apply `color_grading`, loading its LUTs if they have changed. Nothing changes if a LUT
+    /// fails to load.
+    pub fn set_color_grading(
+        &mut self,
+        context: &Context,
+        color_grading: ColorGrading,
+    ) -> eyre::Result<()> {
+        let load = |path: Option<&Path>| -> eyre::Result<Option<LutTexture>> {
+            let Some(path) = path else {
+                return Ok(None);
+            };
+
+            let lut = Lut::load(path)?;
+            Ok(Some(LutTexture::new(context, &lut)))
+        };
+
+        // The outer option is `None` if the LUT hasn't changed.
+        let tonemap_lut = (color_grading.tony_mc_mapface_lut
+            != self.color_grading.tony_mc_mapface_lut)
+            .then(|| load(color_grading.tony_mc_mapface_lut.as_deref()))
+            .transpose()?;
+        let grading_lut = (color_grading.lut != self.color_grading.lut)
+            .then(|| load(color_grading.lut.as_deref()))
+            .transpose()?;
+
+        let has_tonemap_lut = match &tonemap_lut {
+            Some(lut) => lut.is_some(),
+            None => self.tonemap_lut.is_some(),
+        };
+
+        if color_grading.tonemapper == Tonemapper::TonyMcMapface && !has_tonemap_lut {
+            return Err(eyre::eyre!(
+                "Tony McMapface requires `tony_mc_mapface_lut` to be set"
+            ));
+        }
+
+        if let Some(lut) = tonemap_lut {
+            self.tonemap_lut = lut;
+        }
+
+        if let Some(lut) = grading_lut {
+            self.grading_lut = lut;
+        }
+
+        self.grading_bind_group = create_grading_bind_group(
+            context,
+            &self.grading_bind_group_layout,
+            &self.lut_sampler,
+            self.tonemap_lut.as_ref().unwrap_or(&self.identity_lut),
+            self.grading_lut.as_ref().unwrap_or(&self.identity_lut),
+        );
+
+        self.color_grading = color_grading;
+
+        Ok(())
+    }
+
+    fn display_params(&self) -> DisplayParams {
+        let grading = &self.color_grading;
+        let white_balance = grading.white_balance();
+
+        let (tonemapper, white_point) = match grading.tonemapper {
+            Tonemapper::AcesFitted => (0, 0.0),
+            Tonemapper::AgX => (1, 0.0),
+            Tonemapper::TonyMcMapface => (2, 0.0),
+            Tonemapper::ReinhardExtended { white_point } => (3, white_point),
+            Tonemapper::PbrNeutral => (4, 0.0),
+        };
+
+        let (lut_domain_min, lut_domain_max) = match &self.grading_lut {
+            Some(lut) => (lut.domain_min.extend(1.0), lut.domain_max.extend(1.0)),
+            None => (Vec4::ZERO, Vec4::ONE),
+        };
+
+        DisplayParams {
+            white_balance: [
+                white_balance.x_axis.extend(0.0),
+                white_balance.y_axis.extend(0.0),
+                white_balance.z_axis.extend(0.0),
+            ],
+            lut_domain_min,
+            lut_domain_max,
+            tonemapper,
+            white_point,
+            contrast: grading.contrast,
+            saturation: grading.saturation,
+            output: match self.output {
+                DisplayOutput::Sdr => 0,
+                DisplayOutput::Scrgb => 1,
+                DisplayOutput::Hdr10 => 2,
+            },
+            paper_white: self.hdr.paper_white,
+            max_value: (self.hdr.peak / self.hdr.paper_white).max(1.0),
+            padding: 0,
+        }
+    }
+
+    /// The average luminance of the scene the last time it was read back. This is adapted over
+    /// time in the same way as the auto exposure.
+    pub fn measured_luminance(&self) -> f32 {
+        self.measured.average_luminance
+    }
+
+    /// The exposure applied the last time it was read back, or zero before the first read back.
+    pub fn measured_exposure(&self) -> f32 {
+        self.measured.exposure
+    }
+
+    /// Read back the exposure if it has been mapped.
+    fn update_readback(&mut self, context: &Context) {
+        context.device.poll(wgpu::Maintain::Poll);
+
+        if let ReadbackState::Mapping = self.readback_state {
+            if self.readback_mapped.load(atomic::Ordering::Acquire) {
+                {
+                    let data = self.readback_buffer.slice(..).get_mapped_range();
+                    self.measured = bytemuck::pod_read_unaligned(&data);
+                }
+
+                self.readback_buffer.unmap();
+                self.readback_state = ReadbackState::Idle;
+            }
+        }
+    }
+
+    /// Start mapping the exposure copied this frame. Must be called after the frame is
+    /// submitted.
+    pub fn map_readback(&mut self) {
+        if let ReadbackState::Copied = self.readback_state {
+            let mapped = self.readback_mapped.clone();
+            mapped.store(false, atomic::Ordering::Release);
+
+            self.readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    if let Err(err) = result {
+                        eprintln!("failed to map exposure: {err}");
+                    } else {
+                        mapped.store(true, atomic::Ordering::Release);
+                    }
+                });
+
+            self.readback_state = ReadbackState::Mapping;
+        }
+    }
+
+    fn luminance_params(&self, camera: &Camera, delta_time: Duration) -> LuminanceParams {
+        let auto = match self.exposure.mode {
+            ExposureMode::Auto(auto) => auto,
+            _ => AutoExposure::default(),
+        };
+
+        let manual_exposure = match self.exposure.mode {
+            ExposureMode::Manual { ev100 } => ev100_to_exposure(ev100),
+            ExposureMode::Physical => camera.physical.unwrap_or_default().exposure(),
+            ExposureMode::Auto(_) => 0.0,
+        };
+
+        let min_log_luminance = ev100_to_luminance(auto.min_ev100).log2();
+        let max_log_luminance = ev100_to_luminance(auto.max_ev100).log2();
+        let log_luminance_range = (max_log_luminance - min_log_luminance).max(0.001);
+
+        let time_coeff = |speed: f32| 1.0 - (-delta_time.as_secs_f32() * speed).exp();
+
+        let (metering, spot_radius) = match auto.metering {
+            Metering::Average => (0, 0.0),
+            Metering::CenterWeighted => (1, 0.0),
+            Metering::Spot { radius } => (2, radius),
+        };
+
+        LuminanceParams {
+            min_log_luminance,
+            log_luminance_range,
+            inverse_log_luminance_range: log_luminance_range.recip(),
+            speed_up_coeff: time_coeff(auto.speed_up),
+            speed_down_coeff: time_coeff(auto.speed_down),
+            metering,
+            spot_radius,
+            compensation: self.exposure.compensation.exp2(),
+            manual_exposure,
         }
     }
 
     pub fn resize_surface(&mut self, context: &Context, input: &wgpu::TextureView) {
-        self.display_bind_group =
-            create_display_bind_group(context, input, &self.display_bind_group_layout);
+        self.display_bind_group = create_display_bind_group(
+            context,
+            input,
+            &self.display_params_buffer,
+            &self.display_bind_group_layout,
+        );
     }
 
     pub fn record(
-        &self,
+        &mut self,
         context: &Context,
         delta_time: Duration,
+        camera: &Camera,
         frame_buffer: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
     ) {
+        self.update_readback(context);
+        self.display_params_buffer
+            .write(context, 0, &self.display_params());
+
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("luminance"),
         });
@@ -196,21 +719,8 @@ impl DisplayPhase {
         compute_pass.set_bind_group(0, &self.display_bind_group, &[]);
         compute_pass.set_bind_group(1, &self.luminance_bind_group, &[]);
 
-        let min_log_luminance = -8.0;
-        let max_log_luminance = 3.5;
-
-        let log_luminance_range = max_log_luminance - min_log_luminance;
-        let time_coeff = f32::clamp(1.0 - (-delta_time.as_secs_f32() * TAU).exp(), 0.0, 1.0);
-
-        let params = LuminanceParams {
-            min_log_luminance,
-            log_luminance_range,
-            inverse_log_luminance_range: log_luminance_range.recip(),
-            pixel_count: context.surface_size.width * context.surface_size.height,
-            time_coeff,
-        };
-
-        compute_pass.set_push_constants(0, bytemuck::bytes_of(&params));
+        let params = self.luminance_params(camera, delta_time);
+        self.luminance_params_buffer.write(context, 0, &params);
 
         let x = util::div_ceil(context.surface_size.width, 16);
         let y = util::div_ceil(context.surface_size.height, 16);
@@ -221,11 +731,23 @@ impl DisplayPhase {
 
         drop(compute_pass);
 
+        if let ReadbackState::Idle = self.readback_state {
+            encoder.copy_buffer_to_buffer(
+                &self.exposure_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                self.exposure_buffer.size(),
+            );
+
+            self.readback_state = ReadbackState::Copied;
+        }
+
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("display"),
             depth_stencil_attachment: None,
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &frame_buffer,
+                view: frame_buffer,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Load,
@@ -237,10 +759,41 @@ impl DisplayPhase {
         render_pass.set_pipeline(&self.display);
         render_pass.set_bind_group(0, &self.display_bind_group, &[]);
         render_pass.set_bind_group(1, &self.luminance_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.grading_bind_group, &[]);
+
         render_pass.draw(0..3, 0..1);
     }
 }
 
+fn create_grading_bind_group(
+    context: &Context,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    tonemap_lut: &LutTexture,
+    grading_lut: &LutTexture,
+) -> wgpu::BindGroup {
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("color grading"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&tonemap_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&grading_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+}
+
 fn create_luminance_pipelines(
     context: &mut Context,
     display_bind_group_layout: &wgpu::BindGroupLayout,
@@ -251,43 +804,22 @@ fn create_luminance_pipelines(
             .device
             .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("luminance"),
-                bind_group_layouts: &[&display_bind_group_layout, &luminance_bind_group_layout],
-                push_constant_ranges: &[wgpu::PushConstantRange {
-                    stages: wgpu::ShaderStages::COMPUTE,
-                    range: 0..mem::size_of::<LuminanceParams>() as u32,
-                }],
-            });
-
-    let luminance_shader_source = include_str!("shaders/luminance.wgsl");
-    let luminance_shader_path = "shaders/luminance.wgsl";
-
-    let luminance_histogram_module = context.create_shader_module(
-        luminance_shader_source,
-        luminance_shader_path,
-        &[("BUILD_HISTOGRAM", context::ShaderDefValue::Bool(true))],
-    );
-
-    let luminance_histogram_shader =
-        context
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("luminance histogram"),
-                source: wgpu::ShaderSource::Naga(Cow::Owned(luminance_histogram_module)),
+                bind_group_layouts: &[display_bind_group_layout, luminance_bind_group_layout],
+                push_constant_ranges: &[],
             });
 
-    let luminance_average_module = context.create_shader_module(
-        luminance_shader_source,
-        luminance_shader_path,
-        &[("BUILD_HISTOGRAM", context::ShaderDefValue::Bool(false))],
+    let luminance_module = context.create_shader_module(
+        include_str!("shaders/luminance.wgsl"),
+        "shaders/luminance.wgsl",
+        &[],
     );
 
-    let luminance_average_shader =
-        context
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("average luminance"),
-                source: wgpu::ShaderSource::Naga(Cow::Owned(luminance_average_module)),
-            });
+    let luminance_shader = context
+        .device
+        .create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("luminance"),
+            source: wgpu::ShaderSource::Naga(Cow::Owned(luminance_module)),
+        });
 
     let luminance_histogram =
         context
@@ -295,7 +827,7 @@ fn create_luminance_pipelines(
             .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                 label: Some("luminance histogram"),
                 layout: Some(&luminance_pipeline_layout),
-                module: &luminance_histogram_shader,
+                module: &luminance_shader,
                 entry_point: "build_histogram",
             });
 
@@ -305,7 +837,7 @@ fn create_luminance_pipelines(
             .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                 label: Some("average luminance"),
                 layout: Some(&luminance_pipeline_layout),
-                module: &luminance_average_shader,
+                module: &luminance_shader,
                 entry_point: "compute_average",
             });
 
@@ -315,6 +847,7 @@ fn create_luminance_pipelines(
 fn create_display_bind_group(
     context: &Context,
     display: &wgpu::TextureView,
+    params: &ParamsBuffer<DisplayParams>,
     layout: &wgpu::BindGroupLayout,
 ) -> wgpu::BindGroup {
     context
@@ -322,9 +855,27 @@ fn create_display_bind_group(
         .create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("display"),
             layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(display),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(display),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params.binding(),
+                },
+            ],
         })
 }
+
+#[test]
+fn ev100_luminance_round_trip() {
+    for ev100 in [-4.0, 0.0, 3.5, 12.0] {
+        let luminance = ev100_to_luminance(ev100);
+        assert!((luminance_to_ev100(luminance) - ev100).abs() < 1e-4);
+    }
+
+    // The luminance measured at an exposure value is exposed to `0.125 / 1.2` at that value.
+    let exposed = ev100_to_luminance(0.0) * ev100_to_exposure(0.0);
+    assert!((exposed - 0.104).abs() < 1e-3);
+}
@@ -0,0 +1,422 @@
+use std::{borrow::Cow, mem, time::Duration};
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+use crate::{
+    camera::{Camera, PhysicalCamera},
+    context::Context,
+    resources::{self, ConstState, Consts, ParamsBuffer, RenderState, RenderTarget},
+    util,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DepthOfFieldSettings {
+    /// Depth of field needs the aperture of a physical camera, so it's skipped for cameras
+    /// without one even if enabled.
+    pub enabled: bool,
+    /// Focus on the depth at the center of the screen instead of `PhysicalCamera::focus_distance`.
+    pub autofocus: bool,
+    /// How fast autofocus adjusts. The rate at which the remaining distance shrinks per second.
+    pub focus_speed: f32,
+    /// The largest radius of the circle of confusion in pixels. Larger values need more samples
+    /// to look smooth.
+    pub max_coc: f32,
+}
+
+impl Default for DepthOfFieldSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            autofocus: true,
+            focus_speed: 5.0,
+            max_coc: 16.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct DofParams {
+    coc_scale: f32,
+    focal_length: f32,
+    focus_distance: f32,
+    max_coc: f32,
+    autofocus: u32,
+    focus_blend: f32,
+    padding: [f32; 2],
+}
+
+impl DofParams {
+    fn new(
+        camera: &PhysicalCamera,
+        settings: &DepthOfFieldSettings,
+        delta_time: Duration,
+        height: u32,
+    ) -> Self {
+        let focal_length = camera.focal_length / 1000.0;
+        let sensor_height = camera.sensor_size.y / 1000.0;
+        let aperture_diameter = focal_length / camera.aperture;
+
+        Self {
+            coc_scale: 0.5 * aperture_diameter * focal_length / sensor_height * height as f32,
+            focus_distance: camera.focus_distance.max(2.0 * focal_length),
+            max_coc: settings.max_coc.max(1.0),
+            autofocus: settings.autofocus as u32,
+            focus_blend: 1.0 - (-delta_time.as_secs_f32() * settings.focus_speed).exp(),
+            padding: [0.0; 2],
+            focal_length,
+        }
+    }
+
+    /// The same as `circle_of_confusion` in the shader.
+    #[cfg(test)]
+    fn circle_of_confusion(&self, distance: f32) -> f32 {
+        let scale = self.coc_scale / (self.focus_distance - self.focal_length);
+        let coc = scale * (1.0 - self.focus_distance / distance);
+        coc.clamp(-self.max_coc, self.max_coc)
+    }
+}
+
+/// The binding of the parameters in every pass. Must match `params` in `dof.wgsl`.
+const PARAMS_BINDING: u32 = 10;
+
+/// Gather based depth of field at half resolution, with separate near and far fields. It runs
+/// after the temporal resolve and replaces the post buffer.
+pub struct DepthOfFieldPhase {
+    passes: Vec<Pass>,
+    focus_buffer: wgpu::Buffer,
+    params: ParamsBuffer<DofParams>,
+    targets: Targets,
+}
+
+struct Pass {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+struct Targets {
+    half: RenderTarget,
+    near: RenderTarget,
+    far: RenderTarget,
+    output: RenderTarget,
+}
+
+impl Targets {
+    fn new(context: &Context) -> Self {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+        let half_size = half_size(context);
+
+        let target = |label, size| {
+            RenderTarget::new(context, label, size, resources::COLOR_BUFFER_FORMAT, usage)
+        };
+
+        Self {
+            half: target("dof half", half_size),
+            near: target("dof near", half_size),
+            far: target("dof far", half_size),
+            output: RenderTarget::new(
+                context,
+                "dof output",
+                context.surface_size,
+                resources::COLOR_BUFFER_FORMAT,
+                usage | wgpu::TextureUsages::COPY_SRC,
+            ),
+        }
+    }
+}
+
+fn half_size(context: &Context) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: util::div_ceil(context.surface_size.width, 2),
+        height: util::div_ceil(context.surface_size.height, 2),
+        depth_or_array_layers: 1,
+    }
+}
+
+const FILTERABLE: wgpu::TextureSampleType = wgpu::TextureSampleType::Float { filterable: true };
+const UNFILTERABLE: wgpu::TextureSampleType = wgpu::TextureSampleType::Float { filterable: false };
+
+impl DepthOfFieldPhase {
+    pub fn new(context: &mut Context, render_state: &RenderState) -> Self {
+        let module =
+            context.create_shader_module(include_str!("shaders/dof.wgsl"), "shaders/dof.wgsl", &[]);
+
+        let shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("depth of field"),
+                source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+            });
+
+        let focus_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("focus"),
+            size: mem::size_of::<f32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let targets = Targets::new(context);
+
+        let depth = util::texture_layout_entry(1, UNFILTERABLE);
+        let focus = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let storage =
+            |binding| util::storage_texture_layout_entry(binding, resources::COLOR_BUFFER_FORMAT);
+
+        let passes = [
+            ("dof focus", "update_focus", vec![depth, focus]),
+            (
+                "dof downsample",
+                "downsample",
+                vec![
+                    util::texture_layout_entry(0, FILTERABLE),
+                    depth,
+                    focus,
+                    storage(3),
+                ],
+            ),
+            (
+                "dof gather",
+                "gather",
+                vec![
+                    util::texture_layout_entry(4, FILTERABLE),
+                    storage(5),
+                    storage(6),
+                ],
+            ),
+            (
+                "dof composite",
+                "composite",
+                vec![
+                    util::texture_layout_entry(0, FILTERABLE),
+                    depth,
+                    focus,
+                    util::texture_layout_entry(7, FILTERABLE),
+                    util::texture_layout_entry(8, FILTERABLE),
+                    storage(9),
+                ],
+            ),
+        ];
+
+        let params = ParamsBuffer::new(context, "depth of field params", 1);
+        let resources = pass_resources(render_state, &focus_buffer, &params, &targets);
+
+        let passes = passes
+            .into_iter()
+            .zip(resources)
+            .map(|((label, entry_point, mut entries), resources)| {
+                entries.push(ParamsBuffer::<DofParams>::layout_entry(
+                    PARAMS_BINDING,
+                    wgpu::ShaderStages::COMPUTE,
+                    false,
+                ));
+
+                let bind_group_layout =
+                    context
+                        .device
+                        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                            label: Some(label),
+                            entries: &entries,
+                        });
+
+                let pipeline_layout =
+                    context
+                        .device
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            label: Some(label),
+                            bind_group_layouts: &[
+                                ConstState::bind_group_layout(context),
+                                &bind_group_layout,
+                            ],
+                            push_constant_ranges: &[],
+                        });
+
+                let pipeline =
+                    context
+                        .device
+                        .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                            label: Some(label),
+                            module: &shader,
+                            layout: Some(&pipeline_layout),
+                            entry_point,
+                        });
+
+                let bind_group = create_bind_group(context, &bind_group_layout, resources);
+
+                Pass {
+                    pipeline,
+                    bind_group_layout,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        Self {
+            passes,
+            focus_buffer,
+            params,
+            targets,
+        }
+    }
+
+    /// Blur the post buffer if `camera` has a physical camera. Returns false if it was skipped.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        context: &Context,
+        delta_time: Duration,
+        camera: &Camera,
+        settings: &DepthOfFieldSettings,
+        consts: &Consts,
+        const_state: &ConstState,
+        render_state: &RenderState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> bool {
+        let Some(physical) = camera.physical.filter(|_| settings.enabled) else {
+            return false;
+        };
+
+        let params = DofParams::new(&physical, settings, delta_time, consts.surface_size.y);
+        self.params.write(context, 0, &params);
+
+        let half_size = half_size(context);
+        let half = (
+            util::div_ceil(half_size.width, 8),
+            util::div_ceil(half_size.height, 8),
+        );
+        let full = (
+            util::div_ceil(consts.surface_size.x, 8),
+            util::div_ceil(consts.surface_size.y, 8),
+        );
+
+        let workgroups = [(1, 1), half, half, full];
+
+        for (pass, (x, y)) in self.passes.iter().zip(workgroups) {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("depth of field"),
+            });
+
+            compute_pass.set_pipeline(&pass.pipeline);
+            compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
+            compute_pass.set_bind_group(1, &pass.bind_group, &[]);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+
+        encoder.copy_texture_to_texture(
+            self.targets.output.texture.as_image_copy(),
+            render_state.post.texture.as_image_copy(),
+            context.surface_size,
+        );
+
+        true
+    }
+
+    pub fn resize_surface(&mut self, context: &Context, render_state: &RenderState) {
+        self.targets = Targets::new(context);
+
+        let resources = pass_resources(
+            render_state,
+            &self.focus_buffer,
+            &self.params,
+            &self.targets,
+        );
+
+        for (pass, resources) in self.passes.iter_mut().zip(resources) {
+            pass.bind_group = create_bind_group(context, &pass.bind_group_layout, resources);
+        }
+    }
+}
+
+/// The bindings and resources of each pass.
+fn pass_resources<'a>(
+    render_state: &'a RenderState,
+    focus_buffer: &'a wgpu::Buffer,
+    params: &'a ParamsBuffer<DofParams>,
+    targets: &'a Targets,
+) -> [Vec<(u32, wgpu::BindingResource<'a>)>; 4] {
+    use wgpu::BindingResource::TextureView;
+
+    let depth = || (1, TextureView(&render_state.depth.view));
+    let focus = || (2, focus_buffer.as_entire_binding());
+    let params = || (PARAMS_BINDING, params.binding());
+
+    [
+        vec![depth(), focus(), params()],
+        vec![
+            (0, TextureView(&render_state.post.view)),
+            depth(),
+            focus(),
+            (3, TextureView(&targets.half.view)),
+            params(),
+        ],
+        vec![
+            (4, TextureView(&targets.half.view)),
+            (5, TextureView(&targets.near.view)),
+            (6, TextureView(&targets.far.view)),
+            params(),
+        ],
+        vec![
+            (0, TextureView(&render_state.post.view)),
+            depth(),
+            focus(),
+            (7, TextureView(&targets.near.view)),
+            (8, TextureView(&targets.far.view)),
+            (9, TextureView(&targets.output.view)),
+            params(),
+        ],
+    ]
+}
+
+fn create_bind_group(
+    context: &Context,
+    layout: &wgpu::BindGroupLayout,
+    resources: Vec<(u32, wgpu::BindingResource)>,
+) -> wgpu::BindGroup {
+    let entries: Vec<_> = resources
+        .into_iter()
+        .map(|(binding, resource)| wgpu::BindGroupEntry { binding, resource })
+        .collect();
+
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth of field"),
+            entries: &entries,
+            layout,
+        })
+}
+
+#[test]
+fn circle_of_confusion() {
+    let camera = PhysicalCamera {
+        focus_distance: 10.0,
+        ..Default::default()
+    };
+
+    let settings = DepthOfFieldSettings {
+        max_coc: 100.0,
+        ..Default::default()
+    };
+
+    let params = DofParams::new(&camera, &settings, Duration::ZERO, 1080);
+
+    assert_eq!(params.circle_of_confusion(10.0), 0.0);
+    assert!(params.circle_of_confusion(5.0) < 0.0);
+
+    // A 50mm lens at f/1.4 focused at 10m blurs points at infinity into a circle of about 0.18mm
+    // on the 24mm high sensor, which is about 8 of 1080 pixels.
+    let far = params.circle_of_confusion(f32::INFINITY);
+    assert!((far - 4.04).abs() < 0.01, "{far}");
+}
@@ -0,0 +1,135 @@
+use std::borrow::Cow;
+
+use crate::{
+    context::Context,
+    resources::{self, ConstState, Consts, RenderState},
+    util,
+};
+
+pub struct FxaaPhase {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl FxaaPhase {
+    pub fn new(context: &mut Context, render_state: &RenderState) -> Self {
+        let module = context.create_shader_module(
+            include_str!("shaders/fxaa.wgsl"),
+            "shaders/fxaa.wgsl",
+            &[],
+        );
+
+        let shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("fxaa"),
+                source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+            });
+
+        let bind_group_layout =
+            context
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("fxaa"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                                format: resources::COLOR_BUFFER_FORMAT,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+
+        let pipeline_layout =
+            context
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("fxaa"),
+                    bind_group_layouts: &[
+                        ConstState::bind_group_layout(context),
+                        &bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+
+        let pipeline = context
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("fxaa"),
+                entry_point: "main",
+                module: &shader,
+                layout: Some(&pipeline_layout),
+            });
+
+        let bind_group = create_bind_group(context, render_state, &bind_group_layout);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn record(
+        &self,
+        consts: &Consts,
+        const_state: &ConstState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("fxaa"),
+        });
+
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_group, &[]);
+
+        let x = util::div_ceil(consts.surface_size.x, 8);
+        let y = util::div_ceil(consts.surface_size.y, 8);
+
+        compute_pass.dispatch_workgroups(x, y, 1);
+    }
+
+    pub fn resize_surface(&mut self, context: &Context, render_state: &RenderState) {
+        self.bind_group = create_bind_group(context, render_state, &self.bind_group_layout);
+    }
+}
+
+fn create_bind_group(
+    context: &Context,
+    render_state: &RenderState,
+    layout: &wgpu::BindGroupLayout,
+) -> wgpu::BindGroup {
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fxaa"),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&render_state.color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&render_state.post.view),
+                },
+            ],
+            layout,
+        })
+}
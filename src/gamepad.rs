@@ -0,0 +1,98 @@
+//! Gamepad input through gilrs. Gamepad buttons are bound to actions like keys, see
+//! `KeyBindings`, and the sticks are passed to `Inputs`.
+
+use eyre::Result;
+use gilrs::{Axis, Button, EventType, Gamepad, GamepadId, Gilrs};
+use glam::Vec2;
+use winit::event::ElementState;
+
+use crate::input::{Binding, GamepadButton, Inputs};
+
+pub struct Gamepads {
+    gilrs: Gilrs,
+    /// The gamepad that was used last. Only its sticks are read.
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    pub fn new() -> Result<Self> {
+        let gilrs =
+            Gilrs::new().map_err(|err| eyre::eyre!("failed to initialize gamepads: {err}"))?;
+
+        Ok(Self {
+            gilrs,
+            active: None,
+        })
+    }
+
+    /// Update the sticks of `inputs` and return the buttons pressed and released since the last
+    /// poll.
+    pub fn poll(&mut self, inputs: &mut Inputs) -> Vec<(Binding, ElementState)> {
+        let mut bindings = Vec::new();
+
+        while let Some(event) = self.gilrs.next_event() {
+            let (button, state) = match event.event {
+                EventType::ButtonPressed(button, _) => (button, ElementState::Pressed),
+                EventType::ButtonReleased(button, _) => (button, ElementState::Released),
+                EventType::Disconnected => {
+                    if self.active == Some(event.id) {
+                        self.active = None;
+                        inputs.release_gamepad();
+                    }
+
+                    continue;
+                }
+                _ => {
+                    self.active = Some(event.id);
+                    continue;
+                }
+            };
+
+            self.active = Some(event.id);
+
+            if let Some(button) = gamepad_button(button) {
+                bindings.push((Binding::Gamepad(button), state));
+            }
+        }
+
+        let gamepad = self.active.and_then(|id| self.gilrs.connected_gamepad(id));
+
+        match gamepad {
+            Some(gamepad) => inputs.set_sticks(
+                stick(&gamepad, Axis::LeftStickX, Axis::LeftStickY),
+                stick(&gamepad, Axis::RightStickX, Axis::RightStickY),
+            ),
+            None => inputs.set_sticks(Vec2::ZERO, Vec2::ZERO),
+        }
+
+        bindings
+    }
+}
+
+fn stick(gamepad: &Gamepad, x: Axis, y: Axis) -> Vec2 {
+    Vec2::new(gamepad.value(x), gamepad.value(y))
+}
+
+fn gamepad_button(button: Button) -> Option<GamepadButton> {
+    let button = match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        Button::C | Button::Z | Button::Mode | Button::Unknown => return None,
+    };
+
+    Some(button)
+}
@@ -0,0 +1,364 @@
+//! Golden image tests. Small synthetic scenes are rendered headlessly and compared to the
+//! reference images in `tests/golden` with SSIM, which tolerates the small differences between
+//! drivers that a per-pixel comparison wouldn't.
+//!
+//! The scenes are rendered with the fallback adapter, such as llvmpipe or WARP, so that the
+//! tests run the same on machines without a GPU. They fail if there is no such adapter or if a
+//! reference image is missing. Run them with `UPDATE_GOLDEN=1` to write new reference images:
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test golden
+//! ```
+//!
+//! When an image doesn't match, the rendered image and a diff image are written to
+//! `target/golden`.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use glam::{Quat, Vec3, Vec4};
+use image::{Rgb, RgbImage, RgbaImage};
+
+use crate::asset::{Geometry, Scene, SceneBuilder, Transform};
+use crate::camera::Camera;
+use crate::context::{ContextOptions, DisplayOutput};
+use crate::display::HdrSettings;
+use crate::renderer::Renderer;
+
+const IMAGE_SIZE: u32 = 128;
+
+/// Frames rendered before reading back, so that temporal accumulation and auto exposure settle.
+const FRAME_COUNT: u32 = 32;
+
+/// The lowest mean SSIM accepted as a match.
+const MIN_SSIM: f32 = 0.97;
+
+/// SSIM is computed over windows of this size centered at each pixel.
+const WINDOW_SIZE: i32 = 8;
+
+/// Held while rendering, since creating GL contexts on several threads at once fails.
+static RENDER_LOCK: Mutex<()> = Mutex::new(());
+
+/// Create a headless renderer showing `scene`. Panics if there is no adapter that supports the
+/// renderer.
+fn headless_renderer(scene: Scene, hdr: bool) -> Renderer {
+    let size = wgpu::Extent3d {
+        width: IMAGE_SIZE,
+        height: IMAGE_SIZE,
+        depth_or_array_layers: 1,
+    };
+
+    let options = ContextOptions {
+        force_fallback_adapter: true,
+        hdr,
+        ..Default::default()
+    };
+
+    let mut renderer = Renderer::headless(size, options)
+        .unwrap_or_else(|err| panic!("golden tests need a fallback adapter: {err:?}"));
+
+    renderer
+        .set_scene(scene)
+        .unwrap_or_else(|err| panic!("failed to upload scene: {err:?}"));
+
+    renderer
+}
+
+fn render(scene: Scene, camera: &Camera) -> RgbaImage {
+    let _guard = RENDER_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut renderer = headless_renderer(scene, false);
+    renderer.render_headless(camera, FRAME_COUNT)
+}
+
+/// The brightness in nits of a PQ encoded value.
+fn pq_decode(value: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.84375;
+    const C1: f32 = 0.8359375;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.6875;
+
+    let e = value.powf(1.0 / M2);
+    let y = ((e - C1).max(0.0) / (C2 - C3 * e)).powf(1.0 / M1);
+
+    y * 10000.0
+}
+
+fn assert_matches_reference(name: &str, image: &RgbaImage) {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reference_path = manifest_dir.join(format!("tests/golden/{name}.png"));
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(manifest_dir.join("tests/golden"))
+            .expect("failed to create reference image directory");
+        image
+            .save(&reference_path)
+            .unwrap_or_else(|err| panic!("failed to write {reference_path:?}: {err}"));
+        return;
+    }
+
+    let output_dir = manifest_dir.join("target/golden");
+    let output_path = |suffix: &str| -> PathBuf { output_dir.join(format!("{name}{suffix}.png")) };
+
+    let write_outputs = |diff: Option<&RgbImage>| {
+        std::fs::create_dir_all(&output_dir).expect("failed to create golden output directory");
+        image
+            .save(output_path(""))
+            .expect("failed to write rendered image");
+
+        if let Some(diff) = diff {
+            diff.save(output_path(".diff"))
+                .expect("failed to write diff image");
+        }
+    };
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.into_rgba8(),
+        Err(err) => {
+            write_outputs(None);
+            panic!(
+                "failed to open reference image {reference_path:?}: {err}. \
+                 Run with UPDATE_GOLDEN=1 to create it"
+            );
+        }
+    };
+
+    assert_eq!(
+        reference.dimensions(),
+        image.dimensions(),
+        "{name} has a different size than its reference image"
+    );
+
+    let (ssim, diff) = ssim(&reference, image);
+
+    if ssim < MIN_SSIM {
+        write_outputs(Some(&diff));
+        panic!(
+            "{name} doesn't match its reference image, SSIM is {ssim:.4} (min {MIN_SSIM}). \
+             See {:?} and {:?}",
+            output_path(""),
+            output_path(".diff"),
+        );
+    }
+}
+
+/// The mean structural similarity of `a` and `b`, and an image of the dissimilarity of each
+/// pixel. The similarity of a pixel is the lowest of its color channels, so that changes in hue
+/// are caught too.
+fn ssim(a: &RgbaImage, b: &RgbaImage) -> (f32, RgbImage) {
+    const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = a.dimensions();
+    let mut diff = RgbImage::new(width, height);
+    let mut total = 0.0;
+
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let window = (-WINDOW_SIZE / 2..WINDOW_SIZE / 2).flat_map(|dy| {
+                (-WINDOW_SIZE / 2..WINDOW_SIZE / 2).map(move |dx| {
+                    let x = (x + dx).clamp(0, width as i32 - 1) as u32;
+                    let y = (y + dy).clamp(0, height as i32 - 1) as u32;
+                    (a.get_pixel(x, y), b.get_pixel(x, y))
+                })
+            });
+
+            let similarity = (0..3)
+                .map(|channel| {
+                    let samples = window
+                        .clone()
+                        .map(|(a, b)| (a[channel] as f32, b[channel] as f32));
+
+                    let count = (WINDOW_SIZE * WINDOW_SIZE) as f32;
+                    let (sum_a, sum_b) = samples
+                        .clone()
+                        .fold((0.0, 0.0), |(sum_a, sum_b), (a, b)| (sum_a + a, sum_b + b));
+                    let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+
+                    let (var_a, var_b, covar) =
+                        samples.fold((0.0, 0.0, 0.0), |(var_a, var_b, covar), (a, b)| {
+                            let (a, b) = (a - mean_a, b - mean_b);
+                            (var_a + a * a, var_b + b * b, covar + a * b)
+                        });
+                    let (var_a, var_b, covar) = (var_a / count, var_b / count, covar / count);
+
+                    ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+                        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+                })
+                .fold(f32::INFINITY, f32::min);
+
+            let error = ((1.0 - similarity) * 255.0).clamp(0.0, 255.0) as u8;
+            diff.put_pixel(x as u32, y as u32, Rgb([error, 0, 0]));
+
+            total += similarity;
+        }
+    }
+
+    (total / (width * height) as f32, diff)
+}
+
+fn camera_looking_at(position: Vec3, target: Vec3) -> Camera {
+    let mut camera = Camera::new(1.0);
+    let front = (target - position).normalize();
+
+    camera.pos = position;
+    camera.set_orientation(
+        front.z.atan2(front.x).to_degrees(),
+        front.y.asin().to_degrees(),
+    );
+
+    camera
+}
+
+fn ground(builder: &mut SceneBuilder) {
+    let material = builder.add_material(Vec4::new(0.8, 0.8, 0.8, 1.0), 0.0, 0.9);
+    let plane = builder.add_mesh(&Geometry::plane(), material);
+
+    builder.add_instance(
+        plane,
+        Transform {
+            scale: Vec3::splat(10.0),
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn golden_sky() {
+    let camera = camera_looking_at(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.2, 0.0));
+
+    let image = render(Scene::default(), &camera);
+    assert_matches_reference("sky", &image);
+}
+
+#[test]
+fn golden_cube_shadow() {
+    let mut builder = SceneBuilder::new(Vec3::new(0.4, 1.0, 0.3), Vec3::splat(10.0));
+    ground(&mut builder);
+
+    let material = builder.add_material(Vec4::new(0.8, 0.2, 0.1, 1.0), 0.0, 0.5);
+    let cube = builder.add_mesh(&Geometry::cube(), material);
+
+    builder.add_instance(
+        cube,
+        Transform {
+            translation: Vec3::new(0.0, 1.0, 0.0),
+            rotation: Quat::from_rotation_y(0.5),
+            ..Default::default()
+        },
+    );
+
+    let camera = camera_looking_at(Vec3::new(-4.0, 3.0, -4.0), Vec3::new(0.0, 0.5, 0.0));
+
+    let image = render(builder.finish(), &camera);
+    assert_matches_reference("cube_shadow", &image);
+}
+
+#[test]
+fn golden_material_spheres() {
+    let mut builder = SceneBuilder::new(Vec3::new(-0.3, 1.0, 0.6), Vec3::splat(10.0));
+    ground(&mut builder);
+
+    let sphere = Geometry::sphere(32);
+
+    for (index, (metallic, roughness)) in [(0.0, 0.1), (0.0, 0.6), (1.0, 0.1), (1.0, 0.6)]
+        .into_iter()
+        .enumerate()
+    {
+        let material = builder.add_material(Vec4::new(0.9, 0.6, 0.2, 1.0), metallic, roughness);
+        let mesh = builder.add_mesh(&sphere, material);
+
+        builder.add_instance(
+            mesh,
+            Transform {
+                translation: Vec3::new(0.0, 0.5, index as f32 * 1.2 - 1.8),
+                scale: Vec3::splat(0.5),
+                ..Default::default()
+            },
+        );
+    }
+
+    let camera = camera_looking_at(Vec3::new(-5.0, 1.5, 0.0), Vec3::new(0.0, 0.5, 0.0));
+
+    let image = render(builder.finish(), &camera);
+    assert_matches_reference("material_spheres", &image);
+}
+
+#[test]
+fn hdr10_output_is_pq_encoded() {
+    let mut builder = SceneBuilder::new(Vec3::new(0.4, 1.0, 0.3), Vec3::splat(10.0));
+    ground(&mut builder);
+
+    let _guard = RENDER_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut renderer = headless_renderer(builder.finish(), true);
+
+    assert_eq!(renderer.display_output(), DisplayOutput::Hdr10);
+
+    let hdr = HdrSettings {
+        enabled: true,
+        paper_white: 200.0,
+        peak: 600.0,
+    };
+
+    renderer.set_hdr(hdr);
+
+    // The camera looks at the horizon, so that both the bright sky and the ground are visible.
+    let camera = camera_looking_at(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+    let pixels = renderer.render_headless_raw(&camera, FRAME_COUNT);
+
+    // Each pixel is packed as 10 bits of red, green and blue from the least significant bit.
+    let nits: Vec<f32> = pixels
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let packed = u32::from_le_bytes(pixel.try_into().unwrap());
+            (0..3).map(move |channel| {
+                let value = (packed >> (channel * 10)) & 0x3ff;
+                pq_decode(value as f32 / 1023.0)
+            })
+        })
+        .collect();
+
+    let max = nits.iter().copied().fold(0.0, f32::max);
+    let mean = nits.iter().sum::<f32>() / nits.len() as f32;
+
+    // 10 bits of PQ have steps of about 1% at these levels.
+    assert!(max <= hdr.peak * 1.02, "max brightness is {max} nits");
+
+    // Auto exposure brings the scene to around middle gray, which is 18% of paper white.
+    let middle_gray = 0.18 * hdr.paper_white;
+    assert!(
+        (middle_gray * 0.5..middle_gray * 2.0).contains(&mean),
+        "mean brightness is {mean} nits"
+    );
+}
+
+#[test]
+fn pq_decodes_reference_values() {
+    // Reference values from ITU-R BT.2100.
+    assert!(pq_decode(0.0) < 1e-3);
+    assert!((pq_decode(1.0) - 10000.0).abs() < 1.0);
+    assert!((pq_decode(0.508) - 100.0).abs() < 1.0);
+    assert!((pq_decode(0.752) - 1000.0).abs() < 10.0);
+}
+
+#[test]
+fn ssim_detects_changes() {
+    let image = RgbaImage::from_fn(32, 32, |x, y| {
+        image::Rgba([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8, 255])
+    });
+
+    let (identical, _) = ssim(&image, &image);
+    assert!((identical - 1.0).abs() < 1e-4);
+
+    let mut changed = image.clone();
+    for y in 8..24 {
+        for x in 8..24 {
+            changed.put_pixel(x, y, image::Rgba([255, 0, 255, 255]));
+        }
+    }
+
+    let (different, diff) = ssim(&image, &changed);
+    assert!(different < MIN_SSIM);
+    assert!(diff.get_pixel(16, 16)[0] > diff.get_pixel(0, 0)[0]);
+}
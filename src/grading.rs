@@ -0,0 +1,288 @@
+//! Tonemapping and color grading settings, and loading of 3D LUTs in the `.cube` format.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use glam::{Mat3, Vec2, Vec3};
+use serde::Deserialize;
+
+/// Maps the exposed scene luminance to the range of SDR displays. HDR output uses its own
+/// display transform.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+pub enum Tonemapper {
+    /// Krzysztof Narkowicz's per-channel fit of the ACES filmic curve.
+    #[default]
+    AcesFitted,
+    /// Troy Sobotka's AgX, with the polynomial fit of its default contrast curve.
+    AgX,
+    /// Tomasz Stachowiak's Tony McMapface. Samples `ColorGrading::tony_mc_mapface_lut`.
+    TonyMcMapface,
+    /// Reinhard applied to luminance. Luminance at `white_point` maps to white.
+    ReinhardExtended { white_point: f32 },
+    /// The Khronos PBR Neutral tonemapper, which keeps base colors close to sRGB.
+    PbrNeutral,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Self::AcesFitted => Self::AgX,
+            Self::AgX => Self::TonyMcMapface,
+            Self::TonyMcMapface => Self::ReinhardExtended { white_point: 4.0 },
+            Self::ReinhardExtended { .. } => Self::PbrNeutral,
+            Self::PbrNeutral => Self::AcesFitted,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ColorGrading {
+    pub tonemapper: Tonemapper,
+    /// The `.cube` LUT of Tony McMapface. It is distributed as a DDS file, which has to be
+    /// converted first.
+    pub tony_mc_mapface_lut: Option<PathBuf>,
+    /// A `.cube` LUT applied to the sRGB encoded output of the tonemapper. Only used for SDR
+    /// output.
+    pub lut: Option<PathBuf>,
+    /// The color temperature in kelvin of light that appears white. Lower values make the
+    /// image cooler.
+    pub temperature: f32,
+    /// Shifts the white point towards green. Positive values make the image more magenta.
+    pub tint: f32,
+    /// Scales the distance to middle gray in stops.
+    pub contrast: f32,
+    /// Zero is grayscale.
+    pub saturation: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::default(),
+            tony_mc_mapface_lut: None,
+            lut: None,
+            temperature: NEUTRAL_TEMPERATURE,
+            tint: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+/// The temperature at which white balance has no effect.
+const NEUTRAL_TEMPERATURE: f32 = 6500.0;
+
+impl ColorGrading {
+    /// The white balance as a matrix in linear sRGB. Adapts the white point given by
+    /// `temperature` and `tint` to the white point of the neutral temperature in the LMS space of
+    /// the Bradford transform.
+    pub fn white_balance(&self) -> Mat3 {
+        let rgb_to_xyz = Mat3::from_cols_array(&[
+            0.4124564, 0.2126729, 0.0193339, //
+            0.3575761, 0.7151522, 0.119_192, //
+            0.1804375, 0.0721750, 0.9503041,
+        ]);
+
+        let xyz_to_lms = Mat3::from_cols_array(&[
+            0.8951, -0.7502, 0.0389, //
+            0.2664, 1.7135, -0.0685, //
+            -0.1614, 0.0367, 1.0296,
+        ]);
+
+        let white_point = |temperature: f32, tint: f32| {
+            let xy = planckian_locus(temperature) + Vec2::new(0.0, tint * 0.02);
+            xyz_to_lms * Vec3::new(xy.x / xy.y, 1.0, (1.0 - xy.x - xy.y) / xy.y)
+        };
+
+        let source = white_point(self.temperature, self.tint);
+        let target = white_point(NEUTRAL_TEMPERATURE, 0.0);
+        let adaptation = Mat3::from_diagonal(target / source);
+
+        let rgb_to_lms = xyz_to_lms * rgb_to_xyz;
+        rgb_to_lms.inverse() * adaptation * rgb_to_lms
+    }
+}
+
+/// The chromaticity of a black body at `temperature` kelvin, clamped to `1667..=25000`. Uses the
+/// cubic spline approximation of Kim et al.
+fn planckian_locus(temperature: f32) -> Vec2 {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.107_038e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+
+    let (x2, x3) = (x * x, x * x * x);
+
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.348_110_2 * x2 + 2.185_558_3 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.374_185_9 * x2 + 2.091_37 * x - 0.16748867
+    } else {
+        3.081_758 * x3 - 5.873_387 * x2 + 3.751_129_9 * x - 0.37001483
+    };
+
+    Vec2::new(x, y)
+}
+
+/// A 3D lookup table. The red channel varies fastest in `data`, then green, then blue, which is
+/// also the order of texels in a 3D texture.
+#[derive(Clone, Debug)]
+pub struct Lut {
+    pub size: u32,
+    /// The input range mapped to the edges of the table.
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+    pub data: Vec<Vec3>,
+}
+
+impl Lut {
+    /// A table that maps each color to itself.
+    pub fn identity(size: u32) -> Self {
+        let scale = 1.0 / (size - 1) as f32;
+        let data = (0..size * size * size)
+            .map(|index| {
+                let (r, g, b) = (index % size, index / size % size, index / (size * size));
+                Vec3::new(r as f32, g as f32, b as f32) * scale
+            })
+            .collect();
+
+        Self {
+            size,
+            domain_min: Vec3::ZERO,
+            domain_max: Vec3::ONE,
+            data,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read LUT at {path:?}"))?;
+
+        Self::parse(&source).wrap_err_with(|| format!("failed to parse LUT at {path:?}"))
+    }
+
+    /// Parse the Adobe `.cube` format. 1D tables aren't supported.
+    fn parse(source: &str) -> Result<Self> {
+        let parse_vec3 = |values: &[&str]| -> Result<Vec3> {
+            let [r, g, b] = values else {
+                return Err(eyre::eyre!("expected 3 values, found {}", values.len()));
+            };
+
+            Ok(Vec3::new(r.parse()?, g.parse()?, b.parse()?))
+        };
+
+        let mut size = None;
+        let mut domain_min = Vec3::ZERO;
+        let mut domain_max = Vec3::ONE;
+        let mut data = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let error = || format!("invalid line {}: {line:?}", number + 1);
+
+            match words.as_slice() {
+                [] => continue,
+                [word, ..] if word.starts_with('#') => continue,
+                ["TITLE", ..] => continue,
+                ["LUT_1D_SIZE", ..] => return Err(eyre::eyre!("1D LUTs aren't supported")),
+                ["LUT_3D_SIZE", value] => {
+                    let value: u32 = value.parse().wrap_err_with(error)?;
+
+                    if !(2..=256).contains(&value) {
+                        return Err(eyre::eyre!("LUT size {value} is out of range"));
+                    }
+
+                    size = Some(value);
+                }
+                ["DOMAIN_MIN", values @ ..] => {
+                    domain_min = parse_vec3(values).wrap_err_with(error)?;
+                }
+                ["DOMAIN_MAX", values @ ..] => {
+                    domain_max = parse_vec3(values).wrap_err_with(error)?;
+                }
+                values => data.push(parse_vec3(values).wrap_err_with(error)?),
+            }
+        }
+
+        let size = size.ok_or_else(|| eyre::eyre!("LUT_3D_SIZE is missing"))?;
+        let expected = (size * size * size) as usize;
+
+        if data.len() != expected {
+            return Err(eyre::eyre!(
+                "expected {expected} entries for size {size}, found {}",
+                data.len()
+            ));
+        }
+
+        if domain_min.cmpge(domain_max).any() {
+            return Err(eyre::eyre!("DOMAIN_MIN must be less than DOMAIN_MAX"));
+        }
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            data,
+        })
+    }
+}
+
+#[test]
+fn parse_cube() {
+    let source = "
+        # Swaps red and blue
+        TITLE \"swap\"
+        LUT_3D_SIZE 2
+        DOMAIN_MIN 0 0 0
+        DOMAIN_MAX 1 1 2
+
+        0 0 0
+        0 0 1
+        0 1 0
+        0 1 1
+        1 0 0
+        1 0 1
+        1 1 0
+        1 1 1
+    ";
+
+    let lut = Lut::parse(source).unwrap();
+
+    assert_eq!(lut.size, 2);
+    assert_eq!(lut.domain_max, Vec3::new(1.0, 1.0, 2.0));
+    assert_eq!(lut.data[1], Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(lut.data[4], Vec3::new(1.0, 0.0, 0.0));
+
+    assert!(Lut::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+    assert!(Lut::parse("0 0 0\n").is_err());
+}
+
+#[test]
+fn identity_lut_order() {
+    let lut = Lut::identity(3);
+
+    assert_eq!(lut.data[1], Vec3::new(0.5, 0.0, 0.0));
+    assert_eq!(lut.data[3], Vec3::new(0.0, 0.5, 0.0));
+    assert_eq!(lut.data[9], Vec3::new(0.0, 0.0, 0.5));
+}
+
+#[test]
+fn neutral_white_balance() {
+    let neutral = ColorGrading::default().white_balance();
+    assert!(neutral.abs_diff_eq(Mat3::IDENTITY, 1e-5));
+
+    // Light that appears white at a lower temperature is more orange, so correcting for it makes
+    // the image bluer.
+    let warm = ColorGrading {
+        temperature: 3200.0,
+        ..Default::default()
+    };
+
+    let white = warm.white_balance() * Vec3::ONE;
+    assert!(white.z > white.x);
+}
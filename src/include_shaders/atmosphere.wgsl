@@ -0,0 +1,140 @@
+#define_import_path atmosphere
+#import util
+
+// Must match `AtmosphereParams` in `atmosphere.rs`.
+struct AtmosphereParams {
+    rayleigh_scattering: vec3f,
+    planet_radius: f32,
+    mie_scattering: vec3f,
+    atmosphere_height: f32,
+    mie_absorption: vec3f,
+    rayleigh_height: f32,
+    ozone_absorption: vec3f,
+    mie_height: f32,
+    ozone_center_height: f32,
+    ozone_width: f32,
+    mie_g: f32,
+    sample_count: u32,
+}
+
+@group(1) @binding(9)
+var<uniform> atmosphere_params: AtmosphereParams;
+
+// The height of the world origin above the ground.
+const VIEW_HEIGHT = 200.0;
+
+// Scales the scattered sun light to the brightness of the shaded scene.
+const SKY_EXPOSURE = 20.0;
+
+fn ray_sphere_intersection(ray: util::Ray, sphere: util::Sphere) -> vec2f {
+    let ray_origin = ray.origin - sphere.center;
+
+    let a = dot(ray.direction, ray.direction);
+    let b = 2.0 * dot(ray_origin, ray.direction);
+    let c  = dot(ray_origin, ray_origin) - (sphere.radius * sphere.radius);
+
+    let sqrt_disc = sqrt(b * b - 4.0 * a * c);
+
+    return vec2f((-b) - sqrt_disc, (-b) + sqrt_disc) / (2.0 * a);
+}
+
+fn planet_center() -> vec3f {
+    return vec3f(0.0, -atmosphere_params.planet_radius, 0.0);
+}
+
+fn planet_sphere() -> util::Sphere {
+    var planet: util::Sphere;
+    planet.center = planet_center();
+    planet.radius = atmosphere_params.planet_radius;
+    return planet;
+}
+
+fn atmosphere_sphere() -> util::Sphere {
+    var atmosphere: util::Sphere;
+    atmosphere.center = planet_center();
+    atmosphere.radius = atmosphere_params.planet_radius + atmosphere_params.atmosphere_height;
+    return atmosphere;
+}
+
+// The position in the atmosphere of a world space position.
+fn world_to_atmosphere(position: vec3f) -> vec3f {
+    return position + vec3f(0.0, VIEW_HEIGHT, 0.0);
+}
+
+fn height(position: vec3f) -> f32 {
+    return distance(position, planet_center()) - atmosphere_params.planet_radius;
+}
+
+fn rayleigh_density(height: f32) -> f32 {
+	return exp(-max(0.0, height / atmosphere_params.rayleigh_height));
+}
+
+fn mie_density(height: f32) -> f32 {
+	return exp(-max(0.0, height / atmosphere_params.mie_height));
+}
+
+fn ozone_density(height: f32) -> f32 {
+	let center = atmosphere_params.ozone_center_height;
+	return max(0.0, 1.0 - abs(height - center) / (atmosphere_params.ozone_width / 2.0));
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+	return 3.0 * (1.0 + cos_theta * cos_theta) / (16.0 * util::PI);
+}
+
+fn mie_phase(cos_theta: f32) -> f32 {
+	let G = atmosphere_params.mie_g;
+	let K = 1.55 * G - 0.55 * G * G * G;
+	return (1.0 - K * K) / ((4.0 * util::PI) * pow(1.0 - K * cos_theta, 2.0));
+}
+
+// The scattering and extinction coefficients at a height.
+struct Medium {
+    rayleigh_scattering: vec3f,
+    mie_scattering: vec3f,
+    extinction: vec3f,
+}
+
+fn sample_medium(height: f32) -> Medium {
+    var medium: Medium;
+
+    let mie_density = mie_density(height);
+
+    medium.rayleigh_scattering = atmosphere_params.rayleigh_scattering * rayleigh_density(height);
+    medium.mie_scattering = atmosphere_params.mie_scattering * mie_density;
+    medium.extinction = medium.rayleigh_scattering
+        + medium.mie_scattering
+        + atmosphere_params.mie_absorption * mie_density
+        + atmosphere_params.ozone_absorption * ozone_density(height);
+
+    return medium;
+}
+
+// The transmittance LUT is indexed by the cosine of the zenith angle of the view direction and
+// the height. The square root gives more precision close to the ground.
+fn transmittance_lut_uv(height: f32, cos_zenith: f32) -> vec2f {
+    let height_fraction = saturate(height / atmosphere_params.atmosphere_height);
+    return vec2f(cos_zenith * 0.5 + 0.5, sqrt(height_fraction));
+}
+
+fn transmittance_lut_params(uv: vec2f) -> vec2f {
+    let height = uv.y * uv.y * atmosphere_params.atmosphere_height;
+    let cos_zenith = uv.x * 2.0 - 1.0;
+    return vec2f(height, cos_zenith);
+}
+
+// The multi-scattering LUT is indexed by the cosine of the zenith angle of the sun and the
+// height.
+fn multi_scattering_lut_uv(height: f32, sun_cos_zenith: f32) -> vec2f {
+    let height_fraction = saturate(height / atmosphere_params.atmosphere_height);
+    return vec2f(sun_cos_zenith * 0.5 + 0.5, height_fraction);
+}
+
+fn multi_scattering_lut_params(uv: vec2f) -> vec2f {
+    return vec2f(uv.y * atmosphere_params.atmosphere_height, uv.x * 2.0 - 1.0);
+}
+
+// The cosine of the angle between `direction` and the zenith at `position`.
+fn cos_zenith(position: vec3f, direction: vec3f) -> f32 {
+    return dot(normalize(position - planet_center()), direction);
+}
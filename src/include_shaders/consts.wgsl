@@ -7,9 +7,11 @@ struct Consts {
     proj_view: mat4x4f,
     prev_proj_view: mat4x4f,
     inverse_proj_view: mat4x4f,
+    depth_linearize_coeffs: vec4f,
     sun: light::DirectionalLight,
     frustrum_z_planes: vec2f,
     surface_size: vec2u,
+    render_size: vec2u,
     jitter: vec2f,
     frame_index: u32,
     camera_fov: f32,
@@ -13,3 +13,16 @@ struct ShadowCascade {
     far: f32,
     padding: array<u32, 2>,
 }
+
+// The sun and moon as seen from the ground through the atmosphere, in the same units as the
+// skybox. Written by the sky bake. Must match `SKY_LIGHTS_SIZE` in `resources.rs`.
+struct SkyLights {
+    // The average radiance of the sun disk.
+    sun_radiance: vec3f,
+    sun_angular_radius: f32,
+    // The radiance of the moon where the sun light hits it head on.
+    moon_radiance: vec3f,
+    moon_angular_radius: f32,
+    moon_direction: vec3f,
+    star_brightness: f32,
+}
@@ -15,21 +15,80 @@ struct Primitive {
     bounding_sphere: BoundingSphere,
 };
 
+struct TextureTransform {
+    x_axis: vec2f,
+    y_axis: vec2f,
+    offset: vec2f,
+    texcoord: u32,
+    sampler_bits: u32,
+};
+
+// The bits of `TextureTransform::sampler_bits`. Must match `TextureSampler::bits` in
+// `asset/mod.rs`.
+const SAMPLER_ADDRESS_MASK = 3u;
+const SAMPLER_ADDRESS_V_SHIFT = 2u;
+const SAMPLER_MAG_NEAREST = 16u;
+const SAMPLER_MIN_NEAREST = 32u;
+const SAMPLER_MIPMAP_NEAREST = 64u;
+const SAMPLER_NO_MIPMAP = 128u;
+
+const ADDRESS_REPEAT = 0u;
+const ADDRESS_MIRROR_REPEAT = 1u;
+const ADDRESS_CLAMP_TO_EDGE = 2u;
+
+const MATERIAL_OCCLUSION = 1u;
+const MATERIAL_CLEARCOAT = 2u;
+const MATERIAL_SHEEN = 4u;
+const MATERIAL_TRANSMISSION = 8u;
+const MATERIAL_VOLUME = 16u;
+const MATERIAL_CLEARCOAT_TEXTURE = 32u;
+const MATERIAL_CLEARCOAT_ROUGHNESS_TEXTURE = 64u;
+const MATERIAL_CLEARCOAT_NORMAL_TEXTURE = 128u;
+const MATERIAL_SHEEN_COLOR_TEXTURE = 256u;
+const MATERIAL_SHEEN_ROUGHNESS_TEXTURE = 512u;
+const MATERIAL_TRANSMISSION_TEXTURE = 1024u;
+
 struct Material {
     albedo_texture: u32,
     normal_texture: u32,
     specular_texture: u32,
     emissive_texture: u32,
+    occlusion_texture: u32,
+    flags: u32,
+    occlusion_strength: f32,
+    ior: f32,
     base_color: vec4f,
     emissive: vec4f,
     metallic: f32,
     roughness: f32,
-    ior: f32,
-    padding: array<u32, 1>,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen: vec4f,
+    specular: vec4f,
+    attenuation: vec4f,
+    transmission: f32,
+    thickness: f32,
+    clearcoat_texture: u32,
+    clearcoat_roughness_texture: u32,
+    clearcoat_normal_texture: u32,
+    sheen_color_texture: u32,
+    sheen_roughness_texture: u32,
+    transmission_texture: u32,
+    albedo_transform: TextureTransform,
+    normal_transform: TextureTransform,
+    specular_transform: TextureTransform,
+    emissive_transform: TextureTransform,
+    occlusion_transform: TextureTransform,
+    clearcoat_transform: TextureTransform,
+    clearcoat_roughness_transform: TextureTransform,
+    clearcoat_normal_transform: TextureTransform,
+    sheen_color_transform: TextureTransform,
+    sheen_roughness_transform: TextureTransform,
+    transmission_transform: TextureTransform,
 };
 
 struct Vertex {
-    raw: array<u32, 4>,
+    raw: array<u32, 5>,
 };
 
 struct TangentFrame {
@@ -79,6 +138,6 @@ fn material(vertex: Vertex) -> u32 {
     return vertex.raw[2] >> 16u;
 }
 
-fn texcoords(vertex: Vertex) -> vec2f {
-    return unpack2x16float(vertex.raw[0]);
+fn texcoords(vertex: Vertex, texcoord_set: u32) -> vec2f {
+    return unpack2x16float(select(vertex.raw[0], vertex.raw[4], texcoord_set == 1u));
 }
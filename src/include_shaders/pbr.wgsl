@@ -22,6 +22,22 @@ struct LightParameters {
     light_dot_half: f32,
 };
 
+// The fresnel reflectance of a dielectric with an index of refraction of 1.5.
+const CLEARCOAT_FRESNEL = 0.04;
+
+fn light_parameters(normal: vec3f, view_direction: vec3f, light_direction: vec3f) -> LightParameters {
+    var light: LightParameters;
+    light.specular_intensity = 1.0;
+    light.light_direction = light_direction;
+    light.half_vector = normalize(view_direction + light_direction);
+    light.normal_dot_half = saturate(dot(normal, light.half_vector));
+    light.normal_dot_light = saturate(dot(normal, light_direction));
+    light.view_dot_half = saturate(dot(view_direction, light.half_vector));
+    light.light_dot_view = saturate(dot(light_direction, view_direction));
+    light.light_dot_half = saturate(dot(light_direction, light.half_vector));
+    return light;
+}
+
 fn fresnel_schlick(fresnel_min: vec3f, fresnel_max: f32, view_dot_half: f32) -> vec3f {
     let flipped = 1.0 - view_dot_half;
     let flipped_2 = flipped * flipped;
@@ -69,3 +85,64 @@ fn burley_diffuse(shade: ShadeParameters, light: LightParameters) -> vec3f {
     let view_scatter  = fresnel_schlick(vec3f(1.0), shade.fresnel_max, shade.normal_dot_view);
     return light_scatter * view_scatter * (1.0 / util::PI);
 }
+
+// Multi-bounce approximation of ambient occlusion from "Practical Realtime Strategies for
+// Accurate Indirect Occlusion" by Jimenez et al. Light bouncing between bright surfaces makes
+// the occlusion lighter than the single bounce occlusion stored in occlusion maps.
+fn multi_bounce_occlusion(visibility: f32, albedo: vec3f) -> vec3f {
+    let a = 2.0404 * albedo - 0.3324;
+    let b = -4.7951 * albedo + 0.6417;
+    let c = 2.7552 * albedo + 0.6903;
+    return max(vec3f(visibility), ((visibility * a + b) * visibility + c) * visibility);
+}
+
+// The clearcoat layer is a thin dielectric layer on top of the base material. `light` must be
+// relative to the normal of the clearcoat layer.
+fn clearcoat_specular(roughness: f32, normal_dot_view: f32, light: LightParameters) -> f32 {
+    var shade: ShadeParameters;
+    shade.roughness = roughness;
+    shade.normal_dot_view = normal_dot_view;
+    shade.fresnel_min = vec3f(CLEARCOAT_FRESNEL);
+    shade.fresnel_max = 1.0;
+    return specular(shade, light).x;
+}
+
+// The amount of light reflected by the clearcoat layer, which doesn't reach the base material.
+fn clearcoat_fresnel(normal_dot_view: f32) -> f32 {
+    return fresnel_schlick(vec3f(CLEARCOAT_FRESNEL), 1.0, normal_dot_view).x;
+}
+
+// The "Charlie" sheen distribution from "Production Friendly Microfacet Sheen BRDF" by Estevez
+// and Kulla.
+fn charlie_normal_dist(roughness: f32, normal_dot_half: f32) -> f32 {
+    let inverse_alpha = 1.0 / max(roughness, 0.0001);
+    let sin_squared = max(1.0 - normal_dot_half * normal_dot_half, 0.0078125);
+    return (2.0 + inverse_alpha) * pow(sin_squared, inverse_alpha * 0.5) / (2.0 * util::PI);
+}
+
+// The sheen visibility term from "Physically Based Materials in Unreal" by Neubelt and Pettineo.
+fn neubelt_visibility(normal_dot_light: f32, normal_dot_view: f32) -> f32 {
+    let denominator = 4.0 * (normal_dot_light + normal_dot_view - normal_dot_light * normal_dot_view);
+    return saturate(1.0 / denominator);
+}
+
+// `roughness` is the perceptual sheen roughness, which is squared for the distribution.
+fn sheen(color: vec3f, roughness: f32, normal_dot_view: f32, light: LightParameters) -> vec3f {
+    let d = charlie_normal_dist(roughness * roughness, light.normal_dot_half);
+    let v = neubelt_visibility(light.normal_dot_light, normal_dot_view);
+    return color * d * v;
+}
+
+// How much the layers below the sheen are scaled to conserve energy. The directional albedo of
+// the sheen lobe is roughly approximated instead of using a lookup table.
+fn sheen_scaling(color: vec3f, roughness: f32) -> f32 {
+    let albedo = mix(0.1, 0.35, roughness);
+    return 1.0 - max(color.r, max(color.g, color.b)) * albedo;
+}
+
+// Beer-Lambert attenuation of light travelling `thickness` through a volume. `attenuation`
+// is the color white light turns into after travelling the distance in the alpha channel.
+fn volume_attenuation(attenuation: vec4f, thickness: f32) -> vec3f {
+    let coefficient = -log(attenuation.rgb) / attenuation.a;
+    return exp(-coefficient * thickness);
+}
@@ -0,0 +1,41 @@
+#define_import_path shadow
+#import light
+
+// Bound with `ShadowCascades::bind_group` by the passes that sample the shadow cascades.
+@group(2) @binding(0)
+var<storage, read> cascade_infos: array<light::ShadowCascade>;
+
+// Loaded as plain floats, as GL can't read depth textures in compute shaders.
+@group(2) @binding(1)
+var cascades: texture_2d_array<f32>;
+
+// The index of the cascade covering `depth`, which is the view space distance along the camera
+// front.
+fn cascade_index(depth: f32) -> u32 {
+    let cascade_count = arrayLength(&cascade_infos);
+    var cascade_index = 0u;
+
+    for (var i = 0u; i < cascade_count - 1u; i += 1u) {
+        if depth > cascade_infos[i].far {
+            cascade_index = i + 1u;
+        }
+    }
+
+    return cascade_index;
+}
+
+// The position of `world_pos` in the shadow map of a cascade. The xy components are texture
+// coordinates and z is the depth.
+fn light_position(cascade_index: u32, world_pos: vec3f) -> vec3f {
+    return (cascade_infos[cascade_index].matrix * vec4f(world_pos, 1.0)).xyz;
+}
+
+// The occlusion at `light_pos` with the shadow map loaded `texel_offset` texels away. Zero is
+// fully lit.
+fn occlusion(cascade_index: u32, light_pos: vec3f, texel_offset: vec2f, bias: f32) -> f32 {
+    let size = vec2i(textureDimensions(cascades));
+    let texel = vec2i(floor(light_pos.xy * vec2f(size) + texel_offset));
+    let shadow_depth = textureLoad(cascades, clamp(texel, vec2i(0), size - 1), cascade_index, 0).r;
+
+    return light_pos.z - select(0.0, 1.0, bias > shadow_depth);
+}
@@ -0,0 +1,109 @@
+#define_import_path sky
+#import util
+#import light
+
+// The number of cells along each axis of the grid the stars are scattered over.
+const STAR_GRID_SIZE = 256.0;
+
+// The fraction of the cells that contain a star.
+const STAR_DENSITY = 0.08;
+
+// The stars have faded out completely when the sky is this bright.
+const STAR_FADE_LUMINANCE = 0.05;
+
+// Limb darkening exponents of the sun for red, green and blue, from "Simulating the Colors of
+// the Sun and Moon" by Hestroffer and Magnan.
+const LIMB_DARKENING = vec3f(0.397, 0.503, 0.652);
+
+fn pcg3d(seed: vec3u) -> vec3u {
+    var v = seed * 1664525u + 1013904223u;
+
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+
+    v ^= v >> vec3u(16u);
+
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+
+    return v;
+}
+
+fn to_unorm(v: vec3u) -> vec3f {
+    return vec3f(v >> vec3u(8u)) / f32(1u << 24u);
+}
+
+// The sine of the angle between two normalized directions. More precise than `acos` of the
+// dot product for the tiny angles across the sun disk.
+fn sin_angle(a: vec3f, b: vec3f) -> f32 {
+    return length(cross(a, b));
+}
+
+fn sun_disk(lights: light::SkyLights, sun_direction: vec3f, direction: vec3f) -> vec3f {
+    let radius = sin(lights.sun_angular_radius);
+
+    if dot(direction, sun_direction) <= 0.0 || sin_angle(direction, sun_direction) >= radius {
+        return vec3f(0.0);
+    }
+
+    // The cosine of the angle between the surface of the sun and the view direction.
+    let distance = sin_angle(direction, sun_direction) / radius;
+    let mu = sqrt(1.0 - distance * distance);
+
+    // Normalized to keep the average radiance over the disk.
+    return lights.sun_radiance * pow(vec3f(mu), LIMB_DARKENING) * (LIMB_DARKENING + 2.0) / 2.0;
+}
+
+// The moon radiance in rgb and its coverage in a. The moon is a diffuse sphere, so the part lit
+// by the sun gives the phase.
+fn moon(lights: light::SkyLights, sun_direction: vec3f, direction: vec3f) -> vec4f {
+    let radius = sin(lights.moon_angular_radius);
+
+    if radius <= 0.0 || dot(direction, lights.moon_direction) <= 0.0 {
+        return vec4f(0.0);
+    }
+
+    // The position on the disk, with a length of one at the edge.
+    let disk = (direction - lights.moon_direction * dot(direction, lights.moon_direction)) / radius;
+    let disk_length_squared = dot(disk, disk);
+
+    if disk_length_squared >= 1.0 {
+        return vec4f(0.0);
+    }
+
+    let normal = disk - lights.moon_direction * sqrt(1.0 - disk_length_squared);
+    let lit = saturate(dot(normal, sun_direction));
+
+    return vec4f(lights.moon_radiance * lit, 1.0);
+}
+
+// Stars scattered over a grid of cells, with at most one star per cell. `pixel_angle` is the
+// angle covered by a pixel, which keeps the stars about a pixel wide at any resolution.
+fn stars(lights: light::SkyLights, direction: vec3f, sky: vec3f, pixel_angle: f32) -> vec3f {
+    let fade = (1.0 - smoothstep(0.0, STAR_FADE_LUMINANCE, util::luminance(sky)))
+        * smoothstep(0.0, 0.05, direction.y);
+
+    if lights.star_brightness <= 0.0 || fade <= 0.0 {
+        return vec3f(0.0);
+    }
+
+    let cell = floor(direction * STAR_GRID_SIZE);
+    let seed = pcg3d(bitcast<vec3u>(vec3i(cell)));
+    let hash = to_unorm(seed);
+
+    if hash.x > STAR_DENSITY {
+        return vec3f(0.0);
+    }
+
+    // Keep the star away from the edges of the cell, since only this cell is checked.
+    let star = normalize(cell + 0.25 + 0.5 * to_unorm(pcg3d(seed)));
+    let offset = sin_angle(direction, star) / max(pixel_angle, 1e-5);
+
+    // Most stars are faint and a few are bright.
+    let magnitude = pow(hash.y, 6.0);
+    let color = mix(vec3f(1.0, 0.8, 0.6), vec3f(0.7, 0.8, 1.0), hash.z);
+
+    return lights.star_brightness * fade * magnitude * color * exp(-2.0 * offset * offset);
+}
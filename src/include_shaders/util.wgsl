@@ -43,8 +43,9 @@ fn octahedron_decode(octahedron: vec2f) -> vec3f {
     return normalize(normal);
 }
 
-fn linearize_depth(near: f32, far: f32, depth: f32) -> f32 {
-    return (2.0 * near * far) / (far + near - depth * (far - near));
+// The view space distance of a depth buffer value. `coeffs` is `Consts::depth_linearize_coeffs`.
+fn linearize_depth(coeffs: vec4f, depth: f32) -> f32 {
+    return (coeffs.x * depth + coeffs.y) / (coeffs.z * depth + coeffs.w);
 }
 
 fn luminance(color: vec3f) -> f32 {
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+use glam::Vec2;
+use serde::Deserialize;
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    RollLeft,
+    RollRight,
+    Sprint,
+    /// Rotate the camera with the mouse while held.
+    Look,
+    /// Switch to the next camera controller.
+    NextController,
+    /// Switch to the next camera imported from the scene.
+    NextSceneCamera,
+    /// Add the current camera to the end of the camera path.
+    AddKeyframe,
+    /// Play back the camera path.
+    PlayPath,
+    /// Move the camera to the bookmark with the number.
+    GoToBookmark(u32),
+    /// Save the camera to the bookmark with the number.
+    SaveBookmark(u32),
+    /// Switch between perspective and orthographic projection.
+    ToggleProjection,
+    /// Add or remove the sensor and lens of a physical camera. The field of view and depth of
+    /// field follow the physical camera, and so does the exposure in the physical exposure mode.
+    TogglePhysicalCamera,
+    /// Switch between auto, manual and physical camera exposure.
+    NextExposureMode,
+    /// Add half a stop of exposure compensation.
+    IncreaseExposure,
+    /// Remove half a stop of exposure compensation.
+    DecreaseExposure,
+    /// Switch to the next tonemapping operator.
+    NextTonemapper,
+    /// Switch between no anti-aliasing, FXAA, SMAA and TAA.
+    NextAntiAliasing,
+    /// Double the anisotropy of material texture filtering, going back to 1x after 16x.
+    NextTextureAnisotropy,
+    /// Switch to the next atmosphere preset.
+    NextAtmosphere,
+    /// Save the next frame to the capture directory.
+    Capture,
+    /// Start or stop capturing an image sequence.
+    CaptureSequence,
+    /// Load the render settings again.
+    ReloadSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    /// A key pressed while holding a modifier.
+    Chord(Modifier, VirtualKeyCode),
+    Mouse(MouseButton),
+    /// A gamepad button, which needs the `gamepad` feature.
+    Gamepad(GamepadButton),
+}
+
+/// The buttons of a gamepad, by their position on the gamepad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    /// Pressing the left stick.
+    LeftStick,
+    /// Pressing the right stick.
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+}
+
+impl Modifier {
+    fn is_held(self, modifiers: ModifiersState) -> bool {
+        match self {
+            Self::Shift => modifiers.shift(),
+            Self::Ctrl => modifiers.ctrl(),
+            Self::Alt => modifiers.alt(),
+        }
+    }
+}
+
+/// Maps actions to the keys, mouse buttons and gamepad buttons that trigger them.
+///
+/// Bindings are loaded from a RON map. Actions that aren't in the map keep their default
+/// bindings:
+///
+/// ```ron
+/// {
+///     MoveUp: [Key(Space), Gamepad(South)],
+///     Look: [Mouse(Right), Key(LAlt)],
+///     SaveBookmark(1): [Chord(Ctrl, Key1)],
+/// }
+/// ```
+///
+/// The gamepad sticks aren't bound. The left stick always moves and the right stick looks
+/// around.
+///
+/// Pressing a key while holding a modifier only triggers the actions bound to the chord if
+/// there are any, and the actions bound to the key otherwise.
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use Binding::{Chord, Gamepad, Key, Mouse};
+        use VirtualKeyCode as Code;
+
+        const NUMBER_KEYS: [Code; 9] = [
+            Code::Key1,
+            Code::Key2,
+            Code::Key3,
+            Code::Key4,
+            Code::Key5,
+            Code::Key6,
+            Code::Key7,
+            Code::Key8,
+            Code::Key9,
+        ];
+
+        let mut bindings = HashMap::from([
+            (Action::MoveForward, vec![Key(Code::W)]),
+            (Action::MoveBackward, vec![Key(Code::S)]),
+            (Action::MoveLeft, vec![Key(Code::A)]),
+            (Action::MoveRight, vec![Key(Code::D)]),
+            (
+                Action::MoveUp,
+                vec![Key(Code::E), Gamepad(GamepadButton::RightTrigger)],
+            ),
+            (
+                Action::MoveDown,
+                vec![Key(Code::Q), Gamepad(GamepadButton::LeftTrigger)],
+            ),
+            (
+                Action::RollLeft,
+                vec![Key(Code::Z), Gamepad(GamepadButton::LeftBumper)],
+            ),
+            (
+                Action::RollRight,
+                vec![Key(Code::X), Gamepad(GamepadButton::RightBumper)],
+            ),
+            (
+                Action::Sprint,
+                vec![Key(Code::LControl), Gamepad(GamepadButton::LeftStick)],
+            ),
+            (
+                Action::Look,
+                vec![
+                    Mouse(MouseButton::Right),
+                    Key(Code::LShift),
+                    Key(Code::RShift),
+                ],
+            ),
+            (
+                Action::NextController,
+                vec![Key(Code::C), Gamepad(GamepadButton::Select)],
+            ),
+            (Action::NextSceneCamera, vec![Key(Code::V)]),
+            (Action::AddKeyframe, vec![Key(Code::K)]),
+            (Action::PlayPath, vec![Key(Code::P)]),
+            (Action::ToggleProjection, vec![Key(Code::O)]),
+            (Action::TogglePhysicalCamera, vec![Key(Code::L)]),
+            (Action::NextExposureMode, vec![Key(Code::M)]),
+            (Action::IncreaseExposure, vec![Key(Code::Equals)]),
+            (Action::DecreaseExposure, vec![Key(Code::Minus)]),
+            (Action::NextTonemapper, vec![Key(Code::N)]),
+            (Action::NextAntiAliasing, vec![Key(Code::J)]),
+            (Action::NextTextureAnisotropy, vec![Key(Code::T)]),
+            (Action::NextAtmosphere, vec![Key(Code::H)]),
+            (Action::Capture, vec![Key(Code::F12)]),
+            (Action::CaptureSequence, vec![Key(Code::F11)]),
+            (Action::ReloadSettings, vec![Key(Code::F5)]),
+        ]);
+
+        // Control is already bound to sprinting, so bookmarks are saved with alt.
+        for (number, key) in (1..).zip(NUMBER_KEYS) {
+            bindings.insert(Action::GoToBookmark(number), vec![Key(key)]);
+            bindings.insert(
+                Action::SaveBookmark(number),
+                vec![Chord(Modifier::Alt, key)],
+            );
+        }
+
+        Self { bindings }
+    }
+}
+
+impl KeyBindings {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read key bindings at {path:?}"))?;
+
+        Self::parse(&source).wrap_err_with(|| format!("failed to parse key bindings at {path:?}"))
+    }
+
+    fn parse(source: &str) -> Result<Self> {
+        let overrides: HashMap<Action, Vec<Binding>> = ron::from_str(source)?;
+
+        let mut bindings = Self::default();
+        bindings.bindings.extend(overrides);
+
+        Ok(bindings)
+    }
+
+    fn is_bound(&self, binding: Binding) -> bool {
+        self.actions(binding).next().is_some()
+    }
+
+    fn actions(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+}
+
+/// The state of the keyboard, mouse and gamepad.
+#[derive(Default)]
+pub struct Inputs {
+    bindings: KeyBindings,
+    pressed: HashSet<Binding>,
+    modifiers: ModifiersState,
+    mouse_position: Option<Vec2>,
+    mouse_delta: Option<Vec2>,
+    scroll_delta: f32,
+    left_stick: Vec2,
+    right_stick: Vec2,
+}
+
+impl Inputs {
+    pub fn new(bindings: KeyBindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the actions triggered by pressing `binding`.
+    pub fn press(&mut self, binding: Binding) -> Vec<Action> {
+        if !self.pressed.insert(binding) {
+            return Vec::new();
+        }
+
+        let binding = self.held_chord(binding).unwrap_or(binding);
+        self.bindings.actions(binding).collect()
+    }
+
+    /// The bound chord of `binding` and a held modifier.
+    fn held_chord(&self, binding: Binding) -> Option<Binding> {
+        let Binding::Key(key) = binding else {
+            return None;
+        };
+
+        [Modifier::Shift, Modifier::Ctrl, Modifier::Alt]
+            .into_iter()
+            .filter(|modifier| modifier.is_held(self.modifiers))
+            .map(|modifier| Binding::Chord(modifier, key))
+            .find(|chord| self.bindings.is_bound(*chord))
+    }
+
+    pub fn release(&mut self, binding: Binding) {
+        self.pressed.remove(&binding);
+    }
+
+    /// Release the buttons of a disconnected gamepad.
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    pub fn release_gamepad(&mut self) {
+        self.pressed
+            .retain(|binding| !matches!(binding, Binding::Gamepad(_)));
+    }
+
+    /// Set the position of the gamepad sticks. Each axis goes from -1 to 1, with up and right
+    /// being positive.
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    pub fn set_sticks(&mut self, left: Vec2, right: Vec2) {
+        self.left_stick = left;
+        self.right_stick = right;
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn mouse_moved(&mut self, to: Vec2) {
+        let position = self.mouse_position.unwrap_or(to);
+        let delta = self.mouse_delta.unwrap_or_default();
+
+        self.mouse_delta = Some(delta + (position - to));
+        self.mouse_position = Some(to);
+    }
+
+    /// `lines` is positive when scrolling up.
+    pub fn scrolled(&mut self, lines: f32) {
+        self.scroll_delta += lines;
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.bindings
+            .bindings
+            .get(&action)
+            .is_some_and(|bindings| bindings.iter().any(|b| self.is_pressed(*b)))
+    }
+
+    fn is_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Chord(modifier, key) => {
+                modifier.is_held(self.modifiers) && self.pressed.contains(&Binding::Key(key))
+            }
+            binding => self.pressed.contains(&binding),
+        }
+    }
+
+    /// Returns 1 if only `positive` is held, -1 if only `negative` is held and 0 otherwise. The
+    /// left stick adds to the axes of moving forward and right.
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        let held = self.is_held(positive) as i32 as f32 - self.is_held(negative) as i32 as f32;
+
+        let stick = match (positive, negative) {
+            (Action::MoveForward, Action::MoveBackward) => self.left_stick.y,
+            (Action::MoveRight, Action::MoveLeft) => self.left_stick.x,
+            _ => 0.0,
+        };
+
+        (held + stick).clamp(-1.0, 1.0)
+    }
+
+    /// The position of the right stick, which looks around.
+    pub fn look_stick(&self) -> Vec2 {
+        self.right_stick
+    }
+
+    pub fn take_mouse_delta(&mut self) -> Vec2 {
+        self.mouse_delta.take().unwrap_or_default()
+    }
+
+    pub fn take_scroll_delta(&mut self) -> f32 {
+        std::mem::take(&mut self.scroll_delta)
+    }
+}
+
+#[test]
+fn bindings_override_defaults() {
+    let bindings = KeyBindings::parse("{ MoveUp: [Key(Space)], Look: [Mouse(Left)] }").unwrap();
+    let mut inputs = Inputs::new(bindings);
+
+    assert_eq!(
+        inputs.press(Binding::Key(VirtualKeyCode::Space)),
+        [Action::MoveUp]
+    );
+    assert!(inputs.press(Binding::Key(VirtualKeyCode::E)).is_empty());
+    assert!(inputs.is_held(Action::MoveUp));
+    assert!(!inputs.is_held(Action::Look));
+
+    inputs.press(Binding::Key(VirtualKeyCode::W));
+    assert_eq!(inputs.axis(Action::MoveForward, Action::MoveBackward), 1.0);
+}
+
+#[test]
+fn chords_replace_key_actions() {
+    let bindings = KeyBindings::parse("{ SaveBookmark(2): [Chord(Ctrl, Key2)] }").unwrap();
+    let mut inputs = Inputs::new(bindings);
+
+    let key = Binding::Key(VirtualKeyCode::Key2);
+    assert_eq!(inputs.press(key), [Action::GoToBookmark(2)]);
+    inputs.release(key);
+
+    inputs.set_modifiers(ModifiersState::CTRL);
+    assert_eq!(inputs.press(key), [Action::SaveBookmark(2)]);
+    assert!(inputs.is_held(Action::SaveBookmark(2)));
+
+    // Bookmarks are saved with alt by default, as control is bound to sprinting.
+    inputs.set_modifiers(ModifiersState::ALT);
+    assert_eq!(
+        inputs.press(Binding::Key(VirtualKeyCode::Key3)),
+        [Action::SaveBookmark(3)]
+    );
+    assert!(!inputs.is_held(Action::Sprint));
+}
+
+#[test]
+fn gamepad_moves_like_keys() {
+    let bindings = KeyBindings::parse("{ MoveUp: [Gamepad(South)] }").unwrap();
+    let mut inputs = Inputs::new(bindings);
+
+    assert_eq!(
+        inputs.press(Binding::Gamepad(GamepadButton::South)),
+        [Action::MoveUp]
+    );
+
+    inputs.set_sticks(Vec2::new(0.5, -1.0), Vec2::ZERO);
+    assert_eq!(inputs.axis(Action::MoveRight, Action::MoveLeft), 0.5);
+    assert_eq!(inputs.axis(Action::MoveForward, Action::MoveBackward), -1.0);
+
+    // Keys and sticks add up to at most full speed.
+    inputs.press(Binding::Key(VirtualKeyCode::D));
+    assert_eq!(inputs.axis(Action::MoveRight, Action::MoveLeft), 1.0);
+
+    inputs.release_gamepad();
+    assert!(!inputs.is_held(Action::MoveUp));
+    assert!(inputs.is_held(Action::MoveRight));
+}
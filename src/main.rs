@@ -1,40 +1,94 @@
+mod antialiasing;
 mod asset;
 mod atmosphere;
+mod benchmark;
 mod bloom;
+mod bookmarks;
 mod camera;
+mod capture;
 mod context;
+mod controller;
 mod depth_reduce;
 mod display;
+mod dof;
+mod fxaa;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+#[cfg(test)]
+mod golden;
+mod grading;
+mod input;
+mod motion_blur;
+mod profiler;
 mod renderer;
+mod resolution;
 mod resources;
+mod settings;
 mod shade;
 mod shadow;
+mod smaa;
+mod streaming;
 mod temporal_resolve;
 mod util;
 mod visibility;
 
-use asset::{AssetPath, Scene};
-use bit_set::BitSet;
+use asset::{AssetPath, LoadEvent, LoadProgress, Scene, SceneCamera, SceneLoader, SceneSource};
+use eyre::WrapErr;
 use glam::Vec2;
 use winit::dpi::PhysicalSize;
 use winit::event::{
-    ElementState, Event, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent,
+    ElementState, Event, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent,
 };
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::{Duration, Instant};
-
-use camera::{Camera, CameraDelta};
+use std::time::Instant;
+
+use atmosphere::AtmosphereSettings;
+use benchmark::{Benchmark, BenchmarkOptions};
+use bookmarks::Bookmarks;
+use camera::{Camera, PhysicalCamera};
+use capture::{CaptureSequence, CaptureSettings};
+use context::{ContextOptions, DisplayOutput};
+use controller::{CameraController, PathController};
+use display::{ExposureMode, ExposureSettings};
+use input::{Action, Binding, Inputs, KeyBindings};
 use renderer::Renderer;
+use settings::RenderSettings;
+
+/// Key bindings are loaded from this file in the working directory if it exists.
+const KEY_BINDINGS_PATH: &str = "bindings.ron";
+
+/// Render settings are loaded from this file in the working directory if it exists.
+const SETTINGS_PATH: &str = "settings.ron";
 
 fn main() {
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let benchmark_options = BenchmarkOptions::from_args(&args).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(2);
+    });
+
+    if let Some(options) = benchmark_options
+        .as_ref()
+        .filter(|options| options.headless)
+    {
+        if let Err(err) = run_headless_benchmark(options.clone()) {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let window = Rc::new(
         WindowBuilder::new()
+            .with_title("rendinator")
             .with_inner_size(PhysicalSize {
                 width: 800,
                 height: 800,
@@ -43,15 +97,70 @@ fn main() {
             .expect("failed to create window"),
     );
 
-    let mut state = State::new(aspect_ratio(window.inner_size()));
+    let key_bindings = Path::new(KEY_BINDINGS_PATH);
+    let key_bindings = if key_bindings.exists() {
+        KeyBindings::load(key_bindings).unwrap_or_else(|err| {
+            eprintln!("{err:?}");
+            KeyBindings::default()
+        })
+    } else {
+        KeyBindings::default()
+    };
 
-    let mut renderer = {
-        let scene = Scene::from_gltf(&AssetPath::new("sponza/Sponza.gltf", "sponza/Sponza.scene"))
-            .expect("failed to load scene");
+    let settings = load_settings();
+
+    let mut state = State::new(
+        aspect_ratio(window.inner_size()),
+        key_bindings,
+        settings.capture.clone(),
+    );
 
-        Renderer::new(window.clone(), &scene)
+    // Benchmarks don't wait for vertical blank to measure the actual frame time.
+    let context_options = match &benchmark_options {
+        Some(options) => ContextOptions {
+            force_fallback_adapter: options.force_fallback_adapter,
+            vsync: false,
+            hdr: settings.hdr.enabled,
+        },
+        None => ContextOptions {
+            hdr: settings.hdr.enabled,
+            ..Default::default()
+        },
     };
 
+    let mut renderer = Renderer::new(window.clone(), context_options).unwrap_or_else(|err| {
+        eprintln!("{err:?}");
+        std::process::exit(1);
+    });
+    apply_settings(&mut renderer, &settings);
+
+    if settings.hdr.enabled && renderer.display_output() == DisplayOutput::Sdr {
+        eprintln!("the display doesn't support HDR output, falling back to SDR");
+    }
+
+    // Scene descriptions or glTF files can be passed as the first argument.
+    let scene_path = match &benchmark_options {
+        Some(options) => options.scene.clone(),
+        None => args.first().map(PathBuf::from),
+    };
+
+    let source = scene_source(scene_path);
+
+    let mut benchmark = benchmark_options.map(|options| {
+        if !renderer.enable_profiling() {
+            eprintln!("timestamp queries aren't supported, GPU phase times aren't recorded");
+        }
+
+        Benchmark::new(options)
+    });
+
+    let mut scene_loader = Some(SceneLoader::spawn(source));
+
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = gamepad::Gamepads::new()
+        .map_err(|err| eprintln!("{err:?}"))
+        .ok();
+
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
@@ -82,10 +191,25 @@ fn main() {
                     return;
                 };
 
-                match input.state {
-                    ElementState::Pressed => state.inputs.key_pressed(key),
-                    ElementState::Released => state.inputs.key_released(key),
-                }
+                state.handle_binding(Binding::Key(key), input.state, &mut renderer);
+            }
+            WindowEvent::MouseInput {
+                state: button_state,
+                button,
+                ..
+            } => {
+                state.handle_binding(Binding::Mouse(*button), *button_state, &mut renderer);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+
+                state.inputs.scrolled(lines);
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                state.inputs.set_modifiers(*modifiers);
             }
             WindowEvent::CursorMoved { position, .. } => {
                 state.inputs.mouse_moved(Vec2 {
@@ -93,8 +217,9 @@ fn main() {
                     y: position.y as f32,
                 });
             }
-            WindowEvent::ModifiersChanged(modifier_state) => {
-                state.inputs.modifier_change(*modifier_state);
+            WindowEvent::DroppedFile(path) => {
+                println!("loading scene: {path:?}");
+                scene_loader = Some(SceneLoader::spawn(SceneSource::from_path(path)));
             }
             _ => (),
         },
@@ -102,8 +227,51 @@ fn main() {
             let delta_time = state.last_update.elapsed();
             state.last_update = Instant::now();
 
-            state.handle_inputs(delta_time);
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = &mut gamepads {
+                for (binding, element_state) in gamepads.poll(&mut state.inputs) {
+                    state.handle_binding(binding, element_state, &mut renderer);
+                }
+            }
 
+            // Fixed time steps don't measure anything, and frames are slow while loading.
+            if state.sequence.is_none() && benchmark.is_none() && scene_loader.is_none() {
+                renderer.update_dynamic_resolution();
+            }
+
+            // Image sequences are rendered with a fixed time step, like benchmarks.
+            let delta_time = match &state.sequence {
+                Some(sequence) => sequence.delta_time(),
+                None => delta_time,
+            };
+
+            if let Some(sequence) = &mut state.sequence {
+                match sequence.next_request() {
+                    Some(request) => renderer.capture(request),
+                    None => {
+                        println!("finished capturing {:?}", sequence.directory());
+                        state.sequence = None;
+                    }
+                }
+            }
+
+            let delta_time = match &benchmark {
+                Some(benchmark) => {
+                    benchmark.update_camera(&mut state.camera);
+                    benchmark.delta_time()
+                }
+                None => {
+                    state.controller.update(
+                        &mut state.camera,
+                        &mut state.inputs,
+                        delta_time.as_secs_f32(),
+                    );
+
+                    delta_time
+                }
+            };
+
+            let frame_start = Instant::now();
             if let Err(err) = renderer.draw(delta_time, &state.camera) {
                 match err {
                     wgpu::SurfaceError::Lost => {
@@ -113,105 +281,417 @@ fn main() {
                     err => eprintln!("{err:?}"),
                 }
             }
+
+            if let Some(benchmark) = benchmark.as_mut().filter(|b| b.is_started()) {
+                let cpu_frame_time = frame_start.elapsed();
+
+                let submitted = Instant::now();
+                let gpu_phase_times = renderer.wait_for_frame();
+                let gpu_wait_time = submitted.elapsed();
+
+                if benchmark.record(cpu_frame_time, gpu_wait_time, &gpu_phase_times) {
+                    if let Err(err) = benchmark.write_report(renderer.adapter_info()) {
+                        eprintln!("{err:?}");
+                    }
+
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
         }
         Event::MainEventsCleared => {
+            let events = scene_loader.iter().flat_map(SceneLoader::poll);
+            let mut is_finished = false;
+
+            for event in events {
+                match event {
+                    LoadEvent::Progress(progress) => {
+                        println!("{progress}");
+                        window.set_title(&format!("rendinator - {progress}"));
+                    }
+                    LoadEvent::Loaded(scene) => {
+                        let source = scene_loader.as_ref().map(SceneLoader::source);
+                        state.set_scene(&scene, source);
+
+                        if let Err(err) = renderer.set_scene(scene) {
+                            eprintln!("failed to upload scene: {err:?}");
+                            window.set_title("rendinator");
+
+                            if benchmark.is_some() {
+                                *control_flow = ControlFlow::Exit;
+                            }
+
+                            is_finished = true;
+                            continue;
+                        }
+
+                        println!("{}", LoadProgress::Uploaded);
+
+                        if let Some(benchmark) = &mut benchmark {
+                            if let Err(err) = benchmark.start(&state.bookmarks.path) {
+                                eprintln!("{err}");
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
+
+                        window.set_title("rendinator");
+                        is_finished = true;
+                    }
+                    LoadEvent::Failed(err) => {
+                        eprintln!("failed to load scene: {err:?}");
+                        window.set_title("rendinator");
+
+                        if benchmark.is_some() {
+                            *control_flow = ControlFlow::Exit;
+                        }
+
+                        is_finished = true;
+                    }
+                }
+            }
+
+            if is_finished {
+                scene_loader = None;
+            }
+
             window.request_redraw();
         }
         _ => (),
     })
 }
 
-fn aspect_ratio(size: PhysicalSize<u32>) -> f32 {
-    size.width as f32 / size.height as f32
+/// The scene at `path`, or Sponza if there is no path.
+fn scene_source(path: Option<PathBuf>) -> SceneSource {
+    match path {
+        Some(path) => SceneSource::from_path(&path),
+        None => SceneSource::Gltf(AssetPath::new("sponza/Sponza.gltf", "sponza/Sponza.scene")),
+    }
 }
 
-struct State {
-    inputs: Inputs,
-    camera: Camera,
-    last_update: Instant,
-}
+/// Run a benchmark without a window. The scene is loaded on this thread before rendering starts.
+fn run_headless_benchmark(options: BenchmarkOptions) -> eyre::Result<()> {
+    let settings = load_settings();
+    let source = scene_source(options.scene.clone());
 
-impl State {
-    fn new(aspect_ratio: f32) -> Self {
-        Self {
-            inputs: Inputs::default(),
-            camera: Camera::new(aspect_ratio),
-            last_update: Instant::now(),
-        }
-    }
+    let scene = source
+        .load(&|progress| println!("{progress}"))
+        .wrap_err("failed to load scene")?;
 
-    fn handle_inputs(&mut self, delta_time: Duration) {
-        let mut camera_delta = CameraDelta::default();
-        let delta_time = delta_time.as_secs_f32();
+    let bookmarks_path = source.bookmarks_path();
+    let bookmarks = if bookmarks_path.exists() {
+        Bookmarks::load(&bookmarks_path)?
+    } else {
+        Bookmarks::default()
+    };
 
-        let move_speed = 20.0;
-        let mouse_sensitivity = 0.5;
+    let context_options = ContextOptions {
+        force_fallback_adapter: options.force_fallback_adapter,
+        vsync: false,
+        hdr: false,
+    };
 
-        if self.inputs.is_key_pressed(VirtualKeyCode::W) {
-            camera_delta.forward += move_speed * delta_time;
-        }
+    let mut renderer = Renderer::headless(benchmark::HEADLESS_SIZE, context_options)?;
+    apply_settings(&mut renderer, &settings);
 
-        if self.inputs.is_key_pressed(VirtualKeyCode::S) {
-            camera_delta.backward += move_speed * delta_time;
-        }
+    if !renderer.enable_profiling() {
+        eprintln!("timestamp queries aren't supported, GPU phase times aren't recorded");
+    }
 
-        if self.inputs.is_key_pressed(VirtualKeyCode::A) {
-            camera_delta.left += move_speed * delta_time;
-        }
+    renderer
+        .set_scene(scene)
+        .wrap_err("failed to upload scene")?;
+    println!("{}", LoadProgress::Uploaded);
 
-        if self.inputs.is_key_pressed(VirtualKeyCode::D) {
-            camera_delta.right += move_speed * delta_time;
-        }
+    let mut benchmark = Benchmark::new(options);
+    benchmark.start(&bookmarks.path)?;
+
+    let size = benchmark::HEADLESS_SIZE;
+    let mut camera = Camera::new(size.width as f32 / size.height as f32);
+    let frame_buffer = renderer.create_frame_buffer();
+
+    loop {
+        benchmark.update_camera(&mut camera);
 
-        let mouse_delta = self.inputs.take_mouse_delta();
+        let frame_start = Instant::now();
+        renderer.draw_headless(benchmark.delta_time(), &camera, &frame_buffer);
+        let cpu_frame_time = frame_start.elapsed();
 
-        if self.inputs.is_shift_pressed() {
-            camera_delta.yaw += mouse_sensitivity * mouse_delta.x;
-            camera_delta.pitch += mouse_sensitivity * mouse_delta.y;
+        let submitted = Instant::now();
+        let gpu_phase_times = renderer.wait_for_frame();
+        let gpu_wait_time = submitted.elapsed();
 
-            self.camera.move_by_delta(camera_delta);
+        if benchmark.record(cpu_frame_time, gpu_wait_time, &gpu_phase_times) {
+            return benchmark.write_report(renderer.adapter_info());
         }
     }
 }
 
-#[derive(Default)]
-struct Inputs {
-    keys_pressed: BitSet,
-    modifier_state: ModifiersState,
-    mouse_position: Option<Vec2>,
-    mouse_delta: Option<Vec2>,
+fn load_settings() -> RenderSettings {
+    let path = Path::new(SETTINGS_PATH);
+
+    if !path.exists() {
+        return RenderSettings::default();
+    }
+
+    RenderSettings::load(path).unwrap_or_else(|err| {
+        eprintln!("{err:?}");
+        RenderSettings::default()
+    })
 }
 
-impl Inputs {
-    fn mouse_moved(&mut self, to: Vec2) {
-        let position = self.mouse_position.unwrap_or(to);
-        let delta = self.mouse_delta.unwrap_or_default();
+fn apply_settings(renderer: &mut Renderer, settings: &RenderSettings) {
+    renderer.set_exposure(settings.exposure);
+    renderer.set_hdr(settings.hdr);
+    renderer.set_anti_aliasing(settings.anti_aliasing);
+    renderer.set_resolution(settings.resolution);
 
-        self.mouse_delta = Some(delta + (position - to));
-        self.mouse_position = Some(to);
+    // Dynamic resolution measures the GPU time of frames with timestamp queries.
+    if settings.resolution.dynamic.is_some() && !renderer.enable_profiling() {
+        eprintln!("timestamp queries aren't supported, dynamic resolution is disabled");
     }
 
-    fn key_pressed(&mut self, key: VirtualKeyCode) {
-        self.keys_pressed.insert(key as usize);
+    renderer.set_depth_of_field(settings.depth_of_field);
+    renderer.set_motion_blur(settings.motion_blur);
+    renderer.set_atmosphere(settings.atmosphere);
+
+    if let Err(err) = renderer.set_color_grading(settings.color_grading.clone()) {
+        eprintln!("{err:?}");
     }
 
-    fn key_released(&mut self, key: VirtualKeyCode) {
-        self.keys_pressed.remove(key as usize);
+    if let Err(err) = renderer.set_bloom(settings.bloom.clone()) {
+        eprintln!("{err:?}");
     }
+}
 
-    fn modifier_change(&mut self, modifier_state: ModifiersState) {
-        self.modifier_state = modifier_state;
+/// Switch to the next tonemapper, skipping those that fail to apply such as Tony McMapface
+/// without its LUT.
+fn next_tonemapper(renderer: &mut Renderer) {
+    let mut color_grading = renderer.color_grading().clone();
+
+    loop {
+        color_grading.tonemapper = color_grading.tonemapper.next();
+
+        match renderer.set_color_grading(color_grading.clone()) {
+            Ok(()) => break,
+            Err(err) => eprintln!("skipping {:?}: {err}", color_grading.tonemapper),
+        }
     }
+}
+
+/// Cycle from auto exposure to manual exposure at the currently measured exposure value, and
+/// from there to the exposure of the physical camera.
+fn next_exposure_mode(renderer: &Renderer) -> ExposureSettings {
+    let exposure = renderer.exposure();
+
+    let mode = match exposure.mode {
+        ExposureMode::Auto(_) => ExposureMode::Manual {
+            ev100: display::luminance_to_ev100(renderer.measured_luminance()),
+        },
+        ExposureMode::Manual { .. } => ExposureMode::Physical,
+        ExposureMode::Physical => ExposureMode::Auto(Default::default()),
+    };
+
+    ExposureSettings { mode, ..exposure }
+}
 
-    fn is_key_pressed(&self, key: VirtualKeyCode) -> bool {
-        self.keys_pressed.contains(key as usize)
+fn aspect_ratio(size: PhysicalSize<u32>) -> f32 {
+    size.width as f32 / size.height as f32
+}
+
+/// Seconds between keyframes added to the camera path.
+const KEYFRAME_INTERVAL: f32 = 3.0;
+
+/// The orthographic view covers what the perspective view shows at this distance when switching.
+const ORTHOGRAPHIC_DISTANCE: f32 = 10.0;
+
+/// Stops of exposure compensation added or removed at a time.
+const EXPOSURE_STEP: f32 = 0.5;
+
+struct State {
+    inputs: Inputs,
+    camera: Camera,
+    controller: CameraController,
+    bookmarks: Bookmarks,
+    bookmarks_path: Option<PathBuf>,
+    scene_cameras: Vec<SceneCamera>,
+    scene_camera: usize,
+    sequence: Option<CaptureSequence>,
+    /// The capture settings of the loaded render settings.
+    capture_settings: CaptureSettings,
+    last_update: Instant,
+}
+
+impl State {
+    fn new(
+        aspect_ratio: f32,
+        key_bindings: KeyBindings,
+        capture_settings: CaptureSettings,
+    ) -> Self {
+        Self {
+            inputs: Inputs::new(key_bindings),
+            camera: Camera::new(aspect_ratio),
+            controller: CameraController::default(),
+            bookmarks: Bookmarks::default(),
+            bookmarks_path: None,
+            scene_cameras: Vec::new(),
+            scene_camera: 0,
+            sequence: None,
+            capture_settings,
+            last_update: Instant::now(),
+        }
     }
 
-    fn take_mouse_delta(&mut self) -> Vec2 {
-        self.mouse_delta.take().unwrap_or_default()
+    /// Load the bookmarks and cameras of a newly loaded scene.
+    fn set_scene(&mut self, scene: &Scene, source: Option<&SceneSource>) {
+        self.bookmarks_path = source.map(SceneSource::bookmarks_path);
+        self.bookmarks = match &self.bookmarks_path {
+            Some(path) if path.exists() => Bookmarks::load(path).unwrap_or_else(|err| {
+                eprintln!("{err:?}");
+                Bookmarks::default()
+            }),
+            _ => Bookmarks::default(),
+        };
+
+        self.scene_cameras = scene.scene_cameras();
+        self.scene_camera = 0;
     }
 
-    fn is_shift_pressed(&self) -> bool {
-        self.modifier_state.shift()
+    fn save_bookmarks(&self) {
+        let Some(path) = &self.bookmarks_path else {
+            return;
+        };
+
+        if let Err(err) = self.bookmarks.save(path) {
+            eprintln!("{err:?}");
+        }
+    }
+
+    fn handle_binding(
+        &mut self,
+        binding: Binding,
+        element_state: ElementState,
+        renderer: &mut Renderer,
+    ) {
+        if element_state == ElementState::Released {
+            self.inputs.release(binding);
+            return;
+        }
+
+        for action in self.inputs.press(binding) {
+            match action {
+                Action::NextController => {
+                    self.controller = self.controller.next(&mut self.camera);
+                    println!("camera controller: {}", self.controller.name());
+                }
+                Action::NextSceneCamera => {
+                    let Some(scene_camera) = self.scene_cameras.get(self.scene_camera) else {
+                        println!("scene has no cameras");
+                        continue;
+                    };
+
+                    let aspect_ratio = self.camera.aspect_ratio();
+                    self.camera = Camera::from_scene_camera(scene_camera, aspect_ratio);
+                    self.scene_camera = (self.scene_camera + 1) % self.scene_cameras.len();
+
+                    let name = scene_camera.name.as_deref().unwrap_or("unnamed");
+                    println!("scene camera: {name}");
+                }
+                Action::AddKeyframe => {
+                    self.bookmarks.add_keyframe(&self.camera, KEYFRAME_INTERVAL);
+                    self.save_bookmarks();
+                    println!("added keyframe {}", self.bookmarks.path.len());
+                }
+                Action::PlayPath if self.bookmarks.path.is_empty() => {
+                    println!("camera path is empty");
+                }
+                Action::PlayPath => {
+                    let path = self.bookmarks.path.clone();
+                    self.controller = CameraController::Path(PathController::new(path));
+                    println!("playing camera path");
+                }
+                Action::GoToBookmark(number) => {
+                    if let Some(bookmark) = self.bookmarks.cameras.get(&number) {
+                        self.camera.set_view(bookmark);
+                        println!("moved to bookmark {number}");
+                    }
+                }
+                Action::SaveBookmark(number) => {
+                    self.bookmarks.cameras.insert(number, self.camera.clone());
+                    self.save_bookmarks();
+                    println!("saved bookmark {number}");
+                }
+                Action::ToggleProjection => {
+                    self.camera.toggle_projection(ORTHOGRAPHIC_DISTANCE);
+                    println!("projection: {:?}", self.camera.projection);
+                }
+                Action::TogglePhysicalCamera => {
+                    self.camera.physical = match self.camera.physical {
+                        Some(_) => None,
+                        None => Some(PhysicalCamera::default()),
+                    };
+                    self.camera.resize_proj(self.camera.aspect_ratio());
+                    println!("physical camera: {:?}", self.camera.physical);
+                }
+                Action::NextExposureMode => {
+                    renderer.set_exposure(next_exposure_mode(renderer));
+                    println!("exposure: {:?}", renderer.exposure().mode);
+                }
+                Action::IncreaseExposure | Action::DecreaseExposure => {
+                    let step = if action == Action::IncreaseExposure {
+                        EXPOSURE_STEP
+                    } else {
+                        -EXPOSURE_STEP
+                    };
+
+                    let mut exposure = renderer.exposure();
+                    exposure.compensation += step;
+                    renderer.set_exposure(exposure);
+                    println!("exposure compensation: {:+} EV", exposure.compensation);
+                }
+                Action::NextTonemapper => {
+                    next_tonemapper(renderer);
+                    println!("tonemapper: {:?}", renderer.color_grading().tonemapper);
+                }
+                Action::NextAntiAliasing => {
+                    renderer.set_anti_aliasing(renderer.anti_aliasing().next());
+                    println!("anti-aliasing: {:?}", renderer.anti_aliasing());
+                }
+                Action::NextTextureAnisotropy => {
+                    let anisotropy = match renderer.texture_anisotropy() {
+                        16 => 1,
+                        anisotropy => anisotropy * 2,
+                    };
+
+                    renderer.set_texture_anisotropy(anisotropy);
+                    println!("texture anisotropy: {anisotropy}x");
+                }
+                Action::NextAtmosphere => {
+                    let atmosphere = renderer.atmosphere();
+                    renderer.set_atmosphere(AtmosphereSettings {
+                        model: atmosphere.model.next(),
+                        ..atmosphere
+                    });
+                    println!("atmosphere: {:?}", renderer.atmosphere().model);
+                }
+                Action::Capture => {
+                    renderer.capture(capture::screenshot_request(&self.capture_settings));
+                }
+                Action::CaptureSequence if self.sequence.is_some() => {
+                    self.sequence = None;
+                    println!("stopped capturing image sequence");
+                }
+                Action::CaptureSequence => {
+                    let sequence = CaptureSequence::new(&self.capture_settings);
+                    println!("capturing image sequence to {:?}", sequence.directory());
+                    self.sequence = Some(sequence);
+                }
+                Action::ReloadSettings => {
+                    let settings = load_settings();
+                    apply_settings(renderer, &settings);
+                    self.capture_settings = settings.capture;
+                    println!("reloaded render settings");
+                }
+                _ => (),
+            }
+        }
     }
 }
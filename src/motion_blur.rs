@@ -0,0 +1,343 @@
+use std::{borrow::Cow, iter};
+
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+use crate::{
+    context::Context,
+    resources::{self, ConstState, Consts, ParamsBuffer, RenderState, RenderTarget},
+    util,
+};
+
+/// The size of velocity tiles in pixels, which is also the largest blur radius. Must match
+/// `TILE_SIZE` in the shader.
+const TILE_SIZE: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MotionBlurSettings {
+    pub enabled: bool,
+    /// The angle in degrees of a rotary shutter, where 360 keeps the shutter open for the whole
+    /// frame.
+    pub shutter_angle: f32,
+    /// The number of samples along the velocity of each pixel.
+    pub sample_count: u32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shutter_angle: 180.0,
+            sample_count: 15,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct MotionBlurParams {
+    shutter_fraction: f32,
+    sample_count: u32,
+}
+
+impl MotionBlurParams {
+    fn new(settings: &MotionBlurSettings) -> Self {
+        Self {
+            shutter_fraction: (settings.shutter_angle / 360.0).clamp(0.0, 1.0),
+            sample_count: settings.sample_count.clamp(3, 63),
+        }
+    }
+}
+
+/// The binding of the parameters in every pass. Must match `params` in `motion_blur.wgsl`.
+const PARAMS_BINDING: u32 = 9;
+
+/// Camera motion blur with the reconstruction filter of McGuire et al. The velocity is
+/// reconstructed from the depth buffer and the previous view, as there are no moving objects.
+pub struct MotionBlurPhase {
+    passes: Vec<Pass>,
+    params: ParamsBuffer<MotionBlurParams>,
+    targets: Targets,
+}
+
+struct Pass {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+struct Targets {
+    /// The velocity in pixels and the view distance of each pixel.
+    velocity: RenderTarget,
+    tile_max: RenderTarget,
+    neighbor_max: RenderTarget,
+    output: RenderTarget,
+}
+
+impl Targets {
+    fn new(context: &Context) -> Self {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+        let tile_count = tile_count(context);
+
+        let target = |label, size| {
+            RenderTarget::new(context, label, size, resources::COLOR_BUFFER_FORMAT, usage)
+        };
+
+        Self {
+            velocity: target("velocity", context.surface_size),
+            tile_max: target("velocity tile max", tile_count),
+            neighbor_max: target("velocity neighbor max", tile_count),
+            output: RenderTarget::new(
+                context,
+                "motion blur output",
+                context.surface_size,
+                resources::COLOR_BUFFER_FORMAT,
+                usage | wgpu::TextureUsages::COPY_SRC,
+            ),
+        }
+    }
+}
+
+fn tile_count(context: &Context) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: util::div_ceil(context.surface_size.width, TILE_SIZE),
+        height: util::div_ceil(context.surface_size.height, TILE_SIZE),
+        depth_or_array_layers: 1,
+    }
+}
+
+const FILTERABLE: wgpu::TextureSampleType = wgpu::TextureSampleType::Float { filterable: true };
+const UNFILTERABLE: wgpu::TextureSampleType = wgpu::TextureSampleType::Float { filterable: false };
+
+impl MotionBlurPhase {
+    pub fn new(context: &mut Context, render_state: &RenderState) -> Self {
+        let module = context.create_shader_module(
+            include_str!("shaders/motion_blur.wgsl"),
+            "shaders/motion_blur.wgsl",
+            &[],
+        );
+
+        let shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("motion blur"),
+                source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+            });
+
+        let targets = Targets::new(context);
+
+        let texture = |binding| util::texture_layout_entry(binding, FILTERABLE);
+        let storage =
+            |binding| util::storage_texture_layout_entry(binding, resources::COLOR_BUFFER_FORMAT);
+
+        let passes = [
+            (
+                "motion blur velocity",
+                "velocity",
+                vec![util::texture_layout_entry(1, UNFILTERABLE), storage(2)],
+            ),
+            (
+                "motion blur tile max",
+                "tile_max",
+                vec![texture(3), storage(4)],
+            ),
+            (
+                "motion blur neighbor max",
+                "neighbor_max",
+                vec![texture(5), storage(6)],
+            ),
+            (
+                "motion blur reconstruct",
+                "reconstruct",
+                vec![texture(0), texture(3), texture(7), storage(8)],
+            ),
+        ];
+
+        let params = ParamsBuffer::new(context, "motion blur params", 1);
+        let views = pass_views(render_state, &targets);
+
+        let passes = passes
+            .into_iter()
+            .zip(views)
+            .map(|((label, entry_point, mut entries), views)| {
+                entries.push(ParamsBuffer::<MotionBlurParams>::layout_entry(
+                    PARAMS_BINDING,
+                    wgpu::ShaderStages::COMPUTE,
+                    false,
+                ));
+
+                let bind_group_layout =
+                    context
+                        .device
+                        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                            label: Some(label),
+                            entries: &entries,
+                        });
+
+                let pipeline_layout =
+                    context
+                        .device
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            label: Some(label),
+                            bind_group_layouts: &[
+                                ConstState::bind_group_layout(context),
+                                &bind_group_layout,
+                            ],
+                            push_constant_ranges: &[],
+                        });
+
+                let pipeline =
+                    context
+                        .device
+                        .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                            label: Some(label),
+                            module: &shader,
+                            layout: Some(&pipeline_layout),
+                            entry_point,
+                        });
+
+                let bind_group = create_bind_group(context, &bind_group_layout, &params, &views);
+
+                Pass {
+                    pipeline,
+                    bind_group_layout,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        Self {
+            passes,
+            params,
+            targets,
+        }
+    }
+
+    /// Blur the post buffer. Returns false if it was skipped.
+    pub fn record(
+        &self,
+        context: &Context,
+        settings: &MotionBlurSettings,
+        consts: &Consts,
+        const_state: &ConstState,
+        render_state: &RenderState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> bool {
+        // There is no previous view on the first frame.
+        if !settings.enabled || settings.shutter_angle <= 0.0 || consts.frame_index == 0 {
+            return false;
+        }
+
+        self.params
+            .write(context, 0, &MotionBlurParams::new(settings));
+
+        let tile_count = tile_count(context);
+        let tiles = (
+            util::div_ceil(tile_count.width, 8),
+            util::div_ceil(tile_count.height, 8),
+        );
+        let full = (
+            util::div_ceil(consts.surface_size.x, 8),
+            util::div_ceil(consts.surface_size.y, 8),
+        );
+
+        let workgroups = [full, tiles, tiles, full];
+
+        for (pass, (x, y)) in self.passes.iter().zip(workgroups) {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("motion blur"),
+            });
+
+            compute_pass.set_pipeline(&pass.pipeline);
+            compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
+            compute_pass.set_bind_group(1, &pass.bind_group, &[]);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+
+        encoder.copy_texture_to_texture(
+            self.targets.output.texture.as_image_copy(),
+            render_state.post.texture.as_image_copy(),
+            context.surface_size,
+        );
+
+        true
+    }
+
+    pub fn resize_surface(&mut self, context: &Context, render_state: &RenderState) {
+        self.targets = Targets::new(context);
+
+        let views = pass_views(render_state, &self.targets);
+
+        for (pass, views) in self.passes.iter_mut().zip(views) {
+            pass.bind_group =
+                create_bind_group(context, &pass.bind_group_layout, &self.params, &views);
+        }
+    }
+}
+
+/// The bindings and views of each pass.
+fn pass_views<'a>(
+    render_state: &'a RenderState,
+    targets: &'a Targets,
+) -> [Vec<(u32, &'a wgpu::TextureView)>; 4] {
+    [
+        vec![(1, &render_state.depth.view), (2, &targets.velocity.view)],
+        vec![(3, &targets.velocity.view), (4, &targets.tile_max.view)],
+        vec![(5, &targets.tile_max.view), (6, &targets.neighbor_max.view)],
+        vec![
+            (0, &render_state.post.view),
+            (3, &targets.velocity.view),
+            (7, &targets.neighbor_max.view),
+            (8, &targets.output.view),
+        ],
+    ]
+}
+
+fn create_bind_group(
+    context: &Context,
+    layout: &wgpu::BindGroupLayout,
+    params: &ParamsBuffer<MotionBlurParams>,
+    views: &[(u32, &wgpu::TextureView)],
+) -> wgpu::BindGroup {
+    let entries: Vec<_> = views
+        .iter()
+        .map(|(binding, view)| wgpu::BindGroupEntry {
+            binding: *binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        })
+        .chain(iter::once(wgpu::BindGroupEntry {
+            binding: PARAMS_BINDING,
+            resource: params.binding(),
+        }))
+        .collect();
+
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("motion blur"),
+            entries: &entries,
+            layout,
+        })
+}
+
+#[test]
+fn shutter_angle_to_fraction() {
+    let settings = MotionBlurSettings {
+        shutter_angle: 90.0,
+        sample_count: 1,
+        ..Default::default()
+    };
+
+    let params = MotionBlurParams::new(&settings);
+
+    assert_eq!(params.shutter_fraction, 0.25);
+    assert_eq!(params.sample_count, 3);
+
+    let settings = MotionBlurSettings {
+        shutter_angle: 720.0,
+        ..Default::default()
+    };
+
+    assert_eq!(MotionBlurParams::new(&settings).shutter_fraction, 1.0);
+}
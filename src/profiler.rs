@@ -0,0 +1,200 @@
+use std::mem;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::context::Context;
+
+const MAX_TIMESTAMPS: u32 = 32;
+
+/// Measures the GPU time of each phase with timestamp queries written between the passes.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// The label of the phase ending at each timestamp after the first.
+    labels: Vec<&'static str>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// The first and last timestamp of a frame, which are read back without waiting for the
+    /// frame, unlike the other timestamps.
+    frame_readback_buffer: wgpu::Buffer,
+    frame_readback_state: ReadbackState,
+    frame_readback_mapped: Arc<AtomicBool>,
+    frame_time: Option<Duration>,
+}
+
+enum ReadbackState {
+    Idle,
+    Copied,
+    Mapping,
+}
+
+impl GpuProfiler {
+    /// Returns `None` if the device doesn't support timestamp queries.
+    pub fn new(context: &Context) -> Option<Self> {
+        if !context
+            .device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+        {
+            return None;
+        }
+
+        let query_set = context.device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("profiler"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_TIMESTAMPS,
+        });
+
+        let size = MAX_TIMESTAMPS as u64 * mem::size_of::<u64>() as u64;
+
+        let resolve_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler resolve"),
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+            size,
+        });
+
+        let readback_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler readback"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            size,
+        });
+
+        let frame_readback_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler frame readback"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            size: 2 * mem::size_of::<u64>() as u64,
+        });
+
+        Some(Self {
+            timestamp_period: context.queue.get_timestamp_period(),
+            frame_readback_buffer,
+            frame_readback_state: ReadbackState::Idle,
+            frame_readback_mapped: Arc::new(AtomicBool::new(false)),
+            frame_time: None,
+            labels: Vec::new(),
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+        })
+    }
+
+    /// Write the first timestamp of the frame.
+    pub fn begin_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.labels.clear();
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    /// Write a timestamp marking the end of the phase `label`.
+    pub fn timestamp(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        if self.labels.len() as u32 + 1 < MAX_TIMESTAMPS {
+            self.labels.push(label);
+            encoder.write_timestamp(&self.query_set, self.labels.len() as u32);
+        }
+    }
+
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let count = self.labels.len() as u32 + 1;
+        let timestamp_size = mem::size_of::<u64>() as u64;
+        let size = count as u64 * timestamp_size;
+
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, size);
+
+        if let ReadbackState::Idle = self.frame_readback_state {
+            let last = size - timestamp_size;
+            let buffer = &self.frame_readback_buffer;
+
+            encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, buffer, 0, timestamp_size);
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                last,
+                buffer,
+                timestamp_size,
+                timestamp_size,
+            );
+
+            self.frame_readback_state = ReadbackState::Copied;
+        }
+    }
+
+    /// Start mapping the frame timestamps copied this frame. Must be called after the frame is
+    /// submitted.
+    pub fn map_frame_time(&mut self) {
+        if let ReadbackState::Copied = self.frame_readback_state {
+            let mapped = self.frame_readback_mapped.clone();
+            mapped.store(false, atomic::Ordering::Release);
+
+            self.frame_readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    if let Err(err) = result {
+                        eprintln!("failed to map frame timestamps: {err}");
+                    } else {
+                        mapped.store(true, atomic::Ordering::Release);
+                    }
+                });
+
+            self.frame_readback_state = ReadbackState::Mapping;
+        }
+    }
+
+    /// Read back the frame timestamps if they have been mapped.
+    pub fn update_frame_time(&mut self, context: &Context) {
+        context.device.poll(wgpu::Maintain::Poll);
+
+        if let ReadbackState::Mapping = self.frame_readback_state {
+            if self.frame_readback_mapped.load(atomic::Ordering::Acquire) {
+                let ticks = {
+                    let data = self.frame_readback_buffer.slice(..).get_mapped_range();
+                    let timestamps: [u64; 2] = bytemuck::pod_read_unaligned(&data);
+                    timestamps[1].saturating_sub(timestamps[0])
+                };
+
+                let nanoseconds = ticks as f64 * self.timestamp_period as f64;
+                self.frame_time = Some(Duration::from_nanos(nanoseconds as u64));
+
+                self.frame_readback_buffer.unmap();
+                self.frame_readback_state = ReadbackState::Idle;
+            }
+        }
+    }
+
+    /// The GPU time of the latest frame that has been read back since the last call, which is a
+    /// few frames old.
+    pub fn take_frame_time(&mut self) -> Option<Duration> {
+        self.frame_time.take()
+    }
+
+    /// Wait for the resolved timestamps and return the duration of each phase in milliseconds.
+    pub fn read(&self, context: &Context) -> Vec<(&'static str, f64)> {
+        let size = (self.labels.len() as u64 + 1) * mem::size_of::<u64>() as u64;
+        let slice = self.readback_buffer.slice(..size);
+
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        context.device.poll(wgpu::Maintain::Wait);
+
+        let timings = {
+            let data = slice.get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+
+            timestamps
+                .windows(2)
+                .zip(&self.labels)
+                .map(|(pair, label)| {
+                    let ticks = pair[1].saturating_sub(pair[0]);
+                    let milliseconds = ticks as f64 * self.timestamp_period as f64 * 1e-6;
+                    (*label, milliseconds)
+                })
+                .collect()
+        };
+
+        self.readback_buffer.unmap();
+
+        timings
+    }
+}
@@ -1,21 +1,32 @@
-use std::iter;
 use std::rc::Rc;
 use std::time::Duration;
+use std::{iter, mem};
 
 use winit::{dpi::PhysicalSize, window::Window};
 
+use crate::antialiasing::AntiAliasing;
 use crate::asset;
-use crate::atmosphere::AtmospherePhase;
-use crate::bloom::BloomPhase;
+use crate::atmosphere::{AtmospherePhase, AtmosphereSettings};
+use crate::bloom::{BloomPhase, BloomSettings};
 use crate::camera::Camera;
-use crate::context::Context;
+use crate::capture::{CaptureRequest, Capturer};
+use crate::context::{Context, ContextOptions, DisplayOutput};
 use crate::depth_reduce::DepthReducePhase;
-use crate::display::DisplayPhase;
+use crate::display::{DisplayPhase, ExposureSettings, HdrSettings};
+use crate::dof::{DepthOfFieldPhase, DepthOfFieldSettings};
+use crate::fxaa::FxaaPhase;
+use crate::grading::ColorGrading;
+use crate::motion_blur::{MotionBlurPhase, MotionBlurSettings};
+use crate::profiler::GpuProfiler;
+use crate::resolution::{ResolutionController, ResolutionSettings};
 use crate::resources::{
-    ConstState, Consts, DepthPyramid, RenderState, SceneState, ShadowCascades, Skybox,
+    AovTargets, ConstState, Consts, DepthPyramid, FroxelVolume, RenderState, SceneState,
+    ShadowCascades, Skybox,
 };
 use crate::shade::ShadePhase;
 use crate::shadow::ShadowPhase;
+use crate::smaa::SmaaPhase;
+use crate::streaming::{self, TextureStreamer};
 use crate::temporal_resolve::TemporalResolvePhase;
 use crate::visibility::VisiblityPhase;
 
@@ -29,27 +40,59 @@ pub struct Renderer {
     display_phase: DisplayPhase,
     bloom_phase: BloomPhase,
     temporal_resolve_phase: TemporalResolvePhase,
+    fxaa_phase: FxaaPhase,
+    smaa_phase: SmaaPhase,
+    dof_phase: DepthOfFieldPhase,
+    motion_blur_phase: MotionBlurPhase,
     const_state: ConstState,
     shadow_cascades: ShadowCascades,
     render_state: RenderState,
     scene_state: SceneState,
     depth_pyramid: DepthPyramid,
     skybox: Skybox,
+    froxel_volume: FroxelVolume,
+    texture_streamer: TextureStreamer,
     consts: Option<Consts>,
+    texture_anisotropy: u16,
+    profiler: Option<GpuProfiler>,
+    capturer: Capturer,
+    anti_aliasing: AntiAliasing,
+    resolution: ResolutionController,
+    depth_of_field: DepthOfFieldSettings,
+    motion_blur: MotionBlurSettings,
+    atmosphere: AtmosphereSettings,
 }
 
 impl Renderer {
-    pub fn new(window: Rc<Window>, scene: &asset::Scene) -> Self {
-        let mut context = Context::new(window);
+    /// Create the renderer with an empty scene, which only shows the sky. Use `set_scene` once
+    /// a scene has been loaded.
+    pub fn new(window: Rc<Window>, options: ContextOptions) -> eyre::Result<Self> {
+        Self::with_context(Context::new(window, options)?)
+    }
+
+    /// Create a renderer without a window, which renders frames with `draw_headless`.
+    pub fn headless(size: wgpu::Extent3d, options: ContextOptions) -> eyre::Result<Self> {
+        Self::with_context(Context::headless(size, options)?)
+    }
+
+    fn with_context(mut context: Context) -> eyre::Result<Self> {
+        let mut scene = asset::Scene::default();
 
-        let const_state = ConstState::new(&context);
+        let texture_anisotropy = 16;
+        let const_state = ConstState::new(&context, texture_anisotropy);
         let render_state = RenderState::new(&context);
-        let scene_state = SceneState::new(&context, scene);
+        let texture_streamer = TextureStreamer::new(
+            &context,
+            mem::take(&mut scene.textures),
+            streaming::DEFAULT_TEXTURE_MEMORY_BUDGET,
+        )?;
+        let scene_state = SceneState::new(&context, &scene, &texture_streamer);
         let shadow_cascades = ShadowCascades::new(&context);
         let depth_pyramid = DepthPyramid::new(&context);
-        let skybox = Skybox::new(&context);
+        let skybox = Skybox::new(&context, AtmosphereSettings::default().skybox_size);
+        let froxel_volume = FroxelVolume::new(&context);
 
-        let atmosphere_phase = AtmospherePhase::new(&mut context, &skybox);
+        let atmosphere_phase = AtmospherePhase::new(&mut context, &skybox, &froxel_volume);
         let depth_reduce_phase = DepthReducePhase::new(&mut context, &render_state, &depth_pyramid);
         let shadow_phase =
             ShadowPhase::new(&mut context, &scene_state, &shadow_cascades, &depth_pyramid);
@@ -58,17 +101,22 @@ impl Renderer {
             &mut context,
             &scene_state,
             &render_state,
-            &shadow_cascades,
             &skybox,
+            &froxel_volume,
+            &texture_streamer,
         );
         let temporal_resolve_phase = TemporalResolvePhase::new(&mut context, &render_state);
+        let fxaa_phase = FxaaPhase::new(&mut context, &render_state);
+        let smaa_phase = SmaaPhase::new(&mut context, &render_state);
+        let dof_phase = DepthOfFieldPhase::new(&mut context, &render_state);
+        let motion_blur_phase = MotionBlurPhase::new(&mut context, &render_state);
         let bloom_phase = BloomPhase::new(&mut context, &render_state);
 
         let display_format = context.surface_format;
         let display_phase =
             DisplayPhase::new(&mut context, display_format, &render_state.post.view);
 
-        Self {
+        Ok(Self {
             context,
             const_state,
             atmosphere_phase,
@@ -78,14 +126,28 @@ impl Renderer {
             render_state,
             depth_pyramid,
             skybox,
+            froxel_volume,
+            texture_streamer,
             temporal_resolve_phase,
+            fxaa_phase,
+            smaa_phase,
+            dof_phase,
+            motion_blur_phase,
             bloom_phase,
             scene_state,
             shadow_cascades,
             render_phase,
             display_phase,
             consts: None,
-        }
+            texture_anisotropy,
+            profiler: None,
+            capturer: Capturer::default(),
+            anti_aliasing: AntiAliasing::default(),
+            resolution: ResolutionController::new(ResolutionSettings::default()),
+            depth_of_field: DepthOfFieldSettings::default(),
+            motion_blur: MotionBlurSettings::default(),
+            atmosphere: AtmosphereSettings::default(),
+        })
     }
 
     pub fn draw(
@@ -93,7 +155,129 @@ impl Renderer {
         delta_time: Duration,
         camera: &Camera,
     ) -> Result<(), wgpu::SurfaceError> {
-        let consts = Consts::new(camera, &self.context, self.consts.take());
+        let surface = self
+            .context
+            .surface
+            .as_ref()
+            .expect("headless renderers should use render_headless");
+
+        let surface_texture = surface.get_current_texture()?;
+
+        self.render(delta_time, camera, &surface_texture.texture);
+        surface_texture.present();
+
+        Ok(())
+    }
+
+    /// Create a texture that headless renderers can draw frames to with `draw_headless`.
+    pub fn create_frame_buffer(&self) -> wgpu::Texture {
+        self.context
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("headless frame buffer"),
+                format: self.context.surface_format,
+                usage: self.context.surface_usage,
+                dimension: wgpu::TextureDimension::D2,
+                mip_level_count: 1,
+                sample_count: 1,
+                view_formats: &[],
+                size: self.context.surface_size,
+            })
+    }
+
+    /// Draw a frame to `frame_buffer`, which is created with `create_frame_buffer`.
+    pub fn draw_headless(
+        &mut self,
+        delta_time: Duration,
+        camera: &Camera,
+        frame_buffer: &wgpu::Texture,
+    ) {
+        self.render(delta_time, camera, frame_buffer);
+    }
+
+    /// Render `frame_count` frames with a fixed time step and read back the last one. The
+    /// temporal history and exposure converge over the frames.
+    #[cfg(test)]
+    pub fn render_headless(&mut self, camera: &Camera, frame_count: u32) -> image::RgbaImage {
+        let size = self.context.surface_size;
+        let pixels = self.render_headless_raw(camera, frame_count);
+
+        image::RgbaImage::from_raw(size.width, size.height, pixels)
+            .expect("readback buffer should contain every pixel")
+    }
+
+    /// Like `render_headless`, but returns the bytes of the frame in the surface format, which
+    /// must have 4 bytes per pixel.
+    #[cfg(test)]
+    pub fn render_headless_raw(&mut self, camera: &Camera, frame_count: u32) -> Vec<u8> {
+        let size = self.context.surface_size;
+        let frame_buffer = self.create_frame_buffer();
+
+        for _ in 0..frame_count {
+            self.draw_headless(Duration::from_secs_f32(1.0 / 60.0), camera, &frame_buffer);
+            self.context.device.poll(wgpu::Maintain::Wait);
+        }
+
+        // Rows of texture copies must be aligned to 256 bytes.
+        let unpadded_bytes_per_row = size.width * 4;
+        let bytes_per_row =
+            unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let readback_buffer = self.context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("headless readback"),
+            size: bytes_per_row as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("headless readback"),
+                });
+
+        encoder.copy_texture_to_buffer(
+            frame_buffer.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+
+        self.context.queue.submit(iter::once(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        self.context.device.poll(wgpu::Maintain::Wait);
+
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(bytes_per_row as usize)
+            .flat_map(|row| row[..unpadded_bytes_per_row as usize].to_vec())
+            .collect();
+
+        readback_buffer.unmap();
+
+        pixels
+    }
+
+    fn render(&mut self, delta_time: Duration, camera: &Camera, frame_buffer: &wgpu::Texture) {
+        self.capturer.update(&self.context);
+
+        self.texture_streamer.update(&self.context);
+
+        let consts = Consts::new(
+            camera,
+            &self.context,
+            self.consts.take(),
+            self.anti_aliasing.jitter_sequence_length(),
+        );
         self.consts = Some(consts);
 
         let bytes = bytemuck::bytes_of(&consts);
@@ -108,21 +292,30 @@ impl Renderer {
                     label: Some("main encoder"),
                 });
 
-        if consts.frame_index == 0 {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.update_frame_time(&self.context);
+            profiler.begin_frame(&mut encoder);
+        }
+
+        if consts.frame_index == 0 || self.atmosphere_phase.needs_bake() {
             self.atmosphere_phase
                 .record(&self.const_state, &mut encoder);
+            self.timestamp(&mut encoder, "atmosphere");
         }
 
         self.visibility_phase.record(
+            &self.context,
             &self.const_state,
             &self.render_state,
             &self.scene_state,
             camera,
             &mut encoder,
         );
+        self.timestamp(&mut encoder, "visibility");
 
         self.depth_reduce_phase
             .record(&self.depth_pyramid, &self.const_state, &mut encoder);
+        self.timestamp(&mut encoder, "depth reduce");
 
         self.shadow_phase.record(
             &self.const_state,
@@ -130,45 +323,390 @@ impl Renderer {
             &self.scene_state,
             &mut encoder,
         );
+        self.timestamp(&mut encoder, "shadow");
+
+        let volume_distance = self.atmosphere_phase.record_volume(
+            &self.context,
+            camera,
+            &self.atmosphere,
+            &self.const_state,
+            &self.shadow_cascades,
+            &mut encoder,
+        );
+
+        if volume_distance.is_some() {
+            self.timestamp(&mut encoder, "aerial perspective");
+        }
+
+        self.texture_streamer.clear_feedback(&mut encoder);
+
+        let write_aovs = self.capturer.wants_aovs();
+        self.update_aov_targets(write_aovs);
 
         self.render_phase.record(
             &self.context,
             camera,
             &self.const_state,
             &self.scene_state,
+            &self.shadow_cascades,
+            write_aovs,
+            volume_distance,
             &mut encoder,
         );
+        self.timestamp(&mut encoder, "shade");
 
-        self.temporal_resolve_phase.record(
+        self.texture_streamer.copy_feedback(&mut encoder);
+
+        match &self.anti_aliasing {
+            AntiAliasing::Off => {
+                encoder.copy_texture_to_texture(
+                    self.render_state.color.texture.as_image_copy(),
+                    self.render_state.post.texture.as_image_copy(),
+                    self.context.surface_size,
+                );
+            }
+            AntiAliasing::Fxaa => {
+                self.fxaa_phase
+                    .record(&consts, &self.const_state, &mut encoder);
+                self.timestamp(&mut encoder, "fxaa");
+            }
+            AntiAliasing::Smaa => {
+                self.smaa_phase
+                    .record(&consts, &self.const_state, &mut encoder);
+                self.timestamp(&mut encoder, "smaa");
+            }
+            AntiAliasing::Taa(settings) => {
+                self.temporal_resolve_phase.record(
+                    &self.context,
+                    &consts,
+                    &self.const_state,
+                    &self.render_state,
+                    settings,
+                    &mut encoder,
+                );
+                self.timestamp(&mut encoder, "temporal resolve");
+            }
+        }
+
+        let has_depth_of_field = self.dof_phase.record(
             &self.context,
+            delta_time,
+            camera,
+            &self.depth_of_field,
             &consts,
             &self.const_state,
             &self.render_state,
             &mut encoder,
         );
 
-        self.bloom_phase
-            .record(&self.const_state, &self.render_state, &mut encoder);
+        if has_depth_of_field {
+            self.timestamp(&mut encoder, "depth of field");
+        }
 
-        let surface_texture = self.context.surface.get_current_texture()?;
-        let frame_buffer = surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor {
-                label: Some("frame buffer"),
-                ..Default::default()
-            });
+        let has_motion_blur = self.motion_blur_phase.record(
+            &self.context,
+            &self.motion_blur,
+            &consts,
+            &self.const_state,
+            &self.render_state,
+            &mut encoder,
+        );
 
-        self.display_phase
-            .record(&self.context, delta_time, &frame_buffer, &mut encoder);
+        if has_motion_blur {
+            self.timestamp(&mut encoder, "motion blur");
+        }
+
+        self.bloom_phase.record(
+            &self.context,
+            self.display_phase.measured_exposure(),
+            &self.const_state,
+            &self.render_state,
+            &mut encoder,
+        );
+        self.timestamp(&mut encoder, "bloom");
+
+        let frame_buffer_view = frame_buffer.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("frame buffer"),
+            ..Default::default()
+        });
+
+        self.display_phase.record(
+            &self.context,
+            delta_time,
+            camera,
+            &frame_buffer_view,
+            &mut encoder,
+        );
+        self.timestamp(&mut encoder, "display");
+
+        self.capturer.record(
+            &self.context,
+            &self.render_state,
+            frame_buffer,
+            &mut encoder,
+        );
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.resolve(&mut encoder);
+        }
 
         self.context.queue.submit(iter::once(encoder.finish()));
-        surface_texture.present();
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.map_frame_time();
+        }
+
+        self.texture_streamer.map_feedback();
+        self.display_phase.map_readback();
+        self.capturer.map();
+    }
+
+    /// Capture the next frame. The files are written asynchronously a few frames later.
+    pub fn capture(&mut self, request: CaptureRequest) {
+        self.capturer.request(request);
+    }
+
+    /// Replace the scene. The phases that depend on the layout of the scene state are recreated,
+    /// as the texture pools may have changed. The textures are moved to the texture streamer.
+    /// Fails if the textures don't fit in the texture pools, in which case the current scene is
+    /// kept.
+    pub fn set_scene(&mut self, mut scene: asset::Scene) -> eyre::Result<()> {
+        self.texture_streamer = TextureStreamer::new(
+            &self.context,
+            mem::take(&mut scene.textures),
+            streaming::DEFAULT_TEXTURE_MEMORY_BUDGET,
+        )?;
+        self.scene_state = SceneState::new(&self.context, &scene, &self.texture_streamer);
+
+        self.shadow_phase = ShadowPhase::new(
+            &mut self.context,
+            &self.scene_state,
+            &self.shadow_cascades,
+            &self.depth_pyramid,
+        );
+        self.visibility_phase = VisiblityPhase::new(&mut self.context, &self.scene_state);
+        self.render_phase = ShadePhase::new(
+            &mut self.context,
+            &self.scene_state,
+            &self.render_state,
+            &self.skybox,
+            &self.froxel_volume,
+            &self.texture_streamer,
+        );
+
+        // Restart from the first frame to reset the temporal history and the skybox.
+        self.consts = None;
 
         Ok(())
     }
 
+    fn timestamp(&mut self, encoder: &mut wgpu::CommandEncoder, label: &'static str) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.timestamp(encoder, label);
+        }
+    }
+
+    /// Measure the GPU time of each phase. Returns false if the device doesn't support
+    /// timestamp queries.
+    pub fn enable_profiling(&mut self) -> bool {
+        if self.profiler.is_none() {
+            self.profiler = GpuProfiler::new(&self.context);
+        }
+
+        self.profiler.is_some()
+    }
+
+    /// Block until the GPU has finished the submitted frames. Returns the GPU time of each
+    /// phase of the last frame in milliseconds if profiling is enabled.
+    pub fn wait_for_frame(&self) -> Vec<(&'static str, f64)> {
+        match &self.profiler {
+            Some(profiler) => profiler.read(&self.context),
+            None => {
+                self.context.device.poll(wgpu::Maintain::Wait);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn exposure(&self) -> ExposureSettings {
+        self.display_phase.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: ExposureSettings) {
+        self.display_phase.exposure = exposure;
+    }
+
+    pub fn display_output(&self) -> DisplayOutput {
+        self.display_phase.output()
+    }
+
+    /// Set the paper white and peak brightness of HDR output. `enabled` only takes effect when the
+    /// renderer is created.
+    pub fn set_hdr(&mut self, hdr: HdrSettings) {
+        self.display_phase.hdr = hdr;
+    }
+
+    pub fn color_grading(&self) -> &ColorGrading {
+        self.display_phase.color_grading()
+    }
+
+    /// Apply `color_grading`. Fails without changing anything if a LUT fails to load.
+    pub fn set_color_grading(&mut self, color_grading: ColorGrading) -> eyre::Result<()> {
+        self.display_phase
+            .set_color_grading(&self.context, color_grading)
+    }
+
+    /// The average luminance of the scene, adapted over time like auto exposure. This lags a few
+    /// frames behind as it is read back from the GPU.
+    pub fn measured_luminance(&self) -> f32 {
+        self.display_phase.measured_luminance()
+    }
+
+    /// Apply `bloom`. Fails without changing anything if the lens dirt texture fails to load.
+    pub fn set_bloom(&mut self, bloom: BloomSettings) -> eyre::Result<()> {
+        self.bloom_phase.set_settings(&self.context, bloom)
+    }
+
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
+    }
+
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.anti_aliasing = anti_aliasing;
+        self.apply_render_scale();
+    }
+
+    pub fn set_resolution(&mut self, resolution: ResolutionSettings) {
+        self.resolution.set_settings(resolution);
+        self.apply_render_scale();
+    }
+
+    pub fn set_depth_of_field(&mut self, depth_of_field: DepthOfFieldSettings) {
+        self.depth_of_field = depth_of_field;
+    }
+
+    pub fn set_motion_blur(&mut self, motion_blur: MotionBlurSettings) {
+        self.motion_blur = motion_blur;
+    }
+
+    pub fn atmosphere(&self) -> AtmosphereSettings {
+        self.atmosphere
+    }
+
+    /// Apply `atmosphere`. The skybox is recreated if its size changed and baked again if the
+    /// atmosphere changed.
+    pub fn set_atmosphere(&mut self, atmosphere: AtmosphereSettings) {
+        let skybox_size = atmosphere.skybox_size.clamp(16, 2048);
+
+        if skybox_size != self.skybox.size.width {
+            self.skybox = Skybox::new(&self.context, skybox_size);
+            self.atmosphere_phase
+                .set_skybox(&self.context, &self.skybox, &self.froxel_volume);
+            self.render_phase.resize_surface(
+                &self.context,
+                &self.render_state,
+                &self.skybox,
+                &self.froxel_volume,
+                &self.texture_streamer,
+            );
+        }
+
+        self.atmosphere_phase
+            .set_params(&self.context, atmosphere.model.params());
+        self.atmosphere_phase
+            .set_celestial(&self.context, &atmosphere.celestial);
+        self.atmosphere = atmosphere;
+    }
+
+    /// The render scale in use. It is always one without TAA, as the other anti-aliasing modes
+    /// can't upsample.
+    pub fn render_scale(&self) -> f32 {
+        match self.anti_aliasing {
+            AntiAliasing::Taa(_) => self.resolution.render_scale(),
+            _ => 1.0,
+        }
+    }
+
+    /// The render scale the render targets are allocated for.
+    fn max_render_scale(&self) -> f32 {
+        match self.anti_aliasing {
+            AntiAliasing::Taa(_) => self.resolution.max_render_scale(),
+            _ => 1.0,
+        }
+    }
+
+    /// Adjust the render scale to keep the GPU time of frames within the budget of dynamic
+    /// resolution. Does nothing unless profiling is enabled, as the GPU time is measured with
+    /// timestamp queries.
+    pub fn update_dynamic_resolution(&mut self) {
+        let Some(gpu_time) = self
+            .profiler
+            .as_mut()
+            .and_then(GpuProfiler::take_frame_time)
+        else {
+            return;
+        };
+
+        if self.resolution.update(gpu_time) {
+            self.apply_render_scale();
+        }
+    }
+
+    /// Only recreates the render targets if the highest render scale has changed, otherwise
+    /// the passes render to a smaller part of them.
+    fn apply_render_scale(&mut self) {
+        let max_render_scale = self.max_render_scale();
+
+        if self
+            .context
+            .set_render_scale(self.render_scale(), max_render_scale)
+        {
+            self.recreate_render_targets();
+        }
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.context.adapter_info
+    }
+
+    pub fn texture_anisotropy(&self) -> u16 {
+        self.texture_anisotropy
+    }
+
+    /// Set the maximum anisotropy used when sampling material textures. Clamped to `1..=16`.
+    pub fn set_texture_anisotropy(&mut self, anisotropy: u16) {
+        self.texture_anisotropy = anisotropy.clamp(1, 16);
+        self.const_state = ConstState::new(&self.context, self.texture_anisotropy);
+    }
+
     pub fn resize_surface(&mut self, size: PhysicalSize<u32>) {
         self.context.resize_surface(size);
+        self.recreate_render_targets();
+    }
+
+    /// Allocate the AOV targets while they are written, and free them otherwise.
+    fn update_aov_targets(&mut self, write_aovs: bool) {
+        let size = if write_aovs {
+            self.context.render_target_size
+        } else {
+            AovTargets::PLACEHOLDER_SIZE
+        };
+
+        if self.render_state.aovs.size == size {
+            return;
+        }
+
+        self.render_state.aovs = AovTargets::new(&self.context, size);
+        self.render_phase.resize_surface(
+            &self.context,
+            &self.render_state,
+            &self.skybox,
+            &self.froxel_volume,
+            &self.texture_streamer,
+        );
+    }
+
+    fn recreate_render_targets(&mut self) {
         self.render_state = RenderState::new(&self.context);
         self.depth_pyramid = DepthPyramid::new(&self.context);
 
@@ -182,11 +720,20 @@ impl Renderer {
         self.render_phase.resize_surface(
             &self.context,
             &self.render_state,
-            &self.shadow_cascades,
             &self.skybox,
+            &self.froxel_volume,
+            &self.texture_streamer,
         );
         self.temporal_resolve_phase
             .resize_surface(&self.context, &self.render_state);
+        self.fxaa_phase
+            .resize_surface(&self.context, &self.render_state);
+        self.smaa_phase
+            .resize_surface(&self.context, &self.render_state);
+        self.dof_phase
+            .resize_surface(&self.context, &self.render_state);
+        self.motion_blur_phase
+            .resize_surface(&self.context, &self.render_state);
         self.bloom_phase
             .resize_surface(&self.context, &self.render_state);
         self.display_phase
@@ -0,0 +1,195 @@
+//! The internal render resolution and dynamic resolution, which lowers it to keep frames within a
+//! time budget.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// The lowest render scale, below which the upsampled image is too blurry to be useful.
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+
+/// The render scale changes in steps of this size, so noise in the frame time doesn't make it
+/// jump between nearly equal scales.
+const SCALE_STEP: f32 = 0.05;
+
+/// The number of frames to wait after changing the render scale, so the frame time can settle.
+const COOLDOWN_FRAMES: u32 = 30;
+
+/// The render scale is only raised when frames take less than this fraction of the budget.
+const HEADROOM: f32 = 0.85;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResolutionSettings {
+    /// The resolution of the passes before the temporal resolve relative to the output, in the
+    /// range `0.25..=1.0`. Only used with TAA, which reconstructs the output resolution from
+    /// the jittered frames.
+    pub render_scale: f32,
+    /// Lower the render scale when frames exceed a time budget.
+    pub dynamic: Option<DynamicResolution>,
+}
+
+impl Default for ResolutionSettings {
+    fn default() -> Self {
+        Self {
+            render_scale: 1.0,
+            dynamic: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DynamicResolution {
+    /// The target GPU time of frames in milliseconds.
+    pub frame_time_budget: f32,
+    /// The render scale is never lowered below this.
+    pub min_render_scale: f32,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self {
+            frame_time_budget: 1000.0 / 60.0,
+            min_render_scale: 0.5,
+        }
+    }
+}
+
+/// Picks the render scale from the settings and the frame times.
+#[derive(Debug)]
+pub struct ResolutionController {
+    settings: ResolutionSettings,
+    render_scale: f32,
+    /// The exponential moving average of the frame time in milliseconds.
+    average_frame_time: Option<f32>,
+    cooldown: u32,
+}
+
+impl ResolutionController {
+    pub fn new(settings: ResolutionSettings) -> Self {
+        let mut controller = Self {
+            settings,
+            render_scale: 1.0,
+            average_frame_time: None,
+            cooldown: 0,
+        };
+
+        controller.set_settings(settings);
+        controller
+    }
+
+    /// Apply `settings`. Dynamic resolution starts over from the highest render scale.
+    pub fn set_settings(&mut self, settings: ResolutionSettings) {
+        self.settings = settings;
+        self.render_scale = self.max_render_scale();
+        self.average_frame_time = None;
+        self.cooldown = 0;
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// The highest render scale, which dynamic resolution never exceeds.
+    pub fn max_render_scale(&self) -> f32 {
+        self.settings.render_scale.clamp(MIN_RENDER_SCALE, 1.0)
+    }
+
+    /// Update the render scale with the GPU time of a frame. Returns true if it has changed. The
+    /// number of pixels is assumed to be proportional to the frame time.
+    pub fn update(&mut self, frame_time: Duration) -> bool {
+        let Some(dynamic) = self.settings.dynamic else {
+            return false;
+        };
+
+        let frame_time = frame_time.as_secs_f32() * 1000.0;
+        let average = match self.average_frame_time {
+            Some(average) => average + (frame_time - average) * 0.1,
+            None => frame_time,
+        };
+
+        self.average_frame_time = Some(average);
+
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return false;
+        }
+
+        let budget = dynamic.frame_time_budget.max(1.0);
+
+        let render_scale = if average > budget {
+            let target = self.render_scale * (budget / average).sqrt();
+            quantize(target).min(self.render_scale - SCALE_STEP)
+        } else if average < budget * HEADROOM {
+            let target = self.render_scale * (budget * HEADROOM / average).sqrt();
+            quantize(target).clamp(self.render_scale, self.render_scale + SCALE_STEP * 2.0)
+        } else {
+            self.render_scale
+        };
+
+        let min_render_scale = dynamic
+            .min_render_scale
+            .clamp(MIN_RENDER_SCALE, self.max_render_scale());
+        let render_scale = render_scale.clamp(min_render_scale, self.max_render_scale());
+
+        if (render_scale - self.render_scale).abs() < SCALE_STEP / 2.0 {
+            return false;
+        }
+
+        self.render_scale = render_scale;
+        self.cooldown = COOLDOWN_FRAMES;
+
+        true
+    }
+}
+
+fn quantize(render_scale: f32) -> f32 {
+    (render_scale / SCALE_STEP).floor() * SCALE_STEP
+}
+
+#[test]
+fn dynamic_resolution_follows_budget() {
+    let mut controller = ResolutionController::new(ResolutionSettings {
+        render_scale: 1.0,
+        dynamic: Some(DynamicResolution {
+            frame_time_budget: 10.0,
+            min_render_scale: 0.5,
+        }),
+    });
+
+    // A frame time proportional to the number of pixels, which takes 20 ms at full resolution.
+    let frame_time = |scale: f32| Duration::from_secs_f32(0.02 * scale * scale);
+
+    for _ in 0..1000 {
+        controller.update(frame_time(controller.render_scale()));
+    }
+
+    let settled = controller.render_scale();
+    assert!((0.6..=0.75).contains(&settled), "{settled}");
+
+    // Raise the scale again when there is headroom, up to the configured render scale.
+    for _ in 0..1000 {
+        controller.update(Duration::from_millis(1));
+    }
+
+    assert_eq!(controller.render_scale(), 1.0);
+
+    // Never go below the minimum.
+    for _ in 0..1000 {
+        controller.update(Duration::from_millis(100));
+    }
+
+    assert_eq!(controller.render_scale(), 0.5);
+}
+
+#[test]
+fn static_render_scale_is_clamped() {
+    let mut controller = ResolutionController::new(ResolutionSettings {
+        render_scale: 0.1,
+        dynamic: None,
+    });
+
+    assert_eq!(controller.render_scale(), MIN_RENDER_SCALE);
+    assert!(!controller.update(Duration::from_secs(1)));
+}
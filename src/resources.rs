@@ -1,9 +1,4 @@
-use std::{
-    iter, mem,
-    num::{NonZeroU32, NonZeroU64},
-    ops::Range,
-    sync::OnceLock,
-};
+use std::{marker::PhantomData, mem, num::NonZeroU64, ops::Range};
 use wgpu::util::DeviceExt;
 
 use bytemuck::{NoUninit, Pod, Zeroable};
@@ -13,6 +8,7 @@ use crate::{
     asset::{BoundingSphere, DirectionalLight, Scene, Transform},
     camera::Camera,
     context::Context,
+    streaming::{self, TextureStreamer},
     temporal_resolve,
 };
 
@@ -24,16 +20,29 @@ pub struct Consts {
     pub proj_view: Mat4,
     pub prev_proj_view: Mat4,
     pub inverse_proj_view: Mat4,
+    /// See `Camera::depth_linearize_coeffs`.
+    pub depth_linearize_coeffs: Vec4,
     pub sun: DirectionalLight,
     pub frustrum_z_planes: Vec2,
     pub surface_size: UVec2,
+    /// The size of the region of the render targets before the temporal resolve that is
+    /// rendered to.
+    pub render_size: UVec2,
     pub jitter: Vec2,
     pub frame_index: u32,
     pub camera_fov: f32,
+    pub padding: [u32; 2],
 }
 
 impl Consts {
-    pub fn new(camera: &Camera, context: &Context, prev: Option<Consts>) -> Self {
+    /// Create the constants of the next frame. The projection is jittered by a sequence of
+    /// `jitter_sequence_length` subpixel offsets if it is set.
+    pub fn new(
+        camera: &Camera,
+        context: &Context,
+        prev: Option<Consts>,
+        jitter_sequence_length: Option<u32>,
+    ) -> Self {
         let proj_view = camera.proj_view();
         let prev_proj_view = prev.map(|prev| prev.proj_view).unwrap_or(proj_view);
 
@@ -44,7 +53,14 @@ impl Consts {
             y: context.surface_size.height,
         };
 
-        let jitter = temporal_resolve::jitter(frame_index as usize, surface_size);
+        let render_size = UVec2 {
+            x: context.render_size.width,
+            y: context.render_size.height,
+        };
+
+        let jitter = jitter_sequence_length
+            .map(|length| temporal_resolve::jitter(frame_index as usize, render_size, length))
+            .unwrap_or(Vec2::ZERO);
 
         let sun = DirectionalLight {
             direction: Vec4::new(0.0, 1.0, 0.0, 1.0),
@@ -56,6 +72,7 @@ impl Consts {
             camera_front: camera.front.extend(1.0),
             camera_fov: camera.fov,
             inverse_proj_view: proj_view.inverse(),
+            depth_linearize_coeffs: camera.depth_linearize_coeffs(),
             proj_view,
             prev_proj_view,
             sun,
@@ -64,8 +81,10 @@ impl Consts {
                 y: camera.z_far,
             },
             surface_size,
+            render_size,
             frame_index,
             jitter,
+            padding: [0; 2],
         }
     }
 }
@@ -76,7 +95,9 @@ pub struct ConstState {
 }
 
 impl ConstState {
-    pub fn new(context: &Context) -> Self {
+    /// Create the constant state. `anisotropy` is the maximum anisotropy of the material texture
+    /// sampler.
+    pub fn new(context: &Context, anisotropy: u16) -> Self {
         let const_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
             size: mem::size_of::<Consts>() as wgpu::BufferAddress,
@@ -84,6 +105,7 @@ impl ConstState {
             label: Some("constant buffer"),
         });
 
+        // Material textures are sampled with a single sampler, see `asset::TextureSampler`.
         let texture_sampler = context.device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("texture sampler"),
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -91,7 +113,7 @@ impl ConstState {
             mipmap_filter: wgpu::FilterMode::Linear,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            anisotropy_clamp: 1,
+            anisotropy_clamp: anisotropy.clamp(1, 16),
             ..Default::default()
         });
 
@@ -112,7 +134,7 @@ impl ConstState {
             .device
             .create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("const state"),
-                layout: Self::bind_group_layout(&context),
+                layout: Self::bind_group_layout(context),
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
@@ -140,9 +162,7 @@ impl ConstState {
     }
 
     pub fn bind_group_layout(context: &Context) -> &wgpu::BindGroupLayout {
-        static LAYOUT: OnceLock<wgpu::BindGroupLayout> = OnceLock::new();
-
-        LAYOUT.get_or_init(|| {
+        context.shared_layouts.const_state.get_or_init(|| {
             let const_buffer_size = NonZeroU64::new(mem::size_of::<Consts>() as u64);
 
             context
@@ -178,23 +198,96 @@ impl ConstState {
     }
 }
 
+/// The parameters of a pass in a uniform buffer. A pass which records several dispatches or draws
+/// with different parameters in a frame writes each to its own slot, which is selected with a
+/// dynamic offset, as every buffer write of a frame happens before its commands run.
+pub struct ParamsBuffer<T> {
+    buffer: wgpu::Buffer,
+    /// The distance between slots, which is aligned for dynamic offsets.
+    stride: u64,
+    params: PhantomData<T>,
+}
+
+impl<T: NoUninit> ParamsBuffer<T> {
+    pub fn new(context: &Context, label: &str, slot_count: usize) -> Self {
+        let alignment = context.device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = (mem::size_of::<T>() as u64).div_ceil(alignment) * alignment;
+
+        let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: stride * slot_count.max(1) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            buffer,
+            stride,
+            params: PhantomData,
+        }
+    }
+
+    /// A layout entry of the buffer. Buffers with several slots need a dynamic offset.
+    pub fn layout_entry(
+        binding: u32,
+        visibility: wgpu::ShaderStages,
+        has_dynamic_offset: bool,
+    ) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                min_binding_size: NonZeroU64::new(mem::size_of::<T>() as u64),
+                has_dynamic_offset,
+            },
+            count: None,
+        }
+    }
+
+    /// The distance between slots, for recording the dynamic offsets up front.
+    pub fn stride(&self) -> u64 {
+        self.stride
+    }
+
+    /// The binding of the first slot.
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            size: NonZeroU64::new(mem::size_of::<T>() as u64),
+            offset: 0,
+        })
+    }
+
+    /// Write the parameters of `slot`. Returns the dynamic offset of the slot.
+    pub fn write(&self, context: &Context, slot: usize, params: &T) -> wgpu::DynamicOffset {
+        let offset = slot as u64 * self.stride;
+
+        context
+            .queue
+            .write_buffer(&self.buffer, offset, bytemuck::bytes_of(params));
+
+        offset as wgpu::DynamicOffset
+    }
+}
+
 pub struct RenderTarget {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub format: wgpu::TextureFormat,
 }
 
 impl RenderTarget {
     pub fn new(
         context: &Context,
         label: &str,
+        size: wgpu::Extent3d,
         format: wgpu::TextureFormat,
         usage: wgpu::TextureUsages,
     ) -> Self {
         let texture = context.device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             dimension: wgpu::TextureDimension::D2,
-            size: context.surface_size,
+            size,
             mip_level_count: 1,
             sample_count: 1,
             view_formats: &[],
@@ -207,25 +300,30 @@ impl RenderTarget {
             ..Default::default()
         });
 
-        Self {
-            texture,
-            view,
-            format,
-        }
+        Self { texture, view }
     }
 }
 
 pub struct Skybox {
-    pub texture: wgpu::Texture,
     pub array_view: wgpu::TextureView,
     pub cube_view: wgpu::TextureView,
+    /// The size of each of the six faces.
+    pub size: wgpu::Extent3d,
+    /// The sun and moon as seen through the atmosphere, baked together with the skybox.
+    pub lights: wgpu::Buffer,
 }
 
 impl Skybox {
-    pub fn new(context: &Context) -> Self {
+    pub fn new(context: &Context, resolution: u32) -> Self {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 6,
+        };
+
         let texture = context.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("skybox"),
-            size: SKYBOX_SIZE,
+            size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
@@ -246,59 +344,80 @@ impl Skybox {
             ..Default::default()
         });
 
+        let lights = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sky lights"),
+            size: SKY_LIGHTS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
         Self {
-            texture,
             array_view,
             cube_view,
+            size,
+            lights,
         }
     }
 }
 
-pub struct DepthPyramid {
-    pub texture: wgpu::Texture,
-    pub mips: Vec<wgpu::TextureView>,
-    pub whole: wgpu::TextureView,
+/// The aerial perspective and fog of a volume of froxels aligned with the view frustum. Each
+/// froxel contains the light scattered towards the camera up to it and the transmittance.
+///
+/// The slices are the layers of an array texture rather than a 3D texture, as GL only binds the
+/// first slice of 3D storage textures.
+pub struct FroxelVolume {
+    pub view: wgpu::TextureView,
 }
 
-impl DepthPyramid {
+impl FroxelVolume {
     pub fn new(context: &Context) -> Self {
-        let mip_level_count = context.surface_size.max_mips(wgpu::TextureDimension::D2) - 1;
-        let size = context
-            .surface_size
-            .mip_level_size(1, wgpu::TextureDimension::D2);
-
         let texture = context.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("depth pyramid"),
+            label: Some("froxel volume"),
+            size: FROXEL_VOLUME_SIZE,
+            mip_level_count: 1,
+            sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: DEPTH_PYRAMID_FORMAT,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            format: FROXEL_VOLUME_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
-            mip_level_count,
-            sample_count: 1,
-            size,
         });
 
-        let mips = (0..mip_level_count)
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Each mip is a separate texture, as GL ignores the base mip level of texture views when
+/// sampling.
+pub struct DepthPyramid {
+    pub mips: Vec<RenderTarget>,
+}
+
+impl DepthPyramid {
+    pub fn new(context: &Context) -> Self {
+        let mip_level_count = context
+            .render_target_size
+            .max_mips(wgpu::TextureDimension::D2);
+
+        let mips = (1..mip_level_count)
             .map(|level| {
-                texture.create_view(&wgpu::TextureViewDescriptor {
-                    label: Some("depth pyramid"),
-                    base_mip_level: level,
-                    mip_level_count: Some(1),
-                    ..Default::default()
-                })
+                RenderTarget::new(
+                    context,
+                    "depth pyramid",
+                    context
+                        .render_target_size
+                        .mip_level_size(level, wgpu::TextureDimension::D2),
+                    DEPTH_PYRAMID_FORMAT,
+                    wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                )
             })
             .collect();
 
-        let whole = texture.create_view(&wgpu::TextureViewDescriptor {
-            label: Some("whole depth pyramid"),
-            ..Default::default()
-        });
-
-        Self {
-            texture,
-            mips,
-            whole,
-        }
+        Self { mips }
     }
 }
 
@@ -315,8 +434,9 @@ struct ShadowCascade {
 
 pub struct ShadowCascades {
     pub cascades: Vec<wgpu::TextureView>,
-    pub cascade_array: wgpu::TextureView,
     pub cascade_info: wgpu::Buffer,
+    /// The cascades as sampled with the `shadow` shader include, in group 2.
+    pub bind_group: wgpu::BindGroup,
 }
 
 impl ShadowCascades {
@@ -361,20 +481,73 @@ impl ShadowCascades {
             mapped_at_creation: false,
         });
 
+        let bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("shadow cascades"),
+                layout: Self::bind_group_layout(context),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: cascade_info.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&cascade_array),
+                    },
+                ],
+            });
+
         Self {
             cascades,
-            cascade_array,
             cascade_info,
+            bind_group,
         }
     }
+
+    pub fn bind_group_layout(context: &Context) -> &wgpu::BindGroupLayout {
+        context.shared_layouts.shadow_cascades.get_or_init(|| {
+            context
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("shadow cascades"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Texture {
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                    ],
+                })
+        })
+    }
 }
 
+/// The render targets. The targets before the temporal resolve are `Context::render_target_size`
+/// and only the upper left `Context::render_size` is rendered to. The others are
+/// `Context::surface_size`.
 pub struct RenderState {
     pub visibility: RenderTarget,
     pub depth: RenderTarget,
     pub color: RenderTarget,
     pub color_accum: RenderTarget,
     pub post: RenderTarget,
+    pub aovs: AovTargets,
 }
 
 impl RenderState {
@@ -383,40 +556,88 @@ impl RenderState {
             visibility: RenderTarget::new(
                 context,
                 "visibility buffer",
+                context.render_target_size,
                 VISIBILITY_BUFFER_FORMAT,
                 wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             ),
             depth: RenderTarget::new(
                 context,
                 "depth buffer",
+                context.render_target_size,
                 DEPTH_BUFFER_FORMAT,
                 wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             ),
             color: RenderTarget::new(
                 context,
                 "color buffer",
+                context.render_target_size,
                 COLOR_BUFFER_FORMAT,
-                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
             ),
             color_accum: RenderTarget::new(
                 context,
                 "color accum buffer",
+                context.surface_size,
                 COLOR_BUFFER_FORMAT,
                 wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             ),
             post: RenderTarget::new(
                 context,
                 "post buffer",
+                context.surface_size,
                 COLOR_BUFFER_FORMAT,
                 wgpu::TextureUsages::STORAGE_BINDING
                     | wgpu::TextureUsages::RENDER_ATTACHMENT
                     | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::COPY_SRC,
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
             ),
+            aovs: AovTargets::placeholder(context),
         }
     }
 }
 
+/// The targets written on frames that are captured with AOVs. They are only allocated at the
+/// render target size while AOVs are captured, and are a single pixel otherwise.
+pub struct AovTargets {
+    /// The world space normal and view depth of each pixel.
+    pub normal_depth: RenderTarget,
+    /// The base color of each pixel, with an alpha of zero for the sky.
+    pub albedo: RenderTarget,
+    pub size: wgpu::Extent3d,
+}
+
+impl AovTargets {
+    /// The size of the targets while AOVs aren't written.
+    pub const PLACEHOLDER_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+        width: 1,
+        height: 1,
+        depth_or_array_layers: 1,
+    };
+
+    pub fn new(context: &Context, size: wgpu::Extent3d) -> Self {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC;
+
+        Self {
+            normal_depth: RenderTarget::new(
+                context,
+                "normal depth buffer",
+                size,
+                NORMAL_DEPTH_BUFFER_FORMAT,
+                usage,
+            ),
+            albedo: RenderTarget::new(context, "albedo buffer", size, COLOR_BUFFER_FORMAT, usage),
+            size,
+        }
+    }
+
+    pub fn placeholder(context: &Context) -> Self {
+        Self::new(context, Self::PLACEHOLDER_SIZE)
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable, Default)]
 struct Primitive {
@@ -428,17 +649,22 @@ struct Primitive {
 pub struct PrimitiveDrawInfo {
     pub bounding_sphere: BoundingSphere,
     pub indices: Range<u32>,
-    pub material: u32,
 }
 
 pub struct SceneState {
     pub primitive_draw_infos: Vec<PrimitiveDrawInfo>,
+    /// The index of each primitive, bound as an instance vertex buffer with
+    /// `PRIMITIVE_INDEX_LAYOUT`. Primitive `i` is drawn as instance `i..i + 1`, and the buffer is
+    /// used instead of `instance_index` since GL doesn't add the first instance to it.
+    pub primitive_indices: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl SceneState {
-    pub fn new(context: &Context, scene: &Scene) -> Self {
+    /// Create the scene state. The scene textures are owned by `texture_streamer`, which places
+    /// them in its texture pools. `scene` may be empty, which is used while a scene is loading.
+    pub fn new(context: &Context, scene: &Scene, texture_streamer: &TextureStreamer) -> Self {
         let mut primitives = Vec::new();
         let mut primitive_draw_infos = Vec::new();
 
@@ -471,7 +697,6 @@ impl SceneState {
 
                     primitive_draw_infos.push(PrimitiveDrawInfo {
                         indices: primitive.indices.clone(),
-                        material: primitive.material,
                         bounding_sphere,
                     });
                 }
@@ -480,65 +705,30 @@ impl SceneState {
             transform
         });
 
-        let primitive_buffer =
+        let primitive_buffer = create_storage_buffer(context, "primitive buffer", &primitives);
+        let primitive_indices =
             context
                 .device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("primitive buffer"),
-                    usage: wgpu::BufferUsages::STORAGE,
-                    contents: bytemuck::cast_slice(&primitives),
+                    label: Some("primitive indices"),
+                    usage: wgpu::BufferUsages::VERTEX,
+                    contents: bytemuck::cast_slice(
+                        &(0..primitives.len().max(1) as u32).collect::<Vec<_>>(),
+                    ),
                 });
+        let material_buffer = create_storage_buffer(context, "material buffer", &scene.materials);
+        let index_buffer = create_storage_buffer(context, "index buffer", &scene.indices[..]);
+        let vertex_buffer = create_storage_buffer(context, "vertex buffer", &scene.vertices[..]);
 
-        let material_buffer =
-            context
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("material buffer"),
-                    usage: wgpu::BufferUsages::STORAGE,
-                    contents: bytemuck::cast_slice(&scene.materials),
-                });
-
-        let index_buffer = context
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("index buffer"),
-                usage: wgpu::BufferUsages::STORAGE,
-                contents: bytemuck::cast_slice(&scene.indices),
-            });
-
-        let vertex_buffer = context
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("vertex buffer"),
-                usage: wgpu::BufferUsages::STORAGE,
-                contents: bytemuck::cast_slice(&scene.vertices),
-            });
-
-        let textures: Vec<_> = scene
-            .textures
-            .iter()
-            .map(|texture| {
-                context
-                    .device
-                    .create_texture_with_data(
-                        &context.queue,
-                        &wgpu::TextureDescriptor {
-                            dimension: wgpu::TextureDimension::D2,
-                            usage: wgpu::TextureUsages::TEXTURE_BINDING,
-                            mip_level_count: texture.mip_level_count,
-                            size: texture.extent,
-                            format: texture.format,
-                            view_formats: &[],
-                            sample_count: 1,
-                            label: None,
-                        },
-                        &texture.mips,
-                    )
-                    .create_view(&wgpu::TextureViewDescriptor::default())
-            })
-            .collect();
+        let buffers = [
+            &primitive_buffer,
+            &material_buffer,
+            &index_buffer,
+            &vertex_buffer,
+            &texture_streamer.slot_buffer,
+        ];
 
-        let layout_entries: Vec<_> = (0..4)
+        let layout_entries: Vec<_> = (0..buffers.len() as u32)
             .map(|binding| wgpu::BindGroupLayoutEntry {
                 binding,
                 visibility: wgpu::ShaderStages::all(),
@@ -549,16 +739,18 @@ impl SceneState {
                 },
                 count: None,
             })
-            .chain(iter::once(wgpu::BindGroupLayoutEntry {
-                binding: 4,
-                visibility: wgpu::ShaderStages::all(),
-                ty: wgpu::BindingType::Texture {
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    multisampled: false,
-                },
-                count: NonZeroU32::new(textures.len() as u32),
-            }))
+            .chain(
+                (0..streaming::POOL_COUNT as u32).map(|pool| wgpu::BindGroupLayoutEntry {
+                    binding: buffers.len() as u32 + pool,
+                    visibility: wgpu::ShaderStages::all(),
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                }),
+            )
             .collect();
 
         let bind_group_layout =
@@ -569,30 +761,22 @@ impl SceneState {
                     entries: &layout_entries,
                 });
 
-        let buffers = [
-            &primitive_buffer,
-            &material_buffer,
-            &index_buffer,
-            &vertex_buffer,
-        ];
-
-        let texture_refs = textures.iter().collect::<Vec<_>>();
-
         let bind_group_entries: Vec<_> = buffers
             .iter()
             .enumerate()
             .map(|(binding, buffer)| wgpu::BindGroupEntry {
                 binding: binding as u32,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    offset: 0,
-                    size: None,
-                    buffer,
-                }),
+                resource: buffer.as_entire_binding(),
             })
-            .chain(iter::once(wgpu::BindGroupEntry {
-                binding: 4,
-                resource: wgpu::BindingResource::TextureViewArray(&texture_refs),
-            }))
+            .chain(
+                texture_streamer
+                    .pool_views()
+                    .enumerate()
+                    .map(|(pool, view)| wgpu::BindGroupEntry {
+                        binding: (buffers.len() + pool) as u32,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    }),
+            )
             .collect();
 
         let bind_group = context
@@ -607,22 +791,58 @@ impl SceneState {
             bind_group,
             bind_group_layout,
             primitive_draw_infos,
+            primitive_indices,
         }
     }
 }
 
-pub const DEPTH_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+/// The layout of `SceneState::primitive_indices`. Must match the `primitive_index` vertex input.
+pub const PRIMITIVE_INDEX_LAYOUT: wgpu::VertexBufferLayout = wgpu::VertexBufferLayout {
+    array_stride: mem::size_of::<u32>() as wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode::Instance,
+    attributes: &wgpu::vertex_attr_array![0 => Uint32],
+};
+
+/// Create a read only storage buffer. Bindings can't be empty, so a single zeroed element is
+/// used if `contents` is empty.
+fn create_storage_buffer<T: Pod>(context: &Context, label: &str, contents: &[T]) -> wgpu::Buffer {
+    let zeroed = [T::zeroed()];
+    let contents = if contents.is_empty() {
+        &zeroed
+    } else {
+        contents
+    };
+
+    context
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            usage: wgpu::BufferUsages::STORAGE,
+            contents: bytemuck::cast_slice(contents),
+        })
+}
+
+/// The depth buffer is reversed, see `camera::Projection`, which works best with floats.
+pub const DEPTH_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 pub const VISIBILITY_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
 pub const COLOR_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const NORMAL_DEPTH_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
 
 pub const SKYBOX_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
-pub const SKYBOX_SIZE: wgpu::Extent3d = wgpu::Extent3d {
+
+/// Must match the size of `SkyLights` in `light.wgsl`.
+pub const SKY_LIGHTS_SIZE: wgpu::BufferAddress = 48;
+
+pub const FROXEL_VOLUME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const FROXEL_VOLUME_SIZE: wgpu::Extent3d = wgpu::Extent3d {
     width: 64,
     height: 64,
-    depth_or_array_layers: 6,
+    depth_or_array_layers: 64,
 };
 
-pub const DEPTH_PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+/// The farthest and nearest depth in `r` and `g`. Four channels, as `Rg32Float` can't be a
+/// storage texture everywhere.
+pub const DEPTH_PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
 pub const SHADOW_CASCADE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth16Unorm;
 
 pub const SHADOW_CASCADE_SIZE: u32 = 1024;
@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+use crate::antialiasing::AntiAliasing;
+use crate::atmosphere::AtmosphereSettings;
+#[cfg(test)]
+use crate::atmosphere::{AtmosphereModel, AtmosphereParams};
+use crate::bloom::BloomSettings;
+use crate::capture::CaptureSettings;
+use crate::display::{ExposureSettings, HdrSettings};
+use crate::dof::DepthOfFieldSettings;
+use crate::grading::ColorGrading;
+use crate::motion_blur::MotionBlurSettings;
+use crate::resolution::ResolutionSettings;
+
+/// Render settings loaded from a RON file. Settings that aren't in the file keep their defaults:
+///
+/// ```ron
+/// (
+///     exposure: (
+///         mode: Auto((min_ev100: -2.0, metering: CenterWeighted)),
+///         compensation: 0.5,
+///     ),
+///     color_grading: (
+///         tonemapper: AgX,
+///         lut: Some("grading/warm.cube"),
+///         temperature: 5500.0,
+///     ),
+///     hdr: (enabled: true, peak: 600.0),
+///     capture: (aovs: true, sequence_frames: 300),
+///     anti_aliasing: Taa((history_blend: 0.05, sharpness: 0.3)),
+///     resolution: (render_scale: 0.75, dynamic: Some((frame_time_budget: 16.6))),
+///     depth_of_field: (autofocus: false, max_coc: 24.0),
+///     motion_blur: (enabled: true, shutter_angle: 90.0),
+///     bloom: (
+///         mode: Threshold(threshold: 1.0, knee: 0.5),
+///         lens_dirt: Some("textures/lens_dirt.png"),
+///         lens_flare: (ghost_intensity: 0.5, streak_intensity: 0.2),
+///     ),
+///     atmosphere: (
+///         aerial_perspective_scale: 10.0,
+///         height_fog: Some((density: 0.02, albedo: (0.9, 0.9, 1.0))),
+///         model: Custom((mie_g: 0.7, sample_count: 64)),
+///         skybox_size: 512,
+///         celestial: (moon: Some((direction: (0.3, 0.2, -0.9))), star_brightness: 1.0),
+///     ),
+/// )
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub exposure: ExposureSettings,
+    pub color_grading: ColorGrading,
+    pub hdr: HdrSettings,
+    pub capture: CaptureSettings,
+    pub anti_aliasing: AntiAliasing,
+    pub resolution: ResolutionSettings,
+    pub depth_of_field: DepthOfFieldSettings,
+    pub motion_blur: MotionBlurSettings,
+    pub bloom: BloomSettings,
+    pub atmosphere: AtmosphereSettings,
+}
+
+impl RenderSettings {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read render settings at {path:?}"))?;
+
+        ron::from_str(&source)
+            .wrap_err_with(|| format!("failed to parse render settings at {path:?}"))
+    }
+}
+
+#[test]
+fn parse_partial_settings() {
+    use crate::display::{ExposureMode, Metering};
+
+    let settings: RenderSettings =
+        ron::from_str("(exposure: (mode: Auto((min_ev100: -2.0, metering: Spot(radius: 0.1)))))")
+            .unwrap();
+
+    let ExposureMode::Auto(auto) = settings.exposure.mode else {
+        panic!("expected auto exposure");
+    };
+
+    assert_eq!(auto.min_ev100, -2.0);
+    assert_eq!(auto.max_ev100, 6.5);
+    assert_eq!(auto.metering, Metering::Spot { radius: 0.1 });
+    assert_eq!(settings.exposure.compensation, 0.0);
+}
+
+#[test]
+fn parse_anti_aliasing() {
+    use crate::temporal_resolve::TaaSettings;
+
+    let settings: RenderSettings =
+        ron::from_str("(anti_aliasing: Taa((jitter_sequence_length: 8)))").unwrap();
+
+    let expected = TaaSettings {
+        jitter_sequence_length: 8,
+        ..Default::default()
+    };
+
+    assert_eq!(settings.anti_aliasing, AntiAliasing::Taa(expected));
+
+    let settings: RenderSettings = ron::from_str("(anti_aliasing: Smaa)").unwrap();
+    assert_eq!(settings.anti_aliasing, AntiAliasing::Smaa);
+}
+
+#[test]
+fn parse_height_fog() {
+    let settings: RenderSettings =
+        ron::from_str("(atmosphere: (height_fog: Some((density: 0.02, albedo: (0.9, 0.9, 1.0)))))")
+            .unwrap();
+
+    let fog = settings.atmosphere.height_fog.unwrap();
+
+    assert_eq!(fog.density, 0.02);
+    assert_eq!(fog.albedo, glam::Vec3::new(0.9, 0.9, 1.0));
+    assert!(fog.sun_shafts);
+    assert!(settings.atmosphere.aerial_perspective);
+}
+
+#[test]
+fn parse_atmosphere_model() {
+    let settings: RenderSettings =
+        ron::from_str("(atmosphere: (model: Custom((mie_g: 0.7)), skybox_size: 128))").unwrap();
+
+    let AtmosphereModel::Custom(params) = settings.atmosphere.model else {
+        panic!("expected a custom atmosphere");
+    };
+
+    assert_eq!(params.mie_g, 0.7);
+    assert_eq!(
+        params.planet_radius,
+        AtmosphereParams::default().planet_radius
+    );
+    assert_eq!(settings.atmosphere.skybox_size, 128);
+
+    let settings: RenderSettings = ron::from_str("(atmosphere: (model: Mars))").unwrap();
+    assert_eq!(settings.atmosphere.model, AtmosphereModel::Mars);
+}
@@ -1,16 +1,38 @@
-use std::{borrow::Cow, mem};
+use std::borrow::Cow;
 
-use glam::Mat4;
+use bytemuck::NoUninit;
+use glam::{Mat4, Vec3};
 
 use crate::{
     camera::Camera,
-    context::Context,
-    resources::{self, ConstState, RenderState, SceneState, ShadowCascades, Skybox},
+    context::{Context, ShaderDefValue},
+    resources::{
+        self, ConstState, FroxelVolume, ParamsBuffer, RenderState, SceneState, ShadowCascades,
+        Skybox,
+    },
+    streaming::TextureStreamer,
     util,
 };
 
+/// Must match `Params` in `shade.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct ShadeParams {
+    /// Transforms clip space to world space relative to the camera, which avoids precision
+    /// issues far from the origin.
+    ray_matrix: Mat4,
+    /// Write the AOV targets if not zero.
+    write_aovs: u32,
+    /// Apply the froxel volume if not zero.
+    apply_volume: u32,
+    /// The distance covered by the froxel volume.
+    volume_distance: f32,
+    padding: u32,
+}
+
 pub struct ShadePhase {
     shade: wgpu::ComputePipeline,
+    params: ParamsBuffer<ShadeParams>,
     bind_group: wgpu::BindGroup,
     bind_group_layout: wgpu::BindGroupLayout,
 }
@@ -20,13 +42,17 @@ impl ShadePhase {
         context: &mut Context,
         scene_state: &SceneState,
         render_state: &RenderState,
-        shadow_cascades: &ShadowCascades,
         skybox: &Skybox,
+        froxel_volume: &FroxelVolume,
+        texture_streamer: &TextureStreamer,
     ) -> ShadePhase {
         let shade_module = context.create_shader_module(
             include_str!("shaders/shade.wgsl"),
             "shaders/shade.wgsl",
-            &[],
+            &[(
+                "VOLUME_SLICE_COUNT",
+                ShaderDefValue::Int(resources::FROXEL_VOLUME_SIZE.depth_or_array_layers as i32),
+            )],
         );
 
         let shade_shader = context
@@ -56,7 +82,7 @@ impl ShadePhase {
                             binding: 1,
                             visibility: wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Texture {
-                                sample_type: wgpu::TextureSampleType::Depth,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
                                 view_dimension: wgpu::TextureViewDimension::D2,
                                 multisampled: false,
                             },
@@ -78,7 +104,7 @@ impl ShadePhase {
                             ty: wgpu::BindingType::StorageTexture {
                                 format: resources::COLOR_BUFFER_FORMAT,
                                 view_dimension: wgpu::TextureViewDimension::D2,
-                                access: wgpu::StorageTextureAccess::ReadWrite,
+                                access: wgpu::StorageTextureAccess::WriteOnly,
                             },
                             count: None,
                         },
@@ -86,7 +112,7 @@ impl ShadePhase {
                             binding: 4,
                             visibility: wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
                                 has_dynamic_offset: false,
                                 min_binding_size: None,
                             },
@@ -95,21 +121,60 @@ impl ShadePhase {
                         wgpu::BindGroupLayoutEntry {
                             binding: 5,
                             visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                format: resources::NORMAL_DEPTH_BUFFER_FORMAT,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 6,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                format: resources::COLOR_BUFFER_FORMAT,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 7,
+                            visibility: wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Texture {
                                 view_dimension: wgpu::TextureViewDimension::D2Array,
-                                sample_type: wgpu::TextureSampleType::Depth,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
                                 multisampled: false,
                             },
                             count: None,
                         },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 8,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        ParamsBuffer::<ShadeParams>::layout_entry(
+                            9,
+                            wgpu::ShaderStages::COMPUTE,
+                            false,
+                        ),
                     ],
                 });
 
+        let params = ParamsBuffer::new(context, "shade params", 1);
+
         let bind_group = create_shade_bind_group(
             context,
             render_state,
-            shadow_cascades,
             skybox,
+            froxel_volume,
+            texture_streamer,
+            &params,
             &bind_group_layout,
         );
 
@@ -118,13 +183,11 @@ impl ShadePhase {
                 .device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("shade"),
-                    push_constant_ranges: &[wgpu::PushConstantRange {
-                        stages: wgpu::ShaderStages::COMPUTE,
-                        range: 0..mem::size_of::<Mat4>() as u32,
-                    }],
+                    push_constant_ranges: &[],
                     bind_group_layouts: &[
-                        ConstState::bind_group_layout(&context),
+                        ConstState::bind_group_layout(context),
                         &scene_state.bind_group_layout,
+                        ShadowCascades::bind_group_layout(context),
                         &bind_group_layout,
                     ],
                 });
@@ -140,6 +203,7 @@ impl ShadePhase {
 
         Self {
             shade,
+            params,
             bind_group,
             bind_group_layout,
         }
@@ -149,24 +213,33 @@ impl ShadePhase {
         &mut self,
         context: &Context,
         render_state: &RenderState,
-        shadow_cascades: &ShadowCascades,
         skybox: &Skybox,
+        froxel_volume: &FroxelVolume,
+        texture_streamer: &TextureStreamer,
     ) {
         self.bind_group = create_shade_bind_group(
             context,
             render_state,
-            shadow_cascades,
             skybox,
+            froxel_volume,
+            texture_streamer,
+            &self.params,
             &self.bind_group_layout,
         );
     }
 
+    /// Shade the visibility buffer. `volume_distance` is the distance covered by the froxel
+    /// volume if it has been recorded this frame.
+    #[allow(clippy::too_many_arguments)]
     pub fn record(
         &self,
         context: &Context,
         camera: &Camera,
         const_state: &ConstState,
         scene_state: &SceneState,
+        shadow_cascades: &ShadowCascades,
+        write_aovs: bool,
+        volume_distance: Option<f32>,
         encoder: &mut wgpu::CommandEncoder,
     ) {
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
@@ -176,18 +249,22 @@ impl ShadePhase {
         compute_pass.set_pipeline(&self.shade);
         compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
         compute_pass.set_bind_group(1, &scene_state.bind_group, &[]);
-        compute_pass.set_bind_group(2, &self.bind_group, &[]);
+        compute_pass.set_bind_group(2, &shadow_cascades.bind_group, &[]);
+        compute_pass.set_bind_group(3, &self.bind_group, &[]);
 
-        let mut ray_matrix = camera.proj_view();
-        ray_matrix.col_mut(3)[0] = 0.0;
-        ray_matrix.col_mut(3)[1] = 0.0;
-        ray_matrix.col_mut(3)[2] = 0.0;
-        ray_matrix = ray_matrix.inverse();
+        let view = Mat4::look_to_rh(Vec3::ZERO, camera.front, camera.up());
+        let params = ShadeParams {
+            ray_matrix: (camera.proj() * view).inverse(),
+            write_aovs: write_aovs.into(),
+            apply_volume: volume_distance.is_some().into(),
+            volume_distance: volume_distance.unwrap_or(0.0),
+            padding: 0,
+        };
 
-        compute_pass.set_push_constants(0, bytemuck::bytes_of(&ray_matrix));
+        self.params.write(context, 0, &params);
 
-        let x = util::div_ceil(context.surface_size.width, 8);
-        let y = util::div_ceil(context.surface_size.height, 8);
+        let x = util::div_ceil(context.render_size.width, 8);
+        let y = util::div_ceil(context.render_size.height, 8);
 
         compute_pass.dispatch_workgroups(x, y, 1);
     }
@@ -196,8 +273,10 @@ impl ShadePhase {
 fn create_shade_bind_group(
     context: &Context,
     render_state: &RenderState,
-    shadow_cascades: &ShadowCascades,
     skybox: &Skybox,
+    froxel_volume: &FroxelVolume,
+    texture_streamer: &TextureStreamer,
+    params: &ParamsBuffer<ShadeParams>,
     layout: &wgpu::BindGroupLayout,
 ) -> wgpu::BindGroup {
     context
@@ -225,14 +304,32 @@ fn create_shade_bind_group(
                 wgpu::BindGroupEntry {
                     binding: 4,
                     resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &shadow_cascades.cascade_info,
+                        buffer: &texture_streamer.feedback_buffer,
                         offset: 0,
                         size: None,
                     }),
                 },
                 wgpu::BindGroupEntry {
                     binding: 5,
-                    resource: wgpu::BindingResource::TextureView(&shadow_cascades.cascade_array),
+                    resource: wgpu::BindingResource::TextureView(
+                        &render_state.aovs.normal_depth.view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&render_state.aovs.albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&froxel_volume.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: skybox.lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: params.binding(),
                 },
             ],
         })
@@ -1,96 +1,214 @@
 #import consts
 #import util
 #import light
+#import atmosphere
+#import shadow
 
-const EARTH_RADIUS = 6371000.0;
-const EARTH_CENTER = vec3f(0.0, -6371000.0, 0.0);
+const TRANSMITTANCE_SAMPLE_COUNT = 40;
+const MULTI_SCATTERING_SAMPLE_COUNT = 20;
 
-const ATMOS_HEIGHT = 100000.0;
-// const MIE_HEIGHT = ATMOS_HEIGHT * 0.012;
-const MIE_HEIGHT = 1200.0;
-// const RAYLEIGH_HEIGHT = ATMOS_HEIGHT * 0.08;
-const RAYLEIGH_HEIGHT = 8000.0;
+// The directions the multi-scattering LUT integrates over is a grid of this size.
+const MULTI_SCATTERING_DIRECTIONS = 8u;
 
-// The ozone layer start at around 10 km height and has a width of 30 km.
-const OZONE_CENTER_HEIGHT = 25000.0;
-const OZONE_WIDTH = 30000.0;
+// Must match `VolumeFlags` in `atmosphere.rs`.
+const AERIAL_PERSPECTIVE = 1u;
+const HEIGHT_FOG = 2u;
+const SUN_SHAFTS = 4u;
 
-// const RAYLEIGH_COEFF = vec3f(5.802, 13.558, 33.100) * 1e-6;
-const RAYLEIGH_COEFF = vec3f(0.000005802, 0.000013558, 0.0000331);
-// const MIE_COEFF = vec3f(3.996) * 1e-6;
-const MIE_COEFF = vec3f(0.000003996, 0.000003996, 0.000003996);
-// const OZONE_COEFF = vec3f(0.650, 1.881, 0.085) * 1e-6;
-const OZONE_COEFF = vec3f(6.5e-7, 0.000001881, 8.5e-8);
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+@group(0) @binding(2)
+var linear_sampler: sampler;
+
+@group(1) @binding(0)
+var skybox: texture_storage_2d_array<rgba16float, write>;
 
-// The amount of time a ray should sample.
-const RAY_SAMPLE_COUNT = 16;
+@group(1) @binding(1)
+var transmittance_lut_storage: texture_storage_2d<rgba16float, write>;
 
-fn ray_sphere_intersection(ray: util::Ray, sphere: util::Sphere) -> vec2f {
-    let ray_origin = ray.origin - sphere.center;
+@group(1) @binding(2)
+var transmittance_lut: texture_2d<f32>;
 
-    let a = dot(ray.direction, ray.direction);
-    let b = 2.0 * dot(ray_origin, ray.direction);
-    let c  = dot(ray_origin, ray_origin) - (sphere.radius * sphere.radius);
+@group(1) @binding(3)
+var multi_scattering_lut_storage: texture_storage_2d<rgba16float, write>;
 
-    let sqrt_disc = sqrt(b * b - 4.0 * a * c);
+@group(1) @binding(4)
+var multi_scattering_lut: texture_2d<f32>;
 
-    return vec2f((-b) - sqrt_disc, (-b) + sqrt_disc) / (2.0 * a);
+@group(1) @binding(5)
+var volume: texture_storage_2d_array<rgba16float, write>;
+
+@group(1) @binding(8)
+var skybox_cube: texture_cube<f32>;
+
+// Must match `CelestialParams` in `atmosphere.rs`.
+struct CelestialParams {
+    moon_direction: vec3f,
+    moon_angular_radius: f32,
+    sun_angular_radius: f32,
+    moon_albedo: f32,
+    star_brightness: f32,
 }
 
-fn rayleigh_density(height: f32) -> f32 {
-	return exp(-max(0.0, height / RAYLEIGH_HEIGHT));
+@group(1) @binding(10)
+var<uniform> celestial: CelestialParams;
+
+@group(1) @binding(11)
+var<storage, read_write> sky_lights: light::SkyLights;
+
+// Must match `VolumeParams` in `atmosphere.rs`.
+struct Params {
+    ray_matrix: mat4x4f,
+    fog_albedo: vec3f,
+    fog_density: f32,
+    volume_distance: f32,
+    aerial_perspective_scale: f32,
+    fog_height_falloff: f32,
+    fog_base_height: f32,
+    fog_anisotropy: f32,
+    flags: u32,
 }
 
-fn mie_density(height: f32) -> f32 {
-	return exp(-max(0.0, height / MIE_HEIGHT));
+@group(1) @binding(12)
+var<uniform> params: Params;
+
+fn hits_ground(position: vec3f, direction: vec3f) -> bool {
+    let r = distance(position, atmosphere::planet_center());
+    let mu = atmosphere::cos_zenith(position, direction);
+    let radius = atmosphere::atmosphere_params.planet_radius;
+    return mu < 0.0 && r * r * (mu * mu - 1.0) + radius * radius >= 0.0;
 }
 
-fn ozone_density(height: f32) -> f32 {
-	return max(0.0, 1.0 - abs(height - OZONE_CENTER_HEIGHT) / (OZONE_WIDTH / 2.0));
+// The transmittance from `position` to the edge of the atmosphere in `direction`.
+fn sample_transmittance(position: vec3f, direction: vec3f) -> vec3f {
+    let height = atmosphere::height(position);
+    let uv = atmosphere::transmittance_lut_uv(height, atmosphere::cos_zenith(position, direction));
+    return textureSampleLevel(transmittance_lut, linear_sampler, uv, 0.0).rgb;
 }
 
-fn rayleigh_phase(cos_theta: f32) -> f32 {
-	return 3.0 * (1.0 + cos_theta * cos_theta) / (16.0 * util::PI);
+// The light scattered more than once towards `position`, for a sun of unit irradiance.
+fn sample_multi_scattering(position: vec3f, sun_direction: vec3f) -> vec3f {
+    let height = atmosphere::height(position);
+    let cos_zenith = atmosphere::cos_zenith(position, sun_direction);
+    let uv = atmosphere::multi_scattering_lut_uv(height, cos_zenith);
+    return textureSampleLevel(multi_scattering_lut, linear_sampler, uv, 0.0).rgb;
 }
 
-fn mie_phase(cos_theta: f32) -> f32 {
-	let G = 0.85;
-	let K = 1.55 * G - 0.55 * G * G * G;
-	return (1.0 - K * K) / ((4.0 * util::PI) * pow(1.0 - K * cos_theta, 2.0));
+// A position at `height` above the ground and a direction with the cosine of the zenith angle.
+fn lut_ray(height: f32, cos_zenith: f32) -> util::Ray {
+    var ray: util::Ray;
+    ray.origin = atmosphere::planet_center()
+        + vec3f(0.0, atmosphere::atmosphere_params.planet_radius + height, 0.0);
+    ray.direction = vec3f(sqrt(max(1.0 - cos_zenith * cos_zenith, 0.0)), cos_zenith, 0.0);
+    return ray;
 }
 
-struct OpticalDepth {
-    rayleigh: f32,
-    mie: f32,
-    ozone: f32,
+// The distance to the ground or the edge of the atmosphere along `ray`.
+fn ray_length(ray: util::Ray) -> f32 {
+    if hits_ground(ray.origin, ray.direction) {
+        let planet = atmosphere::planet_sphere();
+        return max(atmosphere::ray_sphere_intersection(ray, planet).x, 0.0);
+    }
+
+    return atmosphere::ray_sphere_intersection(ray, atmosphere::atmosphere_sphere()).y;
 }
 
-fn integrate_optical_depth(ray: util::Ray, atmosphere: util::Sphere) -> OpticalDepth {
-    let intersection = ray_sphere_intersection(ray, atmosphere);
-    let ray_lenght = intersection.y;
+@compute
+@workgroup_size(8, 8)
+fn transmittance(@builtin(global_invocation_id) invocation_id: vec3u) {
+    let size = textureDimensions(transmittance_lut_storage);
 
-    let step_size = ray_lenght / f32(RAY_SAMPLE_COUNT);
+    if any(invocation_id.xy >= size) {
+        return;
+    }
 
-    var optical_depth: OpticalDepth;
+    let uv = (vec2f(invocation_id.xy) + 0.5) / vec2f(size);
+    let lut_params = atmosphere::transmittance_lut_params(uv);
+    let ray = lut_ray(lut_params.x, lut_params.y);
 
-    for (var i = 0; i < 8; i += 1) {
-        let position = ray.origin + ray.direction * (f32(i) + 0.5) * step_size;
-        let height = distance(position, EARTH_CENTER) - EARTH_RADIUS;
+    // The sun is below the horizon.
+    if hits_ground(ray.origin, ray.direction) {
+        textureStore(transmittance_lut_storage, invocation_id.xy, vec4f(0.0, 0.0, 0.0, 1.0));
+        return;
+    }
+
+    let step_size = ray_length(ray) / f32(TRANSMITTANCE_SAMPLE_COUNT);
+    var optical_depth = vec3f(0.0);
 
-        optical_depth.rayleigh += rayleigh_density(height) * step_size;
-        optical_depth.mie += mie_density(height) * step_size;
-        optical_depth.ozone += ozone_density(height) * step_size;
+    for (var i = 0; i < TRANSMITTANCE_SAMPLE_COUNT; i += 1) {
+        let position = ray.origin + ray.direction * (f32(i) + 0.5) * step_size;
+        optical_depth += atmosphere::sample_medium(atmosphere::height(position)).extinction * step_size;
     }
 
-    return optical_depth;
+    textureStore(transmittance_lut_storage, invocation_id.xy, vec4f(exp(-optical_depth), 1.0));
 }
 
-fn absorb(optical_depth: OpticalDepth) -> vec3f {
-	return exp(-(
-		optical_depth.rayleigh * RAYLEIGH_COEFF
-			+ optical_depth.mie * MIE_COEFF
-			+ optical_depth.ozone * OZONE_COEFF
-	));
+// The multi-scattering approximation of "A Scalable and Production Ready Sky and Atmosphere
+// Rendering Technique" by Hillaire. The light scattered twice is integrated over the sphere with
+// an isotropic phase function, and higher orders are the geometric series of the fraction of
+// light that is scattered again.
+@compute
+@workgroup_size(8, 8)
+fn multi_scattering(@builtin(global_invocation_id) invocation_id: vec3u) {
+    let size = textureDimensions(multi_scattering_lut_storage);
+
+    if any(invocation_id.xy >= size) {
+        return;
+    }
+
+    let uv = (vec2f(invocation_id.xy) + 0.5) / vec2f(size);
+    let lut_params = atmosphere::multi_scattering_lut_params(uv);
+    let sun = lut_ray(lut_params.x, lut_params.y);
+
+    let isotropic_phase = 1.0 / (4.0 * util::PI);
+
+    var second_order = vec3f(0.0);
+    var transfer = vec3f(0.0);
+
+    for (var i = 0u; i < MULTI_SCATTERING_DIRECTIONS; i += 1u) {
+        for (var j = 0u; j < MULTI_SCATTERING_DIRECTIONS; j += 1u) {
+            let cos_theta = 1.0 - 2.0 * (f32(i) + 0.5) / f32(MULTI_SCATTERING_DIRECTIONS);
+            let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+            let phi = util::TAU * (f32(j) + 0.5) / f32(MULTI_SCATTERING_DIRECTIONS);
+
+            var ray: util::Ray;
+            ray.origin = sun.origin;
+            ray.direction = vec3f(sin_theta * cos(phi), cos_theta, sin_theta * sin(phi));
+
+            let step_size = ray_length(ray) / f32(MULTI_SCATTERING_SAMPLE_COUNT);
+            var view_transmittance = vec3f(1.0);
+
+            for (var k = 0; k < MULTI_SCATTERING_SAMPLE_COUNT; k += 1) {
+                let position = ray.origin + ray.direction * (f32(k) + 0.5) * step_size;
+                let medium = atmosphere::sample_medium(atmosphere::height(position));
+                let scattering = medium.rayleigh_scattering + medium.mie_scattering;
+
+                let step_transmittance = exp(-medium.extinction * step_size);
+                let extinction = max(medium.extinction, vec3f(1e-12));
+
+                // Integrate analytically over the step, as the transmittance falls off
+                // exponentially.
+                let luminance = scattering
+                    * sample_transmittance(position, sun.direction)
+                    * isotropic_phase;
+                second_order += view_transmittance
+                    * (luminance - luminance * step_transmittance) / extinction;
+                transfer += view_transmittance
+                    * (scattering - scattering * step_transmittance) / extinction;
+
+                view_transmittance *= step_transmittance;
+            }
+        }
+    }
+
+    let direction_count = f32(MULTI_SCATTERING_DIRECTIONS * MULTI_SCATTERING_DIRECTIONS);
+    second_order /= direction_count;
+    transfer /= direction_count;
+
+    let multi_scattering = second_order / (1.0 - transfer);
+
+    textureStore(multi_scattering_lut_storage, invocation_id.xy, vec4f(multi_scattering, 1.0));
 }
 
 fn integrate_atmospheric_scattering(
@@ -98,66 +216,49 @@ fn integrate_atmospheric_scattering(
     ray: util::Ray,
     atmosphere: util::Sphere,
 ) -> vec3f {
-    let intersection = ray_sphere_intersection(ray, atmosphere);
+    let intersection = atmosphere::ray_sphere_intersection(ray, atmosphere);
 
 	// The length of the ray will be from the origin to the edge of the atmosphere.
     let ray_length = intersection.y;
 
     let cos_theta = dot(ray.direction, light.direction.xyz);
 
-	let rayleigh_phase = rayleigh_phase(cos_theta);
-	let mie_phase = mie_phase(cos_theta);
+	let rayleigh_phase = atmosphere::rayleigh_phase(cos_theta);
+	let mie_phase = atmosphere::mie_phase(cos_theta);
 
-	var optical_depth: OpticalDepth;
-    var rayleigh = vec3f(0.0);
-    var mie = vec3f(0.0);
+	var optical_depth = vec3f(0.0);
+    var scattered = vec3f(0.0);
 
     var prev_marched = 0.0;
 
-    for (var i = 0; i < 16; i += 1) {
+    let sample_count = atmosphere::atmosphere_params.sample_count;
+
+    for (var i = 0u; i < sample_count; i += 1u) {
 		// Sample at greater and greater intervals. 7 is just an arbitrary exponent.
-		let marched = pow(f32(i) / 16.0, 7.0) * ray_length;
+		let marched = pow(f32(i) / f32(sample_count), 7.0) * ray_length;
 		let step_size = marched - prev_marched;
 
 		let position = ray.origin + ray.direction * marched;
-		let height = distance(position, EARTH_CENTER) - EARTH_RADIUS;
-
-        let rayleigh_density = rayleigh_density(height);
-        let mie_density = mie_density(height);
-
-        optical_depth.rayleigh += rayleigh_density * step_size;
-        optical_depth.mie += mie_density * step_size;
-        optical_depth.ozone += ozone_density(height) * step_size;
+        let medium = atmosphere::sample_medium(atmosphere::height(position));
 
-		let view_transmittance = absorb(optical_depth);
+        optical_depth += medium.extinction * step_size;
 
-	    var light_ray: util::Ray;
-		light_ray.origin = position;
-		light_ray.direction = light.direction.xyz;
+		let view_transmittance = exp(-optical_depth);
+		let light_transmittance = sample_transmittance(position, light.direction.xyz);
+		let multi_scattering = sample_multi_scattering(position, light.direction.xyz);
 
-		let light_transmittance = absorb(integrate_optical_depth(light_ray, atmosphere));
+		let single = medium.rayleigh_scattering * rayleigh_phase
+			+ medium.mie_scattering * mie_phase;
 
-		rayleigh += view_transmittance
-			* light_transmittance
-			* rayleigh_phase
-			* rayleigh_density
-			* step_size;
-
-		mie += view_transmittance
-			* light_transmittance
-			* mie_phase
-			* mie_density
+		scattered += view_transmittance
+			* (single * light_transmittance
+				+ (medium.rayleigh_scattering + medium.mie_scattering) * multi_scattering)
 			* step_size;
 
 		prev_marched = marched;
     }
 
-	let transmittance = absorb(optical_depth);
-
-    let exposure = 20.0;
-    let sky = (rayleigh * RAYLEIGH_COEFF + mie * MIE_COEFF) * light.irradiance.xyz * exposure;
-
-    return sky;
+    return scattered * light.irradiance.xyz * atmosphere::SKY_EXPOSURE;
 }
 
 fn cube_map_face(index: u32) -> mat3x3f {
@@ -207,18 +308,40 @@ fn cube_map_face(index: u32) -> mat3x3f {
     }
 }
 
-@group(0) @binding(0)
-var<uniform> consts: consts::Consts;
+// The sun and moon seen from `position`, scaled like the scattered light of the sky.
+fn write_sky_lights(position: vec3f) {
+    let sun = consts.sun;
+    let sun_solid_angle = util::TAU * (1.0 - cos(celestial.sun_angular_radius));
+    let sun_transmittance = sample_transmittance(position, sun.direction.xyz);
+    let moon_transmittance = sample_transmittance(position, celestial.moon_direction);
 
-@group(1) @binding(0)
-var skybox: texture_storage_2d_array<rgba16float, write>;
+    var lights: light::SkyLights;
+
+    lights.sun_radiance = sun.irradiance.xyz * sun_transmittance / max(sun_solid_angle, 1e-10)
+        * atmosphere::SKY_EXPOSURE;
+    lights.sun_angular_radius = celestial.sun_angular_radius;
+
+    lights.moon_radiance = sun.irradiance.xyz * celestial.moon_albedo / util::PI
+        * moon_transmittance * atmosphere::SKY_EXPOSURE;
+    lights.moon_angular_radius = celestial.moon_angular_radius;
+    lights.moon_direction = celestial.moon_direction;
+
+    lights.star_brightness = celestial.star_brightness;
+
+    sky_lights = lights;
+}
 
 @compute
 @workgroup_size(8, 8, 1)
-fn main(@builtin(global_invocation_id) invocation_id: vec3u) {
+fn sky(@builtin(global_invocation_id) invocation_id: vec3u) {
     let face = invocation_id.z;
 
     let skybox_size = textureDimensions(skybox);
+
+    if any(invocation_id.xy >= skybox_size.xy) {
+        return;
+    }
+
     var uv = (vec2f(invocation_id.xy) + 0.5) / vec2f(skybox_size.xy);
 
     var ndc = vec3f(uv * 2.0 - 1.0, -1.0);
@@ -226,13 +349,137 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3u) {
 
     var ray: util::Ray;
     ray.direction = normalize(cube_map_face(face) * ndc);
-    ray.origin = vec3f(200.0);
+    ray.origin = vec3f(atmosphere::VIEW_HEIGHT);
 
-    var atmosphere: util::Sphere;
-    atmosphere.center = EARTH_CENTER;
-    atmosphere.radius = EARTH_RADIUS + ATMOS_HEIGHT;
+    if all(invocation_id == vec3u(0u)) {
+        write_sky_lights(ray.origin);
+    }
 
-    let color = integrate_atmospheric_scattering(consts.sun, ray, atmosphere);
+    let color = integrate_atmospheric_scattering(consts.sun, ray, atmosphere::atmosphere_sphere());
 
     textureStore(skybox, invocation_id.xy, face, vec4f(color, 1.0));
 }
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * util::PI * pow(max(denom, 1e-5), 1.5));
+}
+
+fn interleaved_gradient_noise(pixel: vec2f) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2f(0.06711056, 0.00583715))));
+}
+
+// The distance from the camera to the far end of a froxel slice. The slices are distributed
+// quadratically to have more of them close to the camera.
+fn slice_distance(slice: u32, slice_count: u32) -> f32 {
+    let t = f32(slice) / f32(slice_count);
+    return params.volume_distance * t * t;
+}
+
+// The visibility of the sun at `world_pos`, which is `depth` from the camera along its front.
+// Uses the same test as `shadow_occlusion` in `shade.wgsl` with a single sample.
+fn sun_visibility(world_pos: vec3f, depth: f32) -> f32 {
+    let cascade_index = shadow::cascade_index(depth);
+    let light_pos = shadow::light_position(cascade_index, world_pos);
+    let shadow = shadow::occlusion(cascade_index, light_pos, vec2f(0.0), 0.0005);
+
+    return saturate(1.0 - shadow);
+}
+
+// Integrate the aerial perspective and height fog through a froxel volume aligned with the view
+// frustum. Each invocation marches through a column of froxels and stores the light scattered
+// towards the camera and the transmittance up to each slice.
+@compute
+@workgroup_size(8, 8)
+fn aerial_perspective(@builtin(global_invocation_id) invocation_id: vec3u) {
+    let size = vec3u(textureDimensions(volume), textureNumLayers(volume));
+
+    if any(invocation_id.xy >= size.xy) {
+        return;
+    }
+
+    var ndc = (vec2f(invocation_id.xy) + 0.5) / vec2f(size.xy) * 2.0 - 1.0;
+    ndc.y *= -1.0;
+
+    var near = params.ray_matrix * vec4f(ndc, 1.0, 1.0);
+    var far = params.ray_matrix * vec4f(ndc, 0.5, 1.0);
+    near /= near.w;
+    far /= far.w;
+
+    let origin = consts.camera_pos.xyz;
+    let direction = normalize(far.xyz - near.xyz);
+    let view_cos = dot(direction, consts.camera_front.xyz);
+
+    let sun_direction = consts.sun.direction.xyz;
+    let irradiance = consts.sun.irradiance.xyz;
+    let cos_theta = dot(direction, sun_direction);
+
+    let rayleigh_phase = atmosphere::rayleigh_phase(cos_theta);
+    let mie_phase = atmosphere::mie_phase(cos_theta);
+    let fog_phase = henyey_greenstein(cos_theta, params.fog_anisotropy);
+
+    // Light from the sky is approximated by the zenith of the skybox.
+    let ambient = textureSampleLevel(skybox_cube, linear_sampler, vec3f(0.0, 1.0, 0.0), 0.0).rgb;
+
+    let frame_offset = vec2f(f32(consts.frame_index % 64u) * 5.588238);
+    let noise = interleaved_gradient_noise(vec2f(invocation_id.xy) + frame_offset);
+
+    var scattered = vec3f(0.0);
+    var transmittance = vec3f(1.0);
+    var previous_distance = 0.0;
+
+    for (var slice = 0u; slice < size.z; slice += 1u) {
+        let slice_end = slice_distance(slice + 1u, size.z);
+        let step_size = slice_end - previous_distance;
+        let distance = previous_distance + 0.5 * step_size;
+
+        var extinction = vec3f(0.0);
+        var source = vec3f(0.0);
+
+        if (params.flags & AERIAL_PERSPECTIVE) != 0u {
+            let scale = params.aerial_perspective_scale;
+            let position = atmosphere::world_to_atmosphere(origin + direction * distance * scale);
+            let medium = atmosphere::sample_medium(atmosphere::height(position));
+
+            let single = medium.rayleigh_scattering * rayleigh_phase
+                + medium.mie_scattering * mie_phase;
+            let multi_scattering = (medium.rayleigh_scattering + medium.mie_scattering)
+                * sample_multi_scattering(position, sun_direction);
+
+            extinction += medium.extinction * scale;
+            source += (single * sample_transmittance(position, sun_direction) + multi_scattering)
+                * irradiance
+                * atmosphere::SKY_EXPOSURE
+                * scale;
+        }
+
+        if (params.flags & HEIGHT_FOG) != 0u {
+            let position = origin + direction * distance;
+            let height = position.y - params.fog_base_height;
+            let density = params.fog_density * exp(-params.fog_height_falloff * height);
+
+            var visibility = 1.0;
+
+            if (params.flags & SUN_SHAFTS) != 0u {
+                // Jitter the shadow sample within the froxel to hide the banding of the slices.
+                let sample_distance = previous_distance + noise * step_size;
+                let sample_position = origin + direction * sample_distance;
+                visibility = sun_visibility(sample_position, sample_distance * view_cos);
+            }
+
+            extinction += vec3f(density);
+            source += density * params.fog_albedo
+                * (fog_phase * irradiance * visibility + ambient);
+        }
+
+        let step_transmittance = exp(-extinction * step_size);
+        scattered += transmittance
+            * (source - source * step_transmittance) / max(extinction, vec3f(1e-12));
+        transmittance *= step_transmittance;
+
+        let average_transmittance = dot(transmittance, vec3f(1.0 / 3.0));
+        textureStore(volume, invocation_id.xy, slice, vec4f(scattered, average_transmittance));
+
+        previous_distance = slice_end;
+    }
+}
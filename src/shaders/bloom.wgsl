@@ -10,7 +10,45 @@ var linear_sampler: sampler;
 @group(1) @binding(0)
 var input: texture_2d<f32>;
 
-var<push_constant> level: u32;
+@group(2) @binding(0)
+var lens_dirt: texture_2d<f32>;
+
+@group(2) @binding(1)
+var flare_buffer: texture_2d<f32>;
+
+const IS_INITIAL = 1u;
+const LENS_FLARE = 2u;
+
+struct Params {
+    level: u32,
+    flags: u32,
+    threshold: f32,
+    knee: f32,
+    lens_dirt_intensity: f32,
+    flare_threshold: f32,
+    ghost_count: u32,
+    ghost_spacing: f32,
+    ghost_intensity: f32,
+    halo_radius: f32,
+    halo_intensity: f32,
+    streak_intensity: f32,
+    streak_length: f32,
+}
+
+@group(1) @binding(1)
+var<uniform> params: Params;
+
+// Remove the parts of `color` below `threshold`, with a quadratic curve of half width `knee`
+// around it. This is the identity when both are zero.
+fn soft_threshold(color: vec3f, threshold: f32, knee: f32) -> vec3f {
+    let brightness = max(color.r, max(color.g, color.b));
+
+    var soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+
+    let contribution = max(soft, brightness - threshold) / max(brightness, 1e-5);
+    return color * max(contribution, 0.0);
+}
 
 fn karis_average(color: vec3f) -> f32 {
     let luma = util::luminance(util::rgb_to_srgb(color)) / 4.0;
@@ -32,7 +70,7 @@ fn downscale_sample(uv: vec2f) -> vec3f {
     let l = textureSampleLevel(input, linear_sampler, uv, 0.0, vec2i(-1, -1)).rgb;
     let m = textureSampleLevel(input, linear_sampler, uv, 0.0, vec2i(1, -1)).rgb;
 
-    if level == 0u {
+    if params.level == 0u {
         var g0 = (a + b + d + e) * (0.125f / 4.0f);
         var g1 = (b + c + e + f) * (0.125f / 4.0f);
         var g2 = (d + e + g + h) * (0.125f / 4.0f);
@@ -107,10 +145,89 @@ fn vertex(in: VertexInput) -> VertexOutput {
 
 @fragment
 fn downsample(in: VertexOutput) -> @location(0) vec4f {
-    return vec4f(downscale_sample(in.uv), 1.0);
+    var color = downscale_sample(in.uv);
+
+    if (params.flags & IS_INITIAL) != 0u {
+        color = soft_threshold(color, params.threshold, params.knee);
+    }
+
+    return vec4f(color, 1.0);
 }
 
 @fragment
 fn upsample(in: VertexOutput) -> @location(0) vec4f {
     return vec4f(upscale_sample(in.uv), 1.0);
 }
+
+fn flare_sample(uv: vec2f) -> vec3f {
+    let color = textureSampleLevel(input, linear_sampler, uv, 0.0).rgb;
+    return soft_threshold(color, params.flare_threshold, 0.5 * params.flare_threshold);
+}
+
+// Fade out samples near the edges of the screen, where the ghosts would be cut off.
+fn edge_fade(uv: vec2f, falloff: f32) -> f32 {
+    let distance = length(vec2f(0.5) - uv) / length(vec2f(0.5));
+    return pow(saturate(1.0 - distance), falloff);
+}
+
+// Ghosts, a halo and a horizontal streak from the bright parts of the input. The ghosts and the
+// halo are mirrored through the center of the screen like reflections between lens elements.
+@fragment
+fn flare(in: VertexOutput) -> @location(0) vec4f {
+    let aspect = f32(consts.surface_size.x) / f32(consts.surface_size.y);
+    let flipped = vec2f(1.0) - in.uv;
+    let ghost_vector = (vec2f(0.5) - flipped) * params.ghost_spacing;
+
+    var out = vec3f(0.0);
+
+    for (var i = 0u; i < params.ghost_count; i++) {
+        let uv = fract(flipped + ghost_vector * f32(i));
+
+        // Tint each ghost differently, as the coatings of lens elements reflect different
+        // wavelengths.
+        let t = f32(i) / max(f32(params.ghost_count), 1.0);
+        let tint = mix(vec3f(1.0, 0.8, 0.6), vec3f(0.6, 0.8, 1.0), t);
+        out += flare_sample(uv) * tint * edge_fade(uv, 10.0) * params.ghost_intensity;
+    }
+
+    if params.halo_intensity > 0.0 {
+        let direction = (vec2f(0.5) - flipped) * vec2f(aspect, 1.0);
+        let halo_vector = normalize(direction + 1e-5) / vec2f(aspect, 1.0) * params.halo_radius * 0.5;
+        let uv = fract(flipped + halo_vector);
+        out += flare_sample(uv) * edge_fade(uv, 5.0) * params.halo_intensity;
+    }
+
+    if params.streak_intensity > 0.0 {
+        var streak = vec3f(0.0);
+        var weight_sum = 0.0;
+
+        for (var i = -8; i <= 8; i++) {
+            let offset = f32(i) / 8.0;
+            let weight = exp(-4.0 * offset * offset);
+            let uv = vec2f(in.uv.x + offset * params.streak_length * 0.5, in.uv.y);
+
+            streak += flare_sample(uv) * weight;
+            weight_sum += weight;
+        }
+
+        // Anamorphic lenses give streaks a blue tint.
+        out += streak / weight_sum * vec3f(0.5, 0.7, 1.0) * params.streak_intensity;
+    }
+
+    return vec4f(out, 1.0);
+}
+
+// The final upsample, which adds the lens flare and lights up the lens dirt with the bloom.
+@fragment
+fn composite(in: VertexOutput) -> @location(0) vec4f {
+    var bloom = upscale_sample(in.uv);
+
+    if (params.flags & LENS_FLARE) != 0u {
+        bloom += textureSampleLevel(flare_buffer, linear_sampler, in.uv, 0.0).rgb;
+    }
+
+    let dirt = textureSampleLevel(lens_dirt, linear_sampler, in.uv, 0.0).rgb;
+    bloom += bloom * dirt * params.lens_dirt_intensity;
+
+    return vec4f(bloom, 1.0);
+}
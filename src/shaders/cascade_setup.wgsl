@@ -2,18 +2,13 @@
 #import light
 #import util
 
-struct ShadowParams {
-    cascade_size: u32,
-    lambda: f32,
-    near_offset: f32,
-}
-
 @group(0) @binding(0)
 var<uniform> consts: consts::Consts;
 
 @group(1) @binding(0)
 var<storage, read_write> shadow_cascades: array<light::ShadowCascade>;
 
+// The last mip of the depth pyramid, with the depth range of the whole frame.
 @group(1) @binding(1)
 var depth_pyramid: texture_2d<f32>;
 
@@ -139,21 +134,14 @@ fn update_matrices(cascade_count: u32, shadow_map_size: u32, light_dir: vec3f, n
 fn main() {
     let cascade_count = arrayLength(&shadow_cascades);
 
-    let depth_pyramid_mip_count = textureNumLevels(depth_pyramid);
-    let max_mip_level = i32(depth_pyramid_mip_count - 1u);
-
-    let depth_min_max = textureLoad(depth_pyramid, vec2u(0u), max_mip_level);
-
-    let near = util::linearize_depth(
-        consts.frustrum_z_planes.x,
-        consts.frustrum_z_planes.y,
-        depth_min_max.x,
-    );
+    let depth_min_max = textureLoad(depth_pyramid, vec2u(0u), 0);
 
-    let far = util::linearize_depth(
-        consts.frustrum_z_planes.x,
+    // The depth is reversed, so the nearest depth is the largest. The sky has a depth of 0,
+    // which is at infinity for perspective projections.
+    let near = util::linearize_depth(consts.depth_linearize_coeffs, depth_min_max.y);
+    let far = min(
+        util::linearize_depth(consts.depth_linearize_coeffs, max(depth_min_max.x, 1e-7)),
         consts.frustrum_z_planes.y,
-        depth_min_max.y,
     );
 
     let lambda = 0.3;
@@ -1,17 +1,29 @@
 
-@group(0) @binding(2)
-var linear_sampler: sampler;
+#import consts
 
-#if INITIAL_REDUCE == true
-@group(1) @binding(0)
-var input: texture_depth_2d;
-#else
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+// The depth buffer in the initial reduce, and the previous mip after that.
 @group(1) @binding(0)
 var input: texture_2d<f32>;
-#endif
 
 @group(1) @binding(1)
-var output: texture_storage_2d<rg32float, write>;
+var output: texture_storage_2d<rgba32float, write>;
+
+// Load the 2x2 block of texels closest to `uv`, which is what a gather at `uv` returns.
+fn load_block(uv: vec2f) -> array<vec4f, 4> {
+    let input_size = vec2i(textureDimensions(input));
+    let base = vec2i(floor(uv * vec2f(input_size) - 0.5));
+
+    var block: array<vec4f, 4>;
+    for (var i = 0; i < 4; i += 1) {
+        let texel = clamp(base + vec2i(i & 1, i >> 1u), vec2i(0), input_size - 1);
+        block[i] = textureLoad(input, texel, 0);
+    }
+
+    return block;
+}
 
 @compute
 @workgroup_size(8, 8)
@@ -22,16 +34,25 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3u) {
         return;
     }
 
-    let uv = (vec2f(invocation_id.xy) + 0.5) / vec2f(output_size);
+    var uv = (vec2f(invocation_id.xy) + 0.5) / vec2f(output_size);
+
+    #if INITIAL_REDUCE == true
+    // Only the upper left of the depth buffer is rendered to when the render scale is lowered.
+    uv *= vec2f(consts.render_size) / vec2f(textureDimensions(input));
+
+    #endif
+
+    let block = load_block(uv);
 
     #if INITIAL_REDUCE == true
-    let depth_min = textureGather(input, linear_sampler, uv);
-    let depth_max = textureGather(input, linear_sampler, uv);
+    let depth_min = vec4f(block[0].x, block[1].x, block[2].x, block[3].x);
+    let depth_max = depth_min;
     #else
-    let depth_min = textureGather(0, input, linear_sampler, uv);
-    let depth_max = textureGather(1, input, linear_sampler, uv);
+    let depth_min = vec4f(block[0].x, block[1].x, block[2].x, block[3].x);
+    let depth_max = vec4f(block[0].y, block[1].y, block[2].y, block[3].y);
     #endif
 
+    // The depth is reversed, so x is the farthest and y the nearest depth.
     let out = vec2f(
         min(depth_min.x, min(depth_min.y, min(depth_min.z, depth_min.w))),
         max(depth_max.x, max(depth_max.y, max(depth_max.z, depth_max.w))),
@@ -12,8 +12,41 @@ struct VertexOutput {
 @group(0) @binding(0)
 var display: texture_2d<f32>;
 
+// Must match `Exposure` in `display.rs`.
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
+
 @group(1) @binding(1)
-var<storage, read_write> average_luminance: f32;
+var<storage, read_write> exposure: Exposure;
+
+@group(2) @binding(0)
+var tonemap_lut: texture_3d<f32>;
+
+@group(2) @binding(1)
+var grading_lut: texture_3d<f32>;
+
+@group(2) @binding(2)
+var lut_sampler: sampler;
+
+// Must match `DisplayParams` in `display.rs`.
+struct Params {
+    white_balance: mat3x3f,
+    lut_domain_min: vec4f,
+    lut_domain_max: vec4f,
+    tonemapper: u32,
+    white_point: f32,
+    contrast: f32,
+    saturation: f32,
+    output: u32,
+    paper_white: f32,
+    max_value: f32,
+    padding: u32,
+}
+
+@group(0) @binding(1)
+var<uniform> params: Params;
 
 @vertex
 fn vertex(in: VertexInput) -> VertexOutput {
@@ -31,39 +64,26 @@ fn vertex(in: VertexInput) -> VertexOutput {
     return out;
 }
 
-// http://www.brucelindbloom.com/index.html?Eqn_RGB_XYZ_Matrix.html
-fn rgb_to_xyz(rgb: vec3f) -> vec3f {
-	var xyz: vec3f;
-	xyz.x = dot(vec3f(0.4124564, 0.3575761, 0.1804375), rgb);
-	xyz.y = dot(vec3f(0.2126729, 0.7151522, 0.0721750), rgb);
-	xyz.z = dot(vec3f(0.0193339, 0.1191920, 0.9503041), rgb);
-	return xyz;
-}
+const TONEMAPPER_ACES_FITTED = 0u;
+const TONEMAPPER_AGX = 1u;
+const TONEMAPPER_TONY_MC_MAPFACE = 2u;
+const TONEMAPPER_REINHARD_EXTENDED = 3u;
+const TONEMAPPER_PBR_NEUTRAL = 4u;
 
-// http://www.brucelindbloom.com/index.html?Eqn_XYZ_to_xyY.html
-fn xyz_to_yxy(xyz: vec3f) -> vec3f {
-	let inverse = 1.0 / dot(xyz, vec3f(1.0));
-	return vec3f(xyz.y, xyz.x * inverse, xyz.y * inverse);
-}
+const OUTPUT_SCRGB = 1u;
+const OUTPUT_HDR10 = 2u;
 
-// http://www.brucelindbloom.com/index.html?Eqn_xyY_to_XYZ.html
-fn yxy_to_xyz(yxy: vec3f) -> vec3f {
-	var xyz: vec3f;
-	xyz.x = yxy.x * yxy.y / yxy.z;
-	xyz.y = yxy.x;
-	xyz.z = yxy.x * (1.0 - yxy.y - yxy.z) / yxy.z;
-	return xyz;
-}
+const MIDDLE_GRAY = 0.18;
+
+// The brightness of 1.0 in scRGB.
+const SCRGB_NITS = 80.0;
 
-fn xyz_to_rgb(xyz: vec3f) -> vec3f {
-    var rgb: vec3f;
-	rgb.x = dot(vec3f(3.2404542, -1.5371385, -0.4985314), xyz);
-	rgb.y = dot(vec3f(-0.9692660, 1.8760108, 0.0415560), xyz);
-	rgb.z = dot(vec3f(0.0556434, -0.2040259, 1.0572252), xyz);
-	return rgb;
+fn luminance(rgb: vec3f) -> f32 {
+    return dot(rgb, vec3f(0.2126, 0.7152, 0.0722));
 }
 
-fn aces_tonemap(x: f32) -> f32 {
+// https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces_fitted(x: vec3f) -> vec3f {
     let a = 2.51;
     let b = 0.03;
     let c = 2.43;
@@ -72,18 +92,181 @@ fn aces_tonemap(x: f32) -> f32 {
     return (x * (a * x + b)) / (x * (c * x + d) + e);
 }
 
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx_contrast(x: vec3f) -> vec3f {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+
+    return 15.5 * x4 * x2
+        - 40.14 * x4 * x
+        + 31.96 * x4
+        - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232;
+}
+
+fn agx(rgb: vec3f) -> vec3f {
+    let inset = mat3x3f(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+
+    let outset = mat3x3f(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var color = inset * rgb;
+    color = clamp(log2(max(color, vec3f(1e-10))), vec3f(min_ev), vec3f(max_ev));
+    color = (color - min_ev) / (max_ev - min_ev);
+    color = agx_contrast(color);
+    color = outset * color;
+
+    // The curve outputs values encoded with a power of 2.2.
+    return pow(max(color, vec3f(0.0)), vec3f(2.2));
+}
+
+// https://github.com/h3r2tic/tony-mc-mapface
+fn tony_mc_mapface(rgb: vec3f) -> vec3f {
+    let encoded = rgb / (rgb + 1.0);
+    return sample_lut(tonemap_lut, encoded);
+}
+
+fn reinhard_extended(rgb: vec3f, white_point: f32) -> vec3f {
+    let luminance = luminance(rgb);
+    let mapped = luminance * (1.0 + luminance / (white_point * white_point)) / (1.0 + luminance);
+    return rgb * (mapped / max(luminance, 1e-6));
+}
+
+// https://github.com/KhronosGroup/ToneMapping/tree/main/PBR_Neutral
+fn pbr_neutral(rgb: vec3f) -> vec3f {
+    let start_compression = 0.8 - 0.04;
+    let desaturation = 0.15;
+
+    let x = min(rgb.r, min(rgb.g, rgb.b));
+    let offset = select(0.04, x - 6.25 * x * x, x < 0.08);
+    var color = rgb - offset;
+
+    let peak = max(color.r, max(color.g, color.b));
+    if peak < start_compression {
+        return color;
+    }
+
+    let d = 1.0 - start_compression;
+    let new_peak = 1.0 - d * d / (peak + d - start_compression);
+    color *= new_peak / peak;
+
+    let g = 1.0 - 1.0 / (desaturation * (peak - new_peak) + 1.0);
+    return mix(color, vec3f(new_peak), g);
+}
+
+// Sample a LUT at `coords` in `0..1`, mapping them to the centers of the edge texels.
+fn sample_lut(lut: texture_3d<f32>, coords: vec3f) -> vec3f {
+    let size = vec3f(textureDimensions(lut));
+    let uvw = clamp(coords, vec3f(0.0), vec3f(1.0)) * ((size - 1.0) / size) + 0.5 / size;
+    return textureSampleLevel(lut, lut_sampler, uvw, 0.0).rgb;
+}
+
+fn linear_to_srgb(linear: vec3f) -> vec3f {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3f(0.0031308));
+}
+
+fn srgb_to_linear(srgb: vec3f) -> vec3f {
+    let low = srgb / 12.92;
+    let high = pow((srgb + 0.055) / 1.055, vec3f(2.4));
+    return select(high, low, srgb <= vec3f(0.04045));
+}
+
+// Keep values up to the knee and compress the rest smoothly towards `max_value`, scaling the
+// channels equally to preserve hue.
+fn hdr_tonemap(rgb: vec3f) -> vec3f {
+    let max_value = params.max_value;
+    let knee = min(1.0, 0.5 * max_value);
+
+    let peak = max(rgb.r, max(rgb.g, rgb.b));
+    if peak <= knee {
+        return rgb;
+    }
+
+    let range = max_value - knee;
+    let compressed = knee + range * (1.0 - exp(-(peak - knee) / range));
+    return rgb * (compressed / peak);
+}
+
+fn rec709_to_rec2020(rgb: vec3f) -> vec3f {
+    let matrix = mat3x3f(
+        0.6274040, 0.0690970, 0.0163916,
+        0.3292820, 0.9195400, 0.0880132,
+        0.0433136, 0.0113612, 0.8955950,
+    );
+    return matrix * rgb;
+}
+
+// SMPTE ST 2084, where 1.0 is 10000 nits.
+fn pq_encode(nits: vec3f) -> vec3f {
+    let m1 = 0.1593017578125;
+    let m2 = 78.84375;
+    let c1 = 0.8359375;
+    let c2 = 18.8515625;
+    let c3 = 18.6875;
+
+    let y = pow(clamp(nits / 10000.0, vec3f(0.0), vec3f(1.0)), vec3f(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3f(m2));
+}
+
+fn tonemap(rgb: vec3f) -> vec3f {
+    let tonemapper = params.tonemapper;
+
+    if tonemapper == TONEMAPPER_AGX {
+        return agx(rgb);
+    } else if tonemapper == TONEMAPPER_TONY_MC_MAPFACE {
+        return tony_mc_mapface(rgb);
+    } else if tonemapper == TONEMAPPER_REINHARD_EXTENDED {
+        return reinhard_extended(rgb, params.white_point);
+    } else if tonemapper == TONEMAPPER_PBR_NEUTRAL {
+        return pbr_neutral(rgb);
+    }
+
+    return aces_fitted(rgb);
+}
+
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4f {
     let texel_id = vec2i(in.uv * vec2f(textureDimensions(display, 0)));
     var rgb = textureLoad(display, texel_id, 0).rgb;
 
-    var yxy = xyz_to_yxy(rgb_to_xyz(rgb));
-    yxy.x /= (9.6 * average_luminance + 0.0001);
-    rgb = xyz_to_rgb(yxy_to_xyz(yxy));
+    rgb *= exposure.exposure;
+    rgb = max(params.white_balance * rgb, vec3f(0.0));
+
+    // Contrast scales the distance to middle gray in stops.
+    rgb = MIDDLE_GRAY * pow(rgb / MIDDLE_GRAY, vec3f(params.contrast));
+    rgb = max(mix(vec3f(luminance(rgb)), rgb, params.saturation), vec3f(0.0));
+
+    if params.output == OUTPUT_SCRGB {
+        return vec4f(hdr_tonemap(rgb) * params.paper_white / SCRGB_NITS, 1.0);
+    }
+
+    if params.output == OUTPUT_HDR10 {
+        let rec2020 = max(rec709_to_rec2020(hdr_tonemap(rgb)), vec3f(0.0));
+        return vec4f(pq_encode(rec2020 * params.paper_white), 1.0);
+    }
+
+    rgb = clamp(tonemap(rgb), vec3f(0.0), vec3f(1.0));
 
-    rgb.r = aces_tonemap(rgb.r);
-    rgb.g = aces_tonemap(rgb.g);
-    rgb.b = aces_tonemap(rgb.b);
+    if params.lut_domain_min.w != 0.0 {
+        let domain_min = params.lut_domain_min.xyz;
+        let domain_max = params.lut_domain_max.xyz;
+        let coords = (linear_to_srgb(rgb) - domain_min) / (domain_max - domain_min);
+        rgb = srgb_to_linear(clamp(sample_lut(grading_lut, coords), vec3f(0.0), vec3f(1.0)));
+    }
 
     return vec4f(rgb, 1.0);
 }
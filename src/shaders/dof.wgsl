@@ -0,0 +1,242 @@
+#import consts
+#import util
+
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+@group(0) @binding(2)
+var linear_sampler: sampler;
+
+@group(1) @binding(0)
+var post_buffer: texture_2d<f32>;
+
+@group(1) @binding(1)
+var depth_buffer: texture_2d<f32>;
+
+struct Focus {
+    // The focus distance in meters.
+    distance: f32,
+}
+
+@group(1) @binding(2)
+var<storage, read_write> focus: Focus;
+
+@group(1) @binding(3)
+var half_out: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(4)
+var half_buffer: texture_2d<f32>;
+
+@group(1) @binding(5)
+var near_out: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(6)
+var far_out: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(7)
+var near_buffer: texture_2d<f32>;
+
+@group(1) @binding(8)
+var far_buffer: texture_2d<f32>;
+
+@group(1) @binding(9)
+var dof_out: texture_storage_2d<rgba16float, write>;
+
+struct Params {
+    // The radius of the circle of confusion of a point at infinity in pixels, times the distance
+    // from the focal plane to the lens.
+    coc_scale: f32,
+    // In meters.
+    focal_length: f32,
+    // The focus distance used without autofocus.
+    focus_distance: f32,
+    // The largest radius of the circle of confusion in pixels.
+    max_coc: f32,
+    autofocus: u32,
+    // How much of the distance to the autofocus target to cover this frame.
+    focus_blend: f32,
+}
+
+// Must match `PARAMS_BINDING` in `dof.rs`.
+@group(1) @binding(10)
+var<uniform> params: Params;
+
+// The number of rings of the gather kernel, which has 8 more samples in each ring.
+const RING_COUNT = 3;
+
+// The depth at `texcoords` in the depth buffer, which may have a lower resolution.
+fn load_depth(texcoords: vec2f) -> f32 {
+    let size = vec2f(consts.render_size);
+    let coords = clamp(vec2i(texcoords * size), vec2i(0), vec2i(size) - 1);
+    return textureLoad(depth_buffer, coords, 0).r;
+}
+
+// The inverse of the view distance, which is zero at infinity.
+fn inverse_distance(depth: f32) -> f32 {
+    let coeffs = consts.depth_linearize_coeffs;
+    return (coeffs.z * depth + coeffs.w) / (coeffs.x * depth + coeffs.y);
+}
+
+// The signed radius of the circle of confusion in pixels of the output. It is negative in front
+// of the focal plane.
+fn circle_of_confusion(depth: f32) -> f32 {
+    let focus_distance = focus.distance;
+    let scale = params.coc_scale / max(focus_distance - params.focal_length, 1e-4);
+    let coc = scale * (1.0 - focus_distance * inverse_distance(depth));
+
+    return clamp(coc, -params.max_coc, params.max_coc);
+}
+
+// Move the focus distance towards the depth at the center of the screen, or set the manual focus
+// distance. The focus is blended in diopters, which moves faster close to the camera.
+@compute
+@workgroup_size(1)
+fn update_focus() {
+    if params.autofocus == 0u {
+        focus.distance = params.focus_distance;
+        return;
+    }
+
+    // Average a few taps to not jump between the edges of objects.
+    var inverse_target = 0.0;
+
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let texcoords = vec2f(0.5) + vec2f(f32(x), f32(y)) * 0.02;
+            inverse_target += inverse_distance(load_depth(texcoords)) / 9.0;
+        }
+    }
+
+    let min_distance = 2.0 * params.focal_length;
+    let max_distance = 10000.0;
+
+    let target_distance = clamp(1.0 / max(inverse_target, 1.0 / max_distance), min_distance, max_distance);
+
+    if focus.distance <= 0.0 {
+        focus.distance = target_distance;
+        return;
+    }
+
+    let inverse_focus = mix(1.0 / focus.distance, 1.0 / target_distance, params.focus_blend);
+    focus.distance = 1.0 / inverse_focus;
+}
+
+// Downsample the post buffer to half resolution with the circle of confusion in alpha. The
+// nearest circle of confusion of the four pixels is kept so the near field isn't eroded.
+@compute
+@workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) texel_id: vec3u) {
+    let half_size = vec2i(textureDimensions(half_out));
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= half_size) {
+        return;
+    }
+
+    let size = vec2i(consts.surface_size);
+
+    var color = vec3f(0.0);
+    var coc = params.max_coc;
+
+    for (var i = 0; i < 4; i++) {
+        let coords = min(center * 2 + vec2i(i % 2, i / 2), size - 1);
+        let texcoords = (vec2f(coords) + 0.5) / vec2f(size);
+
+        color += textureLoad(post_buffer, coords, 0).rgb * 0.25;
+        coc = min(coc, circle_of_confusion(load_depth(texcoords)));
+    }
+
+    textureStore(half_out, center, vec4f(color, coc));
+}
+
+// Gather the near and far fields in a disc of the largest circle of confusion, as if each sample
+// was scattered over its own circle of confusion. Far samples are limited by the circle of
+// confusion of the center, so out of focus backgrounds don't bleed over sharp foregrounds.
+@compute
+@workgroup_size(8, 8)
+fn gather(@builtin(global_invocation_id) texel_id: vec3u) {
+    let half_size = vec2i(textureDimensions(half_buffer));
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= half_size) {
+        return;
+    }
+
+    let center_sample = textureLoad(half_buffer, center, 0);
+    let center_far = max(center_sample.a, 0.0);
+
+    var near = vec4f(0.0);
+    var far = vec4f(center_sample.rgb, 1.0);
+    var near_hits = 0.0;
+    var sample_count = 1.0;
+
+    if center_sample.a < 0.0 {
+        let weight = 1.0 / max(center_sample.a * center_sample.a, 1.0);
+        near = vec4f(center_sample.rgb * weight, weight);
+        near_hits = 1.0;
+    }
+
+    // The radius of the kernel in pixels of the output.
+    let radius = params.max_coc;
+
+    for (var ring = 1; ring <= RING_COUNT; ring++) {
+        let ring_radius = radius * f32(ring) / f32(RING_COUNT);
+        let ring_samples = ring * 8;
+
+        for (var i = 0; i < ring_samples; i++) {
+            // Rotate every other ring by half a step to cover the disc more evenly.
+            let angle = (f32(i) + 0.5 * f32(ring % 2)) / f32(ring_samples) * util::TAU;
+            let offset = vec2f(cos(angle), sin(angle)) * ring_radius;
+
+            let coords = clamp(center + vec2i(round(offset * 0.5)), vec2i(0), half_size - 1);
+            let sample = textureLoad(half_buffer, coords, 0);
+
+            sample_count += 1.0;
+
+            if sample.a < 0.0 {
+                let near_coc = -sample.a;
+                let hit = saturate(near_coc - ring_radius + 1.0);
+
+                // Spread the energy of the sample over the area of its circle.
+                let weight = hit / max(near_coc * near_coc, 1.0);
+
+                near += vec4f(sample.rgb * weight, weight);
+                near_hits += hit;
+            } else {
+                let weight = saturate(min(sample.a, center_far) - ring_radius + 1.0);
+                far += vec4f(sample.rgb * weight, weight);
+            }
+        }
+    }
+
+    let near_color = near.rgb / max(near.a, 1e-5);
+    let near_coverage = saturate(2.0 * near_hits / sample_count);
+
+    textureStore(near_out, center, vec4f(near_color, near_coverage));
+    textureStore(far_out, center, vec4f(far.rgb / far.a, 1.0));
+}
+
+// Blend the sharp image with the far field by the circle of confusion of each pixel, and the near
+// field over it by its coverage.
+@compute
+@workgroup_size(8, 8)
+fn composite(@builtin(global_invocation_id) texel_id: vec3u) {
+    let size = vec2i(consts.surface_size);
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= size) {
+        return;
+    }
+
+    let texcoords = (vec2f(center) + 0.5) / vec2f(size);
+    let sharp = textureLoad(post_buffer, center, 0);
+    let coc = circle_of_confusion(load_depth(texcoords));
+
+    let far = textureSampleLevel(far_buffer, linear_sampler, texcoords, 0.0);
+    let near = textureSampleLevel(near_buffer, linear_sampler, texcoords, 0.0);
+
+    var color = mix(sharp.rgb, far.rgb, smoothstep(0.5, 2.0, coc));
+    color = mix(color, near.rgb, near.a);
+
+    textureStore(dof_out, center, vec4f(color, sharp.a));
+}
@@ -0,0 +1,194 @@
+#import consts
+#import util
+
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+@group(0) @binding(2)
+var linear_sampler: sampler;
+
+@group(1) @binding(0)
+var color_buffer: texture_2d<f32>;
+
+@group(1) @binding(1)
+var post_buffer: texture_storage_2d<rgba16float, write>;
+
+// The minimum local contrast required to apply the algorithm.
+const EDGE_THRESHOLD = 0.125;
+// Skips processing of dark areas.
+const EDGE_THRESHOLD_MIN = 0.0312;
+// The amount of subpixel aliasing removal.
+const SUBPIXEL_QUALITY = 0.75;
+const SEARCH_STEPS = 12;
+
+// Step sizes of the edge search, which grow with the distance from the center.
+fn search_step(index: i32) -> f32 {
+    if index < 5 {
+        return 1.0;
+    } else if index == 5 {
+        return 1.5;
+    } else if index < 10 {
+        return 2.0;
+    } else if index == 10 {
+        return 4.0;
+    }
+
+    return 8.0;
+}
+
+// The color buffer isn't tonemapped, so the luma is compressed to be roughly perceptual.
+fn luma(color: vec3f) -> f32 {
+    let luminance = util::luminance(color);
+    return sqrt(luminance / (1.0 + luminance));
+}
+
+fn sample_luma(texcoords: vec2f) -> f32 {
+    return luma(textureSampleLevel(color_buffer, linear_sampler, texcoords, 0.0).rgb);
+}
+
+fn load_luma(texel: vec2i) -> f32 {
+    let coords = clamp(texel, vec2i(0), vec2i(consts.surface_size) - 1);
+    return luma(textureLoad(color_buffer, coords, 0).rgb);
+}
+
+// FXAA 3.11 by Timothy Lottes, quality preset 29.
+@compute
+@workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) texel_id: vec3u) {
+    let center = vec2i(texel_id.xy);
+    let size = vec2f(consts.surface_size);
+
+    if any(center >= vec2i(consts.surface_size)) {
+        return;
+    }
+
+    let texel_size = 1.0 / size;
+    let texcoords = (vec2f(center) + 0.5) * texel_size;
+    let center_color = textureLoad(color_buffer, center, 0);
+
+    let luma_center = luma(center_color.rgb);
+    let luma_down = load_luma(center + vec2i(0, 1));
+    let luma_up = load_luma(center + vec2i(0, -1));
+    let luma_left = load_luma(center + vec2i(-1, 0));
+    let luma_right = load_luma(center + vec2i(1, 0));
+
+    let luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+
+    if luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD) {
+        textureStore(post_buffer, center, center_color);
+        return;
+    }
+
+    let luma_down_left = load_luma(center + vec2i(-1, 1));
+    let luma_up_right = load_luma(center + vec2i(1, -1));
+    let luma_up_left = load_luma(center + vec2i(-1, -1));
+    let luma_down_right = load_luma(center + vec2i(1, 1));
+
+    let luma_down_up = luma_down + luma_up;
+    let luma_left_right = luma_left + luma_right;
+
+    let luma_left_corners = luma_down_left + luma_up_left;
+    let luma_down_corners = luma_down_left + luma_down_right;
+    let luma_right_corners = luma_down_right + luma_up_right;
+    let luma_up_corners = luma_up_right + luma_up_left;
+
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // The two neighbors across the edge.
+    let luma_1 = select(luma_left, luma_up, is_horizontal);
+    let luma_2 = select(luma_right, luma_down, is_horizontal);
+
+    let gradient_1 = luma_1 - luma_center;
+    let gradient_2 = luma_2 - luma_center;
+    let is_1_steepest = abs(gradient_1) >= abs(gradient_2);
+    let gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    // Step towards the side of the edge with the steepest gradient.
+    var step_length = select(texel_size.x, texel_size.y, is_horizontal);
+    var luma_local_average = 0.0;
+
+    if is_1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_2 + luma_center);
+    }
+
+    // Move to the edge between the pixels.
+    var current_texcoords = texcoords;
+
+    if is_horizontal {
+        current_texcoords.y += step_length * 0.5;
+    } else {
+        current_texcoords.x += step_length * 0.5;
+    }
+
+    let offset = select(vec2f(0.0, texel_size.y), vec2f(texel_size.x, 0.0), is_horizontal);
+
+    var texcoords_1 = current_texcoords - offset;
+    var texcoords_2 = current_texcoords + offset;
+
+    var luma_end_1 = sample_luma(texcoords_1) - luma_local_average;
+    var luma_end_2 = sample_luma(texcoords_2) - luma_local_average;
+
+    var reached_1 = abs(luma_end_1) >= gradient_scaled;
+    var reached_2 = abs(luma_end_2) >= gradient_scaled;
+
+    for (var i = 1; i < SEARCH_STEPS && !(reached_1 && reached_2); i++) {
+        let step = search_step(i);
+
+        if !reached_1 {
+            texcoords_1 -= offset * step;
+            luma_end_1 = sample_luma(texcoords_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+
+        if !reached_2 {
+            texcoords_2 += offset * step;
+            luma_end_2 = sample_luma(texcoords_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+    }
+
+    let distance_1 = select(texcoords.y - texcoords_1.y, texcoords.x - texcoords_1.x, is_horizontal);
+    let distance_2 = select(texcoords_2.y - texcoords.y, texcoords_2.x - texcoords.x, is_horizontal);
+
+    let is_direction_1 = distance_1 < distance_2;
+    let distance_final = min(distance_1, distance_2);
+    let edge_length = distance_1 + distance_2;
+
+    // Only blend if the luma at the nearest end varies in the other direction than the center.
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let luma_end = select(luma_end_2, luma_end_1, is_direction_1);
+    let correct_variation = (luma_end < 0.0) != is_luma_center_smaller;
+
+    var pixel_offset = select(0.0, 0.5 - distance_final / edge_length, correct_variation);
+
+    // Subpixel antialiasing from the average of the whole 3x3 neighborhood.
+    let luma_average = (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners) / 12.0;
+    let subpixel_offset_1 = saturate(abs(luma_average - luma_center) / luma_range);
+    let subpixel_offset_2 = (-2.0 * subpixel_offset_1 + 3.0) * subpixel_offset_1 * subpixel_offset_1;
+    let subpixel_offset = subpixel_offset_2 * subpixel_offset_2 * SUBPIXEL_QUALITY;
+
+    pixel_offset = max(pixel_offset, subpixel_offset);
+
+    var final_texcoords = texcoords;
+
+    if is_horizontal {
+        final_texcoords.y += pixel_offset * step_length;
+    } else {
+        final_texcoords.x += pixel_offset * step_length;
+    }
+
+    let color = textureSampleLevel(color_buffer, linear_sampler, final_texcoords, 0.0);
+    textureStore(post_buffer, center, vec4f(color.rgb, center_color.a));
+}
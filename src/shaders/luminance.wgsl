@@ -1,39 +1,46 @@
-#import util
-
 @group(0) @binding(0)
 var display: texture_2d<f32>;
 
-#if BUILD_HISTOGRAM == true
 @group(1) @binding(0)
 var<storage, read_write> histogram: array<atomic<u32>, 256>;
-#else
-@group(1) @binding(0)
-var<storage, read_write> histogram: array<u32, 256>;
-#endif
+
+// Must match `Exposure` in `display.rs`.
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
 
 @group(1) @binding(1)
-var<storage, read_write> average_luminance: f32;
+var<storage, read_write> exposure: Exposure;
 
-#if BUILD_HISTOGRAM == true
 var<workgroup> shared_histogram: array<atomic<u32>, 256>;
-#else
-var<workgroup> shared_histogram: array<u32, 256>;
-#endif
+var<workgroup> shared_weighted_bins: array<f32, 256>;
+var<workgroup> shared_weights: array<f32, 256>;
 
+// Must match `LuminanceParams` in `display.rs`.
 struct Params {
     min_log_luminance: f32,
     inverse_log_luminance_range: f32,
     log_luminance_range: f32,
-    time_coeff: f32,
-    pixel_count: u32,
+    speed_up_coeff: f32,
+    speed_down_coeff: f32,
+    metering: u32,
+    spot_radius: f32,
+    compensation: f32,
+    manual_exposure: f32,
 }
 
-var<push_constant> params: Params;
+@group(1) @binding(2)
+var<uniform> params: Params;
 
-#if BUILD_HISTOGRAM == true
+const METERING_CENTER_WEIGHTED = 1u;
+const METERING_SPOT = 2u;
+
+// Histogram bins count pixels in units of this, so that pixels can be partially weighted.
+const WEIGHT_SCALE = 16.0;
 
 fn color_index(color: vec3f) -> u32 {
-    let luminance = util::luminance(color);
+    let luminance = dot(color, vec3f(0.2126, 0.7152, 0.0722));
 
     if luminance < 0.005 {
         return 0u;
@@ -46,6 +53,22 @@ fn color_index(color: vec3f) -> u32 {
     return u32(log_luminance * 254.0 + 1.0);
 }
 
+fn metering_weight(pixel: vec2u, size: vec2u) -> u32 {
+    // The distance to the center relative to the height of the view.
+    let offset = (vec2f(pixel) + 0.5 - vec2f(size) * 0.5) / f32(size.y);
+    let distance = length(offset);
+
+    var weight = 1.0;
+
+    if params.metering == METERING_CENTER_WEIGHTED {
+        weight = mix(1.0, 0.1, smoothstep(0.0, 0.6, distance));
+    } else if params.metering == METERING_SPOT {
+        weight = select(0.0, 1.0, distance <= params.spot_radius);
+    }
+
+    return u32(weight * WEIGHT_SCALE + 0.5);
+}
+
 @compute
 @workgroup_size(16, 16)
 fn build_histogram(
@@ -63,7 +86,7 @@ fn build_histogram(
     if all(invocation_id.xy < display_size) {
         let color = textureLoad(display, vec2i(invocation_id.xy), 0).rgb;
         let index = color_index(color);
-        atomicAdd(&shared_histogram[index], 1u);
+        atomicAdd(&shared_histogram[index], metering_weight(invocation_id.xy, display_size));
     }
 
     workgroupBarrier();
@@ -74,45 +97,65 @@ fn build_histogram(
     atomicAdd(&histogram[local_invocation_index], shared_histogram_value);
 }
 
-#else
-
 @compute
 @workgroup_size(256)
 fn compute_average(
     @builtin(global_invocation_id) invocation_id: vec3u,
     @builtin(local_invocation_index) local_invocation_index: u32,
 ) {
-    let count = histogram[local_invocation_index];
-    shared_histogram[local_invocation_index] = count * local_invocation_index;
+    // The first bin holds the pixels too dark to count.
+    let bin = atomicLoad(&histogram[local_invocation_index]);
+    let weight = select(f32(bin), 0.0, local_invocation_index == 0u);
+    shared_weighted_bins[local_invocation_index] = weight * f32(local_invocation_index);
+    shared_weights[local_invocation_index] = weight;
 
     workgroupBarrier();
 
-    histogram[local_invocation_index] = 0u;
+    atomicStore(&histogram[local_invocation_index], 0u);
 
     for (var cutoff = (256u >> 1u); cutoff > 0u; cutoff >>= 1u) {
         if local_invocation_index < cutoff {
-            shared_histogram[local_invocation_index]
-                += shared_histogram[local_invocation_index + cutoff];
+            shared_weighted_bins[local_invocation_index]
+                += shared_weighted_bins[local_invocation_index + cutoff];
+            shared_weights[local_invocation_index]
+                += shared_weights[local_invocation_index + cutoff];
         }
 
         workgroupBarrier();
     }
 
     if local_invocation_index == 0u {
-        let weighted_log_average = (
-            f32(shared_histogram[0]) / f32(max(params.pixel_count - count, 1u))
-        ) - 1.0;
+        var adapted_luminance = exposure.average_luminance;
+
+        // Keep the last average if no pixel is metered, such as when the spot is black.
+        if shared_weights[0] > 0.0 {
+            let weighted_log_average = shared_weighted_bins[0] / shared_weights[0] - 1.0;
+
+            let weighted_average = exp2(
+                ((weighted_log_average / 254.0) * params.log_luminance_range)
+                    + params.min_log_luminance
+            );
+
+            let last_frame_average = exposure.average_luminance;
+            let time_coeff = select(
+                params.speed_down_coeff,
+                params.speed_up_coeff,
+                weighted_average > last_frame_average,
+            );
+
+            adapted_luminance = last_frame_average
+                + (weighted_average - last_frame_average) * time_coeff;
+        }
 
-        let weighted_average = exp2(
-            ((weighted_log_average / 254.0) * params.log_luminance_range)
-                + params.min_log_luminance
-        );
+        exposure.average_luminance = adapted_luminance;
 
-        let last_frame_average = average_luminance;
-        let adapted_luminance = last_frame_average + (weighted_average - last_frame_average) * params.time_coeff;
+        // Auto exposure maps the average luminance to middle gray, which is the same as the
+        // exposure `1 / (1.2 * 2^ev100)` of a camera with the exposure value of the scene.
+        var value = 1.0 / (9.6 * adapted_luminance + 0.0001);
+        if params.manual_exposure > 0.0 {
+            value = params.manual_exposure;
+        }
 
-        average_luminance = adapted_luminance;
+        exposure.exposure = value * params.compensation;
     }
 }
-
-#endif
@@ -0,0 +1,223 @@
+#import consts
+
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+@group(1) @binding(0)
+var post_buffer: texture_2d<f32>;
+
+@group(1) @binding(1)
+var depth_buffer: texture_2d<f32>;
+
+@group(1) @binding(2)
+var velocity_out: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(3)
+var velocity_buffer: texture_2d<f32>;
+
+@group(1) @binding(4)
+var tile_max_out: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(5)
+var tile_max_buffer: texture_2d<f32>;
+
+@group(1) @binding(6)
+var neighbor_max_out: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(7)
+var neighbor_max_buffer: texture_2d<f32>;
+
+@group(1) @binding(8)
+var motion_blur_out: texture_storage_2d<rgba16float, write>;
+
+struct Params {
+    // The fraction of the frame time the shutter is open.
+    shutter_fraction: f32,
+    sample_count: u32,
+}
+
+// Must match `PARAMS_BINDING` in `motion_blur.rs`.
+@group(1) @binding(9)
+var<uniform> params: Params;
+
+// The size of velocity tiles, which is also the largest blur radius in pixels.
+const TILE_SIZE = 20;
+
+// The view distance of the sky, which has to fit in a half float.
+const MAX_DISTANCE = 60000.0;
+
+// The relative difference in view distance at which one sample is in front of another.
+const SOFT_DEPTH_EXTENT = 0.05;
+
+// Half the distance in pixels moved while the shutter is open, clamped to the tile size. It is
+// the blur radius, as the blur reaches half the distance in either direction.
+fn screen_velocity(texcoords: vec2f, depth: f32) -> vec2f {
+    let clip = vec4f(2.0 * texcoords.x - 1.0, 1.0 - 2.0 * texcoords.y, depth, 1.0);
+    let previous_clip = consts.prev_proj_view * consts.inverse_proj_view * clip;
+    let previous_texcoords = (previous_clip.xy / previous_clip.w) * vec2f(0.5, -0.5) + 0.5;
+
+    let displacement = (texcoords - previous_texcoords) * vec2f(consts.surface_size) * params.shutter_fraction;
+    let velocity = 0.5 * displacement;
+    let speed = length(velocity);
+
+    if speed > f32(TILE_SIZE) {
+        return velocity * (f32(TILE_SIZE) / speed);
+    }
+
+    return velocity;
+}
+
+// Store the velocity and the view distance of each pixel. Only the camera moves, so the velocity
+// is found by reprojecting the depth buffer with the previous view.
+@compute
+@workgroup_size(8, 8)
+fn velocity(@builtin(global_invocation_id) texel_id: vec3u) {
+    let size = vec2i(consts.surface_size);
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= size) {
+        return;
+    }
+
+    let texcoords = (vec2f(center) + 0.5) / vec2f(size);
+    let depth_coords = min(vec2i(texcoords * vec2f(consts.render_size)), vec2i(consts.render_size) - 1);
+    let depth = textureLoad(depth_buffer, depth_coords, 0).r;
+
+    let coeffs = consts.depth_linearize_coeffs;
+    let distance = (coeffs.x * depth + coeffs.y) / max(coeffs.z * depth + coeffs.w, 1e-6);
+
+    let velocity = screen_velocity(texcoords, depth);
+    textureStore(velocity_out, center, vec4f(velocity, min(distance, MAX_DISTANCE), 0.0));
+}
+
+// The largest velocity of each tile.
+@compute
+@workgroup_size(8, 8)
+fn tile_max(@builtin(global_invocation_id) texel_id: vec3u) {
+    let tile = vec2i(texel_id.xy);
+
+    if any(tile >= vec2i(textureDimensions(tile_max_out))) {
+        return;
+    }
+
+    let size = vec2i(consts.surface_size);
+    var max_velocity = vec2f(0.0);
+
+    for (var y = 0; y < TILE_SIZE; y++) {
+        for (var x = 0; x < TILE_SIZE; x++) {
+            let coords = min(tile * TILE_SIZE + vec2i(x, y), size - 1);
+            let velocity = textureLoad(velocity_buffer, coords, 0).xy;
+
+            if dot(velocity, velocity) > dot(max_velocity, max_velocity) {
+                max_velocity = velocity;
+            }
+        }
+    }
+
+    textureStore(tile_max_out, tile, vec4f(max_velocity, 0.0, 0.0));
+}
+
+// The largest velocity of the 3x3 neighboring tiles, as pixels can be blurred by objects in
+// neighboring tiles.
+@compute
+@workgroup_size(8, 8)
+fn neighbor_max(@builtin(global_invocation_id) texel_id: vec3u) {
+    let tile = vec2i(texel_id.xy);
+    let tile_count = vec2i(textureDimensions(neighbor_max_out));
+
+    if any(tile >= tile_count) {
+        return;
+    }
+
+    var max_velocity = vec2f(0.0);
+
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let coords = clamp(tile + vec2i(x, y), vec2i(0), tile_count - 1);
+            let velocity = textureLoad(tile_max_buffer, coords, 0).xy;
+
+            if dot(velocity, velocity) > dot(max_velocity, max_velocity) {
+                max_velocity = velocity;
+            }
+        }
+    }
+
+    textureStore(neighbor_max_out, tile, vec4f(max_velocity, 0.0, 0.0));
+}
+
+// One if `a` is in front of `b`, fading out over `SOFT_DEPTH_EXTENT`.
+fn soft_depth_compare(a: f32, b: f32) -> f32 {
+    return saturate(1.0 - (a - b) / (SOFT_DEPTH_EXTENT * b));
+}
+
+fn cone(distance: f32, speed: f32) -> f32 {
+    return saturate(1.0 - distance / speed);
+}
+
+fn cylinder(distance: f32, speed: f32) -> f32 {
+    return 1.0 - smoothstep(0.95 * speed, 1.05 * speed, distance);
+}
+
+// Interleaved gradient noise by Jorge Jimenez.
+fn interleaved_gradient_noise(position: vec2f) -> f32 {
+    return fract(52.9829189 * fract(dot(position, vec2f(0.06711056, 0.00583715))));
+}
+
+// The reconstruction filter of "A Reconstruction Filter for Plausible Motion Blur" by McGuire et
+// al. Samples along the largest velocity of the neighborhood are weighted by whether they are in
+// front and how far their own blur reaches.
+@compute
+@workgroup_size(8, 8)
+fn reconstruct(@builtin(global_invocation_id) texel_id: vec3u) {
+    let size = vec2i(consts.surface_size);
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= size) {
+        return;
+    }
+
+    let color = textureLoad(post_buffer, center, 0);
+    let neighbor_velocity = textureLoad(neighbor_max_buffer, center / TILE_SIZE, 0).xy;
+    let neighbor_speed = length(neighbor_velocity);
+
+    if neighbor_speed < 0.5 {
+        textureStore(motion_blur_out, center, color);
+        return;
+    }
+
+    let center_sample = textureLoad(velocity_buffer, center, 0);
+    let center_speed = max(length(center_sample.xy), 0.5);
+    let center_distance = center_sample.z;
+
+    var weight_sum = 1.0 / center_speed;
+    var color_sum = color.rgb * weight_sum;
+
+    let noise = interleaved_gradient_noise(vec2f(center) + f32(consts.frame_index % 64u) * 5.588238) - 0.5;
+    let sample_count = max(params.sample_count, 3u);
+
+    for (var i = 0u; i < sample_count; i++) {
+        if i == (sample_count - 1u) / 2u {
+            continue;
+        }
+
+        let t = mix(-1.0, 1.0, (f32(i) + noise + 1.0) / f32(sample_count + 1u));
+        let offset = neighbor_velocity * t;
+        let coords = clamp(center + vec2i(round(offset)), vec2i(0), size - 1);
+
+        let sample = textureLoad(velocity_buffer, coords, 0);
+        let sample_speed = max(length(sample.xy), 0.5);
+        let distance = length(offset);
+
+        let front = soft_depth_compare(sample.z, center_distance);
+        let back = soft_depth_compare(center_distance, sample.z);
+
+        let weight = front * cone(distance, sample_speed)
+            + back * cone(distance, center_speed)
+            + 2.0 * cylinder(distance, sample_speed) * cylinder(distance, center_speed);
+
+        weight_sum += weight;
+        color_sum += textureLoad(post_buffer, coords, 0).rgb * weight;
+    }
+
+    textureStore(motion_blur_out, center, vec4f(color_sum / weight_sum, color.a));
+}
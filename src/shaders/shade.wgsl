@@ -3,6 +3,8 @@
 #import consts
 #import util
 #import light
+#import sky
+#import shadow
 
 @group(0) @binding(0)
 var<uniform> consts: consts::Consts;
@@ -26,27 +28,91 @@ var<storage, read> indices: array<u32>;
 var<storage, read> vertices: array<mesh::Vertex>;
 
 @group(1) @binding(4)
-var textures: binding_array<texture_2d<f32>>;
+var<storage, read> texture_slots: array<TextureSlot>;
 
-@group(2) @binding(0)
+// The texture pools of the texture streamer. Each format has a base, mid and top pool, in the
+// order of `POOL_FORMATS` in `streaming.rs`.
+@group(1) @binding(5)
+var texture_pool_0: texture_2d_array<f32>;
+
+@group(1) @binding(6)
+var texture_pool_1: texture_2d_array<f32>;
+
+@group(1) @binding(7)
+var texture_pool_2: texture_2d_array<f32>;
+
+@group(1) @binding(8)
+var texture_pool_3: texture_2d_array<f32>;
+
+@group(1) @binding(9)
+var texture_pool_4: texture_2d_array<f32>;
+
+@group(1) @binding(10)
+var texture_pool_5: texture_2d_array<f32>;
+
+@group(1) @binding(11)
+var texture_pool_6: texture_2d_array<f32>;
+
+@group(1) @binding(12)
+var texture_pool_7: texture_2d_array<f32>;
+
+@group(1) @binding(13)
+var texture_pool_8: texture_2d_array<f32>;
+
+@group(3) @binding(0)
 var visibility_buffer: texture_2d<u32>;
 
-@group(2) @binding(1)
-var depth_buffer: texture_depth_2d;
+@group(3) @binding(1)
+var depth_buffer: texture_2d<f32>;
 
-@group(2) @binding(2)
+@group(3) @binding(2)
 var skybox: texture_cube<f32>;
 
-@group(2) @binding(3)
-var color_buffer: texture_storage_2d<rgba16float, read_write>;
+@group(3) @binding(3)
+var color_buffer: texture_storage_2d<rgba16float, write>;
+
+@group(3) @binding(4)
+var<storage, read_write> texture_feedback: array<atomic<u32>>;
+
+@group(3) @binding(5)
+var normal_depth_buffer: texture_storage_2d<rgba32float, write>;
 
-@group(2) @binding(4)
-var<storage, read> shadow_cascade_infos: array<light::ShadowCascade>;
+@group(3) @binding(6)
+var albedo_buffer: texture_storage_2d<rgba16float, write>;
 
-@group(2) @binding(5)
-var shadow_cascades: texture_depth_2d_array;
+@group(3) @binding(7)
+var volume: texture_2d_array<f32>;
 
-var<push_constant> ray_matrix: mat4x4f;
+@group(3) @binding(8)
+var<storage, read> sky_lights: light::SkyLights;
+
+// Must match `ShadeParams` in `shade.rs`.
+struct Params {
+    ray_matrix: mat4x4f,
+    write_aovs: u32,
+    apply_volume: u32,
+    volume_distance: f32,
+}
+
+@group(3) @binding(9)
+var<uniform> params: Params;
+
+// Where the mips of a material texture are in the texture pools. Must match `TextureSlot` in
+// `streaming.rs`.
+struct TextureSlot {
+    pool: u32,
+    layer: u32,
+    // The level of the layer holding the first resident mip.
+    base_level: u32,
+    // The extent of the pool.
+    extent: u32,
+}
+
+// Must match `FEEDBACK_LEVEL_BIAS` in `streaming.rs`.
+const FEEDBACK_LEVEL_BIAS = 16.0;
+
+// Only a subset of the pixels write texture feedback each frame to reduce contention.
+var<private> write_feedback: bool;
 
 struct Triangle {
     p0: vec3f,
@@ -75,12 +141,23 @@ struct Barycentric {
     ddy: vec3f,
 }
 
-fn barycentric(world_positions: array<vec3f, 3>, ndc: vec2f, screen_size: vec2f) -> Barycentric {
+// The ray through `ndc` starting at the near plane. Works for both perspective and
+// orthographic projections.
+fn camera_ray(ndc: vec2f) -> util::Ray {
+    var near = params.ray_matrix * vec4f(ndc, 1.0, 1.0);
+    var far = params.ray_matrix * vec4f(ndc, 0.5, 1.0);
+    near /= near.w;
+    far /= far.w;
+
     var ray: util::Ray;
-    var bary: Barycentric;
+    ray.origin = consts.camera_pos.xyz + near.xyz;
+    ray.direction = far.xyz - near.xyz;
+    return ray;
+}
 
-    ray.origin = consts.camera_pos.xyz;
-    ray.direction = (ray_matrix * vec4f(ndc, 1.0, 1.0)).xyz;
+fn barycentric(world_positions: array<vec3f, 3>, ndc: vec2f, screen_size: vec2f) -> Barycentric {
+    var bary: Barycentric;
+    var ray = camera_ray(ndc);
 
     var tri: Triangle;
     tri.p0 = world_positions[0];
@@ -90,11 +167,8 @@ fn barycentric(world_positions: array<vec3f, 3>, ndc: vec2f, screen_size: vec2f)
     bary.lambda = intersection(tri, ray);
     let texel_size = 2.0 / screen_size;
 
-    ray.direction = (ray_matrix * vec4f(ndc.x + texel_size.x, ndc.y, 1.0, 1.0)).xyz;
-    let hx = intersection(tri, ray);
-
-    ray.direction = (ray_matrix * vec4f(ndc.x, ndc.y + texel_size.y, 1.0, 1.0)).xyz;
-    let hy = intersection(tri, ray);
+    let hx = intersection(tri, camera_ray(vec2f(ndc.x + texel_size.x, ndc.y)));
+    let hy = intersection(tri, camera_ray(vec2f(ndc.x, ndc.y + texel_size.y)));
 
     bary.ddx = bary.lambda - hx;
     bary.ddy = bary.lambda - hy;
@@ -114,74 +188,364 @@ fn interp_3d(lambda: vec3f, values: array<vec3f, 3>) -> vec3f {
     return fma(vec3f(lambda[0]), values[0], fma(vec3f(lambda[1]), values[1], lambda[2] * values[2]));
 }
 
-fn shadow_occlusion(depth: f32, normal: vec3f, world_pos: vec3f) -> f32 {
-    let light_dir = consts.sun.direction.xyz * -1.0;
-    let cascade_count = arrayLength(&shadow_cascade_infos);
-    var cascade_index = 0u;
+struct TextureCoords {
+    uv: vec2f,
+    ddx: vec2f,
+    ddy: vec2f,
+}
+
+fn texture_coords(bary: Barycentric, texcoords: array<vec2f, 3>) -> TextureCoords {
+    var coords: TextureCoords;
+
+    coords.uv = interp_2d(bary.lambda, texcoords);
+    coords.ddx = interp_2d(bary.ddx, texcoords);
+    coords.ddy = interp_2d(bary.ddy, texcoords);
+
+    return coords;
+}
+
+fn sample_material_texture(
+    texture_index: u32,
+    transform: mesh::TextureTransform,
+    primary: TextureCoords,
+    secondary: TextureCoords,
+) -> vec4f {
+    var coords = primary;
+
+    if transform.texcoord == 1u {
+        coords = secondary;
+    }
+
+    let matrix = mat2x2f(transform.x_axis, transform.y_axis);
+    let uv = matrix * coords.uv + transform.offset;
+    let ddx = matrix * coords.ddx;
+    let ddy = matrix * coords.ddy;
+
+    let slot = texture_slots[texture_index];
+    let size = f32(slot.extent);
+    let base_level = f32(slot.base_level);
+
+    // The level of the pool sampled without a mip bias.
+    let lod = log2(max(length(ddx), length(ddy)) * size);
+    let is_mipmapped = (transform.sampler_bits & mesh::SAMPLER_NO_MIPMAP) == 0u;
+
+    // Textures which aren't mipmapped only sample the most detailed mip.
+    if write_feedback {
+        request_texture_level(
+            texture_index,
+            select(-FEEDBACK_LEVEL_BIAS, lod - base_level, is_mipmapped),
+        );
+    }
+
+    let filter_bit = select(
+        mesh::SAMPLER_MIN_NEAREST,
+        mesh::SAMPLER_MAG_NEAREST,
+        lod <= base_level,
+    );
+
+    let is_nearest = (transform.sampler_bits & filter_bit) != 0u;
+
+    // Mips go down to the size of a block.
+    let max_level = f32(firstTrailingBit(slot.extent)) - 2.0;
+
+    // Let the sampler filter, which is anisotropic, unless it's emulated below. The levels more
+    // detailed than `base_level` may hold another texture, so the gradients are lengthened until
+    // the sampler doesn't select them. Each gradient is lengthened separately, which keeps the
+    // anisotropy along the longer gradient.
+    let mipmap_bits = mesh::SAMPLER_MIPMAP_NEAREST | mesh::SAMPLER_NO_MIPMAP;
+    if !is_nearest && (transform.sampler_bits & mipmap_bits) == 0u {
+        let min_length = exp2(base_level) / size;
+        let level = clamp(floor(lod), base_level, max_level);
+        let level_size = vec2f(size * exp2(-level));
+        let address_uv = apply_address_modes(uv, transform.sampler_bits, level_size);
+
+        return sample_pool_grad(
+            slot,
+            address_uv,
+            clamp_gradient(ddx, min_length),
+            clamp_gradient(ddy, min_length),
+        );
+    }
+
+    var level = base_level;
+    var next_level_weight = 0.0;
+
+    if is_mipmapped {
+        let clamped_lod = clamp(lod, base_level, max_level);
+
+        if (transform.sampler_bits & mesh::SAMPLER_MIPMAP_NEAREST) != 0u {
+            level = round(clamped_lod);
+        } else {
+            level = floor(clamped_lod);
+            next_level_weight = clamped_lod - level;
+        }
+    }
+
+    var color = sample_texture_level(slot, transform.sampler_bits, is_nearest, uv, level);
+
+    if next_level_weight > 0.0 {
+        let next = sample_texture_level(
+            slot,
+            transform.sampler_bits,
+            is_nearest,
+            uv,
+            level + 1.0,
+        );
+
+        color = mix(color, next, next_level_weight);
+    }
+
+    return color;
+}
+
+// Sample `level` of the layer of a material texture, with nearest filtering if `is_nearest`.
+fn sample_texture_level(
+    slot: TextureSlot,
+    sampler_bits: u32,
+    is_nearest: bool,
+    uv: vec2f,
+    level: f32,
+) -> vec4f {
+    let size = f32(slot.extent) * exp2(-level);
+    var address_uv = apply_address_modes(uv, sampler_bits, vec2f(size));
+
+    // The center of the nearest texel is the same after bilinear filtering.
+    if is_nearest {
+        address_uv = (floor(address_uv * size) + 0.5) / size;
+    }
+
+    return sample_pool_level(slot, address_uv, level);
+}
+
+// Lengthen `gradient` to at least `min_length`.
+fn clamp_gradient(gradient: vec2f, min_length: f32) -> vec2f {
+    let gradient_length = length(gradient);
+
+    if gradient_length >= min_length {
+        return gradient;
+    }
+
+    if gradient_length == 0.0 {
+        return vec2f(min_length, 0.0);
+    }
+
+    return gradient * (min_length / gradient_length);
+}
+
+// Apply the address modes of `sampler_bits` to `uv`. The texture sampler repeats, so the other
+// address modes are emulated. They stay half a texel of a mip of `size` away from the edges, so
+// that the filter doesn't blend in the opposite edge.
+fn apply_address_modes(uv: vec2f, sampler_bits: u32, size: vec2f) -> vec2f {
+    let address_u = sampler_bits & mesh::SAMPLER_ADDRESS_MASK;
+    let address_v = (sampler_bits >> mesh::SAMPLER_ADDRESS_V_SHIFT) & mesh::SAMPLER_ADDRESS_MASK;
+    let half_texel = 0.5 / size;
+
+    return vec2f(
+        apply_address_mode(uv.x, address_u, half_texel.x),
+        apply_address_mode(uv.y, address_v, half_texel.y),
+    );
+}
+
+fn apply_address_mode(coord: f32, address_mode: u32, half_texel: f32) -> f32 {
+    if address_mode == mesh::ADDRESS_MIRROR_REPEAT {
+        let mirrored = 1.0 - abs(fract(coord * 0.5) * 2.0 - 1.0);
+        return clamp(mirrored, half_texel, 1.0 - half_texel);
+    }
+
+    if address_mode == mesh::ADDRESS_CLAMP_TO_EDGE {
+        return clamp(coord, half_texel, 1.0 - half_texel);
+    }
+
+    return coord;
+}
+
+// Sample the layer of `slot` with explicit gradients. Textures can't be indexed dynamically
+// without binding arrays, so the pool is selected by a switch.
+fn sample_pool_grad(slot: TextureSlot, uv: vec2f, ddx: vec2f, ddy: vec2f) -> vec4f {
+    let layer = i32(slot.layer);
+    var color: vec4f;
+
+    switch slot.pool {
+        case 0u: {
+            color = textureSampleGrad(texture_pool_0, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 1u: {
+            color = textureSampleGrad(texture_pool_1, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 2u: {
+            color = textureSampleGrad(texture_pool_2, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 3u: {
+            color = textureSampleGrad(texture_pool_3, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 4u: {
+            color = textureSampleGrad(texture_pool_4, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 5u: {
+            color = textureSampleGrad(texture_pool_5, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 6u: {
+            color = textureSampleGrad(texture_pool_6, texture_sampler, uv, layer, ddx, ddy);
+        }
+        case 7u: {
+            color = textureSampleGrad(texture_pool_7, texture_sampler, uv, layer, ddx, ddy);
+        }
+        default: {
+            color = textureSampleGrad(texture_pool_8, texture_sampler, uv, layer, ddx, ddy);
+        }
+    }
 
-    for (var i = 0u; i < cascade_count - 1u; i += 1u) {
-        let info = &shadow_cascade_infos[i];
+    return color;
+}
 
-        if depth > (*info).far {
-            cascade_index = i + 1u;
+// Sample `level` of the layer of `slot`.
+fn sample_pool_level(slot: TextureSlot, uv: vec2f, level: f32) -> vec4f {
+    let layer = i32(slot.layer);
+    var color: vec4f;
+
+    switch slot.pool {
+        case 0u: {
+            color = textureSampleLevel(texture_pool_0, texture_sampler, uv, layer, level);
+        }
+        case 1u: {
+            color = textureSampleLevel(texture_pool_1, texture_sampler, uv, layer, level);
+        }
+        case 2u: {
+            color = textureSampleLevel(texture_pool_2, texture_sampler, uv, layer, level);
+        }
+        case 3u: {
+            color = textureSampleLevel(texture_pool_3, texture_sampler, uv, layer, level);
+        }
+        case 4u: {
+            color = textureSampleLevel(texture_pool_4, texture_sampler, uv, layer, level);
+        }
+        case 5u: {
+            color = textureSampleLevel(texture_pool_5, texture_sampler, uv, layer, level);
+        }
+        case 6u: {
+            color = textureSampleLevel(texture_pool_6, texture_sampler, uv, layer, level);
+        }
+        case 7u: {
+            color = textureSampleLevel(texture_pool_7, texture_sampler, uv, layer, level);
+        }
+        default: {
+            color = textureSampleLevel(texture_pool_8, texture_sampler, uv, layer, level);
         }
     }
 
-    let info = &shadow_cascade_infos[cascade_index];
+    return color;
+}
 
-    let blend = clamp((depth -  (*info).far * 0.995) * 200.0, 0.0, 1.0);
-    let light_pos = (*info).matrix * vec4f(world_pos, 1.0);
-    let light_depth = light_pos.z;
+// Request mip level `lod` of a texture from the texture streamer. The level is relative to the
+// first resident mip and may be negative, so it's biased. The most detailed level is stored as
+// the maximum inverted level.
+fn request_texture_level(texture_index: u32, lod: f32) {
+    let biased = u32(clamp(floor(lod) + FEEDBACK_LEVEL_BIAS, 0.0, 31.0));
+    atomicMax(&texture_feedback[texture_index], 32u - biased);
+}
+
+// Attenuate `color` seen at `distance` from the camera by the aerial perspective and fog, and add
+// the light they scatter towards the camera.
+fn apply_volume(color: vec3f, texcoords: vec2f, distance: f32) -> vec3f {
+    // GL can't query the layer count of sampled textures.
+    let slice_count = #{VOLUME_SLICE_COUNT};
+
+    // Each slice stores the scattering up to its far end, and the slices are distributed
+    // quadratically. Fade in the first slice from nothing at the camera.
+    let w = sqrt(distance / params.volume_distance);
+    let fade = saturate(w * f32(slice_count));
+
+    // The slices are layers, so they are interpolated here instead of by the sampler.
+    let slice = clamp(w * f32(slice_count) - 1.0, 0.0, f32(slice_count - 1));
+    let near_slice = i32(slice);
+    let far_slice = min(near_slice + 1, slice_count - 1);
+
+    let sample = mix(
+        textureSampleLevel(volume, linear_sampler, texcoords, near_slice, 0.0),
+        textureSampleLevel(volume, linear_sampler, texcoords, far_slice, 0.0),
+        fract(slice),
+    );
+    let transmittance = mix(1.0, sample.a, fade);
+
+    return color * transmittance + sample.rgb * fade;
+}
+
+// `depth` is the view space distance along the camera front.
+fn shadow_occlusion(depth: f32, normal: vec3f, world_pos: vec3f) -> f32 {
+    let light_dir = consts.sun.direction.xyz * -1.0;
+    let cascade_index = shadow::cascade_index(depth);
+    let light_pos = shadow::light_position(cascade_index, world_pos);
 
     let bias = max(0.0005 * (1.0 - dot(normal, light_dir)), 0.0005);
 
     var shadow = 0.0;
-    let texel_size = vec2f(1.0) / vec2f(textureDimensions(shadow_cascades));
 
     for (var x = -1; x <= 1; x += 1) {
-	    for (var y = -1; y <= 1; y += 1) {
-			let pcf = textureSampleLevel(
-                shadow_cascades,
-                linear_sampler,
-                light_pos.xy + vec2f(f32(x), f32(y)) * texel_size,
-                cascade_index,
-                0.0,
-            );
-
-	        shadow += light_depth - select(0.0, 1.0, bias > pcf);
-	    }
-	}
+        for (var y = -1; y <= 1; y += 1) {
+            let offset = vec2f(f32(x), f32(y));
+            shadow += shadow::occlusion(cascade_index, light_pos, offset, bias);
+        }
+    }
 
     return shadow / 9.0;
 }
 
+// Add the stars, sun disk and moon to the baked sky, which is too low resolution for them.
+fn shade_sky(direction: vec3f, sky_color: vec3f, ndc: vec2f) -> vec3f {
+    let pixel_offset = vec2f(2.0 / f32(consts.render_size.x), 0.0);
+    let pixel_angle = distance(direction, normalize(camera_ray(ndc + pixel_offset).direction));
+
+    let sun_direction = consts.sun.direction.xyz;
+
+    let background = sky_color
+        + sky::stars(sky_lights, direction, sky_color, pixel_angle)
+        + sky::sun_disk(sky_lights, sun_direction, direction);
+
+    let moon = sky::moon(sky_lights, sun_direction, direction);
+
+    return mix(background, sky_color + moon.rgb, moon.a);
+}
+
 @compute
 @workgroup_size(8, 8)
 fn shade(@builtin(global_invocation_id) invocation_id: vec3u) {
-    if any(invocation_id.xy >= consts.surface_size) {
+    if any(invocation_id.xy >= consts.render_size) {
         return;
     }
 
     let texel_id = vec2i(invocation_id.xy);
 
-    var ndc = (vec2f(texel_id.xy) + 0.5) / vec2f(consts.surface_size) * 2.0 - 1.0;
+    let feedback_pixel = vec2u(consts.frame_index % 4u, (consts.frame_index / 4u) % 4u);
+    write_feedback = all(invocation_id.xy % 4u == feedback_pixel);
+
+    var ndc = (vec2f(texel_id.xy) + 0.5) / vec2f(consts.render_size) * 2.0 - 1.0;
     ndc.y *= -1.0;
 
     let visibility = textureLoad(visibility_buffer, texel_id, 0).x;
-    let depth = textureLoad(depth_buffer, texel_id, 0);
 
     let primitive_index = (visibility >> mesh::TRIANGLE_INDEX_BITS) - 1u;
     var triangle_index = visibility & mesh::TRIANGLE_INDEX_MASK;
 
     if triangle_index == 0u {
-        let skybox_color = textureSampleLevel(
-            skybox,
-            linear_sampler,
-            (ray_matrix * vec4f(ndc, 1.0, 1.0)).xyz,
-            0.0,
-        );
+        let direction = normalize(camera_ray(ndc).direction);
+        let sky_color = textureSampleLevel(skybox, linear_sampler, direction, 0.0).rgb;
+
+        var color = shade_sky(direction, sky_color, ndc);
+
+        // The sky is behind the froxel volume, so it gets the fog and aerial perspective of the
+        // farthest slice to match the geometry at the edge of the volume.
+        if params.apply_volume != 0u {
+            let texcoords = (vec2f(texel_id) + 0.5) / vec2f(consts.render_size);
+            color = apply_volume(color, texcoords, params.volume_distance);
+        }
+
+        textureStore(color_buffer, texel_id, vec4f(color, 1.0));
+
+        if params.write_aovs != 0u {
+            textureStore(normal_depth_buffer, texel_id, vec4f(0.0));
+            textureStore(albedo_buffer, texel_id, vec4f(0.0));
+        }
 
-        textureStore(color_buffer, texel_id, skybox_color);
         return;
     }
 
@@ -202,7 +566,7 @@ fn shade(@builtin(global_invocation_id) invocation_id: vec3u) {
         (primitive.transform * vec4f(mesh::position(primitive.bounding_sphere, vertices[2]), 1.0)).xyz,
     );
 
-    let bary = barycentric(world_positions, ndc, vec2f(consts.surface_size));
+    let bary = barycentric(world_positions, ndc, vec2f(consts.render_size));
 
     let material = materials[mesh::material(vertices[0])];
     let position = interp_3d(bary.lambda, world_positions);
@@ -245,27 +609,29 @@ fn shade(@builtin(global_invocation_id) invocation_id: vec3u) {
 
     let bitangent = sign(bitangent_sign) * cross(normal, tangent);
 
-    let texcoords = array<vec2f, 3>(
-        mesh::texcoords(vertices[0]),
-        mesh::texcoords(vertices[1]),
-        mesh::texcoords(vertices[2])
-    );
+    let primary_texcoords = texture_coords(bary, array<vec2f, 3>(
+        mesh::texcoords(vertices[0], 0u),
+        mesh::texcoords(vertices[1], 0u),
+        mesh::texcoords(vertices[2], 0u),
+    ));
 
-    let uv = interp_2d(bary.lambda, texcoords);
-    let uv_ddx = interp_2d(bary.ddx, texcoords);
-    let uv_ddy = interp_2d(bary.ddy, texcoords);
+    let secondary_texcoords = texture_coords(bary, array<vec2f, 3>(
+        mesh::texcoords(vertices[0], 1u),
+        mesh::texcoords(vertices[1], 1u),
+        mesh::texcoords(vertices[2], 1u),
+    ));
 
     var tangent_space_normal = util::octahedron_decode(
-        textureSampleGrad(
-            textures[material.normal_texture],
-            texture_sampler,
-            uv,
-            uv_ddx,
-            uv_ddy,
-            vec2<i32>(0, 0)
+        sample_material_texture(
+            material.normal_texture,
+            material.normal_transform,
+            primary_texcoords,
+            secondary_texcoords,
         ).xy,
     );
 
+    let geometric_normal = normal;
+
     normal = normalize(
         tangent_space_normal.x * tangent
             + tangent_space_normal.y * bitangent
@@ -275,33 +641,27 @@ fn shade(@builtin(global_invocation_id) invocation_id: vec3u) {
     // Setup shade data.
     var shade: pbr::ShadeParameters;
 
-    var emissive = textureSampleGrad(
-        textures[material.emissive_texture],
-        texture_sampler,
-        uv,
-        uv_ddx,
-        uv_ddy,
-        vec2i(0, 0),
+    var emissive = sample_material_texture(
+        material.emissive_texture,
+        material.emissive_transform,
+        primary_texcoords,
+        secondary_texcoords,
     ).rgb;
 
     emissive *= material.emissive.rgb;
 
-    shade.albedo = textureSampleGrad(
-        textures[material.albedo_texture],
-        texture_sampler,
-        uv,
-        uv_ddx,
-        uv_ddy,
-        vec2i(0, 0),
+    shade.albedo = sample_material_texture(
+        material.albedo_texture,
+        material.albedo_transform,
+        primary_texcoords,
+        secondary_texcoords,
     ).rgb;
 
-    let specular_params = textureSampleGrad(
-        textures[material.specular_texture],
-        texture_sampler,
-        uv,
-        uv_ddx,
-        uv_ddy,
-        vec2i(0, 0),
+    let specular_params = sample_material_texture(
+        material.specular_texture,
+        material.specular_transform,
+        primary_texcoords,
+        secondary_texcoords,
     );
 
     shade.albedo *= material.base_color.rgb;
@@ -313,39 +673,164 @@ fn shade(@builtin(global_invocation_id) invocation_id: vec3u) {
     var dielectric_specular = (material.ior - 1.0) / (material.ior + 1.0);
     dielectric_specular *= dielectric_specular;
 
-    shade.fresnel_min = mix(vec3f(dielectric_specular), shade.albedo, shade.metallic);
+    let dielectric_fresnel = min(dielectric_specular * material.specular.rgb, vec3f(1.0))
+        * material.specular.a;
+
+    shade.fresnel_min = mix(dielectric_fresnel, shade.albedo, shade.metallic);
     shade.fresnel_max = saturate(dot(shade.fresnel_min, vec3f(50.0 * 0.33)));
 
     shade.view_direction = normalize(consts.camera_pos.xyz - position);
     shade.normal_dot_view = clamp(dot(normal, shade.view_direction), 0.0001, 1.0);
 
-    var light: pbr::LightParameters;
-    light.specular_intensity = 1.0;
-    light.light_direction = consts.sun.direction.xyz;
-    light.half_vector = normalize(shade.view_direction + light.light_direction);
-    light.normal_dot_half = saturate(dot(normal, light.half_vector));
-    light.normal_dot_light = saturate(dot(normal, light.light_direction));
-    light.view_dot_half = saturate(dot(shade.view_direction, light.half_vector));
-    light.light_dot_view = saturate(dot(light.light_direction, shade.view_direction));
-    light.light_dot_half = saturate(dot(light.light_direction, light.half_vector));
+    let light = pbr::light_parameters(normal, shade.view_direction, consts.sun.direction.xyz);
 
     let diffuse_color = shade.albedo * (1.0 - shade.metallic);
     let specular = pbr::specular(shade, light);
-    let diffuse = diffuse_color * pbr::burley_diffuse(shade, light);
-    let shadow = shadow_occlusion(depth, normal, position);
+    var diffuse = diffuse_color * pbr::burley_diffuse(shade, light);
+    let view_depth = dot(position - consts.camera_pos.xyz, consts.camera_front.xyz);
+    let shadow = shadow_occlusion(view_depth, normal, position);
+
+    let irradiance = consts.sun.irradiance.xyz * (1.0 - shadow);
+    var ambient = shade.albedo * 0.2;
+
+    if (material.flags & mesh::MATERIAL_OCCLUSION) != 0u {
+        let occlusion = sample_material_texture(
+            material.occlusion_texture,
+            material.occlusion_transform,
+            primary_texcoords,
+            secondary_texcoords,
+        ).r;
+
+        let visibility = 1.0 + material.occlusion_strength * (occlusion - 1.0);
+        ambient *= pbr::multi_bounce_occlusion(visibility, diffuse_color);
+    }
 
-    /*
-    let radiance = (diffuse + specular)
-        * light.normal_dot_light
-        * consts.sun.irradiance.xyz
-        * (1.0 - shadow);
+    // Ambient occlusion only applies to the reflected light, so transmission is added after it.
+    if (material.flags & mesh::MATERIAL_TRANSMISSION) != 0u {
+        var transmission = material.transmission;
+
+        if (material.flags & mesh::MATERIAL_TRANSMISSION_TEXTURE) != 0u {
+            transmission *= sample_material_texture(
+                material.transmission_texture,
+                material.transmission_transform,
+                primary_texcoords,
+                secondary_texcoords,
+            ).r;
+        }
+
+        // Light transmitted through the surface is approximated by the refracted skybox, so
+        // other objects aren't visible through it.
+        let refracted = refract(-shade.view_direction, normal, 1.0 / material.ior);
+        var transmitted = textureSampleLevel(skybox, linear_sampler, refracted, 0.0).rgb
+            * diffuse_color;
+
+        if (material.flags & mesh::MATERIAL_VOLUME) != 0u {
+            transmitted *= pbr::volume_attenuation(material.attenuation, material.thickness);
+        }
+
+        diffuse *= 1.0 - transmission;
+        ambient = mix(ambient, transmitted, transmission);
+    }
 
-    let ambient = shade.albedo * 0.2;
+    var radiance = (diffuse + specular) * light.normal_dot_light * irradiance + ambient;
 
-    let final_color = vec4f(radiance + ambient + emissive, 1.0);
-    */
+    if (material.flags & mesh::MATERIAL_SHEEN) != 0u {
+        var sheen_color = material.sheen.rgb;
+        var sheen_roughness = material.sheen.a;
 
-    let final_color = vec4f(shadow);
+        if (material.flags & mesh::MATERIAL_SHEEN_COLOR_TEXTURE) != 0u {
+            sheen_color *= sample_material_texture(
+                material.sheen_color_texture,
+                material.sheen_color_transform,
+                primary_texcoords,
+                secondary_texcoords,
+            ).rgb;
+        }
+
+        if (material.flags & mesh::MATERIAL_SHEEN_ROUGHNESS_TEXTURE) != 0u {
+            sheen_roughness *= sample_material_texture(
+                material.sheen_roughness_texture,
+                material.sheen_roughness_transform,
+                primary_texcoords,
+                secondary_texcoords,
+            ).r;
+        }
+
+        let sheen = pbr::sheen(sheen_color, sheen_roughness, shade.normal_dot_view, light);
+        radiance = radiance * pbr::sheen_scaling(sheen_color, sheen_roughness)
+            + sheen * light.normal_dot_light * irradiance;
+    }
+
+    if (material.flags & mesh::MATERIAL_CLEARCOAT) != 0u {
+        var clearcoat_factor = material.clearcoat;
+        var clearcoat_roughness = material.clearcoat_roughness;
+
+        if (material.flags & mesh::MATERIAL_CLEARCOAT_TEXTURE) != 0u {
+            clearcoat_factor *= sample_material_texture(
+                material.clearcoat_texture,
+                material.clearcoat_transform,
+                primary_texcoords,
+                secondary_texcoords,
+            ).r;
+        }
+
+        if (material.flags & mesh::MATERIAL_CLEARCOAT_ROUGHNESS_TEXTURE) != 0u {
+            clearcoat_roughness *= sample_material_texture(
+                material.clearcoat_roughness_texture,
+                material.clearcoat_roughness_transform,
+                primary_texcoords,
+                secondary_texcoords,
+            ).r;
+        }
+
+        // The clearcoat layer has its own normal map, and isn't affected by the normal map of
+        // the base layer.
+        var clearcoat_normal = geometric_normal;
+
+        if (material.flags & mesh::MATERIAL_CLEARCOAT_NORMAL_TEXTURE) != 0u {
+            let clearcoat_tangent_normal = util::octahedron_decode(
+                sample_material_texture(
+                    material.clearcoat_normal_texture,
+                    material.clearcoat_normal_transform,
+                    primary_texcoords,
+                    secondary_texcoords,
+                ).xy,
+            );
+
+            clearcoat_normal = normalize(
+                clearcoat_tangent_normal.x * tangent
+                    + clearcoat_tangent_normal.y * bitangent
+                    + clearcoat_tangent_normal.z * geometric_normal,
+            );
+        }
+
+        let clearcoat_light = pbr::light_parameters(
+            clearcoat_normal,
+            shade.view_direction,
+            light.light_direction,
+        );
+
+        let normal_dot_view = clamp(dot(clearcoat_normal, shade.view_direction), 0.0001, 1.0);
+        let roughness = clearcoat_roughness * clearcoat_roughness;
+        let clearcoat = pbr::clearcoat_specular(roughness, normal_dot_view, clearcoat_light);
+        let fresnel = pbr::clearcoat_fresnel(normal_dot_view) * clearcoat_factor;
+
+        radiance = radiance * (1.0 - fresnel)
+            + clearcoat_factor * clearcoat * clearcoat_light.normal_dot_light * irradiance;
+    }
+
+    var final_color = vec4f(radiance + emissive, 1.0);
+
+    if params.apply_volume != 0u {
+        let texcoords = (vec2f(texel_id) + 0.5) / vec2f(consts.render_size);
+        let distance = length(position - consts.camera_pos.xyz);
+        final_color = vec4f(apply_volume(final_color.rgb, texcoords, distance), 1.0);
+    }
 
     textureStore(color_buffer, texel_id, final_color);
+
+    if params.write_aovs != 0u {
+        textureStore(normal_depth_buffer, texel_id, vec4f(normal, view_depth));
+        textureStore(albedo_buffer, texel_id, vec4f(shade.albedo, 1.0));
+    }
 }
@@ -5,6 +5,9 @@
 @group(0) @binding(0)
 var<storage, read> shadow_cascades: array<light::ShadowCascade>;
 
+@group(0) @binding(2)
+var<uniform> cascade_index: u32;
+
 @group(1) @binding(0)
 var<storage, read> primitives: array<mesh::Primitive>;
 
@@ -18,12 +21,10 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4f,
 };
 
-var<push_constant> cascade_index: u32;
-
 @vertex
 fn vertex(
     @builtin(vertex_index) vertex_index: u32,
-    @builtin(instance_index) primitive_index: u32,
+    @location(0) primitive_index: u32,
 ) -> VertexOutput {
     var out: VertexOutput;
 
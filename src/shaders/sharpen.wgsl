@@ -0,0 +1,52 @@
+#import consts
+
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+@group(1) @binding(0)
+var color_accum_buffer: texture_2d<f32>;
+
+@group(1) @binding(1)
+var post_buffer: texture_storage_2d<rgba16float, write>;
+
+// How much to sharpen in the range [0, 1].
+@group(1) @binding(2)
+var<uniform> sharpness: f32;
+
+fn load(texel: vec2i) -> vec3f {
+    let coords = clamp(texel, vec2i(0), vec2i(consts.surface_size) - 1);
+    return saturate(textureLoad(color_accum_buffer, coords, 0).rgb);
+}
+
+// Contrast adaptive sharpening as in AMD FidelityFX CAS. The sharpening is reduced where the
+// neighborhood already has high contrast to avoid ringing.
+@compute
+@workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) texel_id: vec3u) {
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= vec2i(consts.surface_size)) {
+        return;
+    }
+
+    let e = load(center);
+    let b = load(center + vec2i(0, -1));
+    let d = load(center + vec2i(-1, 0));
+    let f = load(center + vec2i(1, 0));
+    let h = load(center + vec2i(0, 1));
+
+    let min_rgb = min(e, min(min(b, d), min(f, h)));
+    let max_rgb = max(e, max(max(b, d), max(f, h)));
+
+    // Distance to the signal limits, relative to the maximum.
+    let amplitude = sqrt(saturate(min(min_rgb, 1.0 - max_rgb) / max(max_rgb, vec3f(1e-5))));
+
+    // The negative lobe goes from -1/8 to -1/5 as the sharpness increases.
+    let peak = -1.0 / mix(8.0, 5.0, sharpness);
+    let weight = amplitude * peak;
+
+    let sharpened = (e + (b + d + f + h) * weight) / (1.0 + 4.0 * weight);
+    let alpha = textureLoad(color_accum_buffer, center, 0).a;
+
+    textureStore(post_buffer, center, vec4f(saturate(sharpened), alpha));
+}
@@ -0,0 +1,291 @@
+#import consts
+#import util
+
+@group(0) @binding(0)
+var<uniform> consts: consts::Consts;
+
+@group(0) @binding(2)
+var linear_sampler: sampler;
+
+@group(1) @binding(0)
+var color_buffer: texture_2d<f32>;
+
+@group(1) @binding(1)
+var edges_out: texture_storage_2d<rgba8unorm, write>;
+
+@group(1) @binding(2)
+var edges_buffer: texture_2d<f32>;
+
+@group(1) @binding(3)
+var weights_out: texture_storage_2d<rgba8unorm, write>;
+
+@group(1) @binding(4)
+var weights_buffer: texture_2d<f32>;
+
+@group(1) @binding(5)
+var post_buffer: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(6)
+var area_texture: texture_2d<f32>;
+
+@group(1) @binding(7)
+var search_texture: texture_2d<f32>;
+
+// The minimum luma difference of an edge.
+const THRESHOLD = 0.1;
+// Edges are discarded if a neighboring edge has this much more contrast.
+const LOCAL_CONTRAST_ADAPTATION = 2.0;
+// The maximum number of bilinear fetches in each direction along an edge, each of which covers
+// two pixels.
+const MAX_SEARCH_STEPS = 16;
+// How much sharp corners are rounded off.
+const CORNER_ROUNDING = 0.25;
+// The size of the area texture of each pattern of crossing edges.
+const AREA_TEXTURE_MAX_DISTANCE = 16.0;
+// The offset of the searches to the right in the search texture.
+const SEARCH_TEXTURE_RIGHT = 33u;
+
+fn clamp_texel(texel: vec2i) -> vec2i {
+    return clamp(texel, vec2i(0), vec2i(consts.surface_size) - 1);
+}
+
+// The color buffer isn't tonemapped, so the luma is compressed to be roughly perceptual.
+fn load_luma(texel: vec2i) -> f32 {
+    let luminance = util::luminance(textureLoad(color_buffer, clamp_texel(texel), 0).rgb);
+    return sqrt(luminance / (1.0 + luminance));
+}
+
+// Find the edges between each pixel and its left and top neighbors. The red channel is the edge
+// on the left and the green channel the edge on the top.
+@compute
+@workgroup_size(8, 8)
+fn detect_edges(@builtin(global_invocation_id) texel_id: vec3u) {
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= vec2i(consts.surface_size)) {
+        return;
+    }
+
+    let luma = load_luma(center);
+    let luma_left = load_luma(center + vec2i(-1, 0));
+    let luma_top = load_luma(center + vec2i(0, -1));
+
+    var delta = abs(luma - vec2f(luma_left, luma_top));
+    var edges = step(vec2f(THRESHOLD), delta);
+
+    // The edges at the border of the screen aren't real.
+    edges = select(edges, vec2f(0.0), center == vec2i(0));
+
+    if all(edges == vec2f(0.0)) {
+        textureStore(edges_out, center, vec4f(0.0));
+        return;
+    }
+
+    // Local contrast adaptation. Find the largest contrast among the neighboring edges.
+    let luma_right = load_luma(center + vec2i(1, 0));
+    let luma_bottom = load_luma(center + vec2i(0, 1));
+    let luma_left_left = load_luma(center + vec2i(-2, 0));
+    let luma_top_top = load_luma(center + vec2i(0, -2));
+
+    let delta_far = abs(vec2f(luma, luma) - vec2f(luma_right, luma_bottom));
+    let delta_far_far = abs(vec2f(luma_left, luma_top) - vec2f(luma_left_left, luma_top_top));
+
+    var max_delta = max(delta, max(delta_far, delta_far_far));
+    max_delta = vec2f(max(max_delta.x, max_delta.y));
+
+    edges *= step(max_delta, LOCAL_CONTRAST_ADAPTATION * delta);
+
+    textureStore(edges_out, center, vec4f(edges, 0.0, 0.0));
+}
+
+// Fetch the edges at `position` in pixels with bilinear filtering, which tells the edges of up
+// to four pixels apart in a single fetch.
+fn sample_edges(position: vec2f) -> vec2f {
+    let size = vec2f(textureDimensions(edges_buffer));
+    return textureSampleLevel(edges_buffer, linear_sampler, position / size, 0.0).rg;
+}
+
+// The distance to step back by after the last fetch of a search, from the edges `e` along the
+// search in the green channel and the crossing edges in the red channel.
+fn search_length(e: vec2f, offset: u32) -> f32 {
+    let texel = vec2u(round(e * 32.0)) + vec2u(offset, 0u);
+    let length = textureLoad(search_texture, texel, 0).r;
+    return 3.25 - (255.0 / 127.0) * length;
+}
+
+// Search for the left end of the edge on the top of the pixels, two pixels at a time. The
+// fetches are an eighth of a pixel up to tell crossing edges above and below apart.
+fn search_left(start: vec2f) -> f32 {
+    var position = start;
+    var e = vec2f(0.0, 1.0);
+
+    for (var i = 0; i < MAX_SEARCH_STEPS && e.g > 0.8281 && e.r == 0.0; i++) {
+        e = sample_edges(position);
+        position.x -= 2.0;
+    }
+
+    return position.x + search_length(e, 0u);
+}
+
+fn search_right(start: vec2f) -> f32 {
+    var position = start;
+    var e = vec2f(0.0, 1.0);
+
+    for (var i = 0; i < MAX_SEARCH_STEPS && e.g > 0.8281 && e.r == 0.0; i++) {
+        e = sample_edges(position);
+        position.x += 2.0;
+    }
+
+    return position.x - search_length(e, SEARCH_TEXTURE_RIGHT);
+}
+
+fn search_up(start: vec2f) -> f32 {
+    var position = start;
+    var e = vec2f(1.0, 0.0);
+
+    for (var i = 0; i < MAX_SEARCH_STEPS && e.r > 0.8281 && e.g == 0.0; i++) {
+        e = sample_edges(position);
+        position.y -= 2.0;
+    }
+
+    return position.y + search_length(e.gr, 0u);
+}
+
+fn search_down(start: vec2f) -> f32 {
+    var position = start;
+    var e = vec2f(1.0, 0.0);
+
+    for (var i = 0; i < MAX_SEARCH_STEPS && e.r > 0.8281 && e.g == 0.0; i++) {
+        e = sample_edges(position);
+        position.y += 2.0;
+    }
+
+    return position.y - search_length(e.gr, SEARCH_TEXTURE_RIGHT);
+}
+
+// Look up the coverage of a pixel from the square roots of the distances to the ends of the edge
+// and the bilinear fetches of the crossing edges at the ends.
+fn area(sqrt_distance: vec2f, e1: f32, e2: f32) -> vec2f {
+    let texel = AREA_TEXTURE_MAX_DISTANCE * round(4.0 * vec2f(e1, e2)) + sqrt_distance + 0.5;
+    let size = vec2f(textureDimensions(area_texture));
+    return textureSampleLevel(area_texture, linear_sampler, texel / size, 0.0).rg;
+}
+
+// How much to reduce the blending at each end of an edge if there is a corner there. Only the
+// nearest end counts, or both in the middle of the edge.
+fn corner_rounding(distance: vec2f) -> vec2f {
+    let nearest = step(distance, distance.yx);
+    return (1.0 - CORNER_ROUNDING) * nearest / (nearest.x + nearest.y);
+}
+
+// Find the blend weights of the edges on the left and top of each pixel from the distances to
+// the ends of the line they are part of and the crossing edges there. The red channel is the
+// weight of the top neighbor for the pixel and green the weight of the pixel for the top
+// neighbor. Blue and alpha are the same for the left.
+@compute
+@workgroup_size(8, 8)
+fn blend_weights(@builtin(global_invocation_id) texel_id: vec3u) {
+    let texel = vec2i(texel_id.xy);
+
+    if any(texel >= vec2i(consts.surface_size)) {
+        return;
+    }
+
+    let center = vec2f(texel) + 0.5;
+    let edges = textureLoad(edges_buffer, texel, 0).rg;
+    var weights = vec4f(0.0);
+
+    if edges.g > 0.0 {
+        let left = search_left(center + vec2f(-0.25, -0.125));
+        let right = search_right(center + vec2f(1.25, -0.125));
+        let distance = abs(round(vec2f(left, right) - center.x));
+
+        // The crossing edges are fetched a quarter pixel up, which tells if they are above the
+        // edge, below it or both.
+        let e1 = sample_edges(vec2f(left, center.y - 0.25)).r;
+        let e2 = sample_edges(vec2f(right + 1.0, center.y - 0.25)).r;
+        var coverage = area(sqrt(distance), e1, e2);
+
+        let rounding = corner_rounding(distance);
+        let factor = 1.0 - rounding.x * vec2f(
+            sample_edges(vec2f(left, center.y + 1.0)).r,
+            sample_edges(vec2f(left, center.y - 2.0)).r,
+        ) - rounding.y * vec2f(
+            sample_edges(vec2f(right + 1.0, center.y + 1.0)).r,
+            sample_edges(vec2f(right + 1.0, center.y - 2.0)).r,
+        );
+        coverage *= saturate(factor);
+
+        weights = vec4f(coverage, weights.zw);
+    }
+
+    if edges.r > 0.0 {
+        let top = search_up(center + vec2f(-0.125, -0.25));
+        let bottom = search_down(center + vec2f(-0.125, 1.25));
+        let distance = abs(round(vec2f(top, bottom) - center.y));
+
+        let e1 = sample_edges(vec2f(center.x - 0.25, top)).g;
+        let e2 = sample_edges(vec2f(center.x - 0.25, bottom + 1.0)).g;
+        var coverage = area(sqrt(distance), e1, e2);
+
+        let rounding = corner_rounding(distance);
+        let factor = 1.0 - rounding.x * vec2f(
+            sample_edges(vec2f(center.x + 1.0, top)).g,
+            sample_edges(vec2f(center.x - 2.0, top)).g,
+        ) - rounding.y * vec2f(
+            sample_edges(vec2f(center.x + 1.0, bottom + 1.0)).g,
+            sample_edges(vec2f(center.x - 2.0, bottom + 1.0)).g,
+        );
+        coverage *= saturate(factor);
+
+        weights = vec4f(weights.xy, coverage);
+    }
+
+    textureStore(weights_out, texel, weights);
+}
+
+fn load_color(texel: vec2i) -> vec4f {
+    return textureLoad(color_buffer, clamp_texel(texel), 0);
+}
+
+fn load_weights(texel: vec2i) -> vec4f {
+    return textureLoad(weights_buffer, clamp_texel(texel), 0);
+}
+
+// Blend each pixel with its neighbors across the edges around it. Only the horizontal or the
+// vertical neighbors are blended, whichever have the largest weight.
+@compute
+@workgroup_size(8, 8)
+fn blend_neighborhood(@builtin(global_invocation_id) texel_id: vec3u) {
+    let center = vec2i(texel_id.xy);
+
+    if any(center >= vec2i(consts.surface_size)) {
+        return;
+    }
+
+    let weights = load_weights(center);
+    let color = load_color(center);
+
+    // The weights of the right, bottom, left and top neighbors.
+    let neighbors = vec4f(
+        load_weights(center + vec2i(1, 0)).a,
+        load_weights(center + vec2i(0, 1)).g,
+        weights.b,
+        weights.r,
+    );
+
+    if dot(neighbors, vec4f(1.0)) < 1e-5 {
+        textureStore(post_buffer, center, color);
+        return;
+    }
+
+    let horizontal = max(neighbors.x, neighbors.z) > max(neighbors.y, neighbors.w);
+    let direction = select(vec2i(0, 1), vec2i(1, 0), horizontal);
+    let blend = select(neighbors.yw, neighbors.xz, horizontal);
+    let scale = blend / (blend.x + blend.y);
+
+    let blended = scale.x * mix(color, load_color(center + direction), blend.x)
+        + scale.y * mix(color, load_color(center - direction), blend.y);
+
+    textureStore(post_buffer, center, blended);
+}
@@ -1,16 +1,14 @@
 #import consts
+#import util
 
 @group(0) @binding(0)
 var<uniform> consts: consts::Consts;
 
-@group(0) @binding(2)
-var linear_sampler: sampler;
-
 @group(1) @binding(0)
 var color_buffer: texture_2d<f32>;
 
 @group(1) @binding(1)
-var depth_buffer: texture_depth_2d;
+var depth_buffer: texture_2d<f32>;
 
 @group(1) @binding(2)
 var color_accum_buffer: texture_2d<f32>;
@@ -18,14 +16,23 @@ var color_accum_buffer: texture_2d<f32>;
 @group(1) @binding(3)
 var post_buffer: texture_storage_2d<rgba16float, write>;
 
-var<push_constant> reproject: mat4x4f;
+struct Params {
+    reproject: mat4x4f,
+    history_blend: f32,
+    variance_gamma: f32,
+}
+
+@group(1) @binding(4)
+var<uniform> params: Params;
 
 const BLOCK_WIDTH = 8u;
-const BORDER_WIDTH = 1u;
-const CACHE_WIDTH = 10u;
-const CACHE_LENGTH = 100u;
+const BORDER_WIDTH = 2;
+const CACHE_WIDTH = 12u;
+const CACHE_LENGTH = 144u;
 
-// 10x10 `color_buffer` and `depth_buffer` cache, which contains all the neighbourhood values of the workgroup.
+// 12x12 `color_buffer` and `depth_buffer` cache, which contains all the neighbourhood values of the workgroup.
+// The color and depth buffers may have a lower resolution than the output, so the cache covers a 12x12 block
+// of the input.
 var<workgroup> color_cache: array<vec4f, CACHE_LENGTH>;
 var<workgroup> depth_cache: array<f32, CACHE_LENGTH>;
 
@@ -39,16 +46,27 @@ fn coords_to_cache_index(coords: vec2i) -> u32 {
     return u32(coords.x) + u32(coords.y) * CACHE_WIDTH;
 }
 
-fn fill_color_cache(workgroup_id: vec2u, local_index: u32, local_id: vec2u) {
-    let edge = vec2i(consts.surface_size) - 1;
-    let upper_left = vec2i(workgroup_id * BLOCK_WIDTH - BORDER_WIDTH);
+// The scale from output to input pixels.
+fn render_scale() -> vec2f {
+    return vec2f(consts.render_size) / vec2f(consts.surface_size);
+}
+
+// The input pixel in the upper left corner of the cache of a workgroup.
+fn cache_upper_left(workgroup_id: vec2u) -> vec2i {
+    let first_pixel = vec2f(workgroup_id * BLOCK_WIDTH) * render_scale();
+    return vec2i(floor(first_pixel)) - BORDER_WIDTH;
+}
+
+fn fill_color_cache(workgroup_id: vec2u, local_index: u32) {
+    let edge = vec2i(consts.render_size) - 1;
+    let upper_left = cache_upper_left(workgroup_id);
 
     for (var t = local_index; t < CACHE_LENGTH; t += BLOCK_WIDTH * BLOCK_WIDTH) {
         let pixel = upper_left + cache_index_to_coords(t);
         let coords = clamp(pixel, vec2i(0), edge);
 
         let color = textureLoad(color_buffer, coords, 0);
-        let depth = textureLoad(depth_buffer, coords, 0);
+        let depth = textureLoad(depth_buffer, coords, 0).r;
 
         color_cache[t] = saturate(color);
         depth_cache[t] = depth;
@@ -57,123 +75,180 @@ fn fill_color_cache(workgroup_id: vec2u, local_index: u32, local_id: vec2u) {
     workgroupBarrier();
 }
 
-// 5 tap catmull-rom sample in a 4x4 grid.
-fn sample_history_catmull_rom(texcoords: vec2f, size: vec2f) -> vec4f {
-    let sample_position = texcoords * size;
-    let p1 = floor(sample_position - 0.5) + 0.5;
+fn lanczos2(x: f32) -> f32 {
+    if abs(x) < 1e-5 {
+        return 1.0;
+    }
+
+    if abs(x) >= 2.0 {
+        return 0.0;
+    }
 
-    // The offset from `p1` to `sample_position`, used to generate the filter weights.
+    let px = util::PI * x;
+    return 2.0 * sin(px) * sin(px / 2.0) / (px * px);
+}
+
+// Lanczos 2 sample in a 4x4 grid. The sharper kernel keeps the history from blurring when it is
+// resampled every frame, and the ringing is removed by clipping.
+fn sample_history_lanczos(texcoords: vec2f, size: vec2f) -> vec4f {
+    let sample_position = texcoords * size - 0.5;
+    let p1 = floor(sample_position);
     let f = sample_position - p1;
 
-    // Calculate the catmull-rom weight.
-    let w0 = f * (-0.5 + f * (1.0 - 0.5 * f));
-    let w1 = 1.0 + f * f * (-2.5 + 1.5 * f);
-    let w2 = f * (0.5 + f * (2.0 - 1.5 * f));
-    let w3 = f * f * (-0.5 + 0.5 * f);
+    var out = vec4f(0.0);
+    var weight_sum = 0.0;
 
-    // Weights and offset for bilinear filter of the inner 2x2 grid around the center.
-    let w12 = w1 + w2;
-    let offset12 = w2 / (w1 + w2);
-    let texel_size = 1.0 / size;
+    for (var y = -1; y <= 2; y++) {
+        let weight_y = lanczos2(f.y - f32(y));
 
-    // The uv coordintes for sampling.
-    let p0 = (p1 - 1.0) * texel_size;
-    let p3 = (p1 + 2.0) * texel_size;
-    let p12 = (p1 + offset12) * texel_size;
+        for (var x = -1; x <= 2; x++) {
+            let weight = lanczos2(f.x - f32(x)) * weight_y;
+            let coords = clamp(vec2i(p1) + vec2i(x, y), vec2i(0), vec2i(size) - 1);
 
-    var out = vec4f(0.0);
-    out += textureSampleLevel(color_accum_buffer, linear_sampler, vec2f(p12.x, p0.y), 0.0) * w12.x * w0.y;
-    out += textureSampleLevel(color_accum_buffer, linear_sampler, vec2f(p0.x, p12.y), 0.0) * w0.x * w12.y;
-    out += textureSampleLevel(color_accum_buffer, linear_sampler, vec2f(p12.x, p12.y), 0.0) * w12.x * w12.y;
-    out += textureSampleLevel(color_accum_buffer, linear_sampler, vec2f(p3.x, p12.y), 0.0) * w3.x * w12.y;
-    out += textureSampleLevel(color_accum_buffer, linear_sampler, vec2f(p12.x, p3.y), 0.0) * w12.x * w3.y;
+            out += textureLoad(color_accum_buffer, coords, 0) * weight;
+            weight_sum += weight;
+        }
+    }
 
-    return out;
+    return out / weight_sum;
 }
 
 struct Neighborhood {
     min: vec3f,
     max: vec3f,
-    center_sample: vec4f,
+    // The first and second moments of the color.
+    mean: vec3f,
+    mean_squared: vec3f,
+    // The current frame reconstructed at the center of the output pixel.
+    current: vec3f,
     nearest_depth: f32,
 };
 
-fn sample_neighborhood(
-    center: vec2i,
-    size: vec2i,
-    workgroup_id: vec2u,
-    local_index: u32,
-    local_id: vec2u,
-) -> Neighborhood {
+// The offset of the sample position of input pixels from their centers.
+fn jitter_offset() -> vec2f {
+    // The jitter is in normalized device coordinates divided by two, and the y axis points up.
+    return vec2f(consts.jitter.x, -consts.jitter.y) * vec2f(consts.render_size);
+}
+
+// Sample the 3x3 input pixels nearest to `position`, which is in input pixels. The current frame
+// is reconstructed by weighting each pixel by the distance of its jittered sample to `position`.
+fn sample_neighborhood(position: vec2f, workgroup_id: vec2u) -> Neighborhood {
     var neighborhood: Neighborhood;
-    neighborhood.nearest_depth = 1.0;
+    neighborhood.nearest_depth = 0.0;
 
     neighborhood.min = vec3f(99.0);
     neighborhood.max = vec3f(0.0);
 
+    let jitter = jitter_offset();
+    let nearest = vec2i(floor(position - jitter));
+    let cache_offset = nearest - cache_upper_left(workgroup_id);
+
+    var sample_count = 0.0;
+    var current_weight = 0.0;
+
     for (var x: i32 = -1; x <= 1; x++) {
         for (var y: i32 = -1; y <= 1; y++) {
             let offset = vec2i(x, y);
 
-            let cache_index = coords_to_cache_index(vec2i(local_id) + i32(BORDER_WIDTH) + offset);
+            let cache_index = coords_to_cache_index(cache_offset + offset);
             let neighbor = color_cache[cache_index];
 
             let depth = depth_cache[cache_index];
 
-            if x == 0 && y == 0 {
-                neighborhood.center_sample = neighbor;
-            }
-
             if neighbor.w <= 0.5 {
                 continue;
             }
 
+            // Approximation of a Blackman-Harris window by a Gaussian.
+            let distance = vec2f(nearest + offset) + 0.5 + jitter - position;
+            let weight = exp(-2.29 * dot(distance, distance));
+
+            neighborhood.current += neighbor.xyz * weight;
+            current_weight += weight;
+
             neighborhood.min = min(neighborhood.min, neighbor.xyz);
             neighborhood.max = max(neighborhood.max, neighbor.xyz);
 
-            neighborhood.nearest_depth = min(neighborhood.nearest_depth, depth);
+            neighborhood.mean += neighbor.xyz;
+            neighborhood.mean_squared += neighbor.xyz * neighbor.xyz;
+            sample_count += 1.0;
+
+            // The depth is reversed, so the nearest depth is the largest.
+            neighborhood.nearest_depth = max(neighborhood.nearest_depth, depth);
         }
     }
 
+    neighborhood.current /= max(current_weight, 1e-5);
+    neighborhood.mean /= max(sample_count, 1.0);
+    neighborhood.mean_squared /= max(sample_count, 1.0);
+
     return neighborhood;
 }
 
+// Clip `history` towards the center of the box spanned by the neighborhood variance, which is
+// tighter than the min and max of the neighborhood when the colors are clustered.
+fn clip_history(history: vec3f, neighborhood: Neighborhood) -> vec3f {
+    // No valid samples in the neighborhood.
+    if any(neighborhood.min > neighborhood.max) {
+        return history;
+    }
+
+    let deviation = sqrt(max(neighborhood.mean_squared - neighborhood.mean * neighborhood.mean, vec3f(0.0)));
+
+    let box_min = max(neighborhood.min, neighborhood.mean - params.variance_gamma * deviation);
+    let box_max = min(neighborhood.max, neighborhood.mean + params.variance_gamma * deviation);
+
+    let center = 0.5 * (box_min + box_max);
+    let extent = max(0.5 * (box_max - box_min), vec3f(1e-5));
+
+    let offset = history - center;
+    let units = abs(offset / extent);
+    let max_unit = max(units.x, max(units.y, units.z));
+
+    if max_unit > 1.0 {
+        return center + offset / max_unit;
+    }
+
+    return history;
+}
+
 fn reproject_texcoords(texcoords: vec2f, velocity: vec2f, depth: f32) -> vec2f {
     let clip = (vec2f(2.0, -2.0) * texcoords) + vec2f(-1.0, 1.0);
-    let previous_clip = reproject * vec4f(clip, depth, 1.0);
+    let previous_clip = params.reproject * vec4f(clip, depth, 1.0);
     return (previous_clip.xy / previous_clip.w) - velocity;
 }
 
+// Resolve at the output resolution, which upsamples the color buffer if the render scale is
+// lower than one.
 @compute
 @workgroup_size(8, 8)
 fn main(
     @builtin(global_invocation_id) texel_id: vec3u,
     @builtin(workgroup_id) workgroup_id: vec3u,
     @builtin(local_invocation_index) local_index: u32,
-    @builtin(local_invocation_id) local_id: vec3u,
 ) {
     let size = vec2i(consts.surface_size);
     let center = vec2i(texel_id.xy);
 
-    fill_color_cache(workgroup_id.xy, local_index, local_id.xy);
+    fill_color_cache(workgroup_id.xy, local_index);
 
     if any(center >= size) {
         return;
     }
 
-    let neighborhood = sample_neighborhood(center, size, workgroup_id.xy, local_index, local_id.xy);
-
     let texcoords = (vec2f(center) + 0.5) / vec2f(size);
+    let neighborhood = sample_neighborhood(texcoords * vec2f(consts.render_size), workgroup_id.xy);
+
     var velocity = vec2f(0.0);
 
     let history_texcoords = reproject_texcoords(texcoords, velocity, neighborhood.nearest_depth);
-    let history = sample_history_catmull_rom(history_texcoords, vec2f(size));
+    let history = sample_history_lanczos(history_texcoords, vec2f(size));
 
     let no_history = any(history_texcoords != saturate(history_texcoords));
-    let source_weight = select(0.1, 1.0, no_history);
+    let source_weight = select(params.history_blend, 1.0, no_history);
 
-    let history_sample = clamp(history.xyz, neighborhood.min, neighborhood.max);
-    let source_sample = neighborhood.center_sample.xyz;
+    let history_sample = clip_history(max(history.xyz, vec3f(0.0)), neighborhood);
+    let source_sample = neighborhood.current;
 
     let result = mix(history_sample, source_sample, source_weight);
 
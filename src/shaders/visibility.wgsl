@@ -23,7 +23,7 @@ struct VertexOutput {
 @vertex
 fn vertex(
     @builtin(vertex_index) vertex_index: u32,
-    @builtin(instance_index) primitive_index: u32,
+    @location(0) primitive_index: u32,
 ) -> VertexOutput {
     var out: VertexOutput;
 
@@ -33,12 +33,12 @@ fn vertex(
     let index = indices[vertex_index];
     let position = mesh::position(bounding_sphere, vertices[index]);
 
-    var jittered_proj_view = consts.proj_view;
-    jittered_proj_view[2][0] += consts.jitter.x;
-    jittered_proj_view[2][1] += consts.jitter.y;
-
     let world_position = transform * vec4f(position, 1.0);
-    out.clip_position = jittered_proj_view * world_position;
+    out.clip_position = consts.proj_view * world_position;
+
+    // Offset by the jitter in normalized device coordinates, which span two units.
+    out.clip_position.x += 2.0 * consts.jitter.x * out.clip_position.w;
+    out.clip_position.y += 2.0 * consts.jitter.y * out.clip_position.w;
 
     out.triangle_index = vertex_index / 3u;
     out.primitive_index = primitive_index;
@@ -1,28 +1,20 @@
-use std::{borrow::Cow, mem};
-
-use bytemuck::NoUninit;
+use std::borrow::Cow;
 
 use crate::{
     context::Context,
     resources::{
-        ConstState, DepthPyramid, SceneState, ShadowCascades, SHADOW_CASCADE_FORMAT,
-        SHADOW_CASCADE_SIZE,
+        ConstState, DepthPyramid, ParamsBuffer, SceneState, ShadowCascades, PRIMITIVE_INDEX_LAYOUT,
+        SHADOW_CASCADE_COUNT, SHADOW_CASCADE_FORMAT,
     },
 };
 
-#[repr(C)]
-#[derive(Clone, Copy, NoUninit)]
-struct ShadowParams {
-    cascade_size: u32,
-    lambda: f32,
-    near_offset: f32,
-}
-
 pub struct ShadowPhase {
     render_cascade: wgpu::RenderPipeline,
     setup_cascades: wgpu::ComputePipeline,
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    /// The index of each cascade in its own slot.
+    cascade_indices: ParamsBuffer<u32>,
 }
 
 impl ShadowPhase {
@@ -86,11 +78,22 @@ impl ShadowPhase {
                             },
                             count: None,
                         },
+                        ParamsBuffer::<u32>::layout_entry(2, wgpu::ShaderStages::VERTEX, true),
                     ],
                 });
 
-        let bind_group =
-            create_bind_group(context, &shadow_cascades, depth_pyramid, &bind_group_layout);
+        let cascade_indices = ParamsBuffer::new(context, "cascade indices", SHADOW_CASCADE_COUNT);
+        for index in 0..SHADOW_CASCADE_COUNT {
+            cascade_indices.write(context, index, &(index as u32));
+        }
+
+        let bind_group = create_bind_group(
+            context,
+            shadow_cascades,
+            depth_pyramid,
+            &cascade_indices,
+            &bind_group_layout,
+        );
 
         let setup_cascades_layout =
             context
@@ -101,10 +104,7 @@ impl ShadowPhase {
                         ConstState::bind_group_layout(context),
                         &bind_group_layout,
                     ],
-                    push_constant_ranges: &[wgpu::PushConstantRange {
-                        stages: wgpu::ShaderStages::COMPUTE,
-                        range: 0..mem::size_of::<ShadowParams>() as u32,
-                    }],
+                    push_constant_ranges: &[],
                 });
 
         let setup_cascades =
@@ -123,10 +123,7 @@ impl ShadowPhase {
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("render cascade"),
                     bind_group_layouts: &[&bind_group_layout, &scene_state.bind_group_layout],
-                    push_constant_ranges: &[wgpu::PushConstantRange {
-                        stages: wgpu::ShaderStages::VERTEX,
-                        range: 0..mem::size_of::<u32>() as u32,
-                    }],
+                    push_constant_ranges: &[],
                 });
 
         let render_cascade =
@@ -138,7 +135,7 @@ impl ShadowPhase {
                     vertex: wgpu::VertexState {
                         module: &cascade_render_shader,
                         entry_point: "vertex",
-                        buffers: &[],
+                        buffers: &[PRIMITIVE_INDEX_LAYOUT],
                     },
                     primitive: wgpu::PrimitiveState::default(),
                     depth_stencil: Some(wgpu::DepthStencilState {
@@ -158,6 +155,7 @@ impl ShadowPhase {
             bind_group_layout,
             setup_cascades,
             render_cascade,
+            cascade_indices,
         }
     }
 
@@ -169,8 +167,9 @@ impl ShadowPhase {
     ) {
         self.bind_group = create_bind_group(
             context,
-            &shadow_cascades,
+            shadow_cascades,
             depth_pyramid,
+            &self.cascade_indices,
             &self.bind_group_layout,
         );
     }
@@ -186,16 +185,9 @@ impl ShadowPhase {
             label: Some("setup cascades"),
         });
 
-        let params = ShadowParams {
-            cascade_size: SHADOW_CASCADE_SIZE,
-            lambda: 0.4,
-            near_offset: 250.0,
-        };
-
         compute_pass.set_pipeline(&self.setup_cascades);
         compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
-        compute_pass.set_bind_group(1, &self.bind_group, &[]);
-        compute_pass.set_push_constants(0, bytemuck::bytes_of(&params));
+        compute_pass.set_bind_group(1, &self.bind_group, &[0]);
         compute_pass.dispatch_workgroups(1, 1, 1);
 
         drop(compute_pass);
@@ -214,12 +206,12 @@ impl ShadowPhase {
                 }),
             });
 
+            let offset = index as u64 * self.cascade_indices.stride();
+
             render_pass.set_pipeline(&self.render_cascade);
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_bind_group(0, &self.bind_group, &[offset as wgpu::DynamicOffset]);
             render_pass.set_bind_group(1, &scene_state.bind_group, &[]);
-
-            let index = index as u32;
-            render_pass.set_push_constants(wgpu::ShaderStages::VERTEX, 0, &index.to_le_bytes());
+            render_pass.set_vertex_buffer(0, scene_state.primitive_indices.slice(..));
 
             for (index, draw) in scene_state.primitive_draw_infos.iter().enumerate() {
                 let index = index as u32;
@@ -233,6 +225,7 @@ fn create_bind_group(
     context: &Context,
     shadow_cascades: &ShadowCascades,
     depth_pyramid: &DepthPyramid,
+    cascade_indices: &ParamsBuffer<u32>,
     layout: &wgpu::BindGroupLayout,
 ) -> wgpu::BindGroup {
     context
@@ -251,7 +244,13 @@ fn create_bind_group(
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&depth_pyramid.whole),
+                    resource: wgpu::BindingResource::TextureView(
+                        &depth_pyramid.mips.last().unwrap().view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: cascade_indices.binding(),
                 },
             ],
         })
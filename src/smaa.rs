@@ -0,0 +1,554 @@
+use std::borrow::Cow;
+
+use glam::Vec2;
+
+use crate::{
+    context::Context,
+    resources::{self, ConstState, Consts, RenderState, RenderTarget},
+    util,
+};
+
+const FILTERABLE: wgpu::TextureSampleType = wgpu::TextureSampleType::Float { filterable: true };
+
+/// The format of the edge and blend weight textures.
+const SMAA_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// The size of the area texture of each pattern of crossing edges. Distances are stored
+/// quadratically, so this is the square root of the longest edge in pixels.
+const AREA_TEXTURE_MAX_DISTANCE: u32 = 16;
+
+/// The area texture has a 5x5 grid of patterns, one for each rounded bilinear fetch of the
+/// crossing edges at the ends.
+const AREA_TEXTURE_SIZE: u32 = AREA_TEXTURE_MAX_DISTANCE * 5;
+
+/// The search texture is indexed by the bilinear fetch of the edges in 32nds, and has the
+/// searches to the left and to the right side by side.
+const SEARCH_TEXTURE_WIDTH: u32 = 66;
+const SEARCH_TEXTURE_HEIGHT: u32 = 33;
+
+/// Enhanced subpixel morphological anti-aliasing (SMAA 1x) in three passes: luma edge detection,
+/// blend weights and neighborhood blending. The ends of each edge are found with bilinear
+/// fetches and the search texture, and the blend weights are looked up in the area texture from
+/// the distances to the ends and the crossing edges there. Sharp corners are rounded, but
+/// diagonal edges aren't detected.
+pub struct SmaaPhase {
+    passes: Vec<Pass>,
+    edges: RenderTarget,
+    weights: RenderTarget,
+    area: wgpu::TextureView,
+    search: wgpu::TextureView,
+}
+
+struct Pass {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl SmaaPhase {
+    pub fn new(context: &mut Context, render_state: &RenderState) -> Self {
+        let module = context.create_shader_module(
+            include_str!("shaders/smaa.wgsl"),
+            "shaders/smaa.wgsl",
+            &[],
+        );
+
+        let shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("smaa"),
+                source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+            });
+
+        let (edges, weights) = create_targets(context);
+
+        let area = create_lookup_texture(
+            context,
+            "smaa area",
+            AREA_TEXTURE_SIZE,
+            AREA_TEXTURE_SIZE,
+            wgpu::TextureFormat::Rg8Unorm,
+            &area_texture_data(),
+        );
+
+        let search = create_lookup_texture(
+            context,
+            "smaa search",
+            SEARCH_TEXTURE_WIDTH,
+            SEARCH_TEXTURE_HEIGHT,
+            wgpu::TextureFormat::R8Unorm,
+            &search_texture_data(),
+        );
+
+        let passes = [
+            (
+                "smaa edges",
+                "detect_edges",
+                vec![
+                    util::texture_layout_entry(0, FILTERABLE),
+                    util::storage_texture_layout_entry(1, SMAA_BUFFER_FORMAT),
+                ],
+            ),
+            (
+                "smaa weights",
+                "blend_weights",
+                vec![
+                    util::texture_layout_entry(2, FILTERABLE),
+                    util::storage_texture_layout_entry(3, SMAA_BUFFER_FORMAT),
+                    util::texture_layout_entry(6, FILTERABLE),
+                    util::texture_layout_entry(7, FILTERABLE),
+                ],
+            ),
+            (
+                "smaa blend",
+                "blend_neighborhood",
+                vec![
+                    util::texture_layout_entry(0, FILTERABLE),
+                    util::texture_layout_entry(4, FILTERABLE),
+                    util::storage_texture_layout_entry(5, resources::COLOR_BUFFER_FORMAT),
+                ],
+            ),
+        ];
+
+        let views = pass_views(render_state, &edges, &weights, &area, &search);
+
+        let passes = passes
+            .into_iter()
+            .zip(views)
+            .map(|((label, entry_point, entries), views)| {
+                let bind_group_layout =
+                    context
+                        .device
+                        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                            label: Some(label),
+                            entries: &entries,
+                        });
+
+                let pipeline_layout =
+                    context
+                        .device
+                        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            label: Some(label),
+                            bind_group_layouts: &[
+                                ConstState::bind_group_layout(context),
+                                &bind_group_layout,
+                            ],
+                            push_constant_ranges: &[],
+                        });
+
+                let pipeline =
+                    context
+                        .device
+                        .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                            label: Some(label),
+                            module: &shader,
+                            layout: Some(&pipeline_layout),
+                            entry_point,
+                        });
+
+                let bind_group = create_bind_group(context, label, &bind_group_layout, &views);
+
+                Pass {
+                    pipeline,
+                    bind_group_layout,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        Self {
+            passes,
+            edges,
+            weights,
+            area,
+            search,
+        }
+    }
+
+    pub fn record(
+        &self,
+        consts: &Consts,
+        const_state: &ConstState,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let x = util::div_ceil(consts.surface_size.x, 8);
+        let y = util::div_ceil(consts.surface_size.y, 8);
+
+        for pass in &self.passes {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("smaa"),
+            });
+
+            compute_pass.set_pipeline(&pass.pipeline);
+            compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
+            compute_pass.set_bind_group(1, &pass.bind_group, &[]);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+    }
+
+    pub fn resize_surface(&mut self, context: &Context, render_state: &RenderState) {
+        (self.edges, self.weights) = create_targets(context);
+
+        let views = pass_views(
+            render_state,
+            &self.edges,
+            &self.weights,
+            &self.area,
+            &self.search,
+        );
+
+        for (pass, views) in self.passes.iter_mut().zip(views) {
+            pass.bind_group = create_bind_group(context, "smaa", &pass.bind_group_layout, &views);
+        }
+    }
+}
+
+fn create_targets(context: &Context) -> (RenderTarget, RenderTarget) {
+    let usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING;
+
+    (
+        RenderTarget::new(
+            context,
+            "smaa edges",
+            context.render_size,
+            SMAA_BUFFER_FORMAT,
+            usage,
+        ),
+        RenderTarget::new(
+            context,
+            "smaa weights",
+            context.render_size,
+            SMAA_BUFFER_FORMAT,
+            usage,
+        ),
+    )
+}
+
+/// The bindings and views of each pass.
+fn pass_views<'a>(
+    render_state: &'a RenderState,
+    edges: &'a RenderTarget,
+    weights: &'a RenderTarget,
+    area: &'a wgpu::TextureView,
+    search: &'a wgpu::TextureView,
+) -> [Vec<(u32, &'a wgpu::TextureView)>; 3] {
+    [
+        vec![(0, &render_state.color.view), (1, &edges.view)],
+        vec![(2, &edges.view), (3, &weights.view), (6, area), (7, search)],
+        vec![
+            (0, &render_state.color.view),
+            (4, &weights.view),
+            (5, &render_state.post.view),
+        ],
+    ]
+}
+
+fn create_bind_group(
+    context: &Context,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    views: &[(u32, &wgpu::TextureView)],
+) -> wgpu::BindGroup {
+    let entries: Vec<_> = views
+        .iter()
+        .map(|(binding, view)| wgpu::BindGroupEntry {
+            binding: *binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        })
+        .collect();
+
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            entries: &entries,
+            layout,
+        })
+}
+
+fn create_lookup_texture(
+    context: &Context,
+    label: &str,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    data: &[u8],
+) -> wgpu::TextureView {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+
+    let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        mip_level_count: 1,
+        sample_count: 1,
+        view_formats: &[],
+        size,
+    });
+
+    let texel_size = format.block_size(None).unwrap();
+
+    context.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width * texel_size),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// The area texture of the orthogonal patterns, as in `AreaTex.py` of the reference SMAA
+/// implementation without the subpixel offsets of SMAA T2x and 4x. The texel at
+/// `(16 * e1 + sqrt(left), 16 * e2 + sqrt(right))` is the coverage of a pixel `left` pixels from
+/// the start and `right` pixels from the end of an edge with the crossing edges `e1` and `e2`.
+fn area_texture_data() -> Vec<u8> {
+    let mut data = vec![0; (AREA_TEXTURE_SIZE * AREA_TEXTURE_SIZE * 2) as usize];
+
+    for e1 in 0..5 {
+        for e2 in 0..5 {
+            for x in 0..AREA_TEXTURE_MAX_DISTANCE {
+                for y in 0..AREA_TEXTURE_MAX_DISTANCE {
+                    let left = (x * x) as f32;
+                    let right = (y * y) as f32;
+                    let area = ortho_area(e1, e2, left, right);
+
+                    let texel_x = e1 * AREA_TEXTURE_MAX_DISTANCE + x;
+                    let texel_y = e2 * AREA_TEXTURE_MAX_DISTANCE + y;
+                    let index = ((texel_y * AREA_TEXTURE_SIZE + texel_x) * 2) as usize;
+
+                    data[index] = unorm8(area.x);
+                    data[index + 1] = unorm8(area.y);
+                }
+            }
+        }
+    }
+
+    data
+}
+
+/// The height of the line at an end of an edge with crossing edges `e`, which is the rounded
+/// bilinear fetch of the crossing edges a quarter pixel towards the far side of the edge. 1 is
+/// a crossing edge on the far side, 3 on the near side and 4 on both. An end without a crossing
+/// edge or with one on both sides doesn't bend the line.
+fn crossing_height(e: u32) -> Option<f32> {
+    match e {
+        1 => Some(0.5),
+        3 => Some(-0.5),
+        _ => None,
+    }
+}
+
+/// The coverage of the pixel `left` pixels from the start of an edge that ends `right` pixels
+/// after it. The first component is the area on the near side of the edge, which blends the
+/// pixel with its neighbor across it, and the second the area on the far side.
+fn ortho_area(e1: u32, e2: u32, left: f32, right: f32) -> Vec2 {
+    let d = left + right + 1.0;
+    let middle = Vec2::new(d / 2.0, 0.0);
+
+    match (crossing_height(e1), crossing_height(e2)) {
+        (None, None) => Vec2::ZERO,
+        // L shapes, which only blend the half of the edge closer to the crossing edge.
+        (Some(start), None) if left <= right => line_area(Vec2::new(0.0, start), middle, left),
+        (None, Some(end)) if left >= right => line_area(middle, Vec2::new(d, end), left),
+        (Some(_), None) | (None, Some(_)) => Vec2::ZERO,
+        // U shapes, which are smoothed as they are likely to be part of a curve.
+        (Some(start), Some(end)) if start == end => {
+            let a1 = line_area(Vec2::new(0.0, start), middle, left);
+            let a2 = line_area(middle, Vec2::new(d, end), left);
+            smooth_area(d, a1) + smooth_area(d, a2)
+        }
+        // Z shapes.
+        (Some(start), Some(end)) => line_area(Vec2::new(0.0, start), Vec2::new(d, end), left),
+    }
+}
+
+/// The area between the line through `p1` and `p2` and the edge under the pixel from `x` to
+/// `x + 1`, split into the area below and above the edge.
+fn line_area(p1: Vec2, p2: Vec2, x: f32) -> Vec2 {
+    let d = p2 - p1;
+    let x1 = x;
+    let x2 = x + 1.0;
+    let y1 = p1.y + d.y * (x1 - p1.x) / d.x;
+    let y2 = p1.y + d.y * (x2 - p1.x) / d.x;
+
+    let inside = (x1 >= p1.x && x1 < p2.x) || (x2 > p1.x && x2 <= p2.x);
+
+    if !inside {
+        return Vec2::ZERO;
+    }
+
+    let trapezoid = y1.signum() == y2.signum() || y1.abs() < 1e-4 || y2.abs() < 1e-4;
+
+    if trapezoid {
+        let area = (y1 + y2) / 2.0;
+
+        return if area < 0.0 {
+            Vec2::new(-area, 0.0)
+        } else {
+            Vec2::new(0.0, area)
+        };
+    }
+
+    // The line crosses the edge inside the pixel, which splits the area into two triangles.
+    let crossing = p1.x - p1.y * d.x / d.y;
+    let a1 = if crossing > p1.x {
+        y1 * (crossing - x1) / 2.0
+    } else {
+        0.0
+    };
+    let a2 = if crossing < p2.x {
+        y2 * (x2 - crossing) / 2.0
+    } else {
+        0.0
+    };
+
+    Vec2::new(-a1.min(0.0) - a2.min(0.0), a1.max(0.0) + a2.max(0.0))
+}
+
+/// Short U shapes blend more than the area under the lines to round them off.
+fn smooth_area(d: f32, area: Vec2) -> Vec2 {
+    let smooth = (area * 2.0).powf(0.5) * 0.5;
+    smooth.lerp(area, (d / 32.0).clamp(0.0, 1.0))
+}
+
+/// The search texture, as in `SearchTex.py` of the reference SMAA implementation. The searches
+/// along an edge step two pixels at a time with a bilinear fetch, and this is the distance to
+/// step back by from the last fetch, times 127. The texel at `(32 * crossing, 32 * edge)` is of
+/// a search to the left and 33 texels to the right of that to the right.
+fn search_texture_data() -> Vec<u8> {
+    let mut data = vec![0; (SEARCH_TEXTURE_WIDTH * SEARCH_TEXTURE_HEIGHT) as usize];
+
+    for x in 0..33 {
+        for y in 0..33 {
+            let (Some(crossing), Some(edge)) = (bilinear_edges(x), bilinear_edges(y)) else {
+                continue;
+            };
+
+            let index = (y * SEARCH_TEXTURE_WIDTH + x) as usize;
+            data[index] = 127 * search_delta_left(crossing, edge);
+            data[index + 33] = 127 * search_delta_right(crossing, edge);
+        }
+    }
+
+    data
+}
+
+/// The edges of the 2x2 pixels of a bilinear fetch from its value in 32nds. The fetch is a
+/// quarter pixel away from the center of pixel 3 towards pixel 2 and an eighth of a pixel
+/// towards pixel 1, so the weights of the pixels are 1, 3, 7 and 21.
+fn bilinear_edges(value: u32) -> Option<[bool; 4]> {
+    let edges = [1, 3, 7, 21];
+
+    (0..16u32)
+        .map(|bits| {
+            let active = [0, 1, 2, 3].map(|i| bits & (1 << i) != 0);
+            let sum: u32 = (0..4).filter(|&i| active[i]).map(|i| edges[i]).sum();
+            (sum, active)
+        })
+        .find(|&(sum, _)| sum == value)
+        .map(|(_, active)| active)
+}
+
+fn search_delta_left(crossing: [bool; 4], edge: [bool; 4]) -> u8 {
+    let mut delta = 0;
+
+    // Continue if there is an edge.
+    if edge[3] {
+        delta += 1;
+    }
+
+    // Continue again if there is another edge and no crossing edges.
+    if delta == 1 && edge[2] && !crossing[1] && !crossing[3] {
+        delta += 1;
+    }
+
+    delta
+}
+
+fn search_delta_right(crossing: [bool; 4], edge: [bool; 4]) -> u8 {
+    let mut delta = 0;
+
+    // Continue if there is an edge and no crossing edges.
+    if edge[3] && !crossing[1] && !crossing[3] {
+        delta += 1;
+    }
+
+    // Continue again if there is another edge and no crossing edges.
+    if delta == 1 && edge[2] && !crossing[0] && !crossing[2] {
+        delta += 1;
+    }
+
+    delta
+}
+
+#[test]
+fn edges_without_crossing_edges_are_not_blended() {
+    for left in 0..16 {
+        for right in 0..16 {
+            assert_eq!(ortho_area(0, 0, left as f32, right as f32), Vec2::ZERO);
+            assert_eq!(ortho_area(4, 4, left as f32, right as f32), Vec2::ZERO);
+        }
+    }
+}
+
+#[test]
+fn l_shapes_blend_the_half_near_the_crossing_edge() {
+    // A line from half a pixel below the start of a 4 pixel edge to its middle.
+    let first = ortho_area(3, 0, 0.0, 3.0);
+    assert!((first.x - 0.375).abs() < 1e-6 && first.y == 0.0);
+
+    let second = ortho_area(3, 0, 1.0, 2.0);
+    assert!((second.x - 0.125).abs() < 1e-6 && second.y == 0.0);
+
+    assert_eq!(ortho_area(3, 0, 2.0, 1.0), Vec2::ZERO);
+
+    // The same edge with the crossing edge above blends the other side.
+    assert_eq!(ortho_area(1, 0, 0.0, 3.0), Vec2::new(first.y, first.x));
+}
+
+#[test]
+fn z_shapes_blend_both_sides() {
+    // A line through the middle of a 2 pixel edge.
+    let first = ortho_area(3, 1, 0.0, 1.0);
+    let second = ortho_area(3, 1, 1.0, 0.0);
+
+    assert!((first.x - 0.25).abs() < 1e-6 && first.y == 0.0);
+    assert!(second.x == 0.0 && (second.y - 0.25).abs() < 1e-6);
+}
+
+#[test]
+fn search_texture_steps_back_to_the_end_of_the_edge() {
+    let data = search_texture_data();
+    let texel = |x: u32, y: u32| data[(y * SEARCH_TEXTURE_WIDTH + x) as usize];
+
+    // No edge at the last fetch.
+    assert_eq!(texel(0, 0), 0);
+    // An edge at the near pixel only.
+    assert_eq!(texel(0, 21), 127);
+    assert_eq!(texel(33, 21), 127);
+    // Edges at both pixels.
+    assert_eq!(texel(0, 28), 254);
+    assert_eq!(texel(33, 28), 254);
+    // A crossing edge at the near pixel stops searches to the right before it.
+    assert_eq!(texel(21, 28), 127);
+    assert_eq!(texel(33 + 21, 28), 0);
+}
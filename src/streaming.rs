@@ -0,0 +1,876 @@
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{mpsc, Arc};
+use std::{mem, thread};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::asset;
+use crate::context::Context;
+
+/// The extent of the base pools. Every texture has a layer in the base pool of its format with
+/// the mips up to this size, so that every texture can be sampled from the first frame.
+const BASE_POOL_EXTENT: u32 = 64;
+
+/// The largest extent of the top pools. More detailed mips are never resident.
+const MAX_POOL_EXTENT: u32 = 4096;
+
+/// The number of layers of every pool is at least this. Texture arrays with a single layer may
+/// be created as regular textures on some backends, which can't be bound as arrays.
+const MIN_POOL_LAYERS: u32 = 2;
+
+/// The formats of material textures. Each format has a pool per size class. The pool of a format
+/// and size class is at `format * SIZE_CLASSES.len() + class`, which must match the order of the
+/// pools in `shade.wgsl`.
+const POOL_FORMATS: [wgpu::TextureFormat; 3] = [
+    wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+    wgpu::TextureFormat::Bc5RgUnorm,
+    wgpu::TextureFormat::Bc4RUnorm,
+];
+
+/// The number of texture pools. Must match the number of pools in `shade.wgsl`.
+pub const POOL_COUNT: usize = POOL_FORMATS.len() * SIZE_CLASSES.len();
+
+/// The maximum number of textures loaded at the same time.
+const MAX_PENDING_LOADS: usize = 8;
+
+/// Requested mip levels are written to the feedback buffer with this bias, so that mips more
+/// detailed than the resident mips can be requested. Must match `FEEDBACK_LEVEL_BIAS` in
+/// `shade.wgsl`.
+const FEEDBACK_LEVEL_BIAS: i32 = 16;
+
+/// The number of frames after which the requested mip of a texture decays by one level, unless
+/// it's requested again.
+const MIP_DECAY_FRAMES: u64 = 120;
+
+pub const DEFAULT_TEXTURE_MEMORY_BUDGET: u64 = 512 * 1024 * 1024;
+
+/// The size classes of the texture pools, from the least to the most detailed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SizeClass {
+    Base,
+    Mid,
+    Top,
+}
+
+const SIZE_CLASSES: [SizeClass; 3] = [SizeClass::Base, SizeClass::Mid, SizeClass::Top];
+
+/// Where the mips of a texture are placed in the texture pools. Must match `TextureSlot` in
+/// `shade.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+struct TextureSlot {
+    pool: u32,
+    layer: u32,
+    /// The level of the layer holding the first resident mip. Textures smaller than the pool
+    /// extent leave the more detailed levels unused.
+    base_level: u32,
+    /// The extent of the pool.
+    extent: u32,
+}
+
+/// A layer of the mid or top pool of the format of a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    class: SizeClass,
+    layer: u32,
+}
+
+struct Residency {
+    /// The index of the format in `POOL_FORMATS`.
+    format: usize,
+    /// The layer of the texture in the base pool of its format.
+    base_layer: u32,
+    /// The slot the texture is sampled from, or `None` if it's sampled from the base pool.
+    resident_slot: Option<Slot>,
+    /// The slot being loaded.
+    loading_slot: Option<Slot>,
+    /// The first mip in the base pool.
+    min_mip: u32,
+    /// The most detailed mip requested by the shade pass.
+    requested_mip: u32,
+    /// The frame `requested_mip` was requested.
+    requested_at: u64,
+    /// The frame the texture was last sampled.
+    last_used: u64,
+    /// The frame the texture was moved to its resident slot.
+    resident_since: u64,
+}
+
+impl Residency {
+    /// The first mip wanted at `frame`. The requested mip decays towards the lowest residency
+    /// as long as the texture isn't requested at the same or a more detailed mip.
+    fn wanted_mip(&self, frame: u64) -> u32 {
+        let decay = frame.saturating_sub(self.requested_at) / MIP_DECAY_FRAMES;
+        (self.requested_mip as u64 + decay).min(self.min_mip as u64) as u32
+    }
+
+    /// Record that `mip` was requested at `frame`.
+    fn request(&mut self, mip: u32, frame: u64) {
+        if mip <= self.wanted_mip(frame) {
+            self.requested_mip = mip;
+            self.requested_at = frame;
+        }
+
+        self.last_used = frame;
+    }
+
+    fn resident_class(&self) -> SizeClass {
+        self.resident_slot
+            .map_or(SizeClass::Base, |slot| slot.class)
+    }
+
+    fn is_loading(&self) -> bool {
+        self.loading_slot.is_some()
+    }
+}
+
+/// A texture array holding the mips of textures of one format and size class, one texture per
+/// layer.
+struct TexturePool {
+    view: wgpu::TextureView,
+    extent: u32,
+    /// The texture placed in each layer of mid and top pools.
+    owners: Vec<Option<usize>>,
+}
+
+struct LoadRequest {
+    texture: usize,
+    pool: usize,
+    slot: TextureSlot,
+    first_mip: u32,
+}
+
+enum FeedbackState {
+    Idle,
+    Copied,
+    Mapping,
+}
+
+/// Streams the mips of material textures based on feedback from the shade pass.
+///
+/// Material textures are placed in texture pools, which are texture arrays of a fixed format and
+/// extent. Each format has a base, mid and top pool. Every texture has a layer in the base pool
+/// with its mips up to `BASE_POOL_EXTENT`. The mid and top pools have a fixed number of layers
+/// given by the memory budget, which hold the more detailed mips of the textures that need them.
+/// The shade pass finds the layer of each texture through `slot_buffer`, so the bind group of
+/// the pools never changes.
+///
+/// The shade pass writes the most detailed mip level it samples of each texture to
+/// `feedback_buffer`, which is read back a few frames later. Textures with more detailed mips
+/// requested are loaded into a layer of a more detailed pool on a loader thread, and textures
+/// whose requests have decayed move back to a less detailed pool. The textures that have gone
+/// the longest without being sampled are evicted from full pools.
+///
+/// The streamer owns the textures of the scene. Mips mapped from the scene cache are only read
+/// when they are loaded, so they don't have to stay in memory.
+pub struct TextureStreamer {
+    textures: Arc<[asset::Texture]>,
+    residency: Vec<Residency>,
+    pools: Vec<TexturePool>,
+    pub slot_buffer: wgpu::Buffer,
+    pub feedback_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    feedback_state: FeedbackState,
+    /// The frame the feedback in `readback_buffer` was written.
+    feedback_frame: u64,
+    feedback_mapped: Arc<AtomicBool>,
+    load_requests: mpsc::Sender<LoadRequest>,
+    loaded_textures: mpsc::Receiver<usize>,
+    frame_index: u64,
+}
+
+impl TextureStreamer {
+    /// Create the texture pools of `textures`. Material textures must be square with a power of
+    /// two extent, and have one of the formats of the pools. The mid and top pools get as many
+    /// layers as fit in `memory_budget`.
+    pub fn new(
+        context: &Context,
+        textures: Vec<asset::Texture>,
+        memory_budget: u64,
+    ) -> eyre::Result<Self> {
+        let textures: Arc<[asset::Texture]> = textures.into();
+        let mut base_layer_counts = [0; POOL_FORMATS.len()];
+
+        let residency = textures
+            .iter()
+            .enumerate()
+            .map(|(index, texture)| {
+                let format = POOL_FORMATS
+                    .iter()
+                    .position(|format| *format == texture.format)
+                    .ok_or_else(|| {
+                        eyre::eyre!(
+                            "texture {index} has unsupported format {:?}",
+                            texture.format
+                        )
+                    })?;
+
+                let extent = texture.extent.width;
+                if !extent.is_power_of_two() || extent != texture.extent.height || extent < 4 {
+                    return Err(eyre::eyre!(
+                        "texture {index} isn't square with a power of two extent: {:?}",
+                        texture.extent,
+                    ));
+                }
+
+                let base_layer = base_layer_counts[format];
+                base_layer_counts[format] += 1;
+
+                let min_mip = first_mip(texture, BASE_POOL_EXTENT);
+
+                Ok(Residency {
+                    format,
+                    base_layer,
+                    resident_slot: None,
+                    loading_slot: None,
+                    requested_mip: min_mip,
+                    requested_at: 0,
+                    last_used: 0,
+                    resident_since: 0,
+                    min_mip,
+                })
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        let max_layers = context.device.limits().max_texture_array_layers;
+        let layouts = pool_layouts(&textures, memory_budget, max_layers)?;
+
+        let pool_textures: Arc<[wgpu::Texture]> = layouts
+            .iter()
+            .enumerate()
+            .map(|(pool, layout)| {
+                context.device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("texture pool"),
+                    size: wgpu::Extent3d {
+                        width: layout.extent,
+                        height: layout.extent,
+                        depth_or_array_layers: layout.layer_count,
+                    },
+                    mip_level_count: layout.extent.ilog2() - 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: POOL_FORMATS[pool / SIZE_CLASSES.len()],
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                })
+            })
+            .collect();
+
+        let pools = pool_textures
+            .iter()
+            .zip(layouts.iter())
+            .map(|(texture, layout)| TexturePool {
+                view: texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2Array),
+                    ..Default::default()
+                }),
+                extent: layout.extent,
+                owners: vec![None; layout.layer_count as usize],
+            })
+            .collect::<Vec<_>>();
+
+        let mut slots = Vec::with_capacity(textures.len());
+
+        for (texture, residency) in textures.iter().zip(residency.iter()) {
+            let pool = pool_index(residency.format, SizeClass::Base);
+            let slot = texture_slot(texture, pool, pools[pool].extent, residency.base_layer);
+
+            write_mips(
+                &context.queue,
+                &pool_textures[pool],
+                texture,
+                slot,
+                residency.min_mip,
+            );
+
+            slots.push(slot);
+        }
+
+        // Bindings can't be empty.
+        if slots.is_empty() {
+            slots.push(TextureSlot::zeroed());
+        }
+
+        let slot_buffer = context
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("texture slots"),
+                contents: bytemuck::cast_slice(&slots),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let feedback_size = (textures.len().max(1) * mem::size_of::<u32>()) as u64;
+
+        let feedback_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("texture feedback"),
+            size: feedback_size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("texture feedback readback"),
+            size: feedback_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (load_requests, requests) = mpsc::channel::<LoadRequest>();
+        let (loaded, loaded_textures) = mpsc::channel();
+
+        let queue = context.queue.clone();
+        let loader_textures = textures.clone();
+
+        // The loader writes into layers which aren't sampled until the slot of the texture is
+        // updated once the load is done.
+        thread::spawn(move || {
+            for request in requests {
+                let texture = &loader_textures[request.texture];
+                let pool = &pool_textures[request.pool];
+
+                write_mips(&queue, pool, texture, request.slot, request.first_mip);
+
+                if loaded.send(request.texture).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            textures,
+            residency,
+            pools,
+            slot_buffer,
+            feedback_buffer,
+            readback_buffer,
+            feedback_state: FeedbackState::Idle,
+            feedback_frame: 0,
+            feedback_mapped: Arc::new(AtomicBool::new(false)),
+            load_requests,
+            loaded_textures,
+            frame_index: 0,
+        })
+    }
+
+    /// The views of the texture pools, in the order of the pools in `shade.wgsl`.
+    pub fn pool_views(&self) -> impl Iterator<Item = &wgpu::TextureView> {
+        self.pools.iter().map(|pool| &pool.view)
+    }
+
+    /// Process feedback from the shade pass, move loaded textures to their new slots and request
+    /// new loads.
+    pub fn update(&mut self, context: &Context) {
+        self.frame_index += 1;
+        context.device.poll(wgpu::Maintain::Poll);
+
+        if let FeedbackState::Mapping = self.feedback_state {
+            if self.feedback_mapped.load(atomic::Ordering::Acquire) {
+                self.read_feedback();
+            }
+        }
+
+        while let Ok(texture) = self.loaded_textures.try_recv() {
+            let slot = self.residency[texture]
+                .loading_slot
+                .take()
+                .expect("loaded texture should have a loading slot");
+
+            self.release_slot(texture);
+            self.set_resident_slot(context, texture, Some(slot));
+        }
+
+        self.request_loads(context);
+    }
+
+    /// Clear the feedback buffer. Must be recorded before the shade pass.
+    pub fn clear_feedback(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.clear_buffer(&self.feedback_buffer, 0, None);
+    }
+
+    /// Copy the feedback buffer so that it can be read back. Must be recorded after the shade
+    /// pass.
+    pub fn copy_feedback(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let FeedbackState::Idle = self.feedback_state {
+            encoder.copy_buffer_to_buffer(
+                &self.feedback_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                self.feedback_buffer.size(),
+            );
+
+            self.feedback_state = FeedbackState::Copied;
+            self.feedback_frame = self.frame_index;
+        }
+    }
+
+    /// Start mapping the feedback copied this frame. Must be called after the frame is submitted.
+    pub fn map_feedback(&mut self) {
+        if let FeedbackState::Copied = self.feedback_state {
+            let mapped = self.feedback_mapped.clone();
+            mapped.store(false, atomic::Ordering::Release);
+
+            self.readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    if let Err(err) = result {
+                        eprintln!("failed to map texture feedback: {err}");
+                    } else {
+                        mapped.store(true, atomic::Ordering::Release);
+                    }
+                });
+
+            self.feedback_state = FeedbackState::Mapping;
+        }
+    }
+
+    fn read_feedback(&mut self) {
+        {
+            let feedback = self.readback_buffer.slice(..).get_mapped_range();
+            let feedback: &[u32] = bytemuck::cast_slice(&feedback);
+
+            for ((residency, texture), level) in self
+                .residency
+                .iter_mut()
+                .zip(self.textures.iter())
+                .zip(feedback)
+            {
+                // The level is relative to the first mip of the slot the texture was sampled
+                // from when the feedback was written, which is unknown if the texture has moved
+                // since.
+                if *level == 0 || residency.resident_since > self.feedback_frame {
+                    continue;
+                }
+
+                let pool = pool_index(residency.format, residency.resident_class());
+                let resident_mip = first_mip(texture, self.pools[pool].extent);
+
+                let level = 32 - *level as i32 - FEEDBACK_LEVEL_BIAS;
+                let mip = (resident_mip as i32 + level).clamp(0, residency.min_mip as i32);
+
+                residency.request(mip as u32, self.feedback_frame);
+            }
+        }
+
+        self.readback_buffer.unmap();
+        self.feedback_state = FeedbackState::Idle;
+    }
+
+    /// The least detailed size class which has the mip `mip` of `texture`.
+    fn size_class(&self, texture: usize, mip: u32) -> SizeClass {
+        let format = self.residency[texture].format;
+
+        SIZE_CLASSES
+            .into_iter()
+            .find(|class| {
+                let extent = self.pools[pool_index(format, *class)].extent;
+                first_mip(&self.textures[texture], extent) <= mip
+            })
+            .unwrap_or(SizeClass::Top)
+    }
+
+    /// Point the slot of `texture` at `slot`, or at its layer in the base pool if `None`.
+    fn set_resident_slot(&mut self, context: &Context, texture: usize, slot: Option<Slot>) {
+        let residency = &mut self.residency[texture];
+
+        residency.resident_slot = slot;
+        residency.resident_since = self.frame_index;
+
+        let (class, layer) = slot.map_or((SizeClass::Base, residency.base_layer), |slot| {
+            (slot.class, slot.layer)
+        });
+
+        let pool = pool_index(residency.format, class);
+        let slot = texture_slot(
+            &self.textures[texture],
+            pool,
+            self.pools[pool].extent,
+            layer,
+        );
+
+        let offset = (texture * mem::size_of::<TextureSlot>()) as u64;
+        context
+            .queue
+            .write_buffer(&self.slot_buffer, offset, bytemuck::bytes_of(&slot));
+    }
+
+    /// Free the layer of the resident slot of `texture`, if any.
+    fn release_slot(&mut self, texture: usize) {
+        let residency = &self.residency[texture];
+
+        if let Some(slot) = residency.resident_slot {
+            let pool = pool_index(residency.format, slot.class);
+            self.pools[pool].owners[slot.layer as usize] = None;
+        }
+    }
+
+    /// Find a layer of the pool of `class` for `texture`. If the pool is full, the least recently
+    /// used texture which isn't used by the latest frame is moved back to the base pool.
+    fn allocate_layer(
+        &mut self,
+        context: &Context,
+        texture: usize,
+        class: SizeClass,
+    ) -> Option<u32> {
+        let pool = pool_index(self.residency[texture].format, class);
+        let owners = &self.pools[pool].owners;
+
+        let layer = match owners.iter().position(Option::is_none) {
+            Some(layer) => layer,
+            None => {
+                let latest_use = self.residency[texture].last_used;
+
+                let (layer, evicted) = owners
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(layer, owner)| Some((layer, (*owner)?)))
+                    .filter(|(_, owner)| {
+                        let residency = &self.residency[*owner];
+                        !residency.is_loading() && residency.last_used < latest_use
+                    })
+                    .min_by_key(|(_, owner)| self.residency[*owner].last_used)?;
+
+                self.release_slot(evicted);
+                self.set_resident_slot(context, evicted, None);
+
+                layer
+            }
+        };
+
+        self.pools[pool].owners[layer] = Some(texture);
+        Some(layer as u32)
+    }
+
+    fn request_load(&mut self, texture: usize, slot: Slot) {
+        let residency = &mut self.residency[texture];
+        residency.loading_slot = Some(slot);
+
+        let pool = pool_index(residency.format, slot.class);
+        let extent = self.pools[pool].extent;
+        let texture_slot = texture_slot(&self.textures[texture], pool, extent, slot.layer);
+
+        let request = LoadRequest {
+            first_mip: first_mip(&self.textures[texture], extent),
+            slot: texture_slot,
+            texture,
+            pool,
+        };
+
+        if self.load_requests.send(request).is_err() {
+            eprintln!("texture loader thread has stopped");
+        }
+    }
+
+    fn request_loads(&mut self, context: &Context) {
+        let mut pending_loads = self
+            .residency
+            .iter()
+            .filter(|residency| residency.is_loading())
+            .count();
+
+        let mut candidates: Vec<_> = self
+            .residency
+            .iter()
+            .enumerate()
+            .filter(|(_, residency)| !residency.is_loading())
+            .filter_map(|(index, residency)| {
+                let class = self.size_class(index, residency.wanted_mip(self.frame_index));
+                (class != residency.resident_class()).then_some((index, class))
+            })
+            .collect();
+
+        // Release the mips of decayed textures first, then prioritize recently sampled textures.
+        candidates.sort_by_key(|(index, class)| {
+            let residency = &self.residency[*index];
+            (
+                *class > residency.resident_class(),
+                std::cmp::Reverse(residency.last_used),
+            )
+        });
+
+        for (index, class) in candidates {
+            // Moving back to the base pool doesn't need a load, as the base pool is always
+            // resident.
+            if class == SizeClass::Base {
+                self.release_slot(index);
+                self.set_resident_slot(context, index, None);
+                continue;
+            }
+
+            if pending_loads >= MAX_PENDING_LOADS {
+                break;
+            }
+
+            if let Some(layer) = self.allocate_layer(context, index, class) {
+                self.request_load(index, Slot { class, layer });
+                pending_loads += 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PoolLayout {
+    extent: u32,
+    layer_count: u32,
+}
+
+fn pool_index(format: usize, class: SizeClass) -> usize {
+    format * SIZE_CLASSES.len() + class as usize
+}
+
+/// The extent and layer count of each pool. The top pool of a format is as large as its largest
+/// texture and the mid pool a quarter of that. Half of `memory_budget` is shared between the mid
+/// pools and half between the top pools, in proportion to the memory of the textures which are
+/// more detailed than the previous pool.
+fn pool_layouts(
+    textures: &[asset::Texture],
+    memory_budget: u64,
+    max_layers: u32,
+) -> eyre::Result<[PoolLayout; POOL_COUNT]> {
+    let mut layouts = [PoolLayout {
+        extent: BASE_POOL_EXTENT,
+        layer_count: MIN_POOL_LAYERS,
+    }; POOL_COUNT];
+
+    for (format_index, format) in POOL_FORMATS.iter().enumerate() {
+        let extents = textures
+            .iter()
+            .filter(|texture| texture.format == *format)
+            .map(|texture| texture.extent.width);
+
+        let top_extent = extents
+            .clone()
+            .max()
+            .unwrap_or(0)
+            .clamp(BASE_POOL_EXTENT, MAX_POOL_EXTENT);
+
+        let mid_extent = u32::max(top_extent / 4, BASE_POOL_EXTENT);
+
+        let base = &mut layouts[pool_index(format_index, SizeClass::Base)];
+        base.layer_count = base.layer_count.max(extents.clone().count() as u32);
+
+        layouts[pool_index(format_index, SizeClass::Mid)].extent = mid_extent;
+        layouts[pool_index(format_index, SizeClass::Top)].extent = top_extent;
+    }
+
+    for class in [SizeClass::Mid, SizeClass::Top] {
+        let previous_class = if class == SizeClass::Mid {
+            SizeClass::Base
+        } else {
+            SizeClass::Mid
+        };
+
+        // The number of textures of each format which have mips in the pool of `class` which
+        // aren't in the previous pool.
+        let texture_counts: Vec<_> = POOL_FORMATS
+            .iter()
+            .enumerate()
+            .map(|(format_index, format)| {
+                let previous_extent = layouts[pool_index(format_index, previous_class)].extent;
+
+                textures
+                    .iter()
+                    .filter(|texture| {
+                        texture.format == *format && texture.extent.width > previous_extent
+                    })
+                    .count() as u64
+            })
+            .collect();
+
+        let layer_memory: Vec<_> = (0..POOL_FORMATS.len())
+            .map(|format| {
+                layer_memory(
+                    POOL_FORMATS[format],
+                    layouts[pool_index(format, class)].extent,
+                )
+            })
+            .collect();
+
+        let total_memory: u64 = texture_counts
+            .iter()
+            .zip(layer_memory.iter())
+            .map(|(count, memory)| count * memory)
+            .sum();
+
+        for format in 0..POOL_FORMATS.len() {
+            let layer_count = (memory_budget / 2 * texture_counts[format])
+                .checked_div(total_memory)
+                .unwrap_or(0)
+                .min(texture_counts[format]);
+
+            layouts[pool_index(format, class)].layer_count =
+                (layer_count as u32).clamp(MIN_POOL_LAYERS, max_layers);
+        }
+    }
+
+    if let Some(layout) = layouts
+        .iter()
+        .find(|layout| layout.layer_count > max_layers)
+    {
+        return Err(eyre::eyre!(
+            "scene has {} textures of the same format, but the adapter only supports {} texture \
+             array layers",
+            layout.layer_count,
+            max_layers,
+        ));
+    }
+
+    Ok(layouts)
+}
+
+/// The memory of a layer of a pool, with mips from `extent` down to the size of a block.
+fn layer_memory(format: wgpu::TextureFormat, extent: u32) -> u64 {
+    let block_size = format
+        .block_size(None)
+        .expect("texture formats should have a block size");
+
+    (0..extent.ilog2() - 1)
+        .map(|level| {
+            let blocks = (extent >> level).div_ceil(4);
+            blocks as u64 * blocks as u64 * block_size as u64
+        })
+        .sum()
+}
+
+/// The first mip of `texture` in a pool of `extent`. Mips more detailed than the pool are left
+/// out.
+fn first_mip(texture: &asset::Texture, extent: u32) -> u32 {
+    texture.extent.width.ilog2().saturating_sub(extent.ilog2())
+}
+
+/// The slot of `texture` in `layer` of `pool`.
+fn texture_slot(texture: &asset::Texture, pool: usize, extent: u32, layer: u32) -> TextureSlot {
+    TextureSlot {
+        pool: pool as u32,
+        base_level: extent.ilog2().saturating_sub(texture.extent.width.ilog2()),
+        layer,
+        extent,
+    }
+}
+
+/// Write the mips of `texture` from `first_mip` to the layer of `slot`. Each mip is written
+/// separately, so mips mapped from the scene cache are copied directly into the staging buffer.
+fn write_mips(
+    queue: &wgpu::Queue,
+    pool: &wgpu::Texture,
+    texture: &asset::Texture,
+    slot: TextureSlot,
+    first_mip: u32,
+) {
+    let (block_width, block_height) = texture.format.block_dimensions();
+    let block_size = texture
+        .format
+        .block_size(None)
+        .expect("texture formats should have a block size");
+
+    for (level, mip) in (slot.base_level..).zip(&texture.mips[first_mip as usize..]) {
+        let extent = slot.extent >> level;
+        let (width_in_blocks, height_in_blocks) =
+            (extent.div_ceil(block_width), extent.div_ceil(block_height));
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: pool,
+                mip_level: level,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: slot.layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            mip,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width_in_blocks * block_size),
+                rows_per_image: Some(height_in_blocks),
+            },
+            wgpu::Extent3d {
+                width: width_in_blocks * block_width,
+                height: height_in_blocks * block_height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+fn test_texture(format: wgpu::TextureFormat, extent: u32) -> asset::Texture {
+    asset::Texture {
+        hash: 0,
+        format,
+        extent: wgpu::Extent3d {
+            width: extent,
+            height: extent,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: extent.ilog2() - 1,
+        mips: Vec::new(),
+    }
+}
+
+#[test]
+fn texture_placement_in_pools() {
+    let large = test_texture(wgpu::TextureFormat::Bc1RgbaUnormSrgb, 256);
+    let small = test_texture(wgpu::TextureFormat::Bc1RgbaUnormSrgb, 16);
+
+    assert_eq!(first_mip(&large, BASE_POOL_EXTENT), 2);
+    assert_eq!(texture_slot(&large, 0, BASE_POOL_EXTENT, 3).base_level, 0);
+
+    assert_eq!(first_mip(&small, BASE_POOL_EXTENT), 0);
+    assert_eq!(texture_slot(&small, 0, BASE_POOL_EXTENT, 3).base_level, 2);
+
+    assert_eq!(layer_memory(large.format, 16), (4 * 4 + 2 * 2 + 1) * 8);
+}
+
+#[test]
+fn pool_layers_fit_memory_budget() {
+    let format = wgpu::TextureFormat::Bc1RgbaUnormSrgb;
+    let mut textures = vec![test_texture(format, 1024); 10];
+    textures.push(test_texture(wgpu::TextureFormat::Bc4RUnorm, 16));
+
+    let budget = 2 * 5 * layer_memory(format, 1024);
+    let layouts = pool_layouts(&textures, budget, 256).unwrap();
+
+    let layout = |format, class| layouts[pool_index(format, class)];
+    let expected = |extent, layer_count| PoolLayout {
+        extent,
+        layer_count,
+    };
+
+    assert_eq!(layout(0, SizeClass::Base), expected(64, 10));
+    assert_eq!(layout(0, SizeClass::Mid), expected(256, 10));
+    assert_eq!(layout(0, SizeClass::Top), expected(1024, 5));
+    assert_eq!(layout(2, SizeClass::Base), expected(64, 2));
+    assert_eq!(layout(2, SizeClass::Top), expected(64, 2));
+
+    assert!(pool_layouts(&textures, budget, 8).is_err());
+}
+
+#[test]
+fn requested_mip_decays() {
+    let mut residency = Residency {
+        format: 0,
+        base_layer: 0,
+        resident_slot: None,
+        loading_slot: None,
+        min_mip: 4,
+        requested_mip: 4,
+        requested_at: 0,
+        last_used: 0,
+        resident_since: 0,
+    };
+
+    residency.request(1, 10);
+    assert_eq!(residency.wanted_mip(10 + MIP_DECAY_FRAMES - 1), 1);
+    assert_eq!(residency.wanted_mip(10 + MIP_DECAY_FRAMES), 2);
+
+    // Coarser requests don't reset the decay.
+    residency.request(3, 10 + MIP_DECAY_FRAMES);
+    assert_eq!(residency.wanted_mip(10 + 2 * MIP_DECAY_FRAMES), 3);
+    assert_eq!(residency.wanted_mip(10 + 10 * MIP_DECAY_FRAMES), 4);
+}
@@ -1,17 +1,59 @@
-use std::{borrow::Cow, mem};
+use std::borrow::Cow;
 
+use bytemuck::{Pod, Zeroable};
 use glam::{Mat4, UVec2, Vec2, Vec3};
+use serde::Deserialize;
 
 use crate::{
     context::Context,
-    resources::{self, ConstState, Consts, RenderState},
+    resources::{self, ConstState, Consts, ParamsBuffer, RenderState},
     util,
 };
 
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TaaSettings {
+    /// The number of subpixel offsets of the Halton sequence before it repeats.
+    pub jitter_sequence_length: u32,
+    /// The weight of the current frame when blended with the history.
+    pub history_blend: f32,
+    /// The number of standard deviations around the mean of the neighborhood the history is
+    /// clipped to. Lower values reduce ghosting but increase flickering.
+    pub variance_gamma: f32,
+    /// The amount of contrast adaptive sharpening after the resolve. Zero skips the pass.
+    pub sharpness: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            jitter_sequence_length: 12,
+            history_blend: 0.1,
+            variance_gamma: 1.0,
+            sharpness: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct TemporalResolveParams {
+    reproject: Mat4,
+    history_blend: f32,
+    variance_gamma: f32,
+    padding: [f32; 2],
+}
+
 pub struct TemporalResolvePhase {
     pipeline: wgpu::ComputePipeline,
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    params: ParamsBuffer<TemporalResolveParams>,
+    sharpen_pipeline: wgpu::ComputePipeline,
+    sharpen_bind_group_layout: wgpu::BindGroupLayout,
+    sharpen_bind_group: wgpu::BindGroup,
+    /// How much to sharpen in the range [0, 1].
+    sharpness: ParamsBuffer<f32>,
 }
 
 impl TemporalResolvePhase {
@@ -49,7 +91,7 @@ impl TemporalResolvePhase {
                             binding: 1,
                             visibility: wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Texture {
-                                sample_type: wgpu::TextureSampleType::Depth,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
                                 view_dimension: wgpu::TextureViewDimension::D2,
                                 multisampled: false,
                             },
@@ -75,6 +117,11 @@ impl TemporalResolvePhase {
                             },
                             count: None,
                         },
+                        ParamsBuffer::<TemporalResolveParams>::layout_entry(
+                            4,
+                            wgpu::ShaderStages::COMPUTE,
+                            false,
+                        ),
                     ],
                 });
 
@@ -87,10 +134,7 @@ impl TemporalResolvePhase {
                         ConstState::bind_group_layout(context),
                         &bind_group_layout,
                     ],
-                    push_constant_ranges: &[wgpu::PushConstantRange {
-                        stages: wgpu::ShaderStages::COMPUTE,
-                        range: 0..mem::size_of::<Mat4>() as u32,
-                    }],
+                    push_constant_ranges: &[],
                 });
 
         let pipeline = context
@@ -102,12 +146,91 @@ impl TemporalResolvePhase {
                 layout: Some(&pipeline_layout),
             });
 
-        let bind_group = create_bind_group(context, render_state, &bind_group_layout);
+        let params = ParamsBuffer::new(context, "temporal resolve params", 1);
+        let bind_group = create_bind_group(context, render_state, &params, &bind_group_layout);
+
+        let module = context.create_shader_module(
+            include_str!("shaders/sharpen.wgsl"),
+            "shaders/sharpen.wgsl",
+            &[],
+        );
+
+        let shader = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("sharpen"),
+                source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+            });
+
+        let sharpen_bind_group_layout =
+            context
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("sharpen"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                                format: resources::COLOR_BUFFER_FORMAT,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        ParamsBuffer::<f32>::layout_entry(2, wgpu::ShaderStages::COMPUTE, false),
+                    ],
+                });
+
+        let pipeline_layout =
+            context
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("sharpen"),
+                    bind_group_layouts: &[
+                        ConstState::bind_group_layout(context),
+                        &sharpen_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+
+        let sharpen_pipeline =
+            context
+                .device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("sharpen"),
+                    entry_point: "main",
+                    module: &shader,
+                    layout: Some(&pipeline_layout),
+                });
+
+        let sharpness = ParamsBuffer::new(context, "sharpness", 1);
+        let sharpen_bind_group = create_sharpen_bind_group(
+            context,
+            render_state,
+            &sharpness,
+            &sharpen_bind_group_layout,
+        );
 
         Self {
             pipeline,
             bind_group_layout,
             bind_group,
+            params,
+            sharpen_pipeline,
+            sharpen_bind_group_layout,
+            sharpen_bind_group,
+            sharpness,
         }
     }
 
@@ -117,6 +240,7 @@ impl TemporalResolvePhase {
         consts: &Consts,
         const_state: &ConstState,
         render_state: &RenderState,
+        settings: &TaaSettings,
         encoder: &mut wgpu::CommandEncoder,
     ) {
         let reproject = Mat4::from_translation(Vec3::new(0.5, 0.5, 0.0))
@@ -124,13 +248,20 @@ impl TemporalResolvePhase {
             * consts.prev_proj_view
             * consts.inverse_proj_view;
 
+        let params = TemporalResolveParams {
+            reproject,
+            history_blend: settings.history_blend.clamp(0.0, 1.0),
+            variance_gamma: settings.variance_gamma.max(0.0),
+            padding: [0.0; 2],
+        };
+
+        self.params.write(context, 0, &params);
+
         let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("temporal resolve"),
         });
 
         compute_pass.set_pipeline(&self.pipeline);
-
-        compute_pass.set_push_constants(0, bytemuck::bytes_of(&reproject));
         compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
         compute_pass.set_bind_group(1, &self.bind_group, &[]);
 
@@ -140,6 +271,8 @@ impl TemporalResolvePhase {
         compute_pass.dispatch_workgroups(x, y, 1);
         drop(compute_pass);
 
+        // The history is kept unsharpened, as sharpening it would accumulate over frames.
+
         encoder.copy_texture_to_texture(
             wgpu::ImageCopyTexture {
                 texture: &render_state.post.texture,
@@ -155,21 +288,92 @@ impl TemporalResolvePhase {
             },
             context.surface_size,
         );
+
+        if settings.sharpness <= 0.0 {
+            return;
+        }
+
+        self.sharpness
+            .write(context, 0, &settings.sharpness.min(1.0));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("sharpen"),
+        });
+
+        compute_pass.set_pipeline(&self.sharpen_pipeline);
+        compute_pass.set_bind_group(0, &const_state.bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.sharpen_bind_group, &[]);
+        compute_pass.dispatch_workgroups(x, y, 1);
     }
 
     pub fn resize_surface(&mut self, context: &Context, render_state: &RenderState) {
-        self.bind_group = create_bind_group(context, render_state, &self.bind_group_layout);
+        self.bind_group =
+            create_bind_group(context, render_state, &self.params, &self.bind_group_layout);
+        self.sharpen_bind_group = create_sharpen_bind_group(
+            context,
+            render_state,
+            &self.sharpness,
+            &self.sharpen_bind_group_layout,
+        );
     }
 }
 
-pub fn jitter(frame_index: usize, surface_size: UVec2) -> Vec2 {
-    let jitter = HALTON_SEQUENCE[frame_index as usize % 12];
+/// The subpixel offset of the projection in clip space for `frame_index`. Cycles through the
+/// first `sequence_length` points of the Halton sequence with bases 2 and 3, skipping the first
+/// point which is zero.
+pub fn jitter(frame_index: usize, surface_size: UVec2, sequence_length: u32) -> Vec2 {
+    let index = (frame_index % sequence_length.max(1) as usize) as u32 + 1;
+    let jitter = Vec2::new(halton(index, 2), halton(index, 3)) - 0.5;
+
     jitter / surface_size.as_vec2()
 }
 
+/// The radical inverse of `index` in `base`.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
+fn create_sharpen_bind_group(
+    context: &Context,
+    render_state: &RenderState,
+    sharpness: &ParamsBuffer<f32>,
+    layout: &wgpu::BindGroupLayout,
+) -> wgpu::BindGroup {
+    context
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sharpen"),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&render_state.color_accum.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&render_state.post.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: sharpness.binding(),
+                },
+            ],
+            layout,
+        })
+}
+
 fn create_bind_group(
     context: &Context,
     render_state: &RenderState,
+    params: &ParamsBuffer<TemporalResolveParams>,
     layout: &wgpu::BindGroupLayout,
 ) -> wgpu::BindGroup {
     let views = [
@@ -186,6 +390,10 @@ fn create_bind_group(
             binding: binding as u32,
             resource: wgpu::BindingResource::TextureView(view),
         })
+        .chain([wgpu::BindGroupEntry {
+            binding: views.len() as u32,
+            resource: params.binding(),
+        }])
         .collect();
 
     context
@@ -197,17 +405,25 @@ fn create_bind_group(
         })
 }
 
-const HALTON_SEQUENCE: [Vec2; 12] = [
-    Vec2::new(0.0, -0.16666666),
-    Vec2::new(-0.25, 0.16666669),
-    Vec2::new(0.25, -0.3888889),
-    Vec2::new(-0.375, -0.055555552),
-    Vec2::new(0.125, 0.2777778),
-    Vec2::new(-0.125, -0.2777778),
-    Vec2::new(0.375, 0.055555582),
-    Vec2::new(-0.4375, 0.3888889),
-    Vec2::new(0.0625, -0.46296296),
-    Vec2::new(-0.1875, -0.12962961),
-    Vec2::new(0.3125, 0.2037037),
-    Vec2::new(-0.3125, -0.35185185),
-];
+#[test]
+fn halton_jitter_sequence() {
+    // The first points of the table that was used before the sequence length was configurable.
+    let expected = [
+        Vec2::new(0.0, -0.16666666),
+        Vec2::new(-0.25, 0.16666669),
+        Vec2::new(0.25, -0.3888889),
+        Vec2::new(-0.375, -0.055555552),
+        Vec2::new(0.125, 0.2777778),
+    ];
+
+    for (frame_index, expected) in expected.into_iter().enumerate() {
+        let jitter = jitter(frame_index, UVec2::ONE, 12);
+        assert!(jitter.abs_diff_eq(expected, 1e-6), "{jitter} != {expected}");
+    }
+
+    assert_eq!(
+        jitter(4, UVec2::ONE, 4),
+        jitter(0, UVec2::ONE, 4),
+        "the sequence should repeat"
+    );
+}
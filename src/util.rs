@@ -1,3 +1,37 @@
 pub fn div_ceil(a: u32, b: u32) -> u32 {
-    (a + b - 1) / b
+    a.div_ceil(b)
+}
+
+/// A layout entry of a 2D texture sampled in compute shaders.
+pub fn texture_layout_entry(
+    binding: u32,
+    sample_type: wgpu::TextureSampleType,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+            sample_type,
+        },
+        count: None,
+    }
+}
+
+/// A layout entry of a write only 2D storage texture in compute shaders.
+pub fn storage_texture_layout_entry(
+    binding: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            format,
+        },
+        count: None,
+    }
 }
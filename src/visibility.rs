@@ -35,7 +35,7 @@ impl VisiblityPhase {
                     label: Some("render"),
                     push_constant_ranges: &[],
                     bind_group_layouts: &[
-                        ConstState::bind_group_layout(&context),
+                        ConstState::bind_group_layout(context),
                         &scene_state.bind_group_layout,
                     ],
                 });
@@ -48,7 +48,7 @@ impl VisiblityPhase {
                 vertex: wgpu::VertexState {
                     entry_point: "vertex",
                     module: &visibility_shader,
-                    buffers: &[],
+                    buffers: &[resources::PRIMITIVE_INDEX_LAYOUT],
                 },
                 fragment: Some(wgpu::FragmentState {
                     entry_point: "fragment",
@@ -66,7 +66,7 @@ impl VisiblityPhase {
                 depth_stencil: Some(wgpu::DepthStencilState {
                     format: resources::DEPTH_BUFFER_FORMAT,
                     depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
+                    depth_compare: wgpu::CompareFunction::Greater,
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
                 }),
@@ -79,6 +79,7 @@ impl VisiblityPhase {
 
     pub fn record(
         &self,
+        context: &Context,
         const_state: &ConstState,
         render_state: &RenderState,
         scene_state: &SceneState,
@@ -99,7 +100,7 @@ impl VisiblityPhase {
                 view: &render_state.depth.view,
                 stencil_ops: None,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    load: wgpu::LoadOp::Clear(0.0),
                     store: true,
                 }),
             }),
@@ -113,9 +114,20 @@ impl VisiblityPhase {
             })],
         });
 
+        let render_size = context.render_size;
+        render_pass.set_viewport(
+            0.0,
+            0.0,
+            render_size.width as f32,
+            render_size.height as f32,
+            0.0,
+            1.0,
+        );
+
         render_pass.set_pipeline(&self.visibility);
         render_pass.set_bind_group(0, &const_state.bind_group, &[]);
         render_pass.set_bind_group(1, &scene_state.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, scene_state.primitive_indices.slice(..));
 
         let frustrum = camera.frustrum();
         for (index, draw) in scene_state.primitive_draw_infos.iter().enumerate() {